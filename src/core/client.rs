@@ -1,6 +1,14 @@
 use super::scenegraph::Scenegraph;
 use crate::{
-	core::{registry::OwnedRegistry, task},
+	core::{
+		handshake,
+		quota::QuotaUsage,
+		recording::Recorder,
+		registry::OwnedRegistry,
+		relay::{self, OutgoingQueue},
+		task,
+		traffic::{TrafficCounters, TrafficStats},
+	},
 	nodes::{
 		audio, data, drawable, fields, hmd, input, items,
 		root::Root,
@@ -12,32 +20,95 @@ use crate::{
 use color_eyre::eyre::{eyre, Result};
 use lazy_static::lazy_static;
 use once_cell::sync::OnceCell;
-use parking_lot::Mutex;
+use parking_lot::{const_mutex, Mutex};
 use rustc_hash::FxHashMap;
-use stardust_xr::messenger::{self, MessageSenderHandle};
-use std::{fs, iter::FromIterator, path::PathBuf, sync::Arc};
+use stardust_xr::{
+	messenger::{self, MessageSenderHandle},
+	schemas::flex::serialize,
+};
+use std::{
+	fs,
+	iter::FromIterator,
+	path::PathBuf,
+	sync::{
+		atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+		Arc,
+	},
+	time::Duration,
+};
 use tokio::{net::UnixStream, task::JoinHandle};
-use tracing::info;
+use tracing::{debug, info};
 
 lazy_static! {
 	pub static ref CLIENTS: OwnedRegistry<Client> = OwnedRegistry::new();
 	pub static ref INTERNAL_CLIENT: Arc<Client> = CLIENTS.add(Client {
+		id: 0,
 		pid: None,
 		// env: None,
 		exe: None,
 
 		dispatch_join_handle: OnceCell::new(),
 		flush_join_handle: OnceCell::new(),
+		keepalive_join_handle: OnceCell::new(),
 		disconnect_status: OnceCell::new(),
+		disconnect_notified: AtomicBool::new(false),
+		connection_slot: Mutex::new(None),
 
 		message_sender_handle: None,
 		scenegraph: Default::default(),
 		root: OnceCell::new(),
 		base_resource_prefixes: Default::default(),
 		startup_settings: None,
+		protocol_version: Some(handshake::PROTOCOL_VERSION),
+		quota_usage: Default::default(),
+		traffic: Default::default(),
+		outgoing_queue: Default::default(),
+		render_layer_mask: AtomicU32::new(u32::MAX),
 	});
 }
 
+/// Connections the event loop accepted that haven't disconnected yet, counting from the handshake.
+pub static CONNECTED_CLIENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts towards `CONNECTED_CLIENTS` for as long as it's alive, so the slot is given back
+/// however the connection ends, even if the client never finished being created.
+pub struct ConnectionSlot(());
+impl ConnectionSlot {
+	/// Takes a slot unless `max` of them are already taken.
+	pub fn reserve(max: Option<usize>) -> Option<Self> {
+		CONNECTED_CLIENTS
+			.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+				max.is_none_or(|max| count < max).then_some(count + 1)
+			})
+			.ok()
+			.map(|_| ConnectionSlot(()))
+	}
+}
+impl Drop for ConnectionSlot {
+	fn drop(&mut self) {
+		CONNECTED_CLIENTS.fetch_sub(1, Ordering::Relaxed);
+	}
+}
+
+pub type ClientId = u64;
+/// The internal client is 0.
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+type DisconnectCallback = Box<dyn Fn(ClientId) + Send + Sync>;
+static DISCONNECT_CALLBACKS: Mutex<Vec<DisconnectCallback>> = const_mutex(Vec::new());
+/// Seconds between pings to each client, a client that hasn't answered by the next one is
+/// disconnected. 0 turns pinging off, which is the default since clients don't implement a
+/// `ping` method and only answer it with an error, so one that's busy for longer than the
+/// interval would get dropped.
+static KEEPALIVE_INTERVAL: AtomicU64 = AtomicU64::new(0);
+
+pub fn set_keepalive_interval(seconds: u64) {
+	KEEPALIVE_INTERVAL.store(seconds, Ordering::Relaxed);
+}
+fn keepalive_interval() -> Option<Duration> {
+	let seconds = KEEPALIVE_INTERVAL.load(Ordering::Relaxed);
+	(seconds > 0).then(|| Duration::from_secs(seconds))
+}
+
 pub fn get_env(pid: i32) -> Result<FxHashMap<String, String>, std::io::Error> {
 	let env = fs::read_to_string(format!("/proc/{pid}/environ"))?;
 	Ok(FxHashMap::from_iter(
@@ -52,50 +123,99 @@ pub fn startup_settings(env: &FxHashMap<String, String>) -> Option<StartupSettin
 }
 
 pub struct Client {
+	pub id: ClientId,
 	pid: Option<i32>,
 	// env: Option<FxHashMap<String, String>>,
 	exe: Option<PathBuf>,
 	dispatch_join_handle: OnceCell<JoinHandle<Result<()>>>,
 	flush_join_handle: OnceCell<JoinHandle<Result<()>>>,
+	keepalive_join_handle: OnceCell<JoinHandle<()>>,
 	disconnect_status: OnceCell<Result<()>>,
+	disconnect_notified: AtomicBool,
+	connection_slot: Mutex<Option<ConnectionSlot>>,
 
 	pub message_sender_handle: Option<MessageSenderHandle>,
 	pub scenegraph: Arc<Scenegraph>,
 	pub root: OnceCell<Arc<Root>>,
 	pub base_resource_prefixes: Mutex<Vec<PathBuf>>,
 	pub startup_settings: Option<StartupSettings>,
+	/// The version the client sent in its handshake, `None` for clients that didn't send one.
+	pub protocol_version: Option<u32>,
+	pub quota_usage: QuotaUsage,
+	pub traffic: TrafficCounters,
+	pub outgoing_queue: Arc<OutgoingQueue>,
+	/// Bits of the render layers this client's models can be drawn on, everything by default.
+	pub render_layer_mask: AtomicU32,
 }
 impl Client {
-	pub fn from_connection(connection: UnixStream) -> Result<Arc<Self>> {
+	pub fn from_connection(
+		connection: UnixStream,
+		protocol_version: Option<u32>,
+		slot: ConnectionSlot,
+	) -> Result<Arc<Self>> {
 		let pid = connection.peer_cred().ok().and_then(|c| c.pid());
+		Self::from_stream(connection, pid, protocol_version, Some(slot))
+	}
+	/// For connections bridged from another transport such as TCP, the peer on the other end of
+	/// the socket is the server itself so there's no process to look up. Clients without a slot,
+	/// like replays, don't count towards the connection limit.
+	pub fn from_bridged_connection(
+		connection: UnixStream,
+		protocol_version: Option<u32>,
+		slot: Option<ConnectionSlot>,
+	) -> Result<Arc<Self>> {
+		Self::from_stream(connection, None, protocol_version, slot)
+	}
+	fn from_stream(
+		connection: UnixStream,
+		pid: Option<i32>,
+		protocol_version: Option<u32>,
+		slot: Option<ConnectionSlot>,
+	) -> Result<Arc<Self>> {
 		let env = pid.and_then(|pid| get_env(pid).ok());
 		let exe = pid.and_then(|pid| fs::read_link(format!("/proc/{}/exe", pid)).ok());
 		info!(
 			pid,
+			protocol_version,
 			exe = exe
 				.as_ref()
 				.and_then(|exe| exe.to_str().map(|s| s.to_string())),
 			"New client connected"
 		);
 
+		let outgoing_queue = Arc::new(OutgoingQueue::default());
+		let connection = relay::relay(
+			connection,
+			outgoing_queue.clone(),
+			Recorder::start(pid, protocol_version),
+		)?;
 		let (mut messenger_tx, mut messenger_rx) = messenger::create(connection);
 		let scenegraph = Arc::new(Scenegraph::default());
 		let startup_settings = env.as_ref().and_then(startup_settings);
 
 		let client = CLIENTS.add(Client {
+			id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
 			pid,
 			// env,
 			exe: exe.clone(),
 
 			dispatch_join_handle: OnceCell::new(),
 			flush_join_handle: OnceCell::new(),
+			keepalive_join_handle: OnceCell::new(),
 			disconnect_status: OnceCell::new(),
+			disconnect_notified: AtomicBool::new(false),
+			connection_slot: Mutex::new(slot),
 
 			message_sender_handle: Some(messenger_tx.handle()),
 			scenegraph: scenegraph.clone(),
 			root: OnceCell::new(),
 			base_resource_prefixes: Default::default(),
 			startup_settings,
+			protocol_version,
+			quota_usage: Default::default(),
+			traffic: Default::default(),
+			outgoing_queue,
+			render_layer_mask: AtomicU32::new(u32::MAX),
 		});
 		let _ = client.scenegraph.client.set(Arc::downgrade(&client));
 		let _ = client.root.set(Root::create(&client)?);
@@ -131,11 +251,8 @@ impl Client {
 					let client = client.clone();
 					async move {
 						loop {
-							match messenger_rx.dispatch(&*scenegraph).await {
-								Err(e) => {
-									client.disconnect(Err(e.into()));
-								}
-								_ => (),
+							if let Err(e) = messenger_rx.dispatch(&*scenegraph).await {
+								client.disconnect(Err(e.into()));
 							}
 						}
 					}
@@ -149,11 +266,8 @@ impl Client {
 					let client = client.clone();
 					async move {
 						loop {
-							match messenger_tx.flush().await {
-								Err(e) => {
-									client.disconnect(Err(e.into()));
-								}
-								_ => (),
+							if let Err(e) = messenger_tx.flush().await {
+								client.disconnect(Err(e.into()));
 							}
 						}
 					}
@@ -161,31 +275,109 @@ impl Client {
 			)
 		});
 
+		if let Some(interval) = keepalive_interval() {
+			let _ = client.keepalive_join_handle.get_or_try_init(|| {
+				task::new(
+					|| {
+						format!(
+							"client keepalive pid={} exe={}",
+							&pid_printable, &exe_printable,
+						)
+					},
+					{
+						let client = Arc::downgrade(&client);
+						async move {
+							loop {
+								tokio::time::sleep(interval).await;
+								let Some(client) = client.upgrade() else {break};
+								if let Err(e) = client.ping(interval).await {
+									client.disconnect(Err(e));
+									break;
+								}
+							}
+						}
+					},
+				)
+			});
+		}
+
 		Ok(client)
 	}
 
+	/// Catches connections left half open by a client that died without closing its socket,
+	/// which otherwise only get noticed once a write fails.
+	async fn ping(&self, timeout: Duration) -> Result<()> {
+		let Some(message_sender_handle) = self.message_sender_handle.as_ref() else {return Ok(())};
+		let response = message_sender_handle.method("/", "ping", &serialize(())?)?;
+		// Even an error response means the client is still around to send it
+		let _ = tokio::time::timeout(timeout, response)
+			.await
+			.map_err(|_| eyre!("No response to keepalive ping within {timeout:?}"))?;
+		Ok(())
+	}
+
 	#[inline]
 	pub fn get_node(&self, name: &'static str, path: &str) -> Result<Arc<Node>> {
 		self.scenegraph
-			.get_node(path)
-			.ok_or_else(|| eyre!("{} not found", name))
+			.resolve(path)
+			.map_err(|e| eyre!("{name} not found: {e}"))
+	}
+
+	/// Logs the traffic over the last `seconds`, with enough to tell which client it is.
+	pub fn log_traffic(&self, since: TrafficStats, seconds: f64) {
+		debug!(
+			id = self.id,
+			pid = self.pid,
+			exe = self
+				.exe
+				.as_ref()
+				.and_then(|exe| exe.to_str().map(|s| s.to_string())),
+			messages_received_per_second = since.messages_received as f64 / seconds,
+			bytes_received_per_second = since.bytes_received as f64 / seconds,
+			messages_sent_per_second = since.messages_sent as f64 / seconds,
+			bytes_sent_per_second = since.bytes_sent as f64 / seconds,
+			queued_bytes = self.outgoing_queue.depth().bytes,
+			"Client traffic"
+		);
+	}
+
+	/// Runs `callback` with the ID of every client whose connection closes, exactly once per
+	/// client whether it disconnected on its own, errored or was dropped by the server.
+	pub fn on_disconnect(callback: impl Fn(ClientId) + Send + Sync + 'static) {
+		DISCONNECT_CALLBACKS.lock().push(Box::new(callback));
+	}
+	fn notify_disconnect(&self) {
+		if self.disconnect_notified.swap(true, Ordering::Relaxed) {
+			return;
+		}
+		self.connection_slot.lock().take();
+		for callback in DISCONNECT_CALLBACKS.lock().iter() {
+			callback(self.id);
+		}
 	}
 
 	pub fn disconnect(&self, reason: Result<()>) {
 		let _ = self.disconnect_status.set(reason);
+		self.notify_disconnect();
 		if let Some(dispatch_join_handle) = self.dispatch_join_handle.get() {
 			dispatch_join_handle.abort();
 		}
 		if let Some(flush_join_handle) = self.flush_join_handle.get() {
 			flush_join_handle.abort();
 		}
+		if let Some(keepalive_join_handle) = self.keepalive_join_handle.get() {
+			keepalive_join_handle.abort();
+		}
 		CLIENTS.remove(self);
 	}
 }
 impl Drop for Client {
 	fn drop(&mut self) {
+		self.notify_disconnect();
 		info!(
+			id = self.id,
 			pid = self.pid,
+			protocol_version = self.protocol_version,
 			exe = self
 				.exe
 				.as_ref()
@@ -21,6 +21,14 @@ impl<T> Delta<T> {
 		self.changed = false;
 		delta
 	}
+	/// Marks the current value as seen without taking it.
+	pub fn reset(&mut self) {
+		self.changed = false;
+	}
+	/// The current value, whether or not it changed, leaving the change flag alone.
+	pub fn peek(&self) -> &T {
+		&self.value
+	}
 	pub fn mark_changed(&mut self) {
 		self.changed = true;
 	}
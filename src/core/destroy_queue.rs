@@ -1,12 +1,51 @@
 use parking_lot::Mutex;
-use std::any::Any;
+use portable_atomic::{AtomicUsize, Ordering};
+use std::{any::Any, collections::VecDeque};
 
-static MAIN_DESTROY_QUEUE: Mutex<Vec<Box<dyn Any + Send + Sync>>> = Mutex::new(Vec::new());
+static MAIN_DESTROY_QUEUE: Mutex<VecDeque<Box<dyn Any + Send + Sync>>> =
+	Mutex::new(VecDeque::new());
+/// Most things destroyed each frame so tearing down a big client doesn't cause a hitch.
+static BUDGET: AtomicUsize = AtomicUsize::new(64);
+/// When the queue gets this many budgets deep at least this fraction of it is destroyed every
+/// frame regardless, so it can't outgrow the budget.
+const BACKLOG_FRACTION: usize = 8;
 
 pub fn add<T: Any + Sync + Send>(thing: T) {
-	MAIN_DESTROY_QUEUE.lock().push(Box::new(thing));
+	MAIN_DESTROY_QUEUE.lock().push_back(Box::new(thing));
 }
 
-pub fn clear() {
-	MAIN_DESTROY_QUEUE.lock().clear();
+pub fn set_budget(budget: usize) {
+	BUDGET.store(budget.max(1), Ordering::Relaxed);
+}
+
+/// How many things are waiting to be destroyed.
+pub fn len() -> usize {
+	MAIN_DESTROY_QUEUE.lock().len()
+}
+
+/// Destroys everything in the queue right away, ignoring the budget. Only call this from the
+/// render thread, like `process`.
+pub fn flush() {
+	// Dropping things can queue more, so keep going until it's actually empty
+	loop {
+		let destroyed = std::mem::take(&mut *MAIN_DESTROY_QUEUE.lock());
+		if destroyed.is_empty() {
+			break;
+		}
+		drop(destroyed);
+	}
+}
+
+/// Destroys the oldest things in the queue, up to the budget. Call once per frame.
+pub fn process() {
+	let destroyed = {
+		let mut queue = MAIN_DESTROY_QUEUE.lock();
+		let count = BUDGET
+			.load(Ordering::Relaxed)
+			.max(queue.len() / BACKLOG_FRACTION)
+			.min(queue.len());
+		queue.drain(..count).collect::<Vec<_>>()
+	};
+	// Dropped outside the lock in case dropping something queues more
+	drop(destroyed);
 }
@@ -1,36 +1,340 @@
-use super::client::Client;
-use super::task;
-use color_eyre::eyre::Result;
-use std::path::PathBuf;
-use std::sync::Arc;
-use tokio::net::UnixListener;
+use super::client::{Client, ConnectionSlot, CLIENTS, CONNECTED_CLIENTS};
+use super::{handshake, task};
+use crate::nodes::root::Root;
+use color_eyre::eyre::{bail, ensure, Result};
+use parking_lot::Mutex;
+use std::ffi::CString;
+use std::fs::Permissions;
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Once};
+use std::time::{Duration, Instant};
+use tokio::io::copy_bidirectional;
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
 use tokio::task::JoinHandle;
-use tracing::error;
+use tracing::{error, info, warn};
+
+/// What clients connect to the server through.
+pub enum Transport {
+	/// The default, clients are identified by the pid on the other end of the socket.
+	Unix {
+		path: PathBuf,
+		permissions: SocketPermissions,
+	},
+	/// A socket in Linux's abstract namespace, which has no file to clean up or set permissions
+	/// on. Binding one fails on other targets.
+	UnixAbstract(String),
+	/// For clients on other machines or in containers that can't share the socket's directory.
+	///
+	/// There's no authentication or encryption, anyone who can reach the address gets the same
+	/// access to the scenegraph as a local client and all traffic is sent in the clear. Only bind
+	/// to loopback or a trusted network. Startup settings can't be applied to these clients since
+	/// there's no process to read the environment of.
+	Tcp(SocketAddr),
+}
+
+/// A transport to accept clients on and how to greet them.
+pub struct Listener {
+	pub transport: Transport,
+	/// Check for the protocol version handshake before accepting each client, see
+	/// [`handshake`](super::handshake). Clients that don't send it are still accepted.
+	pub handshake: bool,
+}
+
+/// Applied to a Unix socket's file right after it's bound, before any clients are accepted.
+#[derive(Debug, Clone, Default)]
+pub struct SocketPermissions {
+	/// Defaults to whatever the umask gives.
+	pub mode: Option<u32>,
+	/// A group name or numeric ID to own the socket, so access can be limited to its members.
+	pub group: Option<String>,
+}
+impl SocketPermissions {
+	fn apply(&self, path: &Path) -> Result<()> {
+		if let Some(group) = &self.group {
+			let gid = match group.parse::<u32>() {
+				Ok(gid) => gid,
+				Err(_) => {
+					let name = CString::new(group.as_str())?;
+					let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+					ensure!(!entry.is_null(), "Group {group} not found");
+					unsafe { (*entry).gr_gid }
+				}
+			};
+			std::os::unix::fs::chown(path, None, Some(gid))?;
+		}
+		if let Some(mode) = self.mode {
+			std::fs::set_permissions(path, Permissions::from_mode(mode))?;
+		}
+		Ok(())
+	}
+}
+
+/// Throttles how fast clients can connect so a flood of connections can't exhaust the server.
+#[derive(Debug, Clone, Copy)]
+pub struct AcceptLimits {
+	/// Connections past this many in a second get closed right away.
+	pub max_accepts_per_second: u32,
+	/// Connections past this many connected clients get closed right away. Every disconnect,
+	/// however it happens, frees up a slot again. No limit if `None`.
+	pub max_connections: Option<usize>,
+}
+impl Default for AcceptLimits {
+	fn default() -> Self {
+		AcceptLimits {
+			max_accepts_per_second: 20,
+			max_connections: None,
+		}
+	}
+}
+
+/// Shared between every transport's accept loop so the limits apply to all of them together.
+struct AcceptLimiter {
+	limits: AcceptLimits,
+	window_start: Instant,
+	accepts_in_window: u32,
+}
+impl AcceptLimiter {
+	fn new(limits: AcceptLimits) -> Self {
+		AcceptLimiter {
+			limits,
+			window_start: Instant::now(),
+			accepts_in_window: 0,
+		}
+	}
+	/// Reserves a slot for the new connection if it should be accepted, logging the reason if it
+	/// isn't.
+	fn allow(&mut self) -> Option<ConnectionSlot> {
+		if self.window_start.elapsed() >= Duration::from_secs(1) {
+			self.window_start = Instant::now();
+			self.accepts_in_window = 0;
+		}
+		if self.accepts_in_window >= self.limits.max_accepts_per_second {
+			warn!(
+				max_accepts_per_second = self.limits.max_accepts_per_second,
+				"Rejected connection, clients are connecting too fast"
+			);
+			return None;
+		}
+		let Some(slot) = ConnectionSlot::reserve(self.limits.max_connections) else {
+			warn!(
+				connected_clients = CONNECTED_CLIENTS.load(Ordering::Relaxed),
+				max_connections = self.limits.max_connections,
+				"Rejected connection, too many clients connected"
+			);
+			return None;
+		};
+		self.accepts_in_window += 1;
+		Some(slot)
+	}
+}
 
 pub struct EventLoop {
-	join_handle: JoinHandle<()>,
+	join_handles: Vec<JoinHandle<()>>,
 }
 
 impl EventLoop {
-	pub fn new(socket_path: PathBuf) -> Result<Arc<Self>> {
-		let socket = UnixListener::bind(socket_path)?;
-
-		let join_handle = task::new(|| "event loop", async move {
-			loop {
-				let Ok((socket, _)) = socket.accept().await else { continue };
-				if let Err(e) = Client::from_connection(socket) {
-					error!(?e, "Unable to create client from connection");
+	/// Spawns an accept loop for each listener, all of them hand clients to the same scenegraph.
+	pub fn new(
+		listeners: impl IntoIterator<Item = Listener>,
+		limits: AcceptLimits,
+	) -> Result<Arc<Self>> {
+		static COUNT_DISCONNECTS: Once = Once::new();
+		COUNT_DISCONNECTS.call_once(|| {
+			Client::on_disconnect(|client_id| {
+				// The client's slot is already given back by the time this runs
+				let connected_clients = CONNECTED_CLIENTS.load(Ordering::Relaxed);
+				info!(client_id, connected_clients, "Client left");
+			});
+		});
+		let limiter = Arc::new(Mutex::new(AcceptLimiter::new(limits)));
+		let join_handles = listeners
+			.into_iter()
+			.map(|listener| Self::listen(listener, limiter.clone()))
+			.collect::<Result<Vec<_>>>()?;
+		let event_loop = Arc::new(EventLoop { join_handles });
+
+		Ok(event_loop)
+	}
+	/// How many clients are connected over all transports.
+	pub fn connected_clients(&self) -> usize {
+		CONNECTED_CLIENTS.load(Ordering::Relaxed)
+	}
+
+	/// Stops accepting clients, asks the connected ones to disconnect and waits up to `timeout`
+	/// for them to do so before dropping the rest. Dropping the event loop instead only stops
+	/// accepting new clients.
+	pub async fn shutdown(&self, timeout: Duration) {
+		info!(
+			connected_clients = self.connected_clients(),
+			"Shutting down event loop"
+		);
+		for join_handle in &self.join_handles {
+			join_handle.abort();
+		}
+		Root::send_disconnect_events();
+
+		let remote_clients = || {
+			CLIENTS
+				.get_vec()
+				.into_iter()
+				.filter(|client| client.message_sender_handle.is_some())
+				.collect::<Vec<_>>()
+		};
+		let all_disconnected = tokio::time::timeout(timeout, async {
+			while !remote_clients().is_empty() {
+				tokio::time::sleep(Duration::from_millis(10)).await;
+			}
+		})
+		.await;
+		if all_disconnected.is_err() {
+			let remaining_clients = remote_clients();
+			info!(
+				count = remaining_clients.len(),
+				"Disconnecting clients that didn't close in time"
+			);
+			for client in remaining_clients {
+				client.disconnect(Ok(()));
+			}
+		}
+	}
+
+	fn accept_unix(
+		socket: UnixListener,
+		handshake: bool,
+		limiter: Arc<Mutex<AcceptLimiter>>,
+	) -> Result<JoinHandle<()>> {
+		let listener = Arc::new(socket);
+		// A panic handling one connection shouldn't stop new clients from connecting
+		task::new_supervised(
+			|| "event loop",
+			move || {
+				let listener = listener.clone();
+				let limiter = limiter.clone();
+				async move {
+					loop {
+						let Ok((socket, _)) = listener.accept().await else { continue };
+						// Dropping the socket closes the connection
+						let Some(slot) = limiter.lock().allow() else { continue };
+						let _ = task::new(|| "client handshake", async move {
+							let mut socket = socket;
+							let protocol_version = negotiate(&mut socket, handshake).await;
+							accepted(protocol_version.and_then(|protocol_version| {
+								Client::from_connection(socket, protocol_version, slot)
+							}));
+						});
+					}
 				}
+			},
+		)
+	}
+	fn listen(listener: Listener, limiter: Arc<Mutex<AcceptLimiter>>) -> Result<JoinHandle<()>> {
+		let handshake = listener.handshake;
+		match listener.transport {
+			Transport::Unix { path, permissions } => {
+				remove_stale_socket(&path)?;
+				let socket = UnixListener::bind(&path)?;
+				permissions.apply(&path)?;
+				Self::accept_unix(socket, handshake, limiter)
+			}
+			#[cfg(target_os = "linux")]
+			Transport::UnixAbstract(name) => {
+				use std::os::linux::net::SocketAddrExt;
+				let address = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())?;
+				let socket = std::os::unix::net::UnixListener::bind_addr(&address)?;
+				socket.set_nonblocking(true)?;
+				Self::accept_unix(UnixListener::from_std(socket)?, handshake, limiter)
+			}
+			#[cfg(not(target_os = "linux"))]
+			Transport::UnixAbstract(_) => {
+				bail!("Abstract namespace sockets are only supported on Linux")
 			}
-		})?;
-		let event_loop = Arc::new(EventLoop { join_handle });
+			Transport::Tcp(address) => {
+				let socket = std::net::TcpListener::bind(address)?;
+				socket.set_nonblocking(true)?;
+				let listener = Arc::new(TcpListener::from_std(socket)?);
+				warn!(%address, "Listening for unauthenticated TCP clients");
+				task::new_supervised(
+					|| "tcp event loop",
+					move || {
+						let listener = listener.clone();
+						let limiter = limiter.clone();
+						async move {
+							loop {
+								let Ok((socket, _)) = listener.accept().await else { continue };
+								let Some(slot) = limiter.lock().allow() else { continue };
+								let Ok(mut socket) = bridge_tcp(socket) else { continue };
+								let _ = task::new(|| "client handshake", async move {
+									let protocol_version = negotiate(&mut socket, handshake).await;
+									accepted(protocol_version.and_then(|protocol_version| {
+										Client::from_bridged_connection(
+											socket,
+											protocol_version,
+											Some(slot),
+										)
+									}));
+								});
+							}
+						}
+					},
+				)
+			}
+		}
+	}
+}
 
-		Ok(event_loop)
+async fn negotiate(socket: &mut UnixStream, handshake: bool) -> Result<Option<u32>> {
+	match handshake {
+		true => handshake::negotiate(socket).await,
+		false => Ok(None),
+	}
+}
+
+/// A crashed server leaves its socket file behind, which makes binding the path fail. If nothing
+/// is listening on it anymore it's safe to remove.
+fn remove_stale_socket(path: &Path) -> Result<()> {
+	if !path.exists() {
+		return Ok(());
+	}
+	match std::os::unix::net::UnixStream::connect(path) {
+		Ok(_) => bail!("Another server is already listening on {}", path.display()),
+		Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+			warn!(path = ?path.display(), "Removing stale socket");
+			std::fs::remove_file(path)?;
+			Ok(())
+		}
+		// Not a socket or not ours to touch, binding will fail with a clearer error
+		Err(_) => Ok(()),
 	}
 }
 
+fn accepted(client: Result<Arc<Client>>) {
+	match client {
+		Ok(_) => {
+			let connected_clients = CONNECTED_CLIENTS.load(Ordering::Relaxed);
+			info!(connected_clients, "Client accepted");
+		}
+		Err(e) => error!(?e, "Unable to create client from connection"),
+	}
+}
+
+/// The messenger only speaks over Unix sockets, so TCP connections get piped through a socket pair.
+fn bridge_tcp(mut tcp_stream: TcpStream) -> Result<UnixStream> {
+	let _ = tcp_stream.set_nodelay(true);
+	let (server_end, mut bridge_end) = UnixStream::pair()?;
+	task::new(|| "tcp bridge", async move {
+		let _ = copy_bidirectional(&mut tcp_stream, &mut bridge_end).await;
+	})?;
+	Ok(server_end)
+}
+
 impl Drop for EventLoop {
 	fn drop(&mut self) {
-		self.join_handle.abort();
+		for join_handle in &self.join_handles {
+			join_handle.abort();
+		}
 	}
 }
@@ -0,0 +1,81 @@
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// How often the stats get logged
+const LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+static DRAW_CALLS: AtomicU32 = AtomicU32::new(0);
+static MODELS_DRAWN: AtomicU32 = AtomicU32::new(0);
+static MODELS_CULLED: AtomicU32 = AtomicU32::new(0);
+static SURFACES_PROCESSED: AtomicU32 = AtomicU32::new(0);
+
+static LAST_FRAME: Mutex<FrameStats> = Mutex::new(FrameStats::new());
+static LAST_LOG: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// What went into rendering the last finished frame.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct FrameStats {
+	/// In seconds
+	pub frame_time: f64,
+	pub draw_calls: u32,
+	pub models_drawn: u32,
+	pub models_culled: u32,
+	pub surfaces_processed: u32,
+}
+impl FrameStats {
+	const fn new() -> Self {
+		FrameStats {
+			frame_time: 0.0,
+			draw_calls: 0,
+			models_drawn: 0,
+			models_culled: 0,
+			surfaces_processed: 0,
+		}
+	}
+}
+
+pub fn count_draw_call() {
+	DRAW_CALLS.fetch_add(1, Ordering::Relaxed);
+}
+pub fn count_model_drawn() {
+	MODELS_DRAWN.fetch_add(1, Ordering::Relaxed);
+	count_draw_call();
+}
+pub fn count_model_culled() {
+	MODELS_CULLED.fetch_add(1, Ordering::Relaxed);
+}
+pub fn count_surface_processed() {
+	SURFACES_PROCESSED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Wraps up the counts for this frame and starts the next one.
+pub fn end_frame(frame_time: f64) {
+	let stats = FrameStats {
+		frame_time,
+		draw_calls: DRAW_CALLS.swap(0, Ordering::Relaxed),
+		models_drawn: MODELS_DRAWN.swap(0, Ordering::Relaxed),
+		models_culled: MODELS_CULLED.swap(0, Ordering::Relaxed),
+		surfaces_processed: SURFACES_PROCESSED.swap(0, Ordering::Relaxed),
+	};
+	*LAST_FRAME.lock() = stats;
+
+	let mut last_log = LAST_LOG.lock();
+	if last_log.is_none_or(|last_log| last_log.elapsed() >= LOG_INTERVAL) {
+		*last_log = Some(Instant::now());
+		debug!(
+			frame_time = stats.frame_time,
+			draw_calls = stats.draw_calls,
+			models_drawn = stats.models_drawn,
+			models_culled = stats.models_culled,
+			surfaces_processed = stats.surfaces_processed,
+			"Frame stats"
+		);
+	}
+}
+
+pub fn last_frame() -> FrameStats {
+	*LAST_FRAME.lock()
+}
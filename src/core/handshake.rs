@@ -0,0 +1,135 @@
+//! Optional version handshake sent before any messages.
+//!
+//! A client that wants its protocol version checked sends `MAGIC` followed by its version as a
+//! little endian u32 right after connecting, and the server answers with the same magic and its
+//! own version if they're compatible or closes the connection if not. Clients that start sending
+//! messages straight away are treated as predating the handshake.
+//!
+//! Listeners only check for it when they're set up to, since a client that waits for the server
+//! to speak first holds up its own connection for `HANDSHAKE_TIMEOUT`.
+
+use color_eyre::eyre::{bail, Result};
+use std::{ffi::c_void, io, os::fd::AsRawFd, time::Duration};
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt, Interest},
+	net::UnixStream,
+};
+
+pub const MAGIC: &[u8; 8] = b"STARDUST";
+/// Bumped whenever the schemas change in a way old clients can't handle.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// The oldest client version the server still understands.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+/// Clients that don't send anything for this long are assumed not to know about the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Returns the client's protocol version, `None` if it didn't send a handshake.
+pub async fn negotiate(connection: &mut UnixStream) -> Result<Option<u32>> {
+	let peeked = tokio::time::timeout(HANDSHAKE_TIMEOUT, peek_magic(connection)).await;
+	match peeked {
+		Ok(Ok(true)) => (),
+		Ok(Err(e)) => return Err(e.into()),
+		_ => return Ok(None),
+	}
+
+	let mut handshake = [0_u8; MAGIC.len() + 4];
+	connection.read_exact(&mut handshake).await?;
+	let version = u32::from_le_bytes(handshake[MAGIC.len()..].try_into().unwrap());
+	if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+		bail!(
+			"Client protocol version {version} isn't supported, the server supports versions \
+			{MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION}"
+		);
+	}
+
+	let mut response = [0_u8; MAGIC.len() + 4];
+	response[..MAGIC.len()].copy_from_slice(MAGIC);
+	response[MAGIC.len()..].copy_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+	connection.write_all(&response).await?;
+	Ok(Some(version))
+}
+
+/// How much of `MAGIC` the peeked bytes settle, `None` if it needs more of them.
+fn magic_matches(peeked: &[u8]) -> Option<bool> {
+	let len = peeked.len().min(MAGIC.len());
+	if peeked[..len] != MAGIC[..len] {
+		Some(false)
+	} else if len == MAGIC.len() {
+		Some(true)
+	} else {
+		None
+	}
+}
+
+/// Waits until the client has sent enough to tell if it starts with `MAGIC` without consuming
+/// anything, returns false if it doesn't or the connection closed first.
+async fn peek_magic(connection: &UnixStream) -> io::Result<bool> {
+	let mut buf = [0_u8; MAGIC.len()];
+	loop {
+		connection.readable().await?;
+		let peeked = connection.try_io(Interest::READABLE, || {
+			let read = unsafe {
+				libc::recv(
+					connection.as_raw_fd(),
+					buf.as_mut_ptr() as *mut c_void,
+					buf.len(),
+					libc::MSG_PEEK,
+				)
+			};
+			if read < 0 {
+				return Err(io::Error::last_os_error());
+			}
+			if read == 0 {
+				return Ok(false);
+			}
+			// Clearing the readiness makes `readable` wait for the rest to come in
+			magic_matches(&buf[..read as usize]).ok_or_else(|| io::ErrorKind::WouldBlock.into())
+		});
+		match peeked {
+			Ok(matches) => return Ok(matches),
+			Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+			Err(e) => return Err(e),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn magic_is_settled_as_soon_as_the_bytes_differ() {
+		assert_eq!(magic_matches(b"STAR"), None);
+		assert_eq!(magic_matches(b"STARDUST"), Some(true));
+		assert_eq!(magic_matches(&[4, 0, 0, 0]), Some(false));
+		assert_eq!(magic_matches(b"STARS"), Some(false));
+	}
+
+	#[test]
+	fn split_handshakes_are_waited_for() {
+		let runtime = tokio::runtime::Builder::new_multi_thread()
+			.enable_io()
+			.enable_time()
+			.build()
+			.unwrap();
+		runtime.block_on(async {
+			let (mut client, mut server) = UnixStream::pair().unwrap();
+			let negotiated = tokio::spawn(async move { negotiate(&mut server).await.unwrap() });
+			client.write_all(b"STAR").await.unwrap();
+			tokio::time::sleep(Duration::from_millis(50)).await;
+			client.write_all(b"DUST").await.unwrap();
+			client.write_all(&1_u32.to_le_bytes()).await.unwrap();
+			assert_eq!(negotiated.await.unwrap(), Some(1));
+			let mut response = [0_u8; MAGIC.len() + 4];
+			client.read_exact(&mut response).await.unwrap();
+			assert_eq!(&response[..MAGIC.len()], MAGIC);
+
+			// Clients predating the handshake aren't held up by the timeout
+			let (mut client, mut server) = UnixStream::pair().unwrap();
+			client.write_all(&4_u32.to_ne_bytes()).await.unwrap();
+			let started = std::time::Instant::now();
+			assert_eq!(negotiate(&mut server).await.unwrap(), None);
+			assert!(started.elapsed() < HANDSHAKE_TIMEOUT);
+		});
+	}
+}
@@ -0,0 +1,127 @@
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use rustc_hash::FxHasher;
+use serde::Serialize;
+use std::{
+	hash::BuildHasherDefault,
+	sync::atomic::{AtomicBool, Ordering},
+	time::{Duration, Instant},
+};
+
+/// Off unless asked for, so handlers don't pay for reading the clock.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Upper bounds of the histogram buckets in microseconds, past the last one is its own bucket.
+const BUCKET_BOUNDS_US: [u64; 9] = [10, 50, 100, 500, 1_000, 5_000, 10_000, 50_000, 100_000];
+/// Far more than the server has handlers, anything past this is dropped instead of growing the
+/// table forever.
+const MAX_HANDLERS: usize = 1024;
+
+lazy_static! {
+	/// By signal or method name, since node paths are different for every client.
+	static ref HANDLER_LATENCIES: DashMap<String, HandlerLatency, BuildHasherDefault<FxHasher>> =
+		DashMap::default();
+}
+
+pub fn set_enabled(enabled: bool) {
+	ENABLED.store(enabled, Ordering::Relaxed);
+}
+pub fn enabled() -> bool {
+	ENABLED.load(Ordering::Relaxed)
+}
+
+#[derive(Default)]
+struct HandlerLatency {
+	count: u64,
+	total: Duration,
+	max: Duration,
+	buckets: [u64; BUCKET_BOUNDS_US.len() + 1],
+}
+impl HandlerLatency {
+	fn record(&mut self, latency: Duration) {
+		self.count += 1;
+		self.total += latency;
+		self.max = self.max.max(latency);
+		let micros = latency.as_micros() as u64;
+		let bucket = BUCKET_BOUNDS_US
+			.iter()
+			.position(|bound| micros < *bound)
+			.unwrap_or(BUCKET_BOUNDS_US.len());
+		self.buckets[bucket] += 1;
+	}
+	/// The upper bound of the bucket the percentile falls in, the max if it's in the last one.
+	fn percentile_us(&self, percentile: f64) -> u64 {
+		let target = (self.count as f64 * percentile).ceil() as u64;
+		let mut seen = 0;
+		for (bucket, count) in self.buckets.iter().enumerate() {
+			seen += count;
+			if seen >= target {
+				if let Some(bound) = BUCKET_BOUNDS_US.get(bucket) {
+					return *bound;
+				}
+				break;
+			}
+		}
+		self.max.as_micros() as u64
+	}
+}
+
+/// How long a handler took from the message arriving to it returning, all times in microseconds.
+/// Percentiles are bucket upper bounds, so they're an overestimate.
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencySummary {
+	pub handler: String,
+	pub count: u64,
+	pub mean_us: u64,
+	pub p50_us: u64,
+	pub p99_us: u64,
+	pub max_us: u64,
+	pub bucket_bounds_us: &'static [u64],
+	/// One more than the bounds, the last is everything slower
+	pub buckets: Vec<u64>,
+}
+
+/// Call before handling a message, `None` when metrics are off.
+pub fn start() -> Option<Instant> {
+	enabled().then(Instant::now)
+}
+/// Call once the handler returns with what `start` gave back.
+pub fn record(handler: &str, start: Option<Instant>) {
+	let Some(start) = start else {return};
+	let latency = start.elapsed();
+	if let Some(mut handler_latency) = HANDLER_LATENCIES.get_mut(handler) {
+		handler_latency.record(latency);
+		return;
+	}
+	if HANDLER_LATENCIES.len() >= MAX_HANDLERS {
+		return;
+	}
+	HANDLER_LATENCIES
+		.entry(handler.to_string())
+		.or_default()
+		.record(latency);
+}
+
+/// Every handler that ran since the server started, the most total time spent first.
+pub fn summaries() -> Vec<LatencySummary> {
+	let mut handlers: Vec<_> = HANDLER_LATENCIES
+		.iter()
+		.map(|entry| (entry.total, summarize(entry.key(), entry.value())))
+		.collect();
+	handlers.sort_by(|(a, _), (b, _)| b.cmp(a));
+	handlers.into_iter().map(|(_, summary)| summary).collect()
+}
+fn summarize(handler: &str, latency: &HandlerLatency) -> LatencySummary {
+	LatencySummary {
+		handler: handler.to_string(),
+		count: latency.count,
+		mean_us: (latency.total.as_micros() as u64)
+			.checked_div(latency.count)
+			.unwrap_or(0),
+		p50_us: latency.percentile_us(0.5),
+		p99_us: latency.percentile_us(0.99),
+		max_us: latency.max.as_micros() as u64,
+		bucket_bounds_us: &BUCKET_BOUNDS_US,
+		buckets: latency.buckets.to_vec(),
+	}
+}
@@ -2,8 +2,16 @@ pub mod client;
 pub mod delta;
 pub mod destroy_queue;
 pub mod eventloop;
+pub mod frame_stats;
+pub mod handshake;
+pub mod latency;
 pub mod node_collections;
+pub mod quota;
+pub mod recording;
 pub mod registry;
+pub mod relay;
 pub mod resource;
+pub mod resource_cache;
 pub mod scenegraph;
 pub mod task;
+pub mod traffic;
@@ -0,0 +1,107 @@
+use super::client::Client;
+use color_eyre::eyre::{eyre, Result};
+use once_cell::sync::OnceCell;
+use std::{
+	fmt::Display,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc, Weak,
+	},
+};
+
+/// Limits on what a single client can create so one client can't exhaust the server, `None`
+/// means unlimited. The internal client is never limited.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Quotas {
+	pub max_nodes: Option<usize>,
+	pub max_models: Option<usize>,
+	pub max_texture_bytes: Option<usize>,
+}
+impl Quotas {
+	fn limit(&self, kind: QuotaKind) -> Option<usize> {
+		match kind {
+			QuotaKind::Nodes => self.max_nodes,
+			QuotaKind::Models => self.max_models,
+			QuotaKind::TextureBytes => self.max_texture_bytes,
+		}
+	}
+}
+pub static QUOTAS: OnceCell<Quotas> = OnceCell::new();
+
+#[derive(Debug, Clone, Copy)]
+pub enum QuotaKind {
+	Nodes,
+	Models,
+	/// Estimated from the decoded size of textures the client sets on materials.
+	TextureBytes,
+}
+impl Display for QuotaKind {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			QuotaKind::Nodes => "node",
+			QuotaKind::Models => "model",
+			QuotaKind::TextureBytes => "texture memory",
+		})
+	}
+}
+
+/// How much of each quota a client is using.
+#[derive(Debug, Default)]
+pub struct QuotaUsage {
+	nodes: AtomicUsize,
+	models: AtomicUsize,
+	texture_bytes: AtomicUsize,
+}
+impl QuotaUsage {
+	fn counter(&self, kind: QuotaKind) -> &AtomicUsize {
+		match kind {
+			QuotaKind::Nodes => &self.nodes,
+			QuotaKind::Models => &self.models,
+			QuotaKind::TextureBytes => &self.texture_bytes,
+		}
+	}
+}
+
+/// Counts towards the client's quota until dropped.
+#[derive(Debug)]
+pub struct QuotaReservation {
+	client: Weak<Client>,
+	kind: QuotaKind,
+	amount: usize,
+}
+impl QuotaReservation {
+	pub fn reserve(client: &Arc<Client>, kind: QuotaKind, amount: usize) -> Result<Self> {
+		let counter = client.quota_usage.counter(kind);
+		let limit = QUOTAS
+			.get()
+			.and_then(|quotas| quotas.limit(kind))
+			.filter(|_| client.message_sender_handle.is_some());
+		match limit {
+			Some(limit) => {
+				counter
+					.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+						used.checked_add(amount).filter(|used| *used <= limit)
+					})
+					.map_err(|used| eyre!("Client is over its {kind} quota ({used}/{limit})"))?;
+			}
+			None => {
+				counter.fetch_add(amount, Ordering::Relaxed);
+			}
+		}
+		Ok(QuotaReservation {
+			client: Arc::downgrade(client),
+			kind,
+			amount,
+		})
+	}
+}
+impl Drop for QuotaReservation {
+	fn drop(&mut self) {
+		if let Some(client) = self.client.upgrade() {
+			client
+				.quota_usage
+				.counter(self.kind)
+				.fetch_sub(self.amount, Ordering::Relaxed);
+		}
+	}
+}
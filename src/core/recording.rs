@@ -0,0 +1,177 @@
+//! Recording what clients send, and replaying it as if a client sent it again.
+//!
+//! A recording starts with `MAGIC` and the client's protocol version as a little endian u32, 0 if
+//! it didn't send a handshake. Every message after that is the microseconds since the client
+//! connected as a little endian u64, followed by the message exactly as the client framed it.
+
+use super::{client::Client, task};
+use color_eyre::eyre::{ensure, eyre, Result};
+use once_cell::sync::OnceCell;
+use std::{
+	fs::{self, File},
+	io::Write,
+	path::{Path, PathBuf},
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		mpsc::{channel, Receiver, Sender},
+	},
+	thread,
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt},
+	net::UnixStream,
+};
+use tracing::{info, warn};
+
+const MAGIC: &[u8; 8] = b"SDRECORD";
+const HEADER_LENGTH: usize = MAGIC.len() + 4;
+
+/// Only set when recording was asked for, so production servers don't record by default.
+static RECORDING_DIR: OnceCell<PathBuf> = OnceCell::new();
+/// Tells apart recordings of clients that connected within the same second.
+static NEXT_RECORDING: AtomicU64 = AtomicU64::new(0);
+
+pub fn set_recording_dir(dir: Option<PathBuf>) {
+	if let Some(dir) = dir {
+		let _ = RECORDING_DIR.set(dir);
+	}
+}
+
+/// Hands entries to a thread that writes them, so a slow disk doesn't hold up the client.
+pub struct Recorder {
+	entries: Sender<Vec<u8>>,
+	connected: Instant,
+}
+impl Recorder {
+	/// A new recording file for a client that just connected, `None` if recording is off or the
+	/// file couldn't be made.
+	pub fn start(pid: Option<i32>, protocol_version: Option<u32>) -> Option<Self> {
+		let dir = RECORDING_DIR.get()?;
+		match Self::create(dir, pid, protocol_version) {
+			Ok(recorder) => Some(recorder),
+			Err(e) => {
+				warn!(?e, "Unable to start recording client");
+				None
+			}
+		}
+	}
+	fn create(dir: &Path, pid: Option<i32>, protocol_version: Option<u32>) -> Result<Self> {
+		let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+		let pid = pid.map_or_else(|| "bridged".to_string(), |pid| pid.to_string());
+		let recording = NEXT_RECORDING.fetch_add(1, Ordering::Relaxed);
+		let dir = dir.to_path_buf();
+		let path = dir.join(format!("{time}-{pid}-{recording}.sdrec"));
+
+		let (entries, entry_rx) = channel();
+		let mut header = MAGIC.to_vec();
+		header.extend_from_slice(&protocol_version.unwrap_or(0).to_le_bytes());
+		entries.send(header)?;
+		thread::Builder::new()
+			.name("client recorder".to_string())
+			.spawn(move || {
+				if let Err(e) = write_entries(&dir, &path, entry_rx) {
+					warn!(?e, path = ?path.display(), "Unable to write client recording");
+				}
+			})?;
+		Ok(Recorder {
+			entries,
+			connected: Instant::now(),
+		})
+	}
+
+	/// Takes the whole message including its length prefix. Written in one go so a server that
+	/// crashes doesn't leave half a message at the end.
+	pub fn record(&mut self, message: &[u8]) -> Result<()> {
+		let elapsed = self.connected.elapsed().as_micros() as u64;
+		let mut entry = Vec::with_capacity(8 + message.len());
+		entry.extend_from_slice(&elapsed.to_le_bytes());
+		entry.extend_from_slice(message);
+		self.entries
+			.send(entry)
+			.map_err(|_| eyre!("Recording stopped after failing to write"))
+	}
+}
+
+/// Runs until the recorder is dropped, the file is made here too since that can block as well.
+fn write_entries(dir: &Path, path: &Path, entries: Receiver<Vec<u8>>) -> Result<()> {
+	fs::create_dir_all(dir)?;
+	let mut file = File::create(path)?;
+	info!(path = ?path.display(), "Recording client");
+	for entry in entries {
+		file.write_all(&entry)?;
+	}
+	Ok(())
+}
+
+/// The protocol version a recording was made with, if any, and each message with when it was sent.
+type ParsedRecording<'a> = (Option<u32>, Vec<(Duration, &'a [u8])>);
+
+/// Each message in a recording with when it was sent relative to the client connecting.
+fn parse(recording: &[u8]) -> Result<ParsedRecording<'_>> {
+	ensure!(
+		recording.len() >= HEADER_LENGTH && &recording[..MAGIC.len()] == MAGIC,
+		"Not a client recording"
+	);
+	let protocol_version = u32::from_le_bytes(recording[MAGIC.len()..HEADER_LENGTH].try_into()?);
+	let mut messages = Vec::new();
+	let mut rest = &recording[HEADER_LENGTH..];
+	while rest.len() >= 12 {
+		let elapsed = u64::from_le_bytes(rest[..8].try_into()?);
+		let length = 4 + u32::from_ne_bytes(rest[8..12].try_into()?) as usize;
+		ensure!(
+			rest.len() >= 8 + length,
+			"Recording ends partway through a message"
+		);
+		messages.push((Duration::from_micros(elapsed), &rest[8..8 + length]));
+		rest = &rest[8 + length..];
+	}
+	ensure!(rest.is_empty(), "Recording ends partway through a message");
+	Ok((
+		(protocol_version != 0).then_some(protocol_version),
+		messages,
+	))
+}
+
+/// Connects a fresh client that sends everything in the recording with the same timing. Once
+/// it's all sent the client stays connected, so whatever it made can be looked at.
+pub fn replay(path: &Path) -> Result<()> {
+	let recording = fs::read(path)?;
+	let (protocol_version, messages) = parse(&recording)?;
+	let messages: Vec<(Duration, Vec<u8>)> = messages
+		.into_iter()
+		.map(|(elapsed, message)| (elapsed, message.to_vec()))
+		.collect();
+	let (server_end, replay_end) = UnixStream::pair()?;
+	Client::from_bridged_connection(server_end, protocol_version, None)?;
+	info!(path = ?path.display(), messages = messages.len(), "Replaying client recording");
+
+	let (mut replay_read, mut replay_write) = replay_end.into_split();
+	task::new(|| "client replay", async move {
+		let connected = tokio::time::Instant::now();
+		let send = async {
+			for (elapsed, message) in &messages {
+				tokio::time::sleep_until(connected + *elapsed).await;
+				replay_write.write_all(message).await?;
+			}
+			info!("Finished replaying client recording");
+			std::future::pending::<()>().await;
+			Ok::<_, std::io::Error>(())
+		};
+		// Nothing reads what the server sends back, so it's thrown away to keep the client from
+		// being disconnected for falling behind
+		let discard = async {
+			let mut buf = vec![0; 64 * 1024];
+			while replay_read.read(&mut buf).await? != 0 {}
+			Ok::<_, std::io::Error>(())
+		};
+		let result = tokio::select! {
+			result = send => result,
+			result = discard => result,
+		};
+		if let Err(e) = result {
+			warn!(?e, "Replay stopped");
+		}
+	})?;
+	Ok(())
+}
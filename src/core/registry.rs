@@ -3,18 +3,46 @@
 use parking_lot::{const_mutex, MappedMutexGuard, Mutex, MutexGuard};
 use rustc_hash::FxHashMap;
 use std::ptr;
-use std::sync::{Arc, Weak};
+use std::sync::{
+	atomic::{AtomicU64, Ordering},
+	Arc, Weak,
+};
 
-pub struct Registry<T: Send + Sync + ?Sized>(Mutex<Option<FxHashMap<usize, Weak<T>>>>);
+/// IDs are unique across every registry and never reused while the server runs.
+static NEXT_REGISTRY_ID: AtomicU64 = AtomicU64::new(1);
+
+struct RegistryEntries<T: Send + Sync + ?Sized> {
+	by_ptr: FxHashMap<usize, (u64, Weak<T>)>,
+	by_id: FxHashMap<u64, usize>,
+}
+impl<T: Send + Sync + ?Sized> Default for RegistryEntries<T> {
+	fn default() -> Self {
+		RegistryEntries {
+			by_ptr: FxHashMap::default(),
+			by_id: FxHashMap::default(),
+		}
+	}
+}
+impl<T: Send + Sync + ?Sized> Clone for RegistryEntries<T> {
+	fn clone(&self) -> Self {
+		RegistryEntries {
+			by_ptr: self.by_ptr.clone(),
+			by_id: self.by_id.clone(),
+		}
+	}
+}
+
+pub struct Registry<T: Send + Sync + ?Sized>(Mutex<Option<RegistryEntries<T>>>);
 
 impl<T: Send + Sync + ?Sized> Registry<T> {
 	pub const fn new() -> Self {
 		Registry(const_mutex(None))
 	}
-	fn lock(&self) -> MappedMutexGuard<FxHashMap<usize, Weak<T>>> {
-		MutexGuard::map(self.0.lock(), |r| {
-			r.get_or_insert_with(|| FxHashMap::default())
-		})
+	fn lock(&self) -> MappedMutexGuard<'_, RegistryEntries<T>> {
+		MutexGuard::map(self.0.lock(), |r| r.get_or_insert_with(Default::default))
+	}
+	fn key(t: &T) -> usize {
+		ptr::addr_of!(*t) as *const () as usize
 	}
 	pub fn add(&self, t: T) -> Arc<T>
 	where
@@ -24,18 +52,56 @@ impl<T: Send + Sync + ?Sized> Registry<T> {
 		self.add_raw(&t_arc);
 		t_arc
 	}
-	pub fn add_raw(&self, t: &Arc<T>) {
-		self.lock()
-			.insert(Arc::as_ptr(t) as *const () as usize, Arc::downgrade(t));
+	/// Returns the entry's ID, the existing one if it was already added and is still alive.
+	pub fn add_raw(&self, t: &Arc<T>) -> u64 {
+		let key = Arc::as_ptr(t) as *const () as usize;
+		let mut entries = self.lock();
+		if let Some((id, weak)) = entries.by_ptr.get(&key) {
+			if weak.strong_count() > 0 {
+				return *id;
+			}
+			// A dead entry at the same address belongs to something that got freed without
+			// being removed, so the new allocation gets its own ID
+			let id = *id;
+			entries.by_id.remove(&id);
+		}
+		let id = NEXT_REGISTRY_ID.fetch_add(1, Ordering::Relaxed);
+		entries.by_ptr.insert(key, (id, Arc::downgrade(t)));
+		entries.by_id.insert(id, key);
+		id
 	}
 	pub fn contains(&self, t: &T) -> bool {
+		self.lock().by_ptr.contains_key(&Self::key(t))
+	}
+	/// The ID the entry was given when it was added.
+	pub fn id_of(&self, t: &T) -> Option<u64> {
+		self.lock().by_ptr.get(&Self::key(t)).map(|(id, _)| *id)
+	}
+	pub fn get(&self, id: u64) -> Option<Arc<T>> {
+		let entries = self.lock();
+		let key = entries.by_id.get(&id)?;
+		entries.by_ptr.get(key)?.1.upgrade()
+	}
+	/// How many entries are still alive, without collecting them.
+	pub fn len(&self) -> usize {
 		self.lock()
-			.contains_key(&(ptr::addr_of!(*t) as *const () as usize))
+			.by_ptr
+			.values()
+			.filter(|(_, weak)| weak.strong_count() > 0)
+			.count()
+	}
+	pub fn is_empty(&self) -> bool {
+		!self
+			.lock()
+			.by_ptr
+			.values()
+			.any(|(_, weak)| weak.strong_count() > 0)
 	}
 	pub fn get_valid_contents(&self) -> Vec<Arc<T>> {
 		self.lock()
-			.iter()
-			.filter_map(|pair| pair.1.upgrade())
+			.by_ptr
+			.values()
+			.filter_map(|(_, weak)| weak.upgrade())
 			.collect()
 	}
 	pub fn take_valid_contents(&self) -> Vec<Arc<T>> {
@@ -43,16 +109,43 @@ impl<T: Send + Sync + ?Sized> Registry<T> {
 			.lock()
 			.take()
 			.unwrap_or_default()
-			.into_iter()
-			.filter_map(|pair| pair.1.upgrade())
+			.by_ptr
+			.into_values()
+			.filter_map(|(_, weak)| weak.upgrade())
 			.collect()
 	}
 	pub fn remove(&self, t: &T) {
-		self.lock()
-			.remove(&(ptr::addr_of!(*t) as *const () as usize));
+		let mut entries = self.lock();
+		if let Some((id, _)) = entries.by_ptr.remove(&Self::key(t)) {
+			entries.by_id.remove(&id);
+		}
+	}
+	/// Removes every entry `f` returns false for, and any that are already dead, in one lock.
+	pub fn retain(&self, mut f: impl FnMut(&Arc<T>) -> bool) {
+		// Entries removing themselves from the registry on drop would deadlock if the last
+		// reference got dropped while it's locked, so they're dropped afterwards
+		let mut upgraded = Vec::new();
+		{
+			let mut entries = self.lock();
+			let RegistryEntries { by_ptr, by_id } = &mut *entries;
+			by_ptr.retain(|_, (id, weak)| {
+				let keep = weak.upgrade().is_some_and(|t| {
+					let keep = f(&t);
+					upgraded.push(t);
+					keep
+				});
+				if !keep {
+					by_id.remove(id);
+				}
+				keep
+			});
+		}
+		drop(upgraded);
 	}
 	pub fn clear(&self) {
-		self.lock().clear();
+		let mut entries = self.lock();
+		entries.by_ptr.clear();
+		entries.by_id.clear();
 	}
 }
 impl<T: Send + Sync + ?Sized> Clone for Registry<T> {
@@ -67,10 +160,8 @@ impl<T: Send + Sync + ?Sized> OwnedRegistry<T> {
 	pub const fn new() -> Self {
 		OwnedRegistry(const_mutex(None))
 	}
-	fn lock(&self) -> MappedMutexGuard<FxHashMap<usize, Arc<T>>> {
-		MutexGuard::map(self.0.lock(), |r| {
-			r.get_or_insert_with(|| FxHashMap::default())
-		})
+	fn lock(&self) -> MappedMutexGuard<'_, FxHashMap<usize, Arc<T>>> {
+		MutexGuard::map(self.0.lock(), |r| r.get_or_insert_with(FxHashMap::default))
 	}
 	pub fn add(&self, t: T) -> Arc<T>
 	where
@@ -0,0 +1,348 @@
+use super::{recording::Recorder, task};
+use color_eyre::eyre::{bail, ensure, Result};
+use parking_lot::Mutex;
+use serde::Serialize;
+use stardust_xr::schemas::flat::message::root_as_message;
+use std::{
+	collections::VecDeque,
+	io,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc,
+	},
+};
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt},
+	net::{
+		unix::{OwnedReadHalf, OwnedWriteHalf},
+		UnixStream,
+	},
+	sync::Notify,
+};
+use tracing::{error, warn};
+
+/// Largest message in bytes a client can send, 0 means unlimited.
+static MAX_MESSAGE_SIZE: AtomicUsize = AtomicUsize::new(16 * 1024 * 1024);
+/// Most bytes waiting to be written to a client before it gets disconnected, 0 means unlimited.
+static MAX_OUTGOING_QUEUE: AtomicUsize = AtomicUsize::new(64 * 1024 * 1024);
+/// Once a client's queue gets this fraction of the way to the limit, older copies of coalescable
+/// signals still waiting in it are dropped for the newest one.
+const BACKLOG_FRACTION: usize = 8;
+/// Client messages are forwarded this many bytes at a time.
+const FORWARD_BUFFER_SIZE: usize = 64 * 1024;
+/// Signals that get sent again with newer state anyway, so a backlogged client only needs the
+/// latest one queued for each node.
+const COALESCABLE_SIGNALS: &[&str] = &["frame", "transform_changed"];
+
+pub fn set_max_message_size(bytes: usize) {
+	MAX_MESSAGE_SIZE.store(bytes, Ordering::Relaxed);
+}
+pub fn set_max_outgoing_queue(bytes: usize) {
+	MAX_OUTGOING_QUEUE.store(bytes, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct QueueDepth {
+	pub messages: usize,
+	pub bytes: usize,
+}
+
+#[derive(Debug)]
+struct QueuedMessage {
+	/// Including the length prefix
+	data: Vec<u8>,
+	/// The node and signal name, for signals where only the latest one matters
+	coalesce_key: Option<(String, String)>,
+}
+impl QueuedMessage {
+	fn new(data: Vec<u8>) -> Self {
+		let coalesce_key = coalesce_key(&data);
+		QueuedMessage { data, coalesce_key }
+	}
+}
+fn coalesce_key(data: &[u8]) -> Option<(String, String)> {
+	let message = root_as_message(data.get(4..)?).ok()?;
+	let method = message.method()?;
+	// Signals are type 1
+	if message.type_() != 1 || !COALESCABLE_SIGNALS.contains(&method) {
+		return None;
+	}
+	Some((message.object()?.to_string(), method.to_string()))
+}
+/// Queues the message, first dropping whatever older copies of it are still waiting if it can be
+/// coalesced. Returns the messages and bytes dropped.
+fn push_coalesced(queued: &mut VecDeque<QueuedMessage>, message: QueuedMessage) -> (usize, usize) {
+	let mut dropped = (0, 0);
+	if let Some(key) = &message.coalesce_key {
+		queued.retain(|queued| {
+			let superseded = queued.coalesce_key.as_ref() == Some(key);
+			if superseded {
+				dropped.0 += 1;
+				dropped.1 += queued.data.len();
+			}
+			!superseded
+		});
+	}
+	queued.push_back(message);
+	dropped
+}
+
+/// Messages to a client the server has sent that the client hasn't read yet.
+#[derive(Debug, Default)]
+pub struct OutgoingQueue {
+	queued: Mutex<VecDeque<QueuedMessage>>,
+	ready: Notify,
+	messages: AtomicUsize,
+	bytes: AtomicUsize,
+}
+impl OutgoingQueue {
+	pub fn depth(&self) -> QueueDepth {
+		QueueDepth {
+			messages: self.messages.load(Ordering::Relaxed),
+			bytes: self.bytes.load(Ordering::Relaxed),
+		}
+	}
+	/// If the client is reading slow enough that coalescable signals should be dropped.
+	fn is_backlogged(&self) -> bool {
+		let max_outgoing_queue = MAX_OUTGOING_QUEUE.load(Ordering::Relaxed);
+		max_outgoing_queue != 0
+			&& self.bytes.load(Ordering::Relaxed) > max_outgoing_queue / BACKLOG_FRACTION
+	}
+	/// Older copies of coalescable signals are dropped once the client is backlogged. Returns the
+	/// bytes queued after this one.
+	fn push(&self, message: QueuedMessage) -> usize {
+		let backlogged = self.is_backlogged();
+		let length = message.data.len();
+		let mut queued = self.queued.lock();
+		let (dropped_messages, dropped_bytes) = match backlogged {
+			true => push_coalesced(&mut queued, message),
+			false => {
+				queued.push_back(message);
+				(0, 0)
+			}
+		};
+		self.messages.fetch_sub(dropped_messages, Ordering::Relaxed);
+		self.messages.fetch_add(1, Ordering::Relaxed);
+		let bytes = self.bytes.fetch_add(length, Ordering::Relaxed) + length;
+		self.bytes.fetch_sub(dropped_bytes, Ordering::Relaxed);
+		drop(queued);
+		self.ready.notify_one();
+		bytes - dropped_bytes
+	}
+	async fn pop(&self) -> QueuedMessage {
+		loop {
+			if let Some(message) = self.queued.lock().pop_front() {
+				return message;
+			}
+			self.ready.notified().await;
+		}
+	}
+}
+
+/// Everything between the messenger and the client's socket goes through here, as the messenger
+/// neither bounds what it queues for a client nor what it allocates for a client's message. The
+/// stardust-xr messenger reads its socket itself and keeps its send queue private, so there's
+/// nowhere in it to hook the limits into, which costs a copy of every byte through the socket
+/// pair.
+///
+/// Messages from the client are checked against the size limit from their length prefix, then
+/// streamed through a fixed buffer so nothing the size of the message is allocated here. Messages
+/// to the client are read out of the messenger right away and queued here instead, so the queue
+/// can be measured, and a client that lets it grow past the limit is disconnected. Messages from
+/// the client are also recorded if there's a recorder.
+pub fn relay(
+	connection: UnixStream,
+	queue: Arc<OutgoingQueue>,
+	recorder: Option<Recorder>,
+) -> Result<UnixStream> {
+	let (server_end, bridge_end) = UnixStream::pair()?;
+	let (mut client_read, mut client_write) = connection.into_split();
+	let (mut bridge_read, mut bridge_write) = bridge_end.into_split();
+	task::new(|| "client relay", async move {
+		// Any of these stopping drops both connections, which the messenger sees as the client
+		// disconnecting
+		let result = tokio::select! {
+			result = forward_incoming(&mut client_read, &mut bridge_write, recorder) => result,
+			result = queue_outgoing(&mut bridge_read, &queue) => result,
+			result = write_outgoing(&mut client_write, &queue) => result,
+		};
+		if let Err(e) = result {
+			error!(?e, "Closing client connection");
+		}
+	})?;
+	Ok(server_end)
+}
+
+/// Messages are a native endian u32 length followed by that many bytes. Returns the length, or
+/// `None` if the stream closed between messages.
+async fn read_length(from: &mut OwnedReadHalf, max_message_size: usize) -> Result<Option<usize>> {
+	let mut length = [0; 4];
+	match from.read_exact(&mut length).await {
+		Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+		result => result?,
+	};
+	let message_length = u32::from_ne_bytes(length) as usize;
+	ensure!(
+		max_message_size == 0 || message_length <= max_message_size,
+		"Client sent a {message_length} byte message, the most allowed is {max_message_size}"
+	);
+	Ok(Some(message_length))
+}
+/// The whole message including its length, or `None` if the stream closed between messages.
+async fn read_message(
+	from: &mut OwnedReadHalf,
+	max_message_size: usize,
+) -> Result<Option<Vec<u8>>> {
+	let Some(message_length) = read_length(from, max_message_size).await? else {return Ok(None)};
+	let mut message = vec![0; 4 + message_length];
+	message[..4].copy_from_slice(&(message_length as u32).to_ne_bytes());
+	from.read_exact(&mut message[4..]).await?;
+	Ok(Some(message))
+}
+
+async fn forward_incoming(
+	from: &mut OwnedReadHalf,
+	to: &mut OwnedWriteHalf,
+	mut recorder: Option<Recorder>,
+) -> Result<()> {
+	let max_message_size = MAX_MESSAGE_SIZE.load(Ordering::Relaxed);
+	let mut buffer = vec![0; FORWARD_BUFFER_SIZE];
+	loop {
+		// Recordings take the whole message at once
+		if let Some(active_recorder) = recorder.as_mut() {
+			let Some(message) = read_message(from, max_message_size).await? else {break};
+			if let Err(e) = active_recorder.record(&message) {
+				// A full disk shouldn't take the client down with it
+				warn!(
+					?e,
+					"Unable to record client message, no longer recording it"
+				);
+				recorder = None;
+			}
+			to.write_all(&message).await?;
+			continue;
+		}
+
+		let Some(message_length) = read_length(from, max_message_size).await? else {break};
+		to.write_all(&(message_length as u32).to_ne_bytes()).await?;
+		let mut remaining = message_length;
+		while remaining > 0 {
+			let chunk = &mut buffer[..remaining.min(FORWARD_BUFFER_SIZE)];
+			from.read_exact(chunk).await?;
+			to.write_all(chunk).await?;
+			remaining -= chunk.len();
+		}
+	}
+	Ok(())
+}
+
+async fn queue_outgoing(from: &mut OwnedReadHalf, queue: &OutgoingQueue) -> Result<()> {
+	let max_outgoing_queue = MAX_OUTGOING_QUEUE.load(Ordering::Relaxed);
+	// The server's own messages aren't limited
+	while let Some(message) = read_message(from, 0).await? {
+		// Everything that isn't coalescable has to get there, so past the limit the client goes
+		let bytes = queue.push(QueuedMessage::new(message));
+		if max_outgoing_queue != 0 && bytes > max_outgoing_queue {
+			bail!("Client isn't reading its messages, {bytes} bytes are queued");
+		}
+	}
+	Ok(())
+}
+
+async fn write_outgoing(to: &mut OwnedWriteHalf, queue: &OutgoingQueue) -> Result<()> {
+	loop {
+		let message = queue.pop().await;
+		to.write_all(&message.data).await?;
+		queue.messages.fetch_sub(1, Ordering::Relaxed);
+		queue.bytes.fetch_sub(message.data.len(), Ordering::Relaxed);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn framed(body: &[u8]) -> Vec<u8> {
+		let mut message = (body.len() as u32).to_ne_bytes().to_vec();
+		message.extend_from_slice(body);
+		message
+	}
+
+	#[test]
+	fn messages_pass_through_whole_and_oversized_ones_disconnect() {
+		let runtime = tokio::runtime::Builder::new_multi_thread()
+			.enable_io()
+			.build()
+			.unwrap();
+		runtime.block_on(async {
+			let (mut client, server_side) = UnixStream::pair().unwrap();
+			let queue = Arc::new(OutgoingQueue::default());
+			let mut messenger_side = relay(server_side, queue.clone(), None).unwrap();
+
+			// Bigger than the forwarding buffer, so it takes a few chunks
+			let incoming = framed(&(0..200_000).map(|i| i as u8).collect::<Vec<_>>());
+			client.write_all(&incoming).await.unwrap();
+			let mut forwarded = vec![0; incoming.len()];
+			messenger_side.read_exact(&mut forwarded).await.unwrap();
+			assert_eq!(forwarded, incoming);
+
+			let outgoing = framed(b"signal");
+			messenger_side.write_all(&outgoing).await.unwrap();
+			let mut delivered = vec![0; outgoing.len()];
+			client.read_exact(&mut delivered).await.unwrap();
+			assert_eq!(delivered, outgoing);
+
+			// The length alone is enough to get disconnected, nothing gets allocated for it
+			client.write_all(&u32::MAX.to_ne_bytes()).await.unwrap();
+			let mut rest = Vec::new();
+			messenger_side.read_to_end(&mut rest).await.unwrap();
+			assert!(rest.is_empty());
+		});
+	}
+
+	fn signal(object: &str, method: &str) -> QueuedMessage {
+		QueuedMessage::new(framed(
+			&stardust_xr::messenger::serialize_signal_call(object, method, &[]).into_data(),
+		))
+	}
+
+	#[test]
+	fn only_frame_and_transform_signals_coalesce() {
+		assert_eq!(
+			signal("/root", "frame").coalesce_key,
+			Some(("/root".to_string(), "frame".to_string()))
+		);
+		assert!(signal("/root", "set_transform").coalesce_key.is_none());
+		let method = framed(
+			&stardust_xr::messenger::serialize_method_call(0, "/root", "frame", &[]).into_data(),
+		);
+		assert!(QueuedMessage::new(method).coalesce_key.is_none());
+		assert!(QueuedMessage::new(framed(b"garbage"))
+			.coalesce_key
+			.is_none());
+	}
+
+	#[test]
+	fn backlogged_signals_drop_their_older_copies() {
+		let mut queued = VecDeque::new();
+		assert_eq!(push_coalesced(&mut queued, signal("/a", "frame")), (0, 0));
+		assert_eq!(
+			push_coalesced(&mut queued, signal("/a", "set_transform")),
+			(0, 0)
+		);
+		assert_eq!(push_coalesced(&mut queued, signal("/b", "frame")), (0, 0));
+		let dropped_bytes = queued[0].data.len();
+		assert_eq!(
+			push_coalesced(&mut queued, signal("/a", "frame")),
+			(1, dropped_bytes)
+		);
+		let order = queued
+			.iter()
+			.map(|queued| queued.coalesce_key.clone().map(|(object, _)| object))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			order,
+			[None, Some("/b".to_string()), Some("/a".to_string())]
+		);
+	}
+}
@@ -1,37 +1,243 @@
-use color_eyre::eyre::eyre;
-use serde::{de::Visitor, Deserialize};
-use std::{ffi::OsStr, path::PathBuf};
+use color_eyre::eyre::{bail, eyre, Result};
+use directories::ProjectDirs;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use rustc_hash::{FxHashMap, FxHasher};
+use serde::{de::Visitor, Deserialize, Serialize};
+use std::{
+	ffi::OsStr,
+	hash::{Hash, Hasher},
+	io::Read,
+	path::{Path, PathBuf},
+	time::{Duration, Instant},
+};
+use tokio::sync::watch;
+use tracing::{debug, warn};
 
-#[derive(Debug)]
+/// Downloads bigger than this get rejected instead of filling up the cache
+const MAX_DOWNLOAD_BYTES: u64 = 256 * 1024 * 1024;
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+/// Cached downloads older than this get downloaded again in the background, the old file is
+/// used until the new one is in place
+const CACHE_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+/// A URL that failed to download isn't tried again until this long after
+const DOWNLOAD_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+lazy_static! {
+	/// By URL, only while downloading or after a download failed
+	static ref DOWNLOADS: Mutex<FxHashMap<String, Download>> = Mutex::new(FxHashMap::default());
+}
+
+enum Download {
+	/// The sender is dropped once the download is done, which wakes up everything waiting on it
+	InProgress(watch::Receiver<()>),
+	Failed(Instant),
+}
+
+/// Where a resource is, or why it can't be used yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResourceState {
+	Found(PathBuf),
+	/// A URL that's downloading in the background
+	Pending,
+	/// A URL that hasn't been downloaded, only from `resolve_cached`
+	Uncached,
+	NotFound,
+}
+
+#[derive(Debug, Clone)]
 pub enum ResourceID {
 	File(PathBuf),
 	Namespaced { namespace: String, path: PathBuf },
+	/// An `http(s)://` URL, downloaded into the cache the first time it's used
+	Url(String),
 }
 impl ResourceID {
+	/// Never blocks, URLs that aren't cached yet start downloading and are `None` until they're
+	/// done. `resolve` tells that apart from the resource not existing.
 	pub fn get_file(&self, prefixes: &[PathBuf], extensions: &[&OsStr]) -> Option<PathBuf> {
+		match self.resolve(prefixes, extensions) {
+			ResourceState::Found(path) => Some(path),
+			_ => None,
+		}
+	}
+	/// Starts downloading URLs that aren't cached or whose cached file is too old.
+	pub fn resolve(&self, prefixes: &[PathBuf], extensions: &[&OsStr]) -> ResourceState {
+		self.lookup(prefixes, extensions, true)
+	}
+	/// Like `resolve` but never starts a download.
+	pub fn resolve_cached(&self, prefixes: &[PathBuf], extensions: &[&OsStr]) -> ResourceState {
+		self.lookup(prefixes, extensions, false)
+	}
+	/// Waits for a URL to finish downloading, `None` if the resource can't be found at all.
+	pub async fn fetch(&self, prefixes: &[PathBuf], extensions: &[&OsStr]) -> Option<PathBuf> {
+		loop {
+			match self.resolve(prefixes, extensions) {
+				ResourceState::Found(path) => return Some(path),
+				ResourceState::Pending => (),
+				ResourceState::Uncached | ResourceState::NotFound => return None,
+			}
+			let ResourceID::Url(url) = self else {return None};
+			let mut download = match DOWNLOADS.lock().get(url) {
+				Some(Download::InProgress(download)) => download.clone(),
+				_ => continue,
+			};
+			// Only returns once the sender is dropped, since nothing is ever sent
+			let _ = download.changed().await;
+		}
+	}
+
+	fn lookup(&self, prefixes: &[PathBuf], extensions: &[&OsStr], fetch: bool) -> ResourceState {
+		let url = match self {
+			ResourceID::Url(url) => url,
+			_ => {
+				return self
+					.local_file(prefixes, extensions)
+					.map_or(ResourceState::NotFound, ResourceState::Found)
+			}
+		};
+		let Some(cached) = Self::cache_path(url) else {return ResourceState::NotFound};
+		if !Self::has_extension(&cached, extensions) {
+			return ResourceState::NotFound;
+		}
+		let age = cached
+			.metadata()
+			.and_then(|metadata| metadata.modified())
+			.ok()
+			.map(|modified| modified.elapsed().unwrap_or_default());
+
+		let mut downloads = DOWNLOADS.lock();
+		let busy = match downloads.get(url) {
+			Some(Download::InProgress(_)) => true,
+			Some(Download::Failed(failed_at)) => failed_at.elapsed() < DOWNLOAD_RETRY_DELAY,
+			None => false,
+		};
+		let fresh = age.is_some_and(|age| age < CACHE_MAX_AGE);
+		if !fetch || busy || fresh {
+			return match (age, downloads.get(url)) {
+				(Some(_), _) => ResourceState::Found(cached),
+				(None, Some(Download::InProgress(_))) => ResourceState::Pending,
+				(None, Some(Download::Failed(_))) => ResourceState::NotFound,
+				(None, None) => ResourceState::Uncached,
+			};
+		}
+		let download = Self::start_download(url, &cached);
+		downloads.insert(url.clone(), Download::InProgress(download));
+		// A stale file still beats nothing while the new one downloads
+		match age {
+			Some(_) => ResourceState::Found(cached),
+			None => ResourceState::Pending,
+		}
+	}
+
+	fn local_file(&self, prefixes: &[PathBuf], extensions: &[&OsStr]) -> Option<PathBuf> {
 		match self {
 			ResourceID::File(file) => (file.is_absolute()
 				&& file.exists() && Self::has_extension(file, extensions))
 			.then_some(file.clone()),
 			ResourceID::Namespaced { namespace, path } => {
 				let file_name = path.file_name()?;
-				prefixes
-					.iter()
-					.filter_map(|prefix| {
-						let prefixed_path = prefix.clone().join(namespace).join(path);
-						let parent = prefixed_path.parent()?;
-						std::fs::read_dir(parent).ok()
+				// Prefixes come first, then the extensions in the order they were given
+				prefixes.iter().find_map(|prefix| {
+					let prefixed_path = prefix.clone().join(namespace).join(path);
+					let parent = prefixed_path.parent()?;
+					let candidates = std::fs::read_dir(parent)
+						.ok()?
+						.filter_map(|item| item.ok())
+						.map(|dir_entry| dir_entry.path())
+						.filter(|path| path.file_stem() == Some(file_name))
+						.collect::<Vec<_>>();
+					extensions.iter().find_map(|extension| {
+						candidates
+							.iter()
+							.find(|path| path.extension() == Some(*extension))
+							.cloned()
 					})
-					.flatten()
-					.filter_map(|item| item.ok())
-					.map(|dir_entry| dir_entry.path())
-					.filter(|path| path.file_stem() == Some(file_name))
-					.find(|path| Self::has_extension(path, extensions))
+				})
 			}
+			ResourceID::Url(_) => None,
 		}
 	}
 
-	fn has_extension(path: &PathBuf, extensions: &[&OsStr]) -> bool {
+	/// Runs on a blocking thread so neither the render thread nor a client's dispatch waits on
+	/// the network.
+	fn start_download(url: &str, destination: &Path) -> watch::Receiver<()> {
+		let (done_tx, done_rx) = watch::channel(());
+		let url = url.to_string();
+		let destination = destination.to_path_buf();
+		let download = move || {
+			let result = Self::download(&url, &destination);
+			let mut downloads = DOWNLOADS.lock();
+			match result {
+				Ok(()) => {
+					downloads.remove(&url);
+				}
+				Err(e) => {
+					warn!(url, error = %e, "Unable to download resource");
+					downloads.insert(url, Download::Failed(Instant::now()));
+				}
+			}
+			drop(done_tx);
+		};
+		match tokio::runtime::Handle::try_current() {
+			Ok(handle) => {
+				handle.spawn_blocking(download);
+			}
+			Err(_) => {
+				std::thread::spawn(download);
+			}
+		}
+		done_rx
+	}
+
+	/// Cached files are named after a hash of the URL and keep its extension.
+	fn cache_path(url: &str) -> Option<PathBuf> {
+		let project_dirs = ProjectDirs::from("", "", "stardust")?;
+		let mut hasher = FxHasher::default();
+		url.hash(&mut hasher);
+		let mut file_name = format!("{:016x}", hasher.finish());
+		let url_path = url.split(['?', '#']).next()?;
+		if let Some(extension) = Path::new(url_path).extension() {
+			file_name.push('.');
+			file_name.push_str(&extension.to_string_lossy());
+		}
+		Some(project_dirs.cache_dir().join("resources").join(file_name))
+	}
+	fn download(url: &str, destination: &Path) -> Result<()> {
+		debug!(url, "Downloading resource");
+		let agent = ureq::AgentBuilder::new().timeout(DOWNLOAD_TIMEOUT).build();
+		let response = agent
+			.get(url)
+			.call()
+			.map_err(|e| eyre!("Request for {url} failed: {e}"))?;
+		if let Some(length) = response
+			.header("Content-Length")
+			.and_then(|length| length.parse::<u64>().ok())
+		{
+			if length > MAX_DOWNLOAD_BYTES {
+				bail!("{url} is {length} bytes, over the {MAX_DOWNLOAD_BYTES} byte limit");
+			}
+		}
+		let mut data = Vec::new();
+		response
+			.into_reader()
+			.take(MAX_DOWNLOAD_BYTES + 1)
+			.read_to_end(&mut data)?;
+		if data.len() as u64 > MAX_DOWNLOAD_BYTES {
+			bail!("{url} is over the {MAX_DOWNLOAD_BYTES} byte limit");
+		}
+
+		// Written under a temporary name first so a failed write never looks like a cached file
+		if let Some(parent) = destination.parent() {
+			std::fs::create_dir_all(parent)?;
+		}
+		let partial = destination.with_extension("partial");
+		std::fs::write(&partial, data)?;
+		std::fs::rename(&partial, destination)?;
+		Ok(())
+	}
+
+	fn has_extension(path: &Path, extensions: &[&OsStr]) -> bool {
 		if let Some(path_extension) = path.extension() {
 			extensions.contains(&path_extension)
 		} else {
@@ -39,6 +245,20 @@ impl ResourceID {
 		}
 	}
 }
+impl Serialize for ResourceID {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: serde::Serializer,
+	{
+		match self {
+			ResourceID::File(path) => serializer.serialize_str(&path.to_string_lossy()),
+			ResourceID::Namespaced { namespace, path } => {
+				serializer.serialize_str(&format!("{namespace}:{}", path.to_string_lossy()))
+			}
+			ResourceID::Url(url) => serializer.serialize_str(url),
+		}
+	}
+}
 impl<'de> Deserialize<'de> for ResourceID {
 	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
 	where
@@ -53,7 +273,7 @@ impl<'de> Visitor<'de> for ResourceVisitor {
 	type Value = ResourceID;
 
 	fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-		formatter.write_str("A string containing an absolute path to file or \"[namespace]:[path]\" for a namespaced resource")
+		formatter.write_str("A string containing an absolute path to file, \"[namespace]:[path]\" for a namespaced resource or an http(s) URL")
 	}
 
 	fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
@@ -64,6 +284,8 @@ impl<'de> Visitor<'de> for ResourceVisitor {
 			let path = PathBuf::from(v);
 			path.metadata().map_err(serde::de::Error::custom)?;
 			ResourceID::File(path)
+		} else if v.starts_with("http://") || v.starts_with("https://") {
+			ResourceID::Url(v.to_string())
 		} else if let Some((namespace, path)) = v.split_once(':') {
 			ResourceID::Namespaced {
 				namespace: namespace.to_string(),
@@ -0,0 +1,117 @@
+use super::destroy_queue;
+use portable_atomic::{AtomicUsize, Ordering};
+use rustc_hash::FxHashMap;
+use send_wrapper::SendWrapper;
+use std::{
+	fs,
+	hash::Hash,
+	path::{Path, PathBuf},
+	sync::Arc,
+	time::SystemTime,
+};
+
+/// Most resources each cache keeps around, including ones nothing is using anymore. Resources
+/// that are still in use are never evicted, so this can be exceeded while they all are.
+static MAX_ENTRIES: AtomicUsize = AtomicUsize::new(64);
+
+pub fn set_max_entries(max_entries: usize) {
+	MAX_ENTRIES.store(max_entries.max(1), Ordering::Relaxed);
+}
+
+struct CacheEntry<T> {
+	resource: Arc<SendWrapper<T>>,
+	path: PathBuf,
+	modified: Option<SystemTime>,
+	last_used: u64,
+}
+
+/// Render thread resources loaded from files, handed out to everything using the same file until
+/// the file changes. Past the size limit the least recently used ones that nothing else holds a
+/// handle to are dropped through the destroy queue, so a file in use is never loaded twice.
+pub struct ResourceCache<K, T> {
+	entries: FxHashMap<K, CacheEntry<T>>,
+	uses: u64,
+}
+impl<K, T> Default for ResourceCache<K, T> {
+	fn default() -> Self {
+		ResourceCache {
+			entries: FxHashMap::default(),
+			uses: 0,
+		}
+	}
+}
+impl<K: Hash + Eq + Clone, T: 'static> ResourceCache<K, T> {
+	/// The cached resource if its file hasn't been modified since it was loaded.
+	pub fn get(&mut self, key: &K) -> Option<Arc<SendWrapper<T>>> {
+		let entry = self.entries.get(key)?;
+		if modified(&entry.path) != entry.modified {
+			let stale = self.entries.remove(key).unwrap();
+			destroy_queue::add(stale.resource);
+			return None;
+		}
+		self.uses += 1;
+		let entry = self.entries.get_mut(key).unwrap();
+		entry.last_used = self.uses;
+		Some(entry.resource.clone())
+	}
+
+	/// Replaces whatever was cached under `key`, `path` is the file it was loaded from.
+	pub fn insert(&mut self, key: K, path: &Path, resource: T) -> Arc<SendWrapper<T>> {
+		let resource = Arc::new(SendWrapper::new(resource));
+		self.uses += 1;
+		let old = self.entries.insert(
+			key,
+			CacheEntry {
+				resource: resource.clone(),
+				path: path.to_path_buf(),
+				modified: modified(path),
+				last_used: self.uses,
+			},
+		);
+		if let Some(old) = old {
+			destroy_queue::add(old.resource);
+		}
+		let max_entries = MAX_ENTRIES.load(Ordering::Relaxed);
+		while self.entries.len() > max_entries {
+			let Some(oldest) = self
+				.entries
+				.iter()
+				.filter(|(_, entry)| Arc::strong_count(&entry.resource) == 1)
+				.min_by_key(|(_, entry)| entry.last_used)
+				.map(|(key, _)| key.clone()) else {break};
+			let evicted = self.entries.remove(&oldest).unwrap();
+			destroy_queue::add(evicted.resource);
+		}
+		resource
+	}
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+	fs::metadata(path)
+		.and_then(|metadata| metadata.modified())
+		.ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn in_use_resources_are_not_evicted() {
+		let max_entries = MAX_ENTRIES.load(Ordering::Relaxed);
+		let mut cache = ResourceCache::default();
+		let path = Path::new("/nonexistent");
+		let held: Vec<_> = (0..=max_entries)
+			.map(|key| cache.insert(key, path, key))
+			.collect();
+		assert_eq!(cache.entries.len(), max_entries + 1);
+		assert!(cache.get(&0).is_some());
+
+		drop(held);
+		cache.insert(max_entries + 1, path, max_entries + 1);
+		assert_eq!(cache.entries.len(), max_entries);
+		// The least recently used ones go first, and 0 was just used
+		assert!(cache.get(&0).is_some());
+		assert!(cache.get(&1).is_none());
+	}
+}
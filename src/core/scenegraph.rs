@@ -1,6 +1,7 @@
 use crate::core::client::Client;
+use crate::core::latency;
 use crate::nodes::Node;
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{bail, eyre, Result};
 use once_cell::sync::OnceCell;
 use stardust_xr::scenegraph;
 use stardust_xr::scenegraph::ScenegraphError;
@@ -41,6 +42,26 @@ impl Scenegraph {
 		}
 		Some(node)
 	}
+	/// Like `get_node` but with an error saying why the path doesn't point to a node, for
+	/// anything that references other nodes by path.
+	pub fn resolve(&self, path: &str) -> Result<Arc<Node>> {
+		if !path.starts_with('/') {
+			bail!("Node path \"{path}\" must start with /");
+		}
+		if path.len() > 1 && path[1..].split('/').any(str::is_empty) {
+			bail!("Node path \"{path}\" has an empty segment");
+		}
+		self.get_node(path)
+			.ok_or_else(|| eyre!("No node at \"{path}\""))
+	}
+
+	/// Every node including aliases, in no particular order.
+	pub fn nodes(&self) -> Vec<Arc<Node>> {
+		self.nodes
+			.iter()
+			.map(|entry| entry.value().clone())
+			.collect()
+	}
 
 	pub fn remove_node(&self, path: &str) -> Option<Arc<Node>> {
 		debug!(path, "Remove node");
@@ -52,11 +73,22 @@ impl Scenegraph {
 impl scenegraph::Scenegraph for Scenegraph {
 	fn send_signal(&self, path: &str, method: &str, data: &[u8]) -> Result<(), ScenegraphError> {
 		let Some(client) = self.get_client() else {return Err(ScenegraphError::SignalNotFound)};
-		debug_span!("Handle signal", path, method).in_scope(|| {
+		client.traffic.count_received(data.len());
+		let start = latency::start();
+		let result = debug_span!("Handle signal", path, method).in_scope(|| {
 			self.get_node(path)
 				.ok_or(ScenegraphError::NodeNotFound)?
 				.send_local_signal(client, method, data)
-		})
+		});
+		// Names that didn't reach a handler are whatever the client made up, so they'd only fill
+		// the latency table
+		if !matches!(
+			result,
+			Err(ScenegraphError::NodeNotFound | ScenegraphError::SignalNotFound)
+		) {
+			latency::record(method, start);
+		}
+		result
 	}
 	fn execute_method(
 		&self,
@@ -65,10 +97,22 @@ impl scenegraph::Scenegraph for Scenegraph {
 		data: &[u8],
 	) -> Result<Vec<u8>, ScenegraphError> {
 		let Some(client) = self.get_client() else {return Err(ScenegraphError::MethodNotFound)};
-		debug_span!("Handle method", path, method).in_scope(|| {
+		client.traffic.count_received(data.len());
+		let start = latency::start();
+		let result = debug_span!("Handle method", path, method).in_scope(|| {
 			self.get_node(path)
 				.ok_or(ScenegraphError::NodeNotFound)?
-				.execute_local_method(client, method, data)
-		})
+				.execute_local_method(client.clone(), method, data)
+		});
+		if !matches!(
+			result,
+			Err(ScenegraphError::NodeNotFound | ScenegraphError::MethodNotFound)
+		) {
+			latency::record(method, start);
+		}
+		if let Ok(response) = &result {
+			client.traffic.count_sent(response.len());
+		}
+		result
 	}
 }
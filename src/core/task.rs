@@ -1,9 +1,22 @@
 use color_eyre::eyre::Result;
-use std::future::Future;
+use std::{
+	backtrace::Backtrace,
+	cell::{Cell, RefCell},
+	future::Future,
+	panic::{self, AssertUnwindSafe},
+	pin::Pin,
+	sync::Once,
+	task::{Context, Poll},
+	time::{Duration, Instant},
+};
 use tokio::task::JoinHandle;
-use tracing::instrument;
+use tracing::{error, info_span, instrument, Instrument};
+
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// How long a supervised task has to run before a panic stops counting towards its backoff.
+const STABLE_RUNTIME: Duration = Duration::from_secs(60);
 
-#[allow(unused_variables)]
 #[instrument(level = "debug", skip_all)]
 pub fn new<
 	F: FnOnce() -> S,
@@ -14,11 +27,214 @@ pub fn new<
 	name_fn: F,
 	async_future: A,
 ) -> Result<JoinHandle<O>> {
+	let name = name_fn();
+	// Everything the task logs is attributed to it
+	let async_future = async_future.instrument(info_span!("task", name = name.as_ref()));
 	#[cfg(not(feature = "profile_tokio"))]
 	let result = Ok(tokio::task::spawn(async_future));
 	#[cfg(feature = "profile_tokio")]
 	let result = tokio::task::Builder::new()
-		.name(name_fn().as_ref())
+		.name(name.as_ref())
 		.spawn(async_future);
 	result
 }
+
+/// Aborts the wrapped task when dropped, so aborting a supervisor takes its task down with it.
+struct AbortOnDrop<O>(JoinHandle<O>);
+impl<O> Drop for AbortOnDrop<O> {
+	fn drop(&mut self) {
+		self.0.abort();
+	}
+}
+
+thread_local! {
+	/// Set while a supervised task is being polled, its panics go to the supervisor instead of
+	/// the default hook.
+	static SUPERVISED: Cell<bool> = const { Cell::new(false) };
+	static CAUGHT_PANIC: RefCell<Option<CaughtPanic>> = const { RefCell::new(None) };
+}
+
+struct CaughtPanic {
+	message: String,
+	/// Only captured if `RUST_BACKTRACE` asks for it, like the default hook
+	backtrace: Backtrace,
+}
+
+/// Leaves panics outside of supervised tasks to the default hook, so they're only printed once.
+fn install_panic_hook() {
+	static INSTALL: Once = Once::new();
+	INSTALL.call_once(|| {
+		let default_hook = panic::take_hook();
+		panic::set_hook(Box::new(move |info| {
+			if !SUPERVISED.with(Cell::get) {
+				return default_hook(info);
+			}
+			let caught = CaughtPanic {
+				message: info.to_string(),
+				backtrace: Backtrace::capture(),
+			};
+			CAUGHT_PANIC.with(|caught_panic| *caught_panic.borrow_mut() = Some(caught));
+		}));
+	});
+}
+
+/// Polls the future with its panics caught, so the supervisor can log and restart it.
+struct CatchPanic<A>(Pin<Box<A>>);
+impl<A: Future<Output = ()>> Future for CatchPanic<A> {
+	type Output = Result<(), CaughtPanic>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let was_supervised = SUPERVISED.with(|supervised| supervised.replace(true));
+		let result = panic::catch_unwind(AssertUnwindSafe(|| self.0.as_mut().poll(cx)));
+		SUPERVISED.with(|supervised| supervised.set(was_supervised));
+		match result {
+			Ok(poll) => poll.map(Ok),
+			Err(_) => Poll::Ready(Err(CAUGHT_PANIC
+				.with(|caught_panic| caught_panic.borrow_mut().take())
+				.unwrap_or_else(|| CaughtPanic {
+					message: "panicked".to_string(),
+					backtrace: Backtrace::disabled(),
+				}))),
+		}
+	}
+}
+
+/// Like `new` but the future gets made again by `future_fn` and re-spawned if it panics, waiting
+/// longer after each panic in a row. Only for tasks where starting over is safe; anything whose
+/// panic should be fatal should use `new` instead. Stops once the future completes normally.
+pub fn new_supervised<
+	F: FnOnce() -> S,
+	S: AsRef<str>,
+	M: Fn() -> A + Send + 'static,
+	A: Future<Output = ()> + Send + 'static,
+>(
+	name_fn: F,
+	future_fn: M,
+) -> Result<JoinHandle<()>> {
+	install_panic_hook();
+	let name = name_fn().as_ref().to_string();
+	new(|| format!("{name} supervisor"), {
+		let name = name.clone();
+		async move {
+			let mut backoff = MIN_BACKOFF;
+			loop {
+				let started = Instant::now();
+				let future = CatchPanic(Box::pin(future_fn()));
+				let Ok(task) = new(|| &name, future) else {return};
+				let mut task = AbortOnDrop(task);
+				match (&mut task.0).await {
+					Ok(Err(panic)) => {
+						// A task that ran for a while before panicking isn't crash looping
+						if started.elapsed() > STABLE_RUNTIME {
+							backoff = MIN_BACKOFF;
+						}
+						error!(
+							task = %name,
+							?backoff,
+							backtrace = %panic.backtrace,
+							"{}, restarting",
+							panic.message
+						);
+						tokio::time::sleep(backoff).await;
+						backoff = (backoff * 2).min(MAX_BACKOFF);
+					}
+					_ => return,
+				}
+			}
+		}
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use parking_lot::Mutex;
+	use std::sync::{
+		atomic::{AtomicU32, Ordering},
+		Arc,
+	};
+	use tracing::{
+		field::{Field, Visit},
+		span::{Attributes, Id},
+		Event, Subscriber,
+	};
+	use tracing_subscriber::{
+		layer::{Context, SubscriberExt},
+		registry::LookupSpan,
+		Layer,
+	};
+
+	/// Collects the `name` of the task span around every event.
+	struct TaskNames(Arc<Mutex<Vec<String>>>);
+	struct TaskName(String);
+	impl Visit for TaskName {
+		fn record_str(&mut self, field: &Field, value: &str) {
+			if field.name() == "name" {
+				self.0 = value.to_string();
+			}
+		}
+		fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+	}
+	impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for TaskNames {
+		fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+			let mut name = TaskName(String::new());
+			attrs.record(&mut name);
+			if let Some(span) = ctx.span(id) {
+				span.extensions_mut().insert(name);
+			}
+		}
+		fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+			for span in ctx.event_scope(event).into_iter().flatten() {
+				if let Some(name) = span.extensions().get::<TaskName>() {
+					if span.name() == "task" {
+						self.0.lock().push(name.0.clone());
+					}
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn tasks_run_inside_a_span_named_after_them() {
+		let names = Arc::new(Mutex::new(Vec::new()));
+		let subscriber = tracing_subscriber::registry().with(TaskNames(names.clone()));
+		// Spawned tasks run on the thread the subscriber is set on
+		let runtime = tokio::runtime::Builder::new_current_thread()
+			.build()
+			.unwrap();
+		tracing::subscriber::with_default(subscriber, || {
+			runtime.block_on(async {
+				let task = new(|| "named task", async { tracing::info!("inside the task") });
+				task.unwrap().await.unwrap();
+			});
+			tracing::info!("outside any task");
+		});
+		assert_eq!(*names.lock(), ["named task"]);
+	}
+
+	#[test]
+	fn supervised_tasks_restart_after_panicking() {
+		let runtime = tokio::runtime::Builder::new_multi_thread()
+			.enable_time()
+			.build()
+			.unwrap();
+		let runs = Arc::new(AtomicU32::new(0));
+		let supervisor = runtime
+			.block_on(async {
+				new_supervised(|| "flaky", {
+					let runs = runs.clone();
+					move || {
+						let runs = runs.clone();
+						async move {
+							if runs.fetch_add(1, Ordering::Relaxed) == 0 {
+								panic!("first run fails");
+							}
+						}
+					}
+				})
+			})
+			.unwrap();
+		runtime.block_on(supervisor).unwrap();
+		assert_eq!(runs.load(Ordering::Relaxed), 2);
+	}
+}
@@ -0,0 +1,109 @@
+use super::{
+	client::{ClientId, CLIENTS},
+	relay::QueueDepth,
+};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::{
+	sync::atomic::{AtomicU64, Ordering},
+	time::{Duration, Instant},
+};
+
+/// How often every client's traffic gets logged
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(10);
+
+static LAST_SUMMARY: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Signals and method calls between a client and the server, in message payload bytes since the
+/// framing is up to the messenger. Method return values count as sent.
+#[derive(Debug, Default)]
+pub struct TrafficCounters {
+	messages_received: AtomicU64,
+	bytes_received: AtomicU64,
+	messages_sent: AtomicU64,
+	bytes_sent: AtomicU64,
+	/// Totals at the last summary, to tell how much happened since
+	last_summary: Mutex<TrafficStats>,
+}
+impl TrafficCounters {
+	pub fn count_received(&self, bytes: usize) {
+		self.messages_received.fetch_add(1, Ordering::Relaxed);
+		self.bytes_received
+			.fetch_add(bytes as u64, Ordering::Relaxed);
+	}
+	pub fn count_sent(&self, bytes: usize) {
+		self.messages_sent.fetch_add(1, Ordering::Relaxed);
+		self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+	}
+
+	pub fn totals(&self) -> TrafficStats {
+		TrafficStats {
+			messages_received: self.messages_received.load(Ordering::Relaxed),
+			bytes_received: self.bytes_received.load(Ordering::Relaxed),
+			messages_sent: self.messages_sent.load(Ordering::Relaxed),
+			bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+		}
+	}
+	/// What changed since the last time this was called.
+	fn take_since_last_summary(&self) -> TrafficStats {
+		let totals = self.totals();
+		let last = std::mem::replace(&mut *self.last_summary.lock(), totals);
+		TrafficStats {
+			messages_received: totals.messages_received - last.messages_received,
+			bytes_received: totals.bytes_received - last.bytes_received,
+			messages_sent: totals.messages_sent - last.messages_sent,
+			bytes_sent: totals.bytes_sent - last.bytes_sent,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct TrafficStats {
+	pub messages_received: u64,
+	pub bytes_received: u64,
+	pub messages_sent: u64,
+	pub bytes_sent: u64,
+}
+
+/// Totals and how far behind on reading each connected client is, the internal client isn't
+/// included.
+pub fn all_totals() -> Vec<(ClientId, TrafficStats, QueueDepth)> {
+	CLIENTS
+		.get_vec()
+		.into_iter()
+		.filter(|client| client.message_sender_handle.is_some())
+		.map(|client| {
+			(
+				client.id,
+				client.traffic.totals(),
+				client.outgoing_queue.depth(),
+			)
+		})
+		.collect()
+}
+
+/// Call every frame, every so often it logs the rate of traffic of each client that had any.
+pub fn log_summaries() {
+	let mut last_summary = LAST_SUMMARY.lock();
+	let Some(elapsed) = last_summary.map(|last_summary| last_summary.elapsed()) else {
+		// Nothing to compare the first summary against
+		*last_summary = Some(Instant::now());
+		return;
+	};
+	if elapsed < SUMMARY_INTERVAL {
+		return;
+	}
+	*last_summary = Some(Instant::now());
+	drop(last_summary);
+
+	for client in CLIENTS.get_vec() {
+		if client.message_sender_handle.is_none() {
+			continue;
+		}
+		let since = client.traffic.take_since_last_summary();
+		if since.messages_received == 0 && since.messages_sent == 0 {
+			continue;
+		}
+		client.log_traffic(since, elapsed.as_secs_f64());
+	}
+}
@@ -4,22 +4,31 @@ mod objects;
 #[cfg(feature = "wayland")]
 mod wayland;
 
+use crate::core::client;
 use crate::core::destroy_queue;
+use crate::core::frame_stats;
+use crate::core::latency;
+use crate::core::recording;
+use crate::core::relay;
+use crate::core::resource_cache;
+use crate::core::traffic;
 use crate::nodes::{audio, drawable, hmd, input};
 use crate::objects::input::mouse_pointer::MousePointer;
 use crate::objects::input::sk_controller::SkController;
 use crate::objects::input::sk_hand::SkHand;
 
-use self::core::eventloop::EventLoop;
+use self::core::eventloop::{AcceptLimits, EventLoop, Listener, SocketPermissions, Transport};
+use self::core::quota::{Quotas, QUOTAS};
 use clap::Parser;
 use color_eyre::eyre::Result;
 use directories::ProjectDirs;
 use once_cell::sync::OnceCell;
 use stardust_xr::server;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::process::Command;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use stereokit::DisplayBlend;
 use stereokit::{
 	named_colors::BLACK, DepthMode, DisplayMode, Handed, LogLevel, StereoKitMultiThread,
@@ -47,6 +56,140 @@ struct CliArgs {
 	/// Run a script when ready for clients to connect. If this is not set the script at $HOME/.config/stardust/startup will be ran if it exists.
 	#[clap(id = "PATH", short = 'e', long = "execute-startup-script", action)]
 	startup_script: Option<PathBuf>,
+
+	/// Number of virtual displays Wayland clients can be put on
+	#[clap(long, default_value_t = 1)]
+	wayland_outputs: u32,
+
+	/// Tint the parts of Wayland surfaces each commit damaged, to spot clients that redraw more than they need to
+	#[clap(long, action)]
+	debug_damage: bool,
+
+	/// Compiled StereoKit shader (.sks) to draw Wayland panels with instead of the built-in one
+	#[clap(id = "SHADER", long = "panel-shader", action)]
+	panel_shader: Option<PathBuf>,
+
+	/// XKB layout Wayland keyboards start out with, e.g. "us" or "de". Defaults to $XKB_DEFAULT_LAYOUT
+	#[clap(long, default_value = "")]
+	keyboard_layout: String,
+
+	/// XKB variant of the keyboard layout, e.g. "dvorak"
+	#[clap(long, default_value = "")]
+	keyboard_variant: String,
+
+	/// XKB options for the keyboard layout, e.g. "caps:escape"
+	#[clap(long)]
+	keyboard_options: Option<String>,
+
+	/// Keys per second a held key repeats at, 0 turns repeating off
+	#[clap(long, default_value_t = 25)]
+	key_repeat_rate: i32,
+
+	/// Milliseconds a key has to be held before it starts repeating
+	#[clap(long, default_value_t = 600)]
+	key_repeat_delay: i32,
+
+	/// Also accept clients over TCP on this address, alongside the Unix socket. There's no authentication or encryption, so only use this on trusted networks.
+	#[clap(id = "ADDRESS", long = "tcp", action)]
+	tcp_address: Option<SocketAddr>,
+
+	/// Close new connections past this many per second
+	#[clap(long, default_value_t = AcceptLimits::default().max_accepts_per_second)]
+	max_accept_rate: u32,
+
+	/// Close new connections while this many clients are connected, slots free up as clients disconnect. Unlimited by default
+	#[clap(long, alias = "max-clients")]
+	max_connections: Option<usize>,
+
+	/// Most nodes a single client can create, unlimited by default
+	#[clap(long)]
+	max_client_nodes: Option<usize>,
+
+	/// Most models a single client can load, unlimited by default
+	#[clap(long)]
+	max_client_models: Option<usize>,
+
+	/// Most texture memory in bytes a single client can use, unlimited by default
+	#[clap(long)]
+	max_client_texture_bytes: Option<usize>,
+
+	/// Largest message in bytes a client can send before it gets disconnected, 0 means unlimited
+	#[clap(long, default_value_t = 16 * 1024 * 1024)]
+	max_message_size: usize,
+
+	/// Most bytes of messages can wait for a client to read them before it gets disconnected, 0 means unlimited
+	#[clap(long, default_value_t = 64 * 1024 * 1024)]
+	max_outgoing_queue: usize,
+
+	/// Seconds between keepalive pings to each client, clients that don't answer before the next one are disconnected. Off (0) by default
+	#[clap(long, default_value_t = 0)]
+	keepalive_interval: u64,
+
+	/// Measure how long every signal and method handler takes, for the root's get_handler_latency
+	#[clap(long, action)]
+	measure_latency: bool,
+
+	/// Write everything each client sends to its own file in this directory, to replay with --replay
+	#[clap(id = "DIR", long = "record-clients", action)]
+	record_dir: Option<PathBuf>,
+
+	/// Once ready, connect a client that sends what's in this recording with the same timing
+	#[clap(id = "RECORDING", long = "replay", action)]
+	replay: Option<PathBuf>,
+
+	/// MSAA samples of the display, one of 1 (off), 2, 4 or 8. Defaults to what StereoKit picks
+	#[clap(long, value_parser = parse_multisample)]
+	msaa: Option<u32>,
+
+	/// Most frames drawn per second, unlimited by default. XR runtimes pace frames themselves, so this only helps below the headset's refresh rate
+	#[clap(long, value_parser = clap::value_parser!(u32).range(1..=1000))]
+	target_framerate: Option<u32>,
+
+	/// Most models and most textures kept loaded for reuse, the least recently used go first
+	#[clap(long, default_value_t = 64)]
+	resource_cache_size: usize,
+
+	/// Most GPU resources freed per frame, larger teardowns get spread over several frames
+	#[clap(long, default_value_t = 64)]
+	destroy_budget: usize,
+
+	/// Octal file mode to set on the Stardust socket, e.g. 660
+	#[clap(long, value_parser = parse_octal)]
+	socket_mode: Option<u32>,
+
+	/// Group (name or ID) to own the Stardust socket
+	#[clap(long)]
+	socket_group: Option<String>,
+
+	/// Also listen on an abstract namespace socket with this name (Linux only)
+	#[clap(id = "NAME", long = "abstract-socket", action)]
+	abstract_socket: Option<String>,
+
+	/// Check for the protocol version handshake on these listeners, clients that don't send one wait up to half a second before they're accepted
+	#[clap(id = "LISTENER", long = "handshake", value_enum, value_delimiter = ',')]
+	handshake: Vec<ListenerKind>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ListenerKind {
+	/// The Stardust socket
+	Unix,
+	/// The --abstract-socket one
+	Abstract,
+	/// The --tcp one
+	Tcp,
+}
+
+fn parse_octal(mode: &str) -> Result<u32, std::num::ParseIntError> {
+	u32::from_str_radix(mode, 8)
+}
+
+fn parse_multisample(samples: &str) -> Result<u32, String> {
+	let samples = samples.parse().map_err(|e| format!("{e}"))?;
+	if !drawable::MULTISAMPLE_LEVELS.contains(&samples) {
+		return Err(format!("must be one of {:?}", drawable::MULTISAMPLE_LEVELS));
+	}
+	Ok(samples)
 }
 
 static STARDUST_INSTANCE: OnceCell<String> = OnceCell::new();
@@ -82,6 +225,22 @@ fn main() -> Result<()> {
 		error!("Unable to get Stardust project directories, default skybox and startup script will not work.");
 	}
 	let cli_args = Arc::new(CliArgs::parse());
+	destroy_queue::set_budget(cli_args.destroy_budget);
+	resource_cache::set_max_entries(cli_args.resource_cache_size);
+	client::set_keepalive_interval(cli_args.keepalive_interval);
+	relay::set_max_message_size(cli_args.max_message_size);
+	relay::set_max_outgoing_queue(cli_args.max_outgoing_queue);
+	latency::set_enabled(cli_args.measure_latency);
+	recording::set_recording_dir(cli_args.record_dir.clone());
+	#[cfg(feature = "wayland")]
+	wayland::set_debug_damage(cli_args.debug_damage);
+	#[cfg(feature = "wayland")]
+	wayland::set_panel_shader(cli_args.panel_shader.clone());
+	let _ = QUOTAS.set(Quotas {
+		max_nodes: cli_args.max_client_nodes,
+		max_models: cli_args.max_client_models,
+		max_texture_bytes: cli_args.max_client_texture_bytes,
+	});
 
 	let sk = stereokit::Settings {
 		app_name: "Stardust XR".to_string(),
@@ -101,6 +260,18 @@ fn main() -> Result<()> {
 	.init()
 	.expect("StereoKit failed to initialize");
 	info!("Init StereoKit");
+	let _ = drawable::model::MODEL_LOADER.set(sk.multithreaded());
+	if let Some(samples) = cli_args.msaa {
+		sk.render_set_multisample(samples as i32);
+	}
+	let frame_period = cli_args
+		.target_framerate
+		.map(|framerate| Duration::from_secs(1) / framerate);
+	info!(
+		msaa = sk.render_get_multisample(),
+		target_framerate = cli_args.target_framerate,
+		"Render settings"
+	);
 
 	sk.material_set_shader(
 		sk.material_find("default/material_pbr")?,
@@ -157,12 +328,24 @@ fn main() -> Result<()> {
 	let (info_sender, info_receiver) = oneshot::channel::<EventLoopInfo>();
 	let event_thread = std::thread::Builder::new()
 		.name("event_loop".to_owned())
-		.spawn(move || event_loop(info_sender, event_stop_rx))?;
+		.spawn({
+			let cli_args = cli_args.clone();
+			move || event_loop(info_sender, event_stop_rx, cli_args)
+		})?;
 	let event_loop_info = info_receiver.blocking_recv()?;
 	let _tokio_handle = event_loop_info.tokio_handle.enter();
 
 	#[cfg(feature = "wayland")]
-	let mut wayland = wayland::Wayland::new()?;
+	let mut wayland = wayland::Wayland::new(
+		cli_args.wayland_outputs,
+		wayland::KeyboardConfig {
+			layout: cli_args.keyboard_layout.clone(),
+			variant: cli_args.keyboard_variant.clone(),
+			options: cli_args.keyboard_options.clone(),
+			repeat_rate: cli_args.key_repeat_rate,
+			repeat_delay: cli_args.key_repeat_delay,
+		},
+	)?;
 	info!("Stardust ready!");
 
 	if let Some(project_dirs) = project_dirs.as_ref() {
@@ -190,6 +373,7 @@ fn main() -> Result<()> {
 
 	let mut last_frame_delta = Duration::ZERO;
 	let mut sleep_duration = Duration::ZERO;
+	let mut next_frame = Instant::now();
 	debug_span!("StereoKit").in_scope(|| {
 		sk.run(
 			|sk| {
@@ -199,7 +383,7 @@ fn main() -> Result<()> {
 				hmd::frame(sk);
 				#[cfg(feature = "wayland")]
 				wayland.frame_event(sk);
-				destroy_queue::clear();
+				destroy_queue::process();
 
 				if let Some(mouse_pointer) = &mouse_pointer {
 					mouse_pointer.update(sk);
@@ -213,7 +397,12 @@ fn main() -> Result<()> {
 					right_controller.update(sk);
 				}
 				input::process_input();
-				nodes::root::Root::send_frame_events(sk.time_elapsed_unscaled());
+				let delta = sk.time_elapsed_unscaled();
+				nodes::spatial::Spatial::update_smoothed_transforms(delta as f32);
+				nodes::spatial::Spatial::update_billboards(sk.input_head().position);
+				nodes::spatial::Spatial::update_velocities(delta as f32);
+				nodes::root::Root::send_frame_events(delta);
+				nodes::spatial::Spatial::send_transform_change_events();
 				{
 					let frame_delta = Duration::from_secs_f64(sk.time_elapsed_unscaled());
 					if last_frame_delta < frame_delta {
@@ -237,12 +426,23 @@ fn main() -> Result<()> {
 				#[cfg(feature = "wayland")]
 				wayland.update(sk);
 				drawable::draw(sk);
+				frame_stats::end_frame(delta);
+				traffic::log_summaries();
 				audio::update(sk);
 				#[cfg(feature = "wayland")]
 				wayland.make_context_current();
+
+				if let Some(frame_period) = frame_period {
+					// Falling behind doesn't make the next frames come faster to catch up
+					next_frame = (next_frame + frame_period).max(Instant::now());
+					std::thread::sleep(next_frame.saturating_duration_since(Instant::now()));
+				}
 			},
 			|_| {
-				info!("Cleanly shut down StereoKit");
+				// Everything has to be freed while StereoKit's context is still around
+				let pending_destroys = destroy_queue::len();
+				destroy_queue::flush();
+				info!(pending_destroys, "Cleanly shut down StereoKit");
 			},
 		)
 	});
@@ -263,11 +463,40 @@ fn main() -> Result<()> {
 async fn event_loop(
 	info_sender: oneshot::Sender<EventLoopInfo>,
 	stop_rx: oneshot::Receiver<()>,
+	cli_args: Arc<CliArgs>,
 ) -> color_eyre::eyre::Result<()> {
 	let socket_path =
 		server::get_free_socket_path().expect("Unable to find a free stardust socket path");
 	STARDUST_INSTANCE.set(socket_path.file_name().unwrap().to_string_lossy().into_owned()).expect("Someone hasn't done their job, yell at Nova because how is this set multiple times what the hell");
-	let _event_loop = EventLoop::new(socket_path.clone()).expect("Couldn't create server socket");
+	let unix_transport = Transport::Unix {
+		path: socket_path.clone(),
+		permissions: SocketPermissions {
+			mode: cli_args.socket_mode,
+			group: cli_args.socket_group.clone(),
+		},
+	};
+	let listener = |kind, transport| Listener {
+		transport,
+		handshake: cli_args.handshake.contains(&kind),
+	};
+	let listeners = std::iter::once(listener(ListenerKind::Unix, unix_transport))
+		.chain(
+			cli_args
+				.abstract_socket
+				.clone()
+				.map(|name| listener(ListenerKind::Abstract, Transport::UnixAbstract(name))),
+		)
+		.chain(
+			cli_args
+				.tcp_address
+				.map(|address| listener(ListenerKind::Tcp, Transport::Tcp(address))),
+		);
+	let accept_limits = AcceptLimits {
+		max_accepts_per_second: cli_args.max_accept_rate,
+		max_connections: cli_args.max_connections,
+	};
+	let event_loop =
+		EventLoop::new(listeners, accept_limits).expect("Couldn't create server socket");
 	info!("Init event loop");
 	info!(
 		socket_path = ?socket_path.display(),
@@ -277,12 +506,18 @@ async fn event_loop(
 		tokio_handle: Handle::current(),
 		socket_path,
 	});
+	if let Some(replay) = &cli_args.replay {
+		if let Err(e) = recording::replay(replay) {
+			error!(?e, "Unable to replay client recording");
+		}
+	}
 
 	let result = tokio::select! {
 		biased;
 		_ = tokio::signal::ctrl_c() => Ok(()),
 		_ = stop_rx => Ok(()),
 	};
+	event_loop.shutdown(Duration::from_secs(1)).await;
 
 	info!("Cleanly shut down event loop");
 
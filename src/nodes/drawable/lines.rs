@@ -1,12 +1,12 @@
 use crate::{
-	core::{client::Client, registry::Registry},
+	core::{client::Client, frame_stats, registry::Registry},
 	nodes::{
 		spatial::{find_spatial_parent, parse_transform, Spatial},
 		Node,
 	},
 };
 use color_eyre::eyre::{bail, ensure, Result};
-use glam::Vec3A;
+use glam::{Mat4, Vec3A};
 use mint::Vector3;
 use parking_lot::Mutex;
 use portable_atomic::{AtomicBool, Ordering};
@@ -57,8 +57,8 @@ impl Lines {
 		Ok(lines)
 	}
 
-	fn draw(&self, draw_ctx: &impl StereoKitDraw) {
-		let transform_mat = self.space.global_transform();
+	/// At `transform_mat` rather than its own, so references can draw it elsewhere.
+	pub(super) fn draw(&self, draw_ctx: &impl StereoKitDraw, transform_mat: Mat4) {
 		let data = self.data.lock().clone();
 		let mut points: VecDeque<SkLinePoint> = data
 			.points
@@ -81,10 +81,11 @@ impl Lines {
 				color: Color128::from([color.red(), color.green(), color.blue(), color.alpha()])
 					.into(),
 			};
-			points.push_front(connect_point.clone());
+			points.push_front(connect_point);
 			points.push_back(connect_point);
 		}
 		draw_ctx.line_add_listv(points.make_contiguous());
+		frame_stats::count_draw_call();
 	}
 
 	pub fn set_points_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
@@ -115,7 +116,7 @@ impl Drop for Lines {
 pub fn draw_all(draw_ctx: &impl StereoKitDraw) {
 	for lines in LINES_REGISTRY.get_valid_contents() {
 		if lines.enabled.load(Ordering::Relaxed) {
-			lines.draw(draw_ctx);
+			lines.draw(draw_ctx, lines.space.global_transform());
 		}
 	}
 }
@@ -0,0 +1,216 @@
+use super::{
+	model::{MaterialParameter, Model},
+	Drawable,
+};
+use crate::{
+	core::{
+		client::Client,
+		destroy_queue,
+		quota::{QuotaKind, QuotaReservation},
+		registry::Registry,
+		resource::ResourceID,
+	},
+	nodes::Node,
+};
+use color_eyre::eyre::{bail, ensure, eyre, Result};
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use rustc_hash::FxHashMap;
+use send_wrapper::SendWrapper;
+use serde::Deserialize;
+use stardust_xr::schemas::flex::deserialize;
+use std::{
+	ffi::OsStr,
+	path::PathBuf,
+	sync::{Arc, Weak},
+};
+use stereokit::{Material, StereoKitDraw};
+use tracing::{error, warn};
+
+static MATERIAL_REGISTRY: Registry<StandaloneMaterial> = Registry::new();
+
+/// A material built from a shader instead of coming out of a model file, which models can swap
+/// in for one of their own. Parameter changes stay live on every model it's applied to.
+pub struct StandaloneMaterial {
+	client: Weak<Client>,
+	/// `None` uses StereoKit's built-in PBR shader
+	shader_path: Option<PathBuf>,
+	/// `None` inside if creating it failed, so it isn't retried every frame
+	material: OnceCell<Option<Arc<SendWrapper<Material>>>>,
+	pending_parameters: Mutex<Vec<(String, MaterialParameter)>>,
+	pending_applications: Mutex<Vec<(Weak<Model>, u32)>>,
+	texture_reservations: Mutex<FxHashMap<String, QuotaReservation>>,
+}
+impl StandaloneMaterial {
+	fn add_to(
+		node: &Arc<Node>,
+		shader_path: Option<PathBuf>,
+		parameters: Vec<(String, MaterialParameter)>,
+	) -> Result<Arc<StandaloneMaterial>> {
+		ensure!(
+			node.drawable.get().is_none(),
+			"Internal: Node already has a drawable attached!"
+		);
+		let material = MATERIAL_REGISTRY.add(StandaloneMaterial {
+			client: node.client.clone(),
+			shader_path,
+			material: OnceCell::new(),
+			pending_parameters: Mutex::new(Vec::new()),
+			pending_applications: Mutex::new(Vec::new()),
+			texture_reservations: Mutex::new(FxHashMap::default()),
+		});
+		for (name, value) in parameters {
+			material.set_parameter(name, value)?;
+		}
+		node.add_local_signal("set_parameter", StandaloneMaterial::set_parameter_flex);
+		node.add_local_signal("apply_to_model", StandaloneMaterial::apply_to_model_flex);
+		let _ = node.drawable.set(Drawable::Material(material.clone()));
+		Ok(material)
+	}
+
+	fn set_parameter(self: &Arc<Self>, name: String, value: MaterialParameter) -> Result<()> {
+		value.validate()?;
+		let client = self
+			.client
+			.upgrade()
+			.ok_or_else(|| eyre!("Client not found"))?;
+		if value.downloading(&client) {
+			// Same as models, going over the quota can only be logged once the texture is there
+			let material = Arc::downgrade(self);
+			return value.after_downloads(&client, move |value| {
+				let Some(material) = material.upgrade() else {return};
+				let Some(client) = material.client.upgrade() else {return};
+				if let Err(e) = material.queue_parameter(&client, name, value) {
+					warn!("Unable to set downloaded texture: {e}");
+				}
+			});
+		}
+		self.queue_parameter(&client, name, value)
+	}
+	fn queue_parameter(
+		&self,
+		client: &Arc<Client>,
+		name: String,
+		value: MaterialParameter,
+	) -> Result<()> {
+		let mut texture_reservations = self.texture_reservations.lock();
+		texture_reservations.remove(&name);
+		let texture_bytes = value.texture_bytes(client);
+		if texture_bytes > 0 {
+			let reservation =
+				QuotaReservation::reserve(client, QuotaKind::TextureBytes, texture_bytes)?;
+			texture_reservations.insert(name.clone(), reservation);
+		}
+		self.pending_parameters.lock().push((name, value));
+		Ok(())
+	}
+
+	fn create(&self, sk: &impl StereoKitDraw) -> Result<Material> {
+		match &self.shader_path {
+			Some(shader_path) => Ok(sk.material_create(&sk.shader_create_file(shader_path)?)),
+			None => Ok(sk.material_copy(sk.material_find("default/material_pbr")?)),
+		}
+	}
+
+	fn update(&self, sk: &impl StereoKitDraw) {
+		let Some(client) = self.client.upgrade() else {return};
+		let material = self.material.get_or_init(|| match self.create(sk) {
+			Ok(material) => Some(Arc::new(SendWrapper::new(material))),
+			Err(e) => {
+				error!(shader = ?self.shader_path, "Unable to create material: {e}");
+				None
+			}
+		});
+		let Some(material) = material else {return};
+		for (name, value) in self.pending_parameters.lock().drain(..) {
+			value.apply_to_material(&client, sk, material.as_ref().as_ref(), &name);
+		}
+		for (model, material_idx) in self.pending_applications.lock().drain(..) {
+			let Some(model) = model.upgrade() else {continue};
+			model
+				.pending_material_replacements
+				.lock()
+				.insert(material_idx, material.clone());
+		}
+	}
+
+	fn set_parameter_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(Drawable::Material(material)) = node.drawable.get() else {bail!("Not a material")};
+		#[derive(Deserialize)]
+		struct MaterialParameterInfo {
+			name: String,
+			value: MaterialParameter,
+		}
+		let info: MaterialParameterInfo = deserialize(data)?;
+		material.set_parameter(info.name, info.value)
+	}
+
+	fn apply_to_model_flex(node: &Node, calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(Drawable::Material(material)) = node.drawable.get() else {bail!("Not a material")};
+		#[derive(Deserialize)]
+		struct ApplyInfo<'a> {
+			model_path: &'a str,
+			idx: u32,
+		}
+		let info: ApplyInfo = deserialize(data)?;
+		let model_node = calling_client.get_node("Model", info.model_path)?;
+		let Some(Drawable::Model(model)) = model_node.drawable.get() else {bail!("Node is not a model")};
+		material
+			.pending_applications
+			.lock()
+			.push((Arc::downgrade(model), info.idx));
+		Ok(())
+	}
+}
+impl Drop for StandaloneMaterial {
+	fn drop(&mut self) {
+		// Models it was applied to keep their own reference through StereoKit
+		if let Some(Some(material)) = self.material.take() {
+			destroy_queue::add(material);
+		}
+		MATERIAL_REGISTRY.remove(self);
+	}
+}
+
+/// Has to run before models draw so replacements are ready for them.
+pub fn update_all(sk: &impl StereoKitDraw) {
+	for material in MATERIAL_REGISTRY.get_valid_contents() {
+		material.update(sk);
+	}
+}
+
+pub fn create_flex(_node: &Node, calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+	#[derive(Deserialize)]
+	struct CreateMaterialInfo<'a> {
+		name: &'a str,
+		/// Defaults to StereoKit's PBR shader
+		#[serde(default)]
+		shader: Option<ResourceID>,
+		#[serde(default)]
+		parameters: Vec<(String, MaterialParameter)>,
+	}
+	let info: CreateMaterialInfo = deserialize(data)?;
+	for (_, value) in &info.parameters {
+		value.validate()?;
+	}
+	let shader_path = info
+		.shader
+		.map(|shader| {
+			shader
+				.get_file(
+					&calling_client.base_resource_prefixes.lock().clone(),
+					&[OsStr::new("sks")],
+				)
+				.ok_or_else(|| eyre!("Shader resource not found"))
+		})
+		.transpose()?;
+	let node = Node::create(&calling_client, "/drawable/material", info.name, true);
+	let node = node.add_to_scenegraph()?;
+	// Texture quotas are only checked once the parameters are set, which shouldn't leave a node
+	// behind without a material
+	if let Err(e) = StandaloneMaterial::add_to(&node, shader_path, info.parameters) {
+		node.destroy();
+		return Err(e);
+	}
+	Ok(())
+}
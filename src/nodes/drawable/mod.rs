@@ -1,25 +1,45 @@
 pub mod lines;
+pub mod material;
 pub mod model;
+pub mod reference;
+pub mod render_target;
+pub mod screenshot;
 pub mod text;
 
-use self::{lines::Lines, model::Model, text::Text};
+use self::{
+	lines::Lines,
+	material::StandaloneMaterial,
+	model::{CubemapSource, MaterialColor, MaterialParameter, Model},
+	reference::DrawableReference,
+	render_target::RenderTarget,
+	text::Text,
+};
 
 use super::Node;
 use crate::core::client::Client;
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{ensure, Result};
 use parking_lot::Mutex;
 use serde::Deserialize;
 use stardust_xr::schemas::flex::deserialize;
 use std::{path::PathBuf, sync::Arc};
 use stereokit::StereoKitDraw;
-use tracing::instrument;
+use tracing::{info, instrument};
+
+/// StereoKit's default near and far clip planes, the server never changes them.
+const CLIP_PLANES: (f32, f32) = (0.02, 50.0);
 
 pub fn create_interface(client: &Arc<Client>) -> Result<()> {
 	let node = Node::create(client, "", "drawable", false);
 	node.add_local_signal("create_lines", lines::create_flex);
+	node.add_local_signal("create_material", material::create_flex);
 	node.add_local_signal("create_model", model::create_flex);
 	node.add_local_signal("create_text", text::create_flex);
+	node.add_local_signal("create_render_target", render_target::create_flex);
+	node.add_local_signal("create_reference", reference::create_flex);
+	node.add_local_signal("capture_screenshot", screenshot::capture_screenshot_flex);
 	node.add_local_signal("set_sky_file", set_sky_file_flex);
+	node.add_local_signal("set_environment", set_environment_flex);
+	node.add_local_signal("set_multisample", set_multisample_flex);
 	node.add_to_scenegraph().map(|_| ())
 }
 
@@ -27,13 +47,30 @@ pub enum Drawable {
 	Lines(Arc<Lines>),
 	Model(Arc<Model>),
 	Text(Arc<Text>),
+	RenderTarget(Arc<RenderTarget>),
+	Material(Arc<StandaloneMaterial>),
+	/// Only held so the reference stays registered for as long as its node
+	#[allow(dead_code)]
+	Reference(Arc<DrawableReference>),
 }
 
 #[instrument(level = "debug", skip(sk))]
 pub fn draw(sk: &impl StereoKitDraw) {
+	let screenshots = screenshot::take_pending();
+	// Models are culled to every view that sees this frame, not just the head's
+	let mut other_views = render_target::view_frustums();
+	other_views.extend(
+		screenshots
+			.iter()
+			.map(|screenshot| screenshot.view_frustum(sk)),
+	);
+	render_target::draw_all(sk);
 	lines::draw_all(sk);
-	model::draw_all(sk);
+	material::update_all(sk);
+	model::draw_all(sk, other_views);
 	text::draw_all(sk);
+	reference::draw_all(sk);
+	screenshot::capture(sk, screenshots);
 
 	if let Some(skytex) = QUEUED_SKYTEX.lock().take() {
 		if let Ok((_skylight, skytex)) = sk.tex_create_cubemap_file(&skytex, true, i32::MAX) {
@@ -45,10 +82,44 @@ pub fn draw(sk: &impl StereoKitDraw) {
 			sk.render_set_skylight(skylight);
 		}
 	}
+	if let Some(samples) = QUEUED_MULTISAMPLE.lock().take() {
+		sk.render_set_multisample(samples as i32);
+		info!(samples = sk.render_get_multisample(), "MSAA changed");
+	}
+	if let Some((client, environment)) = QUEUED_ENVIRONMENT.lock().take() {
+		if let Some(clear_color) = environment.clear_color {
+			sk.render_set_clear_color(clear_color.to_linear());
+		}
+		if let Some(skybox) = &environment.skybox {
+			if let Some(skytex) = MaterialParameter::load_cubemap(&client, sk, skybox) {
+				sk.render_set_skytex(&skytex);
+			}
+		}
+		if let Some(show_skybox) = environment.show_skybox {
+			sk.render_enable_skytex(show_skybox);
+		}
+	}
 }
 
 static QUEUED_SKYLIGHT: Mutex<Option<PathBuf>> = Mutex::new(None);
 static QUEUED_SKYTEX: Mutex<Option<PathBuf>> = Mutex::new(None);
+static QUEUED_MULTISAMPLE: Mutex<Option<u32>> = Mutex::new(None);
+/// The client is kept so the skybox resolves against its resource prefixes
+static QUEUED_ENVIRONMENT: Mutex<Option<(Arc<Client>, EnvironmentInfo)>> = Mutex::new(None);
+
+/// MSAA sample counts StereoKit can render the display with, 1 is off.
+pub const MULTISAMPLE_LEVELS: &[u32] = &[1, 2, 4, 8];
+
+#[derive(Deserialize)]
+struct EnvironmentInfo {
+	#[serde(default)]
+	clear_color: Option<MaterialColor>,
+	#[serde(default)]
+	skybox: Option<CubemapSource>,
+	/// Turn off for a plain clear color, like black for passthrough
+	#[serde(default)]
+	show_skybox: Option<bool>,
+}
 
 fn set_sky_file_flex(_node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
 	#[derive(Deserialize)]
@@ -68,3 +139,30 @@ fn set_sky_file_flex(_node: &Node, _calling_client: Arc<Client>, data: &[u8]) ->
 
 	Ok(())
 }
+
+fn set_environment_flex(_node: &Node, calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+	let info: EnvironmentInfo = deserialize(data)?;
+	if let Some(clear_color) = info.clear_color {
+		let (MaterialColor::Rgba(rgba) | MaterialColor::WithColorSpace(rgba, _)) = clear_color;
+		ensure!(
+			rgba.iter().all(|c| c.is_finite()),
+			"Clear color must be finite"
+		);
+	}
+	if let Some(skybox) = &info.skybox {
+		skybox.validate(&calling_client)?;
+	}
+	QUEUED_ENVIRONMENT.lock().replace((calling_client, info));
+	Ok(())
+}
+
+/// Changes the MSAA sample count of everything drawn, for trading quality against speed.
+fn set_multisample_flex(_node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+	let samples: u32 = deserialize(data)?;
+	ensure!(
+		MULTISAMPLE_LEVELS.contains(&samples),
+		"MSAA sample count must be one of {MULTISAMPLE_LEVELS:?}"
+	);
+	QUEUED_MULTISAMPLE.lock().replace(samples);
+	Ok(())
+}
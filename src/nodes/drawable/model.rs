@@ -1,40 +1,273 @@
 use super::Node;
 use crate::core::client::Client;
 use crate::core::destroy_queue;
+use crate::core::frame_stats;
+use crate::core::quota::{QuotaKind, QuotaReservation};
 use crate::core::registry::Registry;
-use crate::core::resource::ResourceID;
-use crate::nodes::drawable::Drawable;
-use crate::nodes::spatial::{find_spatial_parent, parse_transform, Spatial};
+use crate::core::resource::{ResourceID, ResourceState};
+use crate::core::resource_cache::ResourceCache;
+use crate::core::task;
+use crate::nodes::drawable::render_target::find_render_target;
+use crate::nodes::drawable::{Drawable, CLIP_PLANES};
+use crate::nodes::spatial::{find_reference_space, find_spatial_parent, parse_transform, Spatial};
 use color_eyre::eyre::{bail, ensure, eyre, Result};
-use glam::Mat4;
+use glam::{Mat4, Vec3, Vec4};
+use image::ImageFormat;
+use lazy_static::lazy_static;
 use mint::{ColumnMatrix4, Vector2, Vector3, Vector4};
 use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
-use portable_atomic::{AtomicBool, Ordering};
+use portable_atomic::{AtomicBool, AtomicU32, Ordering};
+use rayon::prelude::*;
 use rustc_hash::FxHashMap;
 use send_wrapper::SendWrapper;
-use serde::Deserialize;
-use stardust_xr::schemas::flex::deserialize;
+use serde::{Deserialize, Serialize};
+use stardust_xr::schemas::flex::{deserialize, serialize};
 use stardust_xr::values::Transform;
-use std::ffi::OsStr;
-use std::fmt::Error;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::collections::hash_map::Entry;
+use std::ffi::{c_char, c_void, CString, OsStr};
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::ptr::NonNull;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 use stereokit::named_colors::WHITE;
 use stereokit::{
-	Color128, Material, Model as SKModel, RenderLayer, Shader, StereoKitDraw, StereoKitMultiThread,
+	Bounds, Color128, CullMode, DepthTest, FovInfo, Material,
+	MaterialParameter as SKMaterialParameter, Model as SKModel, Ray as SKRay, RenderLayer, Shader,
+	Sk, StereoKitDraw, StereoKitMultiThread, Tex, TextureAddress, TextureFormat, TextureSample,
+	TextureType, Transparency,
 };
+use tracing::{error, warn};
 
 static MODEL_REGISTRY: Registry<Model> = Registry::new();
+/// For parsing model files off the render thread, set once StereoKit is up.
+pub static MODEL_LOADER: OnceCell<Sk> = OnceCell::new();
+lazy_static! {
+	/// Base models by resolved path, shared by every model node using the same file.
+	/// Only touched from the render thread.
+	static ref MODEL_CACHE: Mutex<ResourceCache<PathBuf, SKModel>> =
+		Mutex::new(ResourceCache::default());
+	/// Only touched from the render thread, like `MODEL_CACHE`.
+	static ref TEXTURE_CACHE: Mutex<ResourceCache<TextureKey, Tex>> =
+		Mutex::new(ResourceCache::default());
+}
+/// Material index that targets every material in the model
+const ALL_MATERIALS: i32 = -1;
+/// What StereoKit's PBR shader calls its emissive color, multiplied with its emission texture
+const EMISSIVE_PARAMETER: &str = "emission_factor";
+/// What every compiled StereoKit shader file starts with
+const SHADER_MAGIC: &[u8; 8] = b"SKSHADER";
+/// Used when the client doesn't give its own extensions, in order of preference
+const MODEL_EXTENSIONS: &[&str] = &["glb", "gltf", "obj", "ply"];
+/// What StereoKit's model loaders can read, anything else would just show up blank
+const SUPPORTED_MODEL_EXTENSIONS: &[&str] = &["glb", "gltf", "obj", "ply", "stl"];
+const TEXTURE_EXTENSIONS: &[&str] = &["png", "jpg", "ktx2", "webp"];
+const CUBEMAP_EXTENSIONS: &[&str] = &["png", "jpg", "hdr"];
+/// Failed loads in a row before a model stops trying until its file changes
+const MAX_LOAD_ATTEMPTS: u32 = 5;
+/// Wait before the first retry, doubled for each one after
+const LOAD_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+	0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+/// Up to and including the supercompression scheme
+const KTX2_HEADER_LENGTH: usize = 48;
+/// None, BasisLZ and Zstandard, StereoKit's transcoder can't do ZLIB
+const KTX2_SUPPORTED_SUPERCOMPRESSION: &[u32] = &[0, 1, 2];
+
+/// StereoKit gives a blank texture for KTX2 files it can't decode, so they're caught here
+/// with an error that says why.
+fn check_ktx2_header(header: &[u8]) -> Result<()> {
+	ensure!(
+		header.len() >= KTX2_HEADER_LENGTH && header[..12] == KTX2_IDENTIFIER,
+		"Not a KTX2 file"
+	);
+	let supercompression = u32::from_le_bytes(header[44..48].try_into()?);
+	ensure!(
+		KTX2_SUPPORTED_SUPERCOMPRESSION.contains(&supercompression),
+		"KTX2 supercompression scheme {supercompression} is not supported"
+	);
+	Ok(())
+}
+
+/// The client's extensions if it gave any, otherwise the defaults.
+fn extension_list<'a>(custom: Option<&'a [String]>, default: &'a [&str]) -> Vec<&'a OsStr> {
+	match custom {
+		Some(custom) => custom.iter().map(OsStr::new).collect(),
+		None => default.iter().map(OsStr::new).collect(),
+	}
+}
+
+/// stereokit-rs doesn't bind the number of materials (subsets) a model has
+fn material_count(sk_model: &SKModel) -> i32 {
+	unsafe { stereokit::sys::model_subset_count(sk_model.0.as_ptr()) }
+}
+
+/// Hands the replacements for materials the model has to `apply`, the ones for materials that
+/// don't exist yet stay queued for a later frame.
+fn apply_available<R>(
+	replacements: &mut FxHashMap<u32, R>,
+	material_count: i32,
+	mut apply: impl FnMut(i32, &R),
+) {
+	replacements.retain(|material_idx, replacement| {
+		let Ok(material_idx) = i32::try_from(*material_idx) else {return true};
+		if material_idx >= material_count {
+			return true;
+		}
+		apply(material_idx, replacement);
+		false
+	});
+}
+
+/// In the order shaders expect them.
+fn components<T: Copy>(vector: &Vector4<T>) -> [T; 4] {
+	[vector.x, vector.y, vector.z, vector.w]
+}
+
+/// stereokit-rs doesn't bind a model's bounding box
+fn model_bounds(sk_model: &SKModel) -> Bounds {
+	unsafe { stereokit::sys::model_get_bounds(sk_model.0.as_ptr()) }.into()
+}
+
+/// stereokit-rs's binding of this never gives StereoKit anywhere to write the hit to. The hit's
+/// direction is the surface normal.
+fn model_ray_intersect(sk_model: &SKModel, ray: SKRay) -> Option<SKRay> {
+	let mut hit: stereokit::sys::ray_t = SKRay::default().into();
+	let hit_anything = unsafe {
+		stereokit::sys::model_ray_intersect(
+			sk_model.0.as_ptr(),
+			ray.into(),
+			&mut hit,
+			CullMode::Back as stereokit::sys::cull_,
+		)
+	};
+	(hit_anything != 0).then(|| hit.into())
+}
+
+/// Brings a hit in model space back into the space the ray was cast in. Normals go through the
+/// inverse transpose so they stay perpendicular to the surface under non-uniform scale.
+fn raycast_hit(
+	model_to_reference: Mat4,
+	point: Vec3,
+	normal: Vec3,
+	ray_origin: Vec3,
+) -> ModelRaycastHit {
+	let point = model_to_reference.transform_point3(point);
+	let normal = model_to_reference
+		.inverse()
+		.transpose()
+		.transform_vector3(normal)
+		.normalize();
+	ModelRaycastHit {
+		point: point.into(),
+		normal: normal.into(),
+		distance: point.distance(ray_origin),
+	}
+}
+
+/// stereokit-rs doesn't bind uploading pixels, `data` must already match the size and format
+pub(crate) fn tex_set_colors(tex: &Tex, width: usize, height: usize, data: &[u8]) {
+	unsafe {
+		stereokit::sys::tex_set_colors(
+			tex.0.as_ptr(),
+			width as i32,
+			height as i32,
+			data.as_ptr() as *mut c_void,
+		)
+	}
+}
+
+/// stereokit-rs only binds loading a cubemap from a single equirectangular image
+fn tex_create_cubemap_files(face_paths: &[PathBuf], srgb_data: bool, priority: i32) -> Result<Tex> {
+	let faces = face_paths
+		.iter()
+		.map(|path| Ok(CString::new(path.as_os_str().as_bytes())?))
+		.collect::<Result<Vec<_>>>()?;
+	let mut face_ptrs: Vec<*const c_char> = faces.iter().map(|face| face.as_ptr()).collect();
+	let mut spherical_harmonics = unsafe { std::mem::zeroed() };
+	let tex = unsafe {
+		stereokit::sys::tex_create_cubemap_files(
+			face_ptrs.as_mut_ptr(),
+			srgb_data as i32,
+			&mut spherical_harmonics,
+			priority,
+		)
+	};
+	NonNull::new(tex)
+		.map(Tex)
+		.ok_or_else(|| eyre!("StereoKit couldn't load the faces"))
+}
+
+/// Folds resets and new values into `material_parameters`, with `ALL_MATERIALS` expanded to the
+/// materials the model actually has. Materials that need rebuilding from their base end up in
+/// `dirty_materials`, the others only get the returned parameters changed in place, so a value
+/// set every frame doesn't mean a new material copy every frame.
+fn merge_parameter_changes(
+	material_parameters: &mut FxHashMap<i32, FxHashMap<String, MaterialParameter>>,
+	dirty_materials: &mut Vec<i32>,
+	resets: Vec<(i32, String)>,
+	values: Vec<((i32, String), MaterialParameter)>,
+	material_count: i32,
+	is_applied: impl Fn(i32) -> bool,
+) -> FxHashMap<i32, Vec<String>> {
+	let expand_idx = |material_idx: i32| {
+		if material_idx == ALL_MATERIALS {
+			0..material_count
+		} else {
+			material_idx..material_idx + 1
+		}
+	};
+	for (material_idx, parameter_name) in resets {
+		for material_idx in expand_idx(material_idx) {
+			let Some(parameters) = material_parameters.get_mut(&material_idx) else {continue};
+			if parameters.remove(&parameter_name).is_some() {
+				dirty_materials.push(material_idx);
+			}
+		}
+	}
+	let mut updated_parameters: FxHashMap<i32, Vec<String>> = FxHashMap::default();
+	for ((material_idx, parameter_name), parameter_value) in values {
+		for material_idx in expand_idx(material_idx) {
+			material_parameters
+				.entry(material_idx)
+				.or_default()
+				.insert(parameter_name.clone(), parameter_value.clone());
+			updated_parameters
+				.entry(material_idx)
+				.or_default()
+				.push(parameter_name.clone());
+		}
+	}
+	// Only the first change to a material needs a copy of it
+	dirty_materials.extend(
+		updated_parameters
+			.keys()
+			.filter(|material_idx| !is_applied(**material_idx)),
+	);
+	dirty_materials.sort_unstable();
+	dirty_materials.dedup();
+	for material_idx in dirty_materials.iter() {
+		// Rebuilding applies every parameter anyway
+		updated_parameters.remove(material_idx);
+	}
+	updated_parameters
+}
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(tag = "t", content = "c")]
 pub enum MaterialParameter {
 	Float(f32),
+	/// StereoKit shader parameters are f32 only, so this loses precision when applied.
+	Double(f64),
 	Vector2(Vector2<f32>),
 	Vector3(Vector3<f32>),
 	Vector4(Vector4<f32>),
-	Color([f32; 4]),
+	Color(MaterialColor),
 	Int(i32),
 	Int2(Vector2<i32>),
 	Int3(Vector3<i32>),
@@ -46,9 +279,319 @@ pub enum MaterialParameter {
 	UInt4(Vector4<u32>),
 	Matrix(ColumnMatrix4<f32>),
 	Texture(ResourceID),
+	/// A texture along with how it's sampled, use `address: Wrap` for tiling textures.
+	TextureEx {
+		resource: ResourceID,
+		/// Defaults to linear
+		#[serde(default)]
+		sample: Option<TextureSampleMode>,
+		/// Defaults to clamp
+		#[serde(default)]
+		address: Option<TextureAddressMode>,
+		/// Defaults to true, turn off for things like UI atlases
+		#[serde(default)]
+		generate_mips: Option<bool>,
+		/// Loading priority hint, lower loads sooner. Defaults to 0
+		#[serde(default)]
+		priority: Option<i32>,
+		/// Anisotropic filtering level, rounded down to a power of two up to 16. Defaults to 1,
+		/// which is off. Anything above switches the default sampling to anisotropic
+		#[serde(default)]
+		anisotropy: Option<u32>,
+		/// File extensions to look for, the first one found wins. Defaults to png, jpg, ktx2, webp
+		#[serde(default)]
+		extensions: Option<Vec<String>>,
+	},
+	TextureRaw {
+		width: u32,
+		height: u32,
+		format: RawTextureFormat,
+		data: Vec<u8>,
+	},
+	Cubemap(CubemapSource),
+	/// Path to a render target node, its texture gets updated every frame
+	RenderTarget(String),
+	/// A texture owned by something else in the server, like a Wayland surface. Can only be set
+	/// from inside the server, and gets reset once the owner is gone
+	#[serde(skip)]
+	SharedTexture(SharedTexture),
+}
+
+#[derive(Clone)]
+pub struct SharedTexture(pub Weak<SendWrapper<Tex>>);
+impl std::fmt::Debug for SharedTexture {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_tuple("SharedTexture")
+			.field(&(self.0.strong_count() > 0))
+			.finish()
+	}
+}
+
+/// Either just RGBA, passed to StereoKit as-is, or RGBA along with the color space it's in.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(untagged)]
+pub enum MaterialColor {
+	Rgba([f32; 4]),
+	WithColorSpace([f32; 4], ColorSpace),
+}
+impl MaterialColor {
+	/// StereoKit expects linear colors for shader parameters, so sRGB is converted with the
+	/// standard sRGB transfer function. Alpha is always linear and is left untouched.
+	pub(super) fn to_linear(self) -> Color128 {
+		let [r, g, b, a] = match self {
+			MaterialColor::Rgba(rgba) => rgba,
+			MaterialColor::WithColorSpace(rgba, ColorSpace::Linear) => rgba,
+			MaterialColor::WithColorSpace([r, g, b, a], ColorSpace::Srgb) => {
+				[srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a]
+			}
+		};
+		Color128::from([r, g, b, a])
+	}
+}
+fn srgb_to_linear(value: f32) -> f32 {
+	if value <= 0.04045 {
+		value / 12.92
+	} else {
+		((value + 0.055) / 1.055).powf(2.4)
+	}
+}
+
+/// How a model's materials blend with what's behind them.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelTransparency {
+	/// Fully opaque, alpha is ignored
+	None,
+	/// Alpha blended
+	Blend,
+	/// Added onto what's behind, like a hologram
+	Add,
+}
+impl From<ModelTransparency> for Transparency {
+	fn from(transparency: ModelTransparency) -> Self {
+		match transparency {
+			ModelTransparency::None => Transparency::None,
+			ModelTransparency::Blend => Transparency::Blend,
+			ModelTransparency::Add => Transparency::Add,
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub enum ColorSpace {
+	Linear,
+	Srgb,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "t", content = "c")]
+pub enum CubemapSource {
+	Equirectangular(ResourceID),
+	/// +X, -X, +Y, -Y, +Z, -Z
+	Faces(Box<[ResourceID; 6]>),
+}
+impl CubemapSource {
+	/// Makes sure every image the cubemap needs can be found before it gets queued for loading.
+	pub(super) fn validate(&self, client: &Client) -> Result<()> {
+		let resources = match self {
+			CubemapSource::Equirectangular(resource) => std::slice::from_ref(resource),
+			CubemapSource::Faces(faces) => faces.as_slice(),
+		};
+		let prefixes = client.base_resource_prefixes.lock().clone();
+		let extensions = extension_list(None, CUBEMAP_EXTENSIONS);
+		for resource in resources {
+			// Downloads get waited for before the cubemap loads
+			ensure!(
+				matches!(
+					resource.resolve(&prefixes, &extensions),
+					ResourceState::Found(_) | ResourceState::Pending
+				),
+				"Cubemap resource {resource:?} not found"
+			);
+		}
+		Ok(())
+	}
 }
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub enum RawTextureFormat {
+	Rgba32,
+	Rgba32Linear,
+	Rgba128,
+	R8,
+}
+impl RawTextureFormat {
+	fn bytes_per_pixel(self) -> usize {
+		match self {
+			RawTextureFormat::Rgba32 | RawTextureFormat::Rgba32Linear => 4,
+			RawTextureFormat::Rgba128 => 16,
+			RawTextureFormat::R8 => 1,
+		}
+	}
+	/// `None` if it doesn't fit in memory at all.
+	fn texture_len(self, width: u32, height: u32) -> Option<usize> {
+		(width as usize)
+			.checked_mul(height as usize)?
+			.checked_mul(self.bytes_per_pixel())
+	}
+}
+impl From<RawTextureFormat> for TextureFormat {
+	fn from(format: RawTextureFormat) -> Self {
+		match format {
+			RawTextureFormat::Rgba32 => TextureFormat::RGBA32,
+			RawTextureFormat::Rgba32Linear => TextureFormat::RGBA32Linear,
+			RawTextureFormat::Rgba128 => TextureFormat::RGBA128,
+			RawTextureFormat::R8 => TextureFormat::R8,
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureSampleMode {
+	Linear,
+	Point,
+	Anisotropic,
+}
+/// The most GPUs support, StereoKit can't tell us what this one does.
+const MAX_ANISOTROPY: u32 = 16;
+/// Rounds down to a power of two between 1 and `MAX_ANISOTROPY`, 0 counts as 1.
+pub fn anisotropy_level(level: u32) -> i32 {
+	let level = level.clamp(1, MAX_ANISOTROPY);
+	1 << level.ilog2()
+}
+
+impl From<TextureSampleMode> for TextureSample {
+	fn from(mode: TextureSampleMode) -> Self {
+		match mode {
+			TextureSampleMode::Linear => TextureSample::Linear,
+			TextureSampleMode::Point => TextureSample::Point,
+			TextureSampleMode::Anisotropic => TextureSample::Anisotropic,
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureAddressMode {
+	Wrap,
+	Clamp,
+	Mirror,
+}
+impl From<TextureAddressMode> for TextureAddress {
+	fn from(mode: TextureAddressMode) -> Self {
+		match mode {
+			TextureAddressMode::Wrap => TextureAddress::Wrap,
+			TextureAddressMode::Clamp => TextureAddress::Clamp,
+			TextureAddressMode::Mirror => TextureAddress::Mirror,
+		}
+	}
+}
+
+/// Cached textures are shared, so how they're sampled is set once when they're loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct TextureSampler {
+	sample: TextureSampleMode,
+	address: TextureAddressMode,
+	anisotropy: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct TextureKey {
+	path: PathBuf,
+	generate_mips: bool,
+	/// `None` leaves StereoKit's defaults
+	sampler: Option<TextureSampler>,
+}
+
 impl MaterialParameter {
-	fn apply_to_material(
+	/// Catch malformed parameters while the client can still be told about them.
+	pub(super) fn validate(&self) -> Result<()> {
+		if let MaterialParameter::TextureRaw {
+			width,
+			height,
+			format,
+			data,
+		} = self
+		{
+			let Some(expected_len) = format.texture_len(*width, *height) else {
+				bail!("Raw texture is {width}x{height} {format:?}, which is too big");
+			};
+			ensure!(
+				data.len() == expected_len,
+				"Raw texture is {width}x{height} {format:?} so it should be {expected_len} bytes, got {}",
+				data.len()
+			);
+		}
+		Ok(())
+	}
+
+	/// Every image file this sets along with the extensions to look for.
+	fn resources(&self) -> Vec<(&ResourceID, Vec<&OsStr>)> {
+		match self {
+			MaterialParameter::Texture(resource)
+			| MaterialParameter::TextureEx { resource, .. } => {
+				vec![(
+					resource,
+					extension_list(self.extensions(), TEXTURE_EXTENSIONS),
+				)]
+			}
+			MaterialParameter::Cubemap(CubemapSource::Equirectangular(resource)) => {
+				vec![(resource, extension_list(None, CUBEMAP_EXTENSIONS))]
+			}
+			MaterialParameter::Cubemap(CubemapSource::Faces(faces)) => faces
+				.iter()
+				.map(|face| (face, extension_list(None, CUBEMAP_EXTENSIONS)))
+				.collect(),
+			_ => Vec::new(),
+		}
+	}
+	/// Applying a texture that's still downloading would leave the material without it.
+	pub(super) fn downloading(&self, client: &Client) -> bool {
+		let prefixes = client.base_resource_prefixes.lock().clone();
+		self.resources().into_iter().any(|(resource, extensions)| {
+			resource.resolve(&prefixes, &extensions) == ResourceState::Pending
+		})
+	}
+	/// Runs `apply` on a task once every image this sets has finished downloading.
+	pub(super) fn after_downloads(
+		self,
+		client: &Client,
+		apply: impl FnOnce(Self) + Send + 'static,
+	) -> Result<()> {
+		let prefixes = client.base_resource_prefixes.lock().clone();
+		task::new(|| "material parameter download", async move {
+			for (resource, extensions) in self.resources() {
+				resource.fetch(&prefixes, &extensions).await;
+			}
+			apply(self);
+		})?;
+		Ok(())
+	}
+
+	/// Roughly how much memory the texture this sets will take once decoded, 0 if it isn't one.
+	pub(super) fn texture_bytes(&self, client: &Client) -> usize {
+		match self {
+			MaterialParameter::Texture(resource)
+			| MaterialParameter::TextureEx { resource, .. } => {
+				let extensions = self.extensions();
+				let Some(texture_path) = Self::texture_path(client, resource, extensions) else {return 0};
+				// Fall back to the file size for formats the image crate can't read
+				image::image_dimensions(&texture_path)
+					.map(|(width, height)| width as usize * height as usize * 4)
+					.or_else(|_| std::fs::metadata(&texture_path).map(|m| m.len() as usize))
+					.unwrap_or(0)
+			}
+			MaterialParameter::TextureRaw { data, .. } => data.len(),
+			_ => 0,
+		}
+	}
+
+	fn extensions(&self) -> Option<&[String]> {
+		match self {
+			MaterialParameter::TextureEx { extensions, .. } => extensions.as_deref(),
+			_ => None,
+		}
+	}
+
+	pub(super) fn apply_to_material(
 		&self,
 		client: &Client,
 		sk: &impl StereoKitMultiThread,
@@ -59,6 +602,9 @@ impl MaterialParameter {
 			MaterialParameter::Float(val) => {
 				sk.material_set_float(material, parameter_name, *val);
 			}
+			MaterialParameter::Double(val) => {
+				sk.material_set_float(material, parameter_name, *val as f32);
+			}
 			MaterialParameter::Vector2(val) => {
 				sk.material_set_vector2(material, parameter_name, *val);
 			}
@@ -69,7 +615,7 @@ impl MaterialParameter {
 				sk.material_set_vector4(material, parameter_name, *val);
 			}
 			MaterialParameter::Color(val) => {
-				sk.material_set_color(material, parameter_name, Color128::from(val.clone()));
+				sk.material_set_color(material, parameter_name, val.to_linear());
 			}
 			MaterialParameter::Int(val) => {
 				sk.material_set_int(material, parameter_name, *val);
@@ -81,7 +627,8 @@ impl MaterialParameter {
 				sk.material_set_int3(material, parameter_name, val.x, val.y, val.z);
 			}
 			MaterialParameter::Int4(val) => {
-				sk.material_set_int4(material, parameter_name, val.w, val.x, val.y, val.z);
+				let [x, y, z, w] = components(val);
+				sk.material_set_int4(material, parameter_name, x, y, z, w);
 			}
 			MaterialParameter::Bool(val) => {
 				sk.material_set_bool(material, parameter_name, *val);
@@ -96,36 +643,651 @@ impl MaterialParameter {
 				sk.material_set_uint3(material, parameter_name, val.x, val.y, val.z);
 			}
 			MaterialParameter::UInt4(val) => {
-				sk.material_set_uint4(material, parameter_name, val.w, val.x, val.y, val.z);
+				let [x, y, z, w] = components(val);
+				sk.material_set_uint4(material, parameter_name, x, y, z, w);
 			}
 			MaterialParameter::Matrix(val) => {
 				sk.material_set_matrix(material, parameter_name, Mat4::from(*val));
 			}
 			MaterialParameter::Texture(resource) => {
-				let Some(texture_path) = resource.get_file(
-					&client.base_resource_prefixes.lock().clone(),
-					&[OsStr::new("png"), OsStr::new("jpg")],
-				) else {return};
-				if let Ok(tex) = sk.tex_create_file(texture_path, true, 0) {
+				if let Some(tex) = Self::load_texture(client, sk, resource, None, true, 0, None) {
+					sk.material_set_texture(material, parameter_name, tex.as_ref().as_ref());
+				}
+			}
+			MaterialParameter::TextureEx {
+				resource,
+				sample,
+				address,
+				generate_mips,
+				priority,
+				anisotropy,
+				extensions,
+			} => {
+				let generate_mips = generate_mips.unwrap_or(true);
+				let priority = priority.unwrap_or(0);
+				let anisotropy = anisotropy_level(anisotropy.unwrap_or(1));
+				let extensions = extensions.as_deref();
+				let default_sample = if anisotropy > 1 {
+					TextureSampleMode::Anisotropic
+				} else {
+					TextureSampleMode::Linear
+				};
+				let sampler = TextureSampler {
+					sample: sample.unwrap_or(default_sample),
+					address: address.unwrap_or(TextureAddressMode::Clamp),
+					anisotropy,
+				};
+				if let Some(tex) = Self::load_texture(
+					client,
+					sk,
+					resource,
+					extensions,
+					generate_mips,
+					priority,
+					Some(sampler),
+				) {
+					sk.material_set_texture(material, parameter_name, tex.as_ref().as_ref());
+				}
+			}
+			MaterialParameter::TextureRaw {
+				width,
+				height,
+				format,
+				data,
+			} => {
+				let tex = sk.tex_create(TextureType::IMAGE, (*format).into());
+				tex_set_colors(&tex, *width as usize, *height as usize, data.as_slice());
+				sk.material_set_texture(material, parameter_name, &tex);
+			}
+			MaterialParameter::Cubemap(source) => {
+				if let Some(tex) = Self::load_cubemap(client, sk, source) {
 					sk.material_set_texture(material, parameter_name, &tex);
 				}
 			}
+			MaterialParameter::RenderTarget(path) => match find_render_target(client, path) {
+				Ok(render_target) => {
+					sk.material_set_texture(material, parameter_name, render_target.texture(sk));
+				}
+				Err(e) => error!(path, "Unable to use render target: {e}"),
+			},
+			MaterialParameter::SharedTexture(SharedTexture(tex)) => {
+				if let Some(tex) = tex.upgrade() {
+					sk.material_set_texture(material, parameter_name, tex.as_ref().as_ref());
+				}
+			}
+		}
+	}
+
+	/// Shared with everything else using the same file and settings, until the file changes.
+	fn load_texture(
+		client: &Client,
+		sk: &impl StereoKitMultiThread,
+		resource: &ResourceID,
+		extensions: Option<&[String]>,
+		generate_mips: bool,
+		priority: i32,
+		sampler: Option<TextureSampler>,
+	) -> Option<Arc<SendWrapper<Tex>>> {
+		let texture_path = Self::texture_path(client, resource, extensions)?;
+		let key = TextureKey {
+			path: texture_path.clone(),
+			generate_mips,
+			sampler,
+		};
+		let mut texture_cache = TEXTURE_CACHE.lock();
+		if let Some(tex) = texture_cache.get(&key) {
+			return Some(tex);
+		}
+		let tex = if texture_path.extension() == Some(OsStr::new("webp")) {
+			match Self::load_webp(sk, &texture_path, generate_mips) {
+				Ok(tex) => tex,
+				Err(e) => {
+					error!(?texture_path, "Unable to load WebP texture: {e}");
+					return None;
+				}
+			}
+		} else {
+			if texture_path.extension() == Some(OsStr::new("ktx2")) {
+				let mut header = [0; KTX2_HEADER_LENGTH];
+				let checked = std::fs::File::open(&texture_path)
+					.and_then(|mut file| file.read_exact(&mut header))
+					.map_err(|e| eyre!("Unable to read KTX2 header: {e}"))
+					.and_then(|_| check_ktx2_header(&header));
+				if let Err(e) = checked {
+					error!(?texture_path, "Unable to load KTX2 texture: {e}");
+					return None;
+				}
+			}
+			match sk.tex_create_file(&texture_path, generate_mips, priority) {
+				Ok(tex) => tex,
+				Err(e) => {
+					error!(?texture_path, "Unable to load texture: {e}");
+					return None;
+				}
+			}
+		};
+		if let Some(sampler) = sampler {
+			sk.tex_set_sample(&tex, sampler.sample.into());
+			sk.tex_set_address(&tex, sampler.address.into());
+			sk.tex_set_anisotropy(&tex, sampler.anisotropy);
+		}
+		Some(texture_cache.insert(key, &texture_path, tex))
+	}
+
+	fn texture_path(
+		client: &Client,
+		resource: &ResourceID,
+		extensions: Option<&[String]>,
+	) -> Option<PathBuf> {
+		resource.get_file(
+			&client.base_resource_prefixes.lock().clone(),
+			&extension_list(extensions, TEXTURE_EXTENSIONS),
+		)
+	}
+
+	/// StereoKit can't decode WebP itself, so decode it to RGBA here.
+	fn load_webp(sk: &impl StereoKitMultiThread, path: &Path, generate_mips: bool) -> Result<Tex> {
+		let bytes = std::fs::read(path)?;
+		// Extended format header with the animation flag set
+		let animated = bytes.get(12..16) == Some(&b"VP8X"[..])
+			&& bytes.get(20).is_some_and(|flags| flags & 0x02 != 0);
+		ensure!(!animated, "Animated WebP textures are not supported");
+
+		let image = image::load_from_memory_with_format(&bytes, ImageFormat::WebP)?.into_rgba8();
+		let texture_type = if generate_mips {
+			TextureType::IMAGE
+		} else {
+			TextureType::IMAGE_NO_MIPS
+		};
+		let tex = sk.tex_create(texture_type, TextureFormat::RGBA32);
+		tex_set_colors(
+			&tex,
+			image.width() as usize,
+			image.height() as usize,
+			image.as_raw().as_slice(),
+		);
+		Ok(tex)
+	}
+
+	pub(super) fn load_cubemap(
+		client: &Client,
+		sk: &impl StereoKitMultiThread,
+		source: &CubemapSource,
+	) -> Option<Tex> {
+		let prefixes = client.base_resource_prefixes.lock().clone();
+		let extensions = extension_list(None, CUBEMAP_EXTENSIONS);
+		let result = match source {
+			CubemapSource::Equirectangular(resource) => {
+				let path = resource.get_file(&prefixes, &extensions)?;
+				sk.tex_create_cubemap_file(&path, true, 0)
+					.map(|(_spherical_harmonics, tex)| tex)
+					.map_err(Into::into)
+			}
+			CubemapSource::Faces(faces) => {
+				let mut face_paths = Vec::with_capacity(6);
+				for face in faces.iter() {
+					let Some(path) = face.get_file(&prefixes, &extensions) else {
+						error!(?face, "Cubemap face not found");
+						return None;
+					};
+					face_paths.push(path);
+				}
+				tex_create_cubemap_files(&face_paths, true, 0)
+			}
+		};
+		let tex = match result {
+			Ok(tex) => tex,
+			Err(e) => {
+				error!(?source, "Unable to load cubemap: {e}");
+				return None;
+			}
+		};
+		if sk.tex_get_width(&tex) != sk.tex_get_height(&tex) {
+			error!(?source, "Cubemap faces must be square");
+			return None;
+		}
+		Some(tex)
+	}
+}
+
+#[derive(Deserialize)]
+struct MaterialParameterInfo {
+	idx: i32,
+	name: String,
+	value: MaterialParameter,
+}
+
+/// What introspection shows about a model.
+#[derive(Debug, Serialize)]
+pub struct ModelIntrospection {
+	/// `None` when the resource couldn't be found and the placeholder is shown instead
+	pub path: Option<PathBuf>,
+	/// `None` until the model has loaded
+	pub material_count: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelRaycastHit {
+	pub point: Vector3<f32>,
+	pub normal: Vector3<f32>,
+	pub distance: f32,
+}
+
+/// One view this frame's draws end up in, as the planes of its frustum in world space.
+pub(super) struct ViewFrustum {
+	/// Normalized, facing inwards
+	planes: [Vec4; 6],
+}
+impl ViewFrustum {
+	/// `projection` has glam's 0 to 1 depth range, `view` is the inverse of the camera's transform.
+	pub(super) fn new(projection: Mat4, view: Mat4) -> Self {
+		let view_projection = projection * view;
+		let [x, y, z, w] = [0, 1, 2, 3].map(|row| view_projection.row(row));
+		let planes =
+			[w + x, w - x, w + y, w - y, z, w - z].map(|plane| plane / plane.truncate().length());
+		ViewFrustum { planes }
+	}
+
+	/// How the head sees, from StereoKit's field of view for the left eye (in degrees, left and
+	/// bottom negative). The right eye mirrors it, so this covers the widest side both ways and
+	/// starts a bit behind the head so neither eye sees past its sides.
+	fn for_head(head: Mat4, fov: FovInfo) -> Self {
+		// Eyes are never more than this far from the head's center
+		const EYE_OFFSET: f32 = 0.05;
+		let max_angle = 89.0_f32.to_radians();
+		let half_width = fov
+			.left
+			.abs()
+			.max(fov.right.abs())
+			.to_radians()
+			.min(max_angle);
+		let half_height = fov
+			.top
+			.abs()
+			.max(fov.bottom.abs())
+			.to_radians()
+			.min(max_angle);
+		let setback = EYE_OFFSET / half_width.tan();
+		let (near, far) = CLIP_PLANES;
+		let projection = Mat4::perspective_rh(
+			half_height * 2.0,
+			half_width.tan() / half_height.tan(),
+			near,
+			far + setback,
+		);
+		let eyes = head * Mat4::from_translation(Vec3::new(0.0, 0.0, setback));
+		ViewFrustum::new(projection, eyes.inverse())
+	}
+
+	fn intersects_sphere(&self, center: Vec3, radius: f32) -> bool {
+		self.planes
+			.iter()
+			.all(|plane| plane.truncate().dot(center) + plane.w >= -radius)
+	}
+}
+
+/// Everything that sees this frame's draws, that's the head and any render targets or
+/// screenshots. StereoKit sends every draw to all of them, so models only get culled once
+/// they're outside every one.
+struct FrameViews {
+	/// The head's position, which LOD levels and transparent sorting go by
+	origin: Vec3,
+	frustums: Vec<ViewFrustum>,
+}
+impl FrameViews {
+	fn for_frame(sk: &impl StereoKitMultiThread, mut other_views: Vec<ViewFrustum>) -> Self {
+		let head = sk.input_head();
+		let head_transform = Mat4::from_rotation_translation(head.orientation, head.position);
+		other_views.push(ViewFrustum::for_head(
+			head_transform,
+			sk.device_display_get_fov(),
+		));
+		FrameViews {
+			origin: head.position,
+			frustums: other_views,
+		}
+	}
+
+	fn intersects_sphere(&self, center: Vec3, radius: f32) -> bool {
+		self.frustums
+			.iter()
+			.any(|frustum| frustum.intersects_sphere(center, radius))
+	}
+
+	/// Local space bounds put into world space, the radius grows with the largest scale axis so
+	/// it still covers everything under uneven scaling.
+	fn intersects_bounds(&self, bounds: (Vec3, f32), transform: Mat4) -> bool {
+		let (center, radius) = bounds;
+		let (scale, _, _) = transform.to_scale_rotation_translation();
+		self.intersects_sphere(
+			transform.transform_point3(center),
+			radius * scale.abs().max_element(),
+		)
+	}
+}
+
+/// Everything that comes from loading the model file, replaced as a whole on reload
+struct LoadedModel {
+	/// From `MODEL_CACHE`, so it's shared with other models of the same file
+	shared: Arc<SendWrapper<SKModel>>,
+	/// Our own copy, so materials can be changed without affecting the other models
+	sk_model: SendWrapper<SKModel>,
+	// Methods run off the render thread, so cache everything they need to read
+	material_count: u32,
+	material_names: Vec<String>,
+	node_names: Vec<String>,
+	/// Model space transform of every node by name, redone when node transforms are set
+	node_transforms: Mutex<FxHashMap<String, Mat4>>,
+	/// Copy of `sk_model` for raycasts to run against off the render thread, redone when nodes
+	/// move
+	raycast_model: Mutex<ParsedModel>,
+	/// Which materials' shaders have a color to glow with, redone when materials change
+	emissive_slots: Mutex<Vec<bool>>,
+	/// Local space bounding sphere center and radius
+	bounds: (Vec3, f32),
+}
+
+/// Where a loaded model sits inside its spatial, which is all culling needs to know about it
+#[derive(Clone, Copy)]
+struct ModelPlacement {
+	bounds: (Vec3, f32),
+}
+
+/// What can be worked out for a model without StereoKit, before the serial draw pass
+struct PreparedDraw {
+	transform: Mat4,
+	/// `None` when the model isn't loaded yet so its bounds aren't known
+	visible: Option<bool>,
+	/// From the head, to sort transparent models by
+	distance: f32,
+}
+impl PreparedDraw {
+	fn new(
+		transform: Mat4,
+		placement: Option<ModelPlacement>,
+		always_visible: bool,
+		views: &FrameViews,
+	) -> Self {
+		let visible = placement.map(|placement| {
+			always_visible || views.intersects_bounds(placement.bounds, transform)
+		});
+		let distance = transform.w_axis.truncate().distance(views.origin);
+		PreparedDraw {
+			transform,
+			visible,
+			distance,
+		}
+	}
+}
+
+/// The prepare phase of drawing, run on rayon's pool as it only reads the scenegraph. Anything
+/// `prepare` returns `None` for is left out. Material parameters are still merged in the draw
+/// pass, since replacements have to go through StereoKit before them.
+fn prepare_draws<T: Send>(
+	items: Vec<T>,
+	prepare: impl Fn(&T) -> Option<PreparedDraw> + Sync,
+) -> Vec<(T, PreparedDraw)> {
+	items
+		.into_par_iter()
+		.filter_map(|item| {
+			let prepared = prepare(&item)?;
+			Some((item, prepared))
+		})
+		.collect()
+}
+
+/// Scales a model with these bounds so its largest dimension is `size` and centers it, identity
+/// if it isn't being normalized or has no size to scale.
+fn fit_transform(size: Option<f32>, center: Vec3, dimensions: Vec3) -> Mat4 {
+	let largest_dimension = dimensions.max_element();
+	match size {
+		Some(size) if largest_dimension > 0.0 => {
+			Mat4::from_scale(Vec3::splat(size / largest_dimension))
+				* Mat4::from_translation(-center)
+		}
+		_ => Mat4::IDENTITY,
+	}
+}
+/// Swaps the fit a local transform was given for a new one.
+fn refit(local_transform: Mat4, old_fit_transform: Mat4, fit_transform: Mat4) -> Mat4 {
+	local_transform * old_fit_transform.inverse() * fit_transform
+}
+
+/// The first level (they're sorted nearest first) that still covers the distance, a level's
+/// `max_distance` included. `None` past the last one.
+fn select_lod(distance: f32, levels: &[LodLevel]) -> Option<&LodLevel> {
+	levels.iter().find(|level| distance <= level.max_distance)
+}
+
+struct LodLevel {
+	path: PathBuf,
+	max_distance: f32,
+	sk_model: OnceCell<Arc<SendWrapper<SKModel>>>,
+	/// Our own copy, given the full model's materials
+	copy: OnceCell<SendWrapper<SKModel>>,
+	/// `Model::material_version` the copy's materials were last taken from
+	synced_version: AtomicU32,
+	parse: Mutex<Option<ModelParse>>,
+	/// Broken files aren't parsed again, the full model stands in for them
+	failed: AtomicBool,
+}
+impl LodLevel {
+	fn new(path: PathBuf, max_distance: f32) -> Self {
+		LodLevel {
+			path,
+			max_distance,
+			sk_model: OnceCell::new(),
+			copy: OnceCell::new(),
+			synced_version: AtomicU32::new(u32::MAX),
+			parse: Mutex::new(None),
+			failed: AtomicBool::new(false),
+		}
+	}
+	/// Starts parsing the file the first time it's needed, `None` until that's done.
+	fn model(&self) -> Option<&Arc<SendWrapper<SKModel>>> {
+		if let Some(sk_model) = self.sk_model.get() {
+			return Some(sk_model);
+		}
+		if self.failed.load(Ordering::Relaxed) {
+			return None;
+		}
+		if let Some(sk_model) = MODEL_CACHE.lock().get(&self.path) {
+			return Some(self.sk_model.get_or_init(|| sk_model));
+		}
+		let mut parse = self.parse.lock();
+		let result = match parse.as_ref() {
+			Some(running) => running.poll()?,
+			None => match ModelParse::start(self.path.clone()) {
+				Ok(started) => {
+					*parse = Some(started);
+					return None;
+				}
+				Err(e) => Err(e),
+			},
+		};
+		*parse = None;
+		match result {
+			Ok(sk_model) => {
+				let sk_model = MODEL_CACHE
+					.lock()
+					.insert(self.path.clone(), &self.path, sk_model);
+				Some(self.sk_model.get_or_init(|| sk_model))
+			}
+			Err(e) => {
+				warn!(path = ?self.path, "Unable to load LOD model: {e}");
+				self.failed.store(true, Ordering::Relaxed);
+				None
+			}
+		}
+	}
+}
+
+/// StereoKit's assets are reference counted on its side and fine to hand between threads, it's
+/// only the wrapper that isn't marked as such.
+struct ParsedModel(SKModel);
+unsafe impl Send for ParsedModel {}
+
+/// A model file being read and parsed on a blocking thread, so big files don't stall the frame
+/// they first show up in.
+struct ModelParse(Receiver<Result<ParsedModel>>);
+impl ModelParse {
+	fn start(path: PathBuf) -> Result<Self> {
+		// StereoKit only takes UTF-8 paths, panicking here would take down the thread
+		let path_str = path
+			.to_str()
+			.ok_or_else(|| eyre!("Model path {} is not valid UTF-8", path.display()))?
+			.to_string();
+		let sk = MODEL_LOADER
+			.get()
+			.ok_or_else(|| eyre!("StereoKit isn't running yet"))?;
+		let (result_tx, result_rx) = channel();
+		task::new(|| "model parse", async move {
+			let _ = tokio::task::spawn_blocking(move || {
+				let result = std::fs::read(&path)
+					.map_err(|e| eyre!("Unable to read model file: {e}"))
+					.and_then(|data| {
+						// StereoKit caches models by file name, so it's always loaded from memory
+						// to pick up changes on reload
+						let sk_model = sk.model_create_mem(&path_str, &data, None::<&Shader>)?;
+						Ok(ParsedModel(sk_model))
+					});
+				let _ = result_tx.send(result);
+			})
+			.await;
+		})?;
+		Ok(ModelParse(result_rx))
+	}
+	/// `None` while it's still going.
+	fn poll(&self) -> Option<Result<SKModel>> {
+		match self.0.try_recv() {
+			Ok(result) => Some(result.map(|parsed| parsed.0)),
+			Err(TryRecvError::Empty) => None,
+			Err(TryRecvError::Disconnected) => {
+				Some(Err(eyre!("Model parse stopped before finishing")))
+			}
 		}
 	}
 }
 
+/// Keeps a model whose file is missing or broken from being loaded again every frame.
+#[derive(Default)]
+struct LoadFailures {
+	attempts: u32,
+	retry_at: Option<Instant>,
+}
+impl LoadFailures {
+	fn given_up(&self) -> bool {
+		self.attempts >= MAX_LOAD_ATTEMPTS
+	}
+	fn should_retry(&self) -> bool {
+		!self.given_up()
+			&& self
+				.retry_at
+				.is_none_or(|retry_at| Instant::now() >= retry_at)
+	}
+	/// Returns true once it's time to give up, which is logged just the once.
+	fn failed(&mut self, resource_id: &ResourceID, error: color_eyre::eyre::Error) -> bool {
+		self.attempts += 1;
+		if self.given_up() {
+			error!(
+				?resource_id,
+				?error,
+				attempts = self.attempts,
+				"Giving up on loading model"
+			);
+			self.retry_at = None;
+			return true;
+		}
+		let backoff = LOAD_RETRY_BACKOFF * 2_u32.pow(self.attempts - 1);
+		warn!(
+			?resource_id,
+			?error,
+			?backoff,
+			"Unable to load model, retrying"
+		);
+		self.retry_at = Some(Instant::now() + backoff);
+		false
+	}
+}
+
+/// The model's tint alpha going between transparent and opaque, advanced by the render thread.
+struct Fade {
+	from: f32,
+	to: f32,
+	started: Instant,
+	duration: Duration,
+}
+impl Fade {
+	fn opacity(&self) -> f32 {
+		let progress = self.started.elapsed().as_secs_f32() / self.duration.as_secs_f32();
+		self.from + (self.to - self.from) * progress.min(1.0)
+	}
+	fn is_done(&self) -> bool {
+		self.started.elapsed() >= self.duration
+	}
+}
+
 pub struct Model {
 	enabled: Arc<AtomicBool>,
 	space: Arc<Spatial>,
 	resource_id: ResourceID,
+	/// The client's extensions to look for the model and its LOD levels with, if it gave any
+	extensions: Option<Vec<String>>,
 	pending_model_path: OnceCell<PathBuf>,
 	pending_material_parameters: Mutex<FxHashMap<(i32, String), MaterialParameter>>,
+	pending_material_parameter_resets: Mutex<Vec<(i32, String)>>,
+	material_parameters: Mutex<FxHashMap<i32, FxHashMap<String, MaterialParameter>>>,
+	base_materials: Mutex<FxHashMap<i32, SendWrapper<Material>>>,
+	/// The copy of the base material that's on the model, parameter updates change it in place
+	applied_materials: Mutex<FxHashMap<i32, SendWrapper<Material>>>,
 	pub pending_material_replacements: Mutex<FxHashMap<u32, Arc<SendWrapper<Material>>>>,
-	sk_model: OnceCell<SendWrapper<SKModel>>,
+	pending_node_transforms: Mutex<FxHashMap<String, Mat4>>,
+	wireframe: AtomicBool,
+	depth_test: AtomicBool,
+	/// `None` keeps each material's own transparency
+	transparency: Mutex<Option<ModelTransparency>>,
+	/// Materials blend while this is going, a finished fade out disables the node
+	fade: Mutex<Option<Fade>>,
+	pending_shader: Mutex<Option<PathBuf>>,
+	shader: Mutex<Option<SendWrapper<Shader>>>,
+	render_state_changed: AtomicBool,
+	customized: AtomicBool,
+	always_visible: AtomicBool,
+	/// Drawn after everything opaque, farthest first, so blending looks right where they overlap
+	transparent: AtomicBool,
+	/// Size in meters to scale the largest dimension of the model's bounds to once it loads
+	normalize_size: Mutex<Option<f32>>,
+	/// What `normalize_size` last multiplied into the spatial's local transform
+	fit_transform: Mutex<Mat4>,
+	/// Its layers get masked by the client's render layer mask
+	client: Weak<Client>,
+	/// Bits of the `RenderLayer` the whole model is drawn on
+	render_layer: AtomicU32,
+	/// Materials drawn on a different layer than the model, which splits up its draw
+	material_render_layers: Mutex<FxHashMap<i32, u32>>,
+	lod_levels: Mutex<Vec<LodLevel>>,
+	/// Goes up whenever the loaded model's materials change, so LOD levels know to catch up
+	material_version: AtomicU32,
+	reload_pending: Arc<AtomicBool>,
+	load_failures: Mutex<LoadFailures>,
+	#[cfg(feature = "hot_reload")]
+	watcher: OnceCell<notify::RecommendedWatcher>,
+	loaded: Mutex<Option<LoadedModel>>,
+	/// The model file being parsed on a blocking thread, adopted by the render thread when done
+	model_parse: Mutex<Option<ModelParse>>,
+	_quota_reservation: QuotaReservation,
+	/// The texture memory of each texture parameter the client set.
+	texture_reservations: Mutex<FxHashMap<(i32, String), QuotaReservation>>,
 }
 
 impl Model {
-	pub fn add_to(node: &Arc<Node>, resource_id: ResourceID) -> Result<Arc<Model>> {
+	pub fn add_to(
+		node: &Arc<Node>,
+		resource_id: ResourceID,
+		extensions: Option<&[String]>,
+		watch: bool,
+		fallback: bool,
+	) -> Result<Arc<Model>> {
 		ensure!(
 			node.spatial.get().is_some(),
 			"Internal: Node does not have a spatial attached!"
@@ -134,128 +1296,1393 @@ impl Model {
 			node.drawable.get().is_none(),
 			"Internal: Node already has a drawable attached!"
 		);
+		let client = node.get_client().ok_or_else(|| eyre!("Client not found"))?;
+		let quota_reservation = QuotaReservation::reserve(&client, QuotaKind::Models, 1)?;
 		let model = Model {
 			enabled: node.enabled.clone(),
 			space: node.spatial.get().unwrap().clone(),
 			resource_id,
+			extensions: extensions.map(<[String]>::to_vec),
 			pending_model_path: OnceCell::new(),
 			pending_material_parameters: Mutex::new(FxHashMap::default()),
+			pending_material_parameter_resets: Mutex::new(Vec::new()),
+			material_parameters: Mutex::new(FxHashMap::default()),
+			base_materials: Mutex::new(FxHashMap::default()),
+			applied_materials: Mutex::new(FxHashMap::default()),
 			pending_material_replacements: Mutex::new(FxHashMap::default()),
-			sk_model: OnceCell::new(),
+			pending_node_transforms: Mutex::new(FxHashMap::default()),
+			wireframe: AtomicBool::new(false),
+			depth_test: AtomicBool::new(true),
+			transparency: Mutex::new(None),
+			fade: Mutex::new(None),
+			pending_shader: Mutex::new(None),
+			shader: Mutex::new(None),
+			render_state_changed: AtomicBool::new(false),
+			customized: AtomicBool::new(false),
+			always_visible: AtomicBool::new(false),
+			transparent: AtomicBool::new(false),
+			normalize_size: Mutex::new(None),
+			fit_transform: Mutex::new(Mat4::IDENTITY),
+			client: Arc::downgrade(&client),
+			render_layer: AtomicU32::new(RenderLayer::LAYER0.bits()),
+			material_render_layers: Mutex::new(FxHashMap::default()),
+			lod_levels: Mutex::new(Vec::new()),
+			material_version: AtomicU32::new(0),
+			reload_pending: Arc::new(AtomicBool::new(false)),
+			load_failures: Mutex::new(LoadFailures::default()),
+			#[cfg(feature = "hot_reload")]
+			watcher: OnceCell::new(),
+			loaded: Mutex::new(None),
+			model_parse: Mutex::new(None),
+			_quota_reservation: quota_reservation,
+			texture_reservations: Mutex::new(FxHashMap::default()),
 		};
 		node.add_local_signal("set_material_parameter", Model::set_material_parameter_flex);
-		let model_arc = MODEL_REGISTRY.add(model);
-		let _ = model_arc.pending_model_path.set(
-			model_arc
-				.resource_id
-				.get_file(
-					&node
-						.get_client()
-						.ok_or_else(|| eyre!("Client not found"))?
-						.base_resource_prefixes
-						.lock()
-						.clone(),
-					&[OsStr::new("glb"), OsStr::new("gltf")],
-				)
-				.ok_or_else(|| eyre!("Resource not found"))?,
+		node.add_local_signal(
+			"set_material_parameters",
+			Model::set_material_parameters_flex,
 		);
+		node.add_local_signal("set_emissive", Model::set_emissive_flex);
+		node.add_local_signal(
+			"reset_material_parameter",
+			Model::reset_material_parameter_flex,
+		);
+		node.add_local_signal("set_wireframe", Model::set_wireframe_flex);
+		node.add_local_signal("set_depth_test", Model::set_depth_test_flex);
+		node.add_local_signal("set_transparency", Model::set_transparency_flex);
+		node.add_local_signal("set_enabled_smoothed", Model::set_enabled_smoothed_flex);
+		node.add_local_signal("set_shader", Model::set_shader_flex);
+		node.add_local_signal("set_always_visible", Model::set_always_visible_flex);
+		node.add_local_signal("set_lod_levels", Model::set_lod_levels_flex);
+		node.add_local_signal("set_render_layer", Model::set_render_layer_flex);
+		node.add_local_signal("set_node_transform", Model::set_node_transform_flex);
+		node.add_local_method("get_material_count", Model::get_material_count_flex);
+		node.add_local_method("get_material_names", Model::get_material_names_flex);
+		node.add_local_method("get_node_names", Model::get_node_names_flex);
+		node.add_local_method("raycast", Model::raycast_flex);
+		node.add_local_method(
+			"get_last_set_material_parameter",
+			Model::get_last_set_material_parameter_flex,
+		);
+		node.add_local_method("get_node_transform", Model::get_node_transform_flex);
+		let model_arc = MODEL_REGISTRY.add(model);
+		let prefixes = client.base_resource_prefixes.lock().clone();
+		let extensions = extension_list(extensions, MODEL_EXTENSIONS);
+		match model_arc.resource_id.resolve(&prefixes, &extensions) {
+			ResourceState::Found(model_path) => model_arc.set_model_path(model_path, watch)?,
+			// The placeholder shows until the download is done, then the model reloads
+			ResourceState::Pending => {
+				let model = Arc::downgrade(&model_arc);
+				let resource_id = model_arc.resource_id.clone();
+				let extensions: Vec<_> = extensions.into_iter().map(OsStr::to_owned).collect();
+				task::new(|| "model download", async move {
+					let extensions: Vec<&OsStr> =
+						extensions.iter().map(|e| e.as_os_str()).collect();
+					let Some(model_path) = resource_id.fetch(&prefixes, &extensions).await else {
+						warn!(?resource_id, "Model resource couldn't be downloaded");
+						return;
+					};
+					let Some(model) = model.upgrade() else {return};
+					if let Err(e) = model.set_model_path(model_path, watch) {
+						warn!(?resource_id, "Unable to use downloaded model: {e}");
+						return;
+					}
+					model.reload_pending.store(true, Ordering::Relaxed);
+				})?;
+			}
+			// With no model path set the placeholder gets loaded instead
+			ResourceState::Uncached | ResourceState::NotFound if fallback => {
+				warn!(resource = ?model_arc.resource_id, "Model resource not found, using placeholder");
+			}
+			ResourceState::Uncached | ResourceState::NotFound => bail!("Resource not found"),
+		}
 		let _ = node.drawable.set(Drawable::Model(model_arc.clone()));
 		Ok(model_arc)
 	}
 
-	fn set_material_parameter_flex(
-		node: &Node,
-		_calling_client: Arc<Client>,
-		data: &[u8],
-	) -> Result<()> {
-		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
-
-		#[derive(Deserialize)]
-		struct MaterialParameterInfo {
-			idx: u32,
-			name: String,
-			value: MaterialParameter,
+	fn set_model_path(&self, model_path: PathBuf, watch: bool) -> Result<()> {
+		let extension = model_path
+			.extension()
+			.and_then(OsStr::to_str)
+			.unwrap_or_default()
+			.to_ascii_lowercase();
+		if !SUPPORTED_MODEL_EXTENSIONS.contains(&extension.as_str()) {
+			bail!(
+				"Model format \"{extension}\" isn't supported, use one of {}",
+				SUPPORTED_MODEL_EXTENSIONS.join(", ")
+			);
+		}
+		if extension == "obj" {
+			let client = self
+				.client
+				.upgrade()
+				.ok_or_else(|| eyre!("Client not found"))?;
+			let mut pending_material_parameters = self.pending_material_parameters.lock();
+			for (name, value) in obj_material_parameters(&model_path) {
+				// Counted the same as if the client had set it
+				let key = (0, name);
+				self.reserve_texture_memory(&client, &key, &value)?;
+				pending_material_parameters.insert(key, value);
+			}
+		}
+		let _ = self.pending_model_path.set(model_path);
+		if watch {
+			self.watch()?;
+		}
+		Ok(())
+	}
+
+	/// Reload the model whenever its file changes, material parameters are kept.
+	#[cfg(feature = "hot_reload")]
+	fn watch(&self) -> Result<()> {
+		use notify::{RecursiveMode, Watcher};
+
+		let path = self.pending_model_path.get().unwrap().clone();
+		let reload_pending = self.reload_pending.clone();
+		let watched_path = path.clone();
+		let mut watcher =
+			notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+				let Ok(event) = event else { return };
+				if (event.kind.is_modify() || event.kind.is_create())
+					&& event.paths.contains(&watched_path)
+				{
+					reload_pending.store(true, Ordering::Relaxed);
+				}
+			})?;
+		// Editors often save by replacing the file, which a watch on the file itself would miss
+		watcher.watch(
+			path.parent()
+				.ok_or_else(|| eyre!("Model path has no parent"))?,
+			RecursiveMode::NonRecursive,
+		)?;
+		let _ = self.watcher.set(watcher);
+		Ok(())
+	}
+	#[cfg(not(feature = "hot_reload"))]
+	fn watch(&self) -> Result<()> {
+		bail!("Server was built without hot reload support")
+	}
+
+	/// Texture memory is counted when the parameter is set, so the client gets the error.
+	fn reserve_texture_memory(
+		&self,
+		client: &Arc<Client>,
+		key: &(i32, String),
+		value: &MaterialParameter,
+	) -> Result<()> {
+		let mut texture_reservations = self.texture_reservations.lock();
+		// Whatever texture was set before gets replaced, so it shouldn't count against this one
+		texture_reservations.remove(key);
+		let texture_bytes = value.texture_bytes(client);
+		if texture_bytes > 0 {
+			let reservation =
+				QuotaReservation::reserve(client, QuotaKind::TextureBytes, texture_bytes)?;
+			texture_reservations.insert(key.clone(), reservation);
 		}
+		Ok(())
+	}
+
+	/// The texture memory is only known once the download is done, so going over the quota can
+	/// only be logged.
+	fn set_material_parameter_after_download(
+		self: &Arc<Self>,
+		client: &Arc<Client>,
+		key: (i32, String),
+		value: MaterialParameter,
+	) -> Result<()> {
+		let model = Arc::downgrade(self);
+		let weak_client = Arc::downgrade(client);
+		value.after_downloads(client, move |value| {
+			let (Some(model), Some(client)) = (model.upgrade(), weak_client.upgrade()) else {return};
+			if let Err(e) = model.reserve_texture_memory(&client, &key, &value) {
+				warn!(?key, "Unable to set downloaded texture: {e}");
+				return;
+			}
+			model.pending_material_parameters.lock().insert(key, value);
+		})
+	}
+
+	fn set_material_parameter_flex(
+		node: &Node,
+		calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
 		let info: MaterialParameterInfo = deserialize(data)?;
+		model.check_material_idx(info.idx)?;
+		info.value.validate()?;
+		let key = (info.idx, info.name);
+		if info.value.downloading(&calling_client) {
+			return model.set_material_parameter_after_download(&calling_client, key, info.value);
+		}
+		model.reserve_texture_memory(&calling_client, &key, &info.value)?;
 
 		model
 			.pending_material_parameters
 			.lock()
-			.insert((info.idx as i32, info.name), info.value);
+			.insert(key, info.value);
 
 		Ok(())
 	}
 
-	fn draw(&self, sk: &impl StereoKitDraw) {
-		let sk_model = self
-			.sk_model
-			.get_or_try_init(|| -> color_eyre::eyre::Result<SendWrapper<SKModel>> {
-				let pending_model_path = self.pending_model_path.get().ok_or(Error)?;
-				let model =
-					sk.model_create_file(pending_model_path.to_str().unwrap(), None::<Shader>)?;
+	fn set_material_parameters_flex(
+		node: &Node,
+		calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
+		let infos: Vec<MaterialParameterInfo> = deserialize(data)?;
+		for info in &infos {
+			model.check_material_idx(info.idx)?;
+			info.value.validate()?;
+		}
+		let (downloading, infos): (Vec<_>, Vec<_>) = infos
+			.into_iter()
+			.partition(|info| info.value.downloading(&calling_client));
+		for info in &infos {
+			let key = (info.idx, info.name.clone());
+			model.reserve_texture_memory(&calling_client, &key, &info.value)?;
+		}
+		for info in downloading {
+			let key = (info.idx, info.name);
+			model.set_material_parameter_after_download(&calling_client, key, info.value)?;
+		}
+
+		let mut pending_material_parameters = model.pending_material_parameters.lock();
+		for info in infos {
+			pending_material_parameters.insert((info.idx, info.name), info.value);
+		}
+
+		Ok(())
+	}
+
+	/// Makes a material glow without the client knowing what the shader calls its emissive
+	/// parameter. The color gets multiplied by the intensity, so it can go past 1 for bloom.
+	fn set_emissive_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
+		ensure!(model.loaded.lock().is_some(), "Model is not loaded yet");
+
+		#[derive(Deserialize)]
+		struct EmissiveInfo {
+			#[serde(default = "all_materials")]
+			idx: i32,
+			color: MaterialColor,
+			intensity: f32,
+		}
+		fn all_materials() -> i32 {
+			ALL_MATERIALS
+		}
+		let info: EmissiveInfo = deserialize(data)?;
+		model.check_material_idx(info.idx)?;
+		ensure!(
+			info.intensity.is_finite() && info.intensity >= 0.0,
+			"Emissive intensity must be a positive number"
+		);
+
+		model.check_emissive_slot(info.idx)?;
+
+		let linear = info.color.to_linear();
+		let color = MaterialColor::Rgba([
+			linear.r * info.intensity,
+			linear.g * info.intensity,
+			linear.b * info.intensity,
+			linear.a,
+		]);
+		model.pending_material_parameters.lock().insert(
+			(info.idx, EMISSIVE_PARAMETER.to_string()),
+			MaterialParameter::Color(color),
+		);
+		Ok(())
+	}
 
-				Ok(SendWrapper::new(sk.model_copy(model)))
+	/// Binds a texture owned by something else to a material parameter, for as long as it lives.
+	pub fn set_shared_texture(
+		&self,
+		idx: i32,
+		name: String,
+		tex: Weak<SendWrapper<Tex>>,
+	) -> Result<()> {
+		self.check_material_idx(idx)?;
+		self.pending_material_parameters.lock().insert(
+			(idx, name),
+			MaterialParameter::SharedTexture(SharedTexture(tex)),
+		);
+		Ok(())
+	}
+
+	fn reset_material_parameter_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
+
+		#[derive(Deserialize)]
+		struct MaterialParameterResetInfo {
+			idx: i32,
+			name: String,
+		}
+		let info: MaterialParameterResetInfo = deserialize(data)?;
+		model.check_material_idx(info.idx)?;
+
+		let key = (info.idx, info.name);
+		model.texture_reservations.lock().remove(&key);
+		model.pending_material_parameters.lock().remove(&key);
+		model.pending_material_parameter_resets.lock().push(key);
+
+		Ok(())
+	}
+
+	fn set_wireframe_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
+		let wireframe: bool = deserialize(data)?;
+		if model.wireframe.swap(wireframe, Ordering::Relaxed) != wireframe {
+			model.render_state_changed.store(true, Ordering::Relaxed);
+		}
+		Ok(())
+	}
+
+	fn set_depth_test_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
+		let depth_test: bool = deserialize(data)?;
+		if model.depth_test.swap(depth_test, Ordering::Relaxed) != depth_test {
+			model.render_state_changed.store(true, Ordering::Relaxed);
+		}
+		Ok(())
+	}
+
+	/// Overrides the transparency of every material, `None` goes back to each one's own. This
+	/// doesn't change draw order, create the model as transparent for blending to sort right.
+	fn set_transparency_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
+		let transparency: Option<ModelTransparency> = deserialize(data)?;
+		let mut current = model.transparency.lock();
+		if *current != transparency {
+			*current = transparency;
+			model.render_state_changed.store(true, Ordering::Relaxed);
+		}
+		Ok(())
+	}
+
+	/// Fades the model in or out over `duration` seconds instead of popping, a fade out keeps
+	/// drawing until it's done and only then disables the node. Reversing partway picks up from
+	/// wherever the last fade got to, and a duration of 0 is the same as `set_enabled`.
+	fn set_enabled_smoothed_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		#[derive(Deserialize)]
+		struct SmoothedEnabled {
+			enabled: bool,
+			duration: f32,
+		}
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
+		let info: SmoothedEnabled = deserialize(data)?;
+		ensure!(info.duration.is_finite(), "Fade duration must be finite");
+		let mut fade = model.fade.lock();
+		let visible = model.enabled.load(Ordering::Relaxed);
+		if info.duration <= 0.0 {
+			if fade.take().is_some() {
+				model.render_state_changed.store(true, Ordering::Relaxed);
+			}
+			model.enabled.store(info.enabled, Ordering::Relaxed);
+			return Ok(());
+		}
+		if fade.is_none() && visible == info.enabled {
+			return Ok(());
+		}
+		let from = fade
+			.as_ref()
+			.map_or(if visible { 1.0 } else { 0.0 }, Fade::opacity);
+		*fade = Some(Fade {
+			from,
+			to: if info.enabled { 1.0 } else { 0.0 },
+			started: Instant::now(),
+			duration: Duration::from_secs_f32(info.duration),
+		});
+		model.enabled.store(true, Ordering::Relaxed);
+		model.render_state_changed.store(true, Ordering::Relaxed);
+		Ok(())
+	}
+	/// Instant toggles win over a fade in progress.
+	pub fn cancel_fade(&self) {
+		if self.fade.lock().take().is_some() {
+			self.render_state_changed.store(true, Ordering::Relaxed);
+		}
+	}
+	fn is_fading(&self) -> bool {
+		self.fade.lock().is_some()
+	}
+	/// Ends a finished fade, disabling the node if it faded out.
+	fn update_fade(&self) {
+		let mut fade = self.fade.lock();
+		let Some(current) = fade.as_ref() else {return};
+		if !current.is_done() {
+			return;
+		}
+		if current.to == 0.0 {
+			self.enabled.store(false, Ordering::Relaxed);
+		}
+		*fade = None;
+		self.render_state_changed.store(true, Ordering::Relaxed);
+	}
+	fn tint(&self) -> Color128 {
+		let opacity = self.fade.lock().as_ref().map_or(1.0, Fade::opacity);
+		Color128::from([1.0, 1.0, 1.0, opacity])
+	}
+
+	fn set_shader_flex(node: &Node, calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
+		let resource: ResourceID = deserialize(data)?;
+		let shader_path = resource
+			.get_file(
+				&calling_client.base_resource_prefixes.lock().clone(),
+				&[OsStr::new("sks")],
+			)
+			.ok_or_else(|| eyre!("Shader resource not found"))?;
+		// Shaders can only be loaded on the render thread, so anything that's obviously not one
+		// gets caught here where the client can still be told
+		let mut magic = [0; SHADER_MAGIC.len()];
+		std::fs::File::open(&shader_path)
+			.and_then(|mut file| file.read_exact(&mut magic))
+			.map_err(|e| eyre!("Unable to read shader: {e}"))?;
+		ensure!(&magic == SHADER_MAGIC, "Not a compiled StereoKit shader");
+
+		model.pending_shader.lock().replace(shader_path);
+		Ok(())
+	}
+
+	fn set_always_visible_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
+		model
+			.always_visible
+			.store(deserialize(data)?, Ordering::Relaxed);
+		Ok(())
+	}
+
+	fn set_lod_levels_flex(node: &Node, calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
+		let levels: Vec<(ResourceID, f32)> = deserialize(data)?;
+		let prefixes = calling_client.base_resource_prefixes.lock().clone();
+		let extensions = extension_list(model.extensions.as_deref(), MODEL_EXTENSIONS);
+		let mut lod_levels = levels
+			.into_iter()
+			.map(|(resource, max_distance)| {
+				let path = resource
+					.get_file(&prefixes, &extensions)
+					.ok_or_else(|| eyre!("LOD resource {resource:?} not found"))?;
+				Ok(LodLevel::new(path, max_distance))
+			})
+			.collect::<Result<Vec<_>>>()?;
+		lod_levels.sort_by(|a, b| a.max_distance.total_cmp(&b.max_distance));
+
+		let old_levels = std::mem::replace(&mut *model.lod_levels.lock(), lod_levels);
+		for level in old_levels {
+			if let Some(sk_model) = level.sk_model.into_inner() {
+				destroy_queue::add(sk_model);
+			}
+			if let Some(copy) = level.copy.into_inner() {
+				destroy_queue::add(copy);
+			}
+		}
+		Ok(())
+	}
+
+	fn set_render_layer_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
+		#[derive(Deserialize)]
+		struct RenderLayerInfo {
+			layer: u32,
+			/// Only this material's part of the model goes on the layer, defaults to all of it
+			#[serde(default)]
+			material_idx: Option<i32>,
+		}
+		let info: RenderLayerInfo = deserialize(data)?;
+		let layer = RenderLayer::from_bits_truncate(info.layer).bits();
+		match info.material_idx.filter(|idx| *idx != ALL_MATERIALS) {
+			Some(material_idx) => {
+				model.check_material_idx(material_idx)?;
+				model
+					.material_render_layers
+					.lock()
+					.insert(material_idx, layer);
+			}
+			None => {
+				model.material_render_layers.lock().clear();
+				model.render_layer.store(layer, Ordering::Relaxed);
+			}
+		}
+		Ok(())
+	}
+	/// The layers the client lets its models draw on.
+	fn layer_mask(&self) -> RenderLayer {
+		self.client
+			.upgrade()
+			.map_or(RenderLayer::empty(), |client| {
+				RenderLayer::from_bits_truncate(client.render_layer_mask.load(Ordering::Relaxed))
 			})
-			.ok();
+	}
+	fn render_layer(&self) -> RenderLayer {
+		let layer = RenderLayer::from_bits_truncate(self.render_layer.load(Ordering::Relaxed));
+		layer & self.layer_mask()
+	}
+
+	/// Each material gets its own draw so they can go on different layers.
+	fn draw_split_layers(
+		&self,
+		sk: &impl StereoKitDraw,
+		sk_model: &SKModel,
+		transform: Mat4,
+		tint: Color128,
+		material_layers: &FxHashMap<i32, u32>,
+	) {
+		let model_layer = self.render_layer();
+		let mask = self.layer_mask();
+		for subset in 0..material_count(sk_model) {
+			let layer = material_layers.get(&subset).map_or(model_layer, |bits| {
+				RenderLayer::from_bits_truncate(*bits) & mask
+			});
+			if layer.is_empty() {
+				continue;
+			}
+			let Some(mesh) = sk.model_get_mesh(sk_model, subset) else {continue};
+			let Some(material) = sk.model_get_material(sk_model, subset) else {continue};
+			let subset_transform = sk.model_get_transform(sk_model, subset);
+			sk.mesh_draw(&mesh, &material, transform * subset_transform, tint, layer);
+			frame_stats::count_draw_call();
+		}
+	}
+
+	/// Draws the closest matching LOD level, returns false if there are no LOD levels.
+	fn draw_lod(
+		&self,
+		sk: &impl StereoKitDraw,
+		loaded: &LoadedModel,
+		views: &FrameViews,
+		transform: Mat4,
+		tint: Color128,
+	) -> bool {
+		let lod_levels = self.lod_levels.lock();
+		if lod_levels.is_empty() {
+			return false;
+		}
+		let distance = transform
+			.transform_point3(Vec3::ZERO)
+			.distance(views.origin);
+		// Past the last level's distance nothing gets drawn at all
+		let Some(level) = select_lod(distance, &lod_levels) else {return true};
+		// The full model stands in until the level's file has been parsed
+		let Some(sk_model) = level.model() else {return false};
+		let copy = level
+			.copy
+			.get_or_init(|| SendWrapper::new(sk.model_copy(sk_model.as_ref().as_ref())));
+		let material_version = self.material_version.load(Ordering::Relaxed);
+		let synced_version = level
+			.synced_version
+			.swap(material_version, Ordering::Relaxed);
+		if synced_version != material_version {
+			// LOD files are expected to have the same materials in the same order as the full
+			// model, so replacements, parameters and the shader carry over by index
+			let customized = self.customized.load(Ordering::Relaxed);
+			for idx in 0..material_count(copy.as_ref()) {
+				let material = customized
+					.then(|| sk.model_get_material(loaded.sk_model.as_ref(), idx))
+					.flatten()
+					.or_else(|| sk.model_get_material(sk_model.as_ref().as_ref(), idx));
+				let Some(material) = material else {continue};
+				sk.model_set_material(copy.as_ref(), idx, &material);
+			}
+		}
+		sk.model_draw(copy.as_ref(), transform, tint, self.render_layer());
+		frame_stats::count_model_drawn();
+		true
+	}
+
+	/// For references, draws the model as it currently looks at another transform. Nothing shows
+	/// up until the model has loaded, and LOD levels aren't used.
+	pub(super) fn draw_at(&self, sk: &impl StereoKitDraw, transform: Mat4) {
+		let loaded = self.loaded.lock();
+		let Some(loaded) = loaded.as_ref() else {return};
+		let layer = self.render_layer();
+		if layer.is_empty() {
+			return;
+		}
+		sk.model_draw(loaded.sk_model.as_ref(), transform, self.tint(), layer);
+		frame_stats::count_model_drawn();
+	}
+
+	fn is_visible(&self, views: &FrameViews, bounds: (Vec3, f32), transform: Mat4) -> bool {
+		if self.always_visible.load(Ordering::Relaxed) {
+			return true;
+		}
+		views.intersects_bounds(bounds, transform)
+	}
+
+	fn has_custom_render_state(&self) -> bool {
+		self.wireframe.load(Ordering::Relaxed)
+			|| !self.depth_test.load(Ordering::Relaxed)
+			|| self.transparency.lock().is_some()
+			|| self.is_fading()
+			|| self.shader.lock().is_some()
+	}
 
-		if let Some(sk_model) = sk_model {
+	fn get_material_count_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		_data: &[u8],
+	) -> Result<Vec<u8>> {
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
+		let loaded = model.loaded.lock();
+		let Some(loaded) = loaded.as_ref() else {bail!("Model is not loaded yet")};
+		serialize(loaded.material_count).map_err(|e| e.into())
+	}
+
+	fn get_material_names_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		_data: &[u8],
+	) -> Result<Vec<u8>> {
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
+		let loaded = model.loaded.lock();
+		let Some(loaded) = loaded.as_ref() else {bail!("Model is not loaded yet")};
+		serialize(&loaded.material_names).map_err(|e| e.into())
+	}
+
+	fn get_node_names_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		_data: &[u8],
+	) -> Result<Vec<u8>> {
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
+		let loaded = model.loaded.lock();
+		let Some(loaded) = loaded.as_ref() else {bail!("Model is not loaded yet")};
+		serialize(&loaded.node_names).map_err(|e| e.into())
+	}
+
+	/// Raycasts run on the client's thread, against the copy of the model kept for them.
+	fn raycast_flex(node: &Node, calling_client: Arc<Client>, data: &[u8]) -> Result<Vec<u8>> {
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
+
+		#[derive(Deserialize)]
+		struct RaycastInfo<'a> {
+			reference_space_path: &'a str,
+			ray_origin: Vector3<f32>,
+			ray_direction: Vector3<f32>,
+		}
+		let info: RaycastInfo = deserialize(data)?;
+		let reference_space = find_reference_space(&calling_client, info.reference_space_path)?;
+
+		let loaded = model.loaded.lock();
+		let Some(loaded) = loaded.as_ref() else {bail!("Model is not loaded yet")};
+		let reference_to_model =
+			Spatial::space_to_space_matrix(Some(&reference_space), Some(&model.space));
+		let ray = SKRay {
+			pos: reference_to_model.transform_point3(info.ray_origin.into()),
+			dir: reference_to_model.transform_vector3(info.ray_direction.into()),
+		};
+		let hit = model_ray_intersect(&loaded.raycast_model.lock().0, ray).map(|hit| {
+			raycast_hit(
+				reference_to_model.inverse(),
+				hit.pos,
+				hit.dir,
+				info.ray_origin.into(),
+			)
+		});
+		serialize(hit).map_err(|e| e.into())
+	}
+
+	fn get_last_set_material_parameter_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<Vec<u8>> {
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
+		ensure!(model.loaded.lock().is_some(), "Model is not loaded yet");
+
+		#[derive(Deserialize)]
+		struct MaterialParameterQueryInfo {
+			idx: i32,
+			name: String,
+		}
+		let info: MaterialParameterQueryInfo = deserialize(data)?;
+		ensure!(
+			info.idx != ALL_MATERIALS,
+			"Material index must be a single material"
+		);
+		model.check_material_idx(info.idx)?;
+
+		let value = model.last_set_material_parameter(info.idx, &info.name)?;
+		serialize(value).map_err(|e| e.into())
+	}
+
+	/// The value the client last set for the parameter, including ones the render thread hasn't
+	/// applied yet. This is a cache of what was set rather than a read of the StereoKit material,
+	/// so the shader's defaults and the model file's own values are never returned. Textures and
+	/// such aren't sent back as they can be a lot of data.
+	fn last_set_material_parameter(&self, idx: i32, name: &str) -> Result<MaterialParameter> {
+		let value = {
+			let pending = self.pending_material_parameters.lock();
+			let pending_value = pending
+				.get(&(idx, name.to_string()))
+				.or_else(|| pending.get(&(ALL_MATERIALS, name.to_string())))
+				.cloned();
+			match pending_value {
+				Some(value) => Some(value),
+				None => {
+					let resets = self.pending_material_parameter_resets.lock();
+					let resetting = resets.iter().any(|(reset_idx, reset_name)| {
+						(*reset_idx == idx || *reset_idx == ALL_MATERIALS) && reset_name == name
+					});
+					if resetting {
+						None
+					} else {
+						self.material_parameters
+							.lock()
+							.get(&idx)
+							.and_then(|parameters| parameters.get(name))
+							.cloned()
+					}
+				}
+			}
+		};
+		let Some(value) = value else {
+			bail!("Parameter {name} hasn't been set on material {idx}")
+		};
+		match value {
+			MaterialParameter::Texture(_)
+			| MaterialParameter::TextureEx { .. }
+			| MaterialParameter::TextureRaw { .. }
+			| MaterialParameter::Cubemap(_)
+			| MaterialParameter::RenderTarget(_)
+			| MaterialParameter::SharedTexture(_) => {
+				bail!("Parameter {name} can't be read back from the material")
+			}
+			value => Ok(value),
+		}
+	}
+
+	pub fn introspect(&self) -> ModelIntrospection {
+		ModelIntrospection {
+			path: self.pending_model_path.get().cloned(),
+			material_count: self.loaded.lock().as_ref().map(|l| l.material_count),
+		}
+	}
+
+	pub fn global_transform(&self) -> Mat4 {
+		self.space.global_transform()
+	}
+
+	fn check_node_name(&self, name: &str) -> Result<()> {
+		let loaded = self.loaded.lock();
+		let Some(loaded) = loaded.as_ref() else {bail!("Model is not loaded yet")};
+		ensure!(
+			loaded.node_names.iter().any(|node_name| node_name == name),
+			"Model has no node named {name}"
+		);
+		Ok(())
+	}
+
+	fn set_node_transform_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
+
+		#[derive(Deserialize)]
+		struct NodeTransformInfo {
+			name: String,
+			/// Relative to the model node's parent
+			transform: Transform,
+		}
+		let info: NodeTransformInfo = deserialize(data)?;
+		model.check_node_name(&info.name)?;
+
+		model
+			.pending_node_transforms
+			.lock()
+			.insert(info.name, parse_transform(info.transform, true, true, true));
+		Ok(())
+	}
+
+	fn get_node_transform_flex(
+		node: &Node,
+		calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<Vec<u8>> {
+		let Some(Drawable::Model(model)) = node.drawable.get() else {bail!("Not a drawable??")};
+
+		#[derive(Deserialize)]
+		struct NodeTransformQueryInfo<'a> {
+			name: String,
+			reference_space_path: &'a str,
+		}
+		let info: NodeTransformQueryInfo = deserialize(data)?;
+		model.check_node_name(&info.name)?;
+		let reference_space = find_reference_space(&calling_client, info.reference_space_path)?;
+
+		let model_space_transform = model
+			.loaded
+			.lock()
+			.as_ref()
+			.and_then(|loaded| loaded.node_transforms.lock().get(&info.name).copied())
+			.ok_or_else(|| eyre!("Node not found"))?;
+
+		let (scale, rotation, position) =
+			(Spatial::space_to_space_matrix(Some(&model.space), Some(&reference_space))
+				* model_space_transform)
+				.to_scale_rotation_translation();
+		serialize((
+			mint::Vector3::from(position),
+			mint::Quaternion::from(rotation),
+			mint::Vector3::from(scale),
+		))
+		.map_err(|e| e.into())
+	}
+
+	/// For every material when it's `ALL_MATERIALS`, as long as at least one of them has a slot.
+	fn check_emissive_slot(&self, idx: i32) -> Result<()> {
+		let loaded = self.loaded.lock();
+		let Some(loaded) = loaded.as_ref() else {bail!("Model is not loaded yet")};
+		let emissive_slots = loaded.emissive_slots.lock();
+		let has_slot = if idx == ALL_MATERIALS {
+			emissive_slots.iter().any(|has_slot| *has_slot)
+		} else {
+			emissive_slots.get(idx as usize).copied().unwrap_or(false)
+		};
+		ensure!(
+			has_slot,
+			"Material's shader has no {EMISSIVE_PARAMETER} color to glow with"
+		);
+		Ok(())
+	}
+	fn read_node_transforms(
+		sk: &impl StereoKitDraw,
+		sk_model: &SKModel,
+		node_names: &[String],
+	) -> FxHashMap<String, Mat4> {
+		node_names
+			.iter()
+			.filter_map(|name| {
+				let node = sk.model_node_find(sk_model, name)?;
+				let transform = sk.model_node_get_transform_model(sk_model, node);
+				Some((name.clone(), transform))
+			})
+			.collect()
+	}
+	fn read_emissive_slots(sk: &impl StereoKitDraw, sk_model: &SKModel) -> Vec<bool> {
+		(0..material_count(sk_model))
+			.map(|idx| {
+				sk.model_get_material(sk_model, idx)
+					.is_some_and(|material| {
+						sk.material_has_param(
+							&material,
+							EMISSIVE_PARAMETER,
+							SKMaterialParameter::Color128,
+						)
+					})
+			})
+			.collect()
+	}
+
+	fn process_render_thread_requests(&self, sk: &impl StereoKitDraw) {
+		if let Some(shader_path) = self.pending_shader.lock().take() {
+			match sk.shader_create_file(&shader_path) {
+				Ok(shader) => {
+					if let Some(old_shader) = self.shader.lock().replace(SendWrapper::new(shader)) {
+						destroy_queue::add(old_shader);
+					}
+					self.render_state_changed.store(true, Ordering::Relaxed);
+				}
+				Err(e) => error!(?shader_path, "Unable to load shader: {e}"),
+			}
+		}
+	}
+
+	/// Indices can only be checked once the model has loaded, before that they're queued as-is.
+	fn check_material_idx(&self, idx: i32) -> Result<()> {
+		if idx == ALL_MATERIALS {
+			return Ok(());
+		}
+		ensure!(idx >= 0, "Material index {idx} is negative");
+		if let Some(material_count) = self.loaded.lock().as_ref().map(|l| l.material_count) {
+			ensure!(
+				(idx as u32) < material_count,
+				"Material index {idx} is out of range, model has {material_count} materials"
+			);
+		}
+		Ok(())
+	}
+
+	/// `None` while the model file is still being parsed.
+	fn load(&self, sk: &impl StereoKitDraw, bypass_cache: bool) -> Result<Option<LoadedModel>> {
+		let model = match self.pending_model_path.get() {
+			Some(pending_model_path) => match self.model_file(pending_model_path, bypass_cache)? {
+				Some(model) => model,
+				None => return Ok(None),
+			},
+			None => Arc::new(SendWrapper::new(Self::create_placeholder(sk)?)),
+		};
+		let material_count = material_count(model.as_ref().as_ref());
+		let material_names = (0..material_count)
+			.filter_map(|idx| sk.model_get_material(model.as_ref().as_ref(), idx))
+			.map(|material| sk.material_get_id(&material).to_string())
+			.collect();
+		// Unnamed nodes can't be looked up by name, so they're left out
+		let node_names: Vec<String> = (0..sk.model_node_count(model.as_ref().as_ref()))
+			.filter_map(|idx| sk.model_node_index(model.as_ref().as_ref(), idx))
+			.filter_map(|node| sk.model_node_get_name(model.as_ref().as_ref(), node))
+			.map(str::to_string)
+			.collect();
+		let node_transforms = Self::read_node_transforms(sk, model.as_ref().as_ref(), &node_names);
+		let emissive_slots = Self::read_emissive_slots(sk, model.as_ref().as_ref());
+		let raycast_model = ParsedModel(sk.model_copy(model.as_ref().as_ref()));
+		let bounds = model_bounds(model.as_ref().as_ref());
+		let dimensions = bounds.dimensions;
+		let center = bounds.center;
+		let fit_transform = fit_transform(*self.normalize_size.lock(), center, dimensions);
+		// A reload takes the old fit back out, so the client's own transform is kept
+		let old_fit_transform = std::mem::replace(&mut *self.fit_transform.lock(), fit_transform);
+		if old_fit_transform != fit_transform {
+			self.space.set_local_transform(refit(
+				self.space.local_transform(),
+				old_fit_transform,
+				fit_transform,
+			));
+		}
+
+		Ok(Some(LoadedModel {
+			sk_model: SendWrapper::new(sk.model_copy(model.as_ref().as_ref())),
+			shared: model,
+			material_count: material_count as u32,
+			material_names,
+			node_names,
+			node_transforms: Mutex::new(node_transforms),
+			raycast_model: Mutex::new(raycast_model),
+			emissive_slots: Mutex::new(emissive_slots),
+			bounds: (center, dimensions.length() * 0.5),
+		}))
+	}
+
+	fn model_file(
+		&self,
+		path: &Path,
+		bypass_cache: bool,
+	) -> Result<Option<Arc<SendWrapper<SKModel>>>> {
+		if !bypass_cache {
+			if let Some(model) = MODEL_CACHE.lock().get(&path.to_path_buf()) {
+				return Ok(Some(model));
+			}
+		}
+		let mut model_parse = self.model_parse.lock();
+		let result = match model_parse.as_ref() {
+			Some(running) => match running.poll() {
+				Some(result) => result,
+				None => return Ok(None),
+			},
+			None => {
+				*model_parse = Some(ModelParse::start(path.to_path_buf())?);
+				return Ok(None);
+			}
+		};
+		*model_parse = None;
+		let model = result
+			.map_err(|e| eyre!("Unable to load model file for {:?}: {e}", self.resource_id))?;
+		// A reload replaces the cached model, other models of the file pick it up on their own
+		// reload
+		Ok(Some(MODEL_CACHE.lock().insert(
+			path.to_path_buf(),
+			path,
+			model,
+		)))
+	}
+
+	/// A magenta cube, so models with missing resources can still be found in the scene.
+	fn create_placeholder(sk: &impl StereoKitDraw) -> Result<SKModel> {
+		let material = sk.material_copy(sk.material_find("default/material")?);
+		sk.material_set_color(&material, "color", Color128::from([1.0, 0.0, 1.0, 1.0]));
+		let mesh = sk.mesh_gen_cube(Vec3::splat(0.1), 0);
+		Ok(sk.model_create_mesh(&mesh, &material))
+	}
+
+	/// `None` for disabled models, which aren't drawn at all.
+	fn prepare_draw(&self, views: &FrameViews) -> Option<PreparedDraw> {
+		if !self.enabled.load(Ordering::Relaxed) {
+			return None;
+		}
+		let placement = self.loaded.lock().as_ref().map(|loaded| ModelPlacement {
+			bounds: loaded.bounds,
+		});
+		Some(PreparedDraw::new(
+			self.space.global_transform(),
+			placement,
+			self.always_visible.load(Ordering::Relaxed),
+			views,
+		))
+	}
+
+	fn draw(
+		&self,
+		sk: &impl StereoKitDraw,
+		views: &FrameViews,
+		prepared: PreparedDraw,
+	) -> Option<Mat4> {
+		let mut loaded = self.loaded.lock();
+		let reloading = self.reload_pending.load(Ordering::Relaxed);
+		// The old model keeps being drawn until the new file has been read
+		if loaded.is_none() || reloading {
+			let mut load_failures = self.load_failures.lock();
+			if reloading && load_failures.given_up() {
+				// The file changed, so it might load now
+				*load_failures = LoadFailures::default();
+			}
+			let load = if load_failures.should_retry() {
+				self.load(sk, reloading)
+			} else {
+				Ok(None)
+			};
+			let new_loaded = match load {
+				Ok(new_loaded) => new_loaded,
+				Err(e) => {
+					if load_failures.failed(&self.resource_id, e) {
+						// A model that's already there keeps showing its old version
+						self.reload_pending.store(false, Ordering::Relaxed);
+					}
+					None
+				}
+			};
+			if new_loaded.is_some() {
+				*load_failures = LoadFailures::default();
+			}
+			drop(load_failures);
+			if let Some(new_loaded) = new_loaded {
+				if let Some(old_loaded) = loaded.replace(new_loaded) {
+					destroy_queue::add(old_loaded);
+				}
+				self.material_version.fetch_add(1, Ordering::Relaxed);
+				if reloading {
+					self.reload_pending.store(false, Ordering::Relaxed);
+					// Re-apply all the material parameters on top of the new materials
+					for (_, material) in self.base_materials.lock().drain() {
+						destroy_queue::add(material);
+					}
+					for (_, material) in self.applied_materials.lock().drain() {
+						destroy_queue::add(material);
+					}
+					self.customized.store(false, Ordering::Relaxed);
+					self.render_state_changed.store(true, Ordering::Relaxed);
+				}
+			}
+		}
+
+		if let Some(loaded) = loaded.as_ref() {
+			let sk_model = &loaded.sk_model;
+			let mut dirty_materials = Vec::new();
+			if self.render_state_changed.swap(false, Ordering::Relaxed) {
+				dirty_materials.extend(0..material_count(sk_model.as_ref()));
+			}
+			// Replaced or rebuilt materials might have a different shader
+			let mut materials_changed = false;
 			{
+				let mut base_materials = self.base_materials.lock();
+				let mut applied_materials = self.applied_materials.lock();
 				let mut material_replacements = self.pending_material_replacements.lock();
-				for (material_idx, replacement_material) in material_replacements.iter() {
-					if sk
-						.model_get_material(sk_model.as_ref(), *material_idx as i32)
-						.is_some()
-					{
+				let material_count = material_count(sk_model.as_ref());
+				apply_available(
+					&mut material_replacements,
+					material_count,
+					|material_idx, replacement_material| {
 						sk.model_set_material(
 							sk_model.as_ref(),
-							*material_idx as i32,
+							material_idx,
 							replacement_material.as_ref().as_ref(),
 						);
-					}
-				}
-				material_replacements.clear();
+						self.customized.store(true, Ordering::Relaxed);
+						materials_changed = true;
+						// The replacement becomes the new base for parameters and render state
+						let old_base = base_materials.remove(&material_idx);
+						if old_base.is_some() || self.has_custom_render_state() {
+							dirty_materials.push(material_idx);
+						}
+						if let Some(old_base) = old_base {
+							destroy_queue::add(old_base);
+						}
+						if let Some(old_applied) = applied_materials.remove(&material_idx) {
+							destroy_queue::add(old_applied);
+						}
+					},
+				);
 			}
 
 			if let Some(client) = self.space.node.upgrade().and_then(|n| n.client.upgrade()) {
-				let mut material_parameters = self.pending_material_parameters.lock();
-				for ((material_idx, parameter_name), parameter_value) in material_parameters.drain()
-				{
-					let Some(material) = sk.model_get_material(sk_model.as_ref(), material_idx) else {continue};
-					let new_material = sk.material_copy(material);
-					parameter_value.apply_to_material(
-						&client,
-						sk,
-						&new_material,
-						parameter_name.as_str(),
-					);
+				let mut material_parameters = self.material_parameters.lock();
+				// Shared textures whose owner is gone would keep showing its last contents
+				for (material_idx, parameters) in material_parameters.iter_mut() {
+					let len = parameters.len();
+					parameters.retain(|_, parameter| match parameter {
+						MaterialParameter::SharedTexture(SharedTexture(tex)) => {
+							tex.strong_count() > 0
+						}
+						_ => true,
+					});
+					if parameters.len() != len {
+						dirty_materials.push(*material_idx);
+					}
+				}
+				// Taken one at a time, `material_parameter` locks them the other way around
+				let resets: Vec<_> = self
+					.pending_material_parameter_resets
+					.lock()
+					.drain(..)
+					.collect();
+				let values: Vec<_> = self.pending_material_parameters.lock().drain().collect();
+				let mut applied_materials = self.applied_materials.lock();
+				let updated_parameters = merge_parameter_changes(
+					&mut material_parameters,
+					&mut dirty_materials,
+					resets,
+					values,
+					material_count(sk_model.as_ref()),
+					|material_idx| applied_materials.contains_key(&material_idx),
+				);
+				for (material_idx, parameter_names) in updated_parameters {
+					let Some(applied_material) = applied_materials.get(&material_idx) else {continue};
+					let Some(parameters) = material_parameters.get(&material_idx) else {continue};
+					for parameter_name in parameter_names {
+						let Some(parameter_value) = parameters.get(&parameter_name) else {continue};
+						parameter_value.apply_to_material(
+							&client,
+							sk,
+							applied_material.as_ref(),
+							&parameter_name,
+						);
+					}
+				}
+
+				// Rebuild from the original material so resetting one parameter keeps the others
+				let wireframe = self.wireframe.load(Ordering::Relaxed);
+				let depth_test = self.depth_test.load(Ordering::Relaxed);
+				// A fade's alpha only shows up on materials that blend
+				let transparency = if self.is_fading() {
+					Some(ModelTransparency::Blend)
+				} else {
+					*self.transparency.lock()
+				};
+				let shader = self.shader.lock();
+				let mut base_materials = self.base_materials.lock();
+				for material_idx in dirty_materials {
+					if let Entry::Vacant(entry) = base_materials.entry(material_idx) {
+						let Some(material) = sk.model_get_material(sk_model.as_ref(), material_idx) else {continue};
+						entry.insert(SendWrapper::new(material));
+					}
+					let base_material = base_materials.get(&material_idx).unwrap();
+					let new_material = sk.material_copy(base_material.as_ref());
+					if let Some(shader) = shader.as_ref() {
+						sk.material_set_shader(&new_material, shader.as_ref());
+					}
+					for (parameter_name, parameter_value) in
+						material_parameters.get(&material_idx).into_iter().flatten()
+					{
+						parameter_value.apply_to_material(
+							&client,
+							sk,
+							&new_material,
+							parameter_name.as_str(),
+						);
+					}
+					sk.material_set_wireframe(&new_material, wireframe);
+					if let Some(transparency) = transparency {
+						sk.material_set_transparency(&new_material, transparency.into());
+					}
+					if !depth_test {
+						// Draw over everything else, after the main scene
+						sk.material_set_depth_test(&new_material, DepthTest::Always);
+						sk.material_set_depth_write(&new_material, false);
+						sk.material_set_queue_offset(&new_material, 100);
+					}
 					sk.model_set_material(sk_model.as_ref(), material_idx, &new_material);
+					self.customized.store(true, Ordering::Relaxed);
+					materials_changed = true;
+					if let Some(old_applied) =
+						applied_materials.insert(material_idx, SendWrapper::new(new_material))
+					{
+						destroy_queue::add(old_applied);
+					}
 				}
 			}
+			if materials_changed {
+				*loaded.emissive_slots.lock() = Self::read_emissive_slots(sk, sk_model.as_ref());
+				self.material_version.fetch_add(1, Ordering::Relaxed);
+			}
 
-			sk.model_draw(
-				sk_model.as_ref(),
-				self.space.global_transform(),
-				WHITE,
-				RenderLayer::LAYER0,
-			);
+			let mut nodes_moved = false;
+			for (name, transform) in self.pending_node_transforms.lock().drain() {
+				let Some(node) = sk.model_node_find(sk_model.as_ref(), &name) else {continue};
+				sk.model_node_set_transform_local(sk_model.as_ref(), node, transform);
+				self.customized.store(true, Ordering::Relaxed);
+				nodes_moved = true;
+			}
+			// Moving a node moves its children too, so every node is read again
+			if nodes_moved {
+				*loaded.node_transforms.lock() =
+					Self::read_node_transforms(sk, sk_model.as_ref(), &loaded.node_names);
+				*loaded.raycast_model.lock() = ParsedModel(sk.model_copy(sk_model.as_ref()));
+			}
+
+			let transform = prepared.transform;
+			let visible = prepared
+				.visible
+				.unwrap_or_else(|| self.is_visible(views, loaded.bounds, transform));
+			if !visible {
+				frame_stats::count_model_culled();
+				return None;
+			}
+			let material_layers = self.material_render_layers.lock();
+			// Masked off every layer it would be drawn on
+			if material_layers.is_empty() && self.render_layer().is_empty() {
+				return None;
+			}
+			let tint = self.tint();
+			if self.draw_lod(sk, loaded, views, transform, tint) {
+				return None;
+			}
+			// Models with their own materials can't share draws with anything else
+			// Transparent ones also have to be drawn in order, which instancing would break
+			// Shared draws all go on the default layer
+			if !self.customized.load(Ordering::Relaxed)
+				&& !self.transparent.load(Ordering::Relaxed)
+				&& !self.is_fading()
+				&& material_layers.is_empty()
+				&& self.render_layer() == RenderLayer::LAYER0
+				&& self.pending_model_path.get().is_some()
+			{
+				return Some(transform);
+			}
+			if !material_layers.is_empty() {
+				self.draw_split_layers(sk, sk_model.as_ref(), transform, tint, &material_layers);
+				return None;
+			}
+			sk.model_draw(sk_model.as_ref(), transform, tint, self.render_layer());
+			frame_stats::count_model_drawn();
 		}
+		None
 	}
 }
 impl Drop for Model {
 	fn drop(&mut self) {
-		if let Some(model) = self.sk_model.take() {
-			destroy_queue::add(model);
+		if let Some(loaded) = self.loaded.get_mut().take() {
+			destroy_queue::add(loaded);
+		}
+		for (_, material) in self.base_materials.get_mut().drain() {
+			destroy_queue::add(material);
+		}
+		for (_, material) in self.applied_materials.get_mut().drain() {
+			destroy_queue::add(material);
+		}
+		if let Some(shader) = self.shader.get_mut().take() {
+			destroy_queue::add(shader);
+		}
+		for level in self.lod_levels.get_mut().drain(..) {
+			if let Some(sk_model) = level.sk_model.into_inner() {
+				destroy_queue::add(sk_model);
+			}
+			if let Some(copy) = level.copy.into_inner() {
+				destroy_queue::add(copy);
+			}
 		}
 		MODEL_REGISTRY.remove(self);
 	}
 }
 
-pub fn draw_all(sk: &impl StereoKitDraw) {
-	for model in MODEL_REGISTRY.get_valid_contents() {
-		if model.enabled.load(Ordering::Relaxed) {
-			model.draw(sk);
+/// StereoKit's OBJ loader doesn't read the `.mtl` file, so the diffuse color and texture of its
+/// first material get turned into parameters for the model's material instead.
+fn obj_material_parameters(obj_path: &Path) -> Vec<(String, MaterialParameter)> {
+	let Ok(obj) = std::fs::read_to_string(obj_path) else {return Vec::new()};
+	let Some(mtl_name) = obj
+		.lines()
+		.find_map(|line| line.trim().strip_prefix("mtllib "))
+	else {
+		return Vec::new();
+	};
+	let mtl_path = obj_path.with_file_name(mtl_name.trim());
+	let Ok(mtl) = std::fs::read_to_string(&mtl_path) else {return Vec::new()};
+
+	let mut parameters = Vec::new();
+	let first_material = mtl
+		.lines()
+		.map(str::trim)
+		.skip_while(|line| !line.starts_with("newmtl "))
+		.skip(1)
+		.take_while(|line| !line.starts_with("newmtl "));
+	for line in first_material {
+		if let Some(diffuse) = line.strip_prefix("Kd ") {
+			let rgb: Vec<f32> = diffuse
+				.split_whitespace()
+				.filter_map(|value| value.parse().ok())
+				.collect();
+			if let [r, g, b] = rgb[..] {
+				let color = MaterialColor::Rgba([r, g, b, 1.0]);
+				parameters.push(("color".to_string(), MaterialParameter::Color(color)));
+			}
+		} else if let Some(texture) = line.strip_prefix("map_Kd ") {
+			let texture = ResourceID::File(mtl_path.with_file_name(texture.trim()));
+			parameters.push(("diffuse".to_string(), MaterialParameter::Texture(texture)));
 		}
 	}
+	parameters
+}
+
+/// Transparent models blend over what's behind them, so the farthest one has to draw first.
+fn farthest_first(a: &PreparedDraw, b: &PreparedDraw) -> std::cmp::Ordering {
+	b.distance.total_cmp(&a.distance)
+}
+
+/// `other_views` are the render targets and screenshots that see this frame's draws too.
+pub(super) fn draw_all(sk: &impl StereoKitDraw, other_views: Vec<ViewFrustum>) {
+	let views = FrameViews::for_frame(sk, other_views);
+	let mut instanced_draws: FxHashMap<usize, (Arc<SendWrapper<SKModel>>, Vec<Mat4>)> =
+		FxHashMap::default();
+	let models = MODEL_REGISTRY.get_valid_contents();
+	for model in &models {
+		model.process_render_thread_requests(sk);
+		model.update_fade();
+	}
+	let prepared_draws = prepare_draws(models, |model| model.prepare_draw(&views));
+	let (mut transparent_draws, opaque_draws): (Vec<_>, Vec<_>) = prepared_draws
+		.into_iter()
+		.partition(|(model, _)| model.transparent.load(Ordering::Relaxed) || model.is_fading());
+	for (model, prepared) in opaque_draws {
+		let Some(transform) = model.draw(sk, &views, prepared) else {continue};
+		let Some(shared) = model.loaded.lock().as_ref().map(|l| l.shared.clone()) else {continue};
+		instanced_draws
+			.entry(Arc::as_ptr(&shared) as usize)
+			.or_insert_with(|| (shared, Vec::new()))
+			.1
+			.push(transform);
+	}
+
+	// StereoKit merges back to back draws of the same mesh and material into one instanced draw
+	for (shared, transforms) in instanced_draws.into_values() {
+		for transform in transforms {
+			sk.model_draw(
+				shared.as_ref().as_ref(),
+				transform,
+				WHITE,
+				RenderLayer::LAYER0,
+			);
+			frame_stats::count_model_drawn();
+		}
+	}
+
+	transparent_draws.sort_by(|(_, a), (_, b)| farthest_first(a, b));
+	for (model, prepared) in transparent_draws {
+		model.draw(sk, &views, prepared);
+	}
 }
 
 pub fn create_flex(_node: &Node, calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
@@ -265,13 +2692,477 @@ pub fn create_flex(_node: &Node, calling_client: Arc<Client>, data: &[u8]) -> Re
 		parent_path: &'a str,
 		transform: Transform,
 		resource: ResourceID,
+		#[serde(default)]
+		watch: bool,
+		/// Show a placeholder instead of failing when the resource can't be found
+		#[serde(default)]
+		fallback: bool,
+		/// File extensions to look for, the first one found wins. Defaults to glb, gltf, obj, ply
+		#[serde(default)]
+		extensions: Option<Vec<String>>,
+		/// Sorts the model with other transparent ones instead of drawing it in any order
+		#[serde(default)]
+		transparent: bool,
+		/// Scales the model so its largest dimension is this many meters and centers it on the
+		/// node by multiplying that into the node's transform once it loads, so children and
+		/// `get_transform` see it too. Sending `true` fits it to 1 meter
+		#[serde(default)]
+		normalize_scale: Option<NormalizeScale>,
+	}
+	#[derive(Deserialize)]
+	#[serde(untagged)]
+	enum NormalizeScale {
+		Enabled(bool),
+		Size(f32),
 	}
 	let info: CreateModelInfo = deserialize(data)?;
+	let normalize_size = match info.normalize_scale {
+		Some(NormalizeScale::Enabled(true)) => Some(1.0),
+		Some(NormalizeScale::Size(size)) => {
+			ensure!(
+				size.is_finite() && size > 0.0,
+				"Normalized size must be positive"
+			);
+			Some(size)
+		}
+		Some(NormalizeScale::Enabled(false)) | None => None,
+	};
 	let node = Node::create(&calling_client, "/drawable/model", info.name, true);
 	let parent = find_spatial_parent(&calling_client, info.parent_path)?;
 	let transform = parse_transform(info.transform, true, true, true);
 	let node = node.add_to_scenegraph()?;
 	Spatial::add_to(&node, Some(parent), transform, false)?;
-	Model::add_to(&node, info.resource)?;
+	let extensions = info.extensions.as_deref();
+	let model = Model::add_to(&node, info.resource, extensions, info.watch, info.fallback)?;
+	model.transparent.store(info.transparent, Ordering::Relaxed);
+	*model.normalize_size.lock() = normalize_size;
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::core::client::INTERNAL_CLIENT;
+
+	fn float(value: f32) -> MaterialParameter {
+		MaterialParameter::Float(value)
+	}
+	fn value(idx: i32, name: &str, value: f32) -> ((i32, String), MaterialParameter) {
+		((idx, name.to_string()), float(value))
+	}
+
+	#[test]
+	fn obj_textures_count_against_the_texture_quota() {
+		let dir = std::env::temp_dir().join(format!("stardust-obj-quota-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		std::fs::write(dir.join("cube.obj"), "mtllib cube.mtl\nv 0 0 0\n").unwrap();
+		std::fs::write(dir.join("cube.mtl"), "newmtl cube\nmap_Kd cube.png\n").unwrap();
+		image::RgbaImage::new(8, 4)
+			.save(dir.join("cube.png"))
+			.unwrap();
+
+		let client = INTERNAL_CLIENT.clone();
+		let node = Node::create(&client, "", "obj_quota", true)
+			.add_to_scenegraph()
+			.unwrap();
+		Spatial::add_to(&node, None, Mat4::IDENTITY, false).unwrap();
+		let resource = ResourceID::File(dir.join("cube.obj"));
+		let model = Model::add_to(&node, resource, None, false, false).unwrap();
+		let key = (0, "diffuse".to_string());
+		assert!(model.pending_material_parameters.lock().contains_key(&key));
+		assert!(model.texture_reservations.lock().contains_key(&key));
+		assert_eq!(
+			model.pending_material_parameters.lock()[&key].texture_bytes(&client),
+			8 * 4 * 4
+		);
+		node.destroy();
+		let _ = std::fs::remove_dir_all(dir);
+	}
+
+	#[test]
+	fn lod_levels_are_picked_by_distance() {
+		let levels = [
+			LodLevel::new("near.glb".into(), 2.0),
+			LodLevel::new("middle.glb".into(), 10.0),
+			LodLevel::new("far.glb".into(), 50.0),
+		];
+		let selected = |distance| select_lod(distance, &levels).map(|level| &level.path);
+		assert_eq!(selected(0.0), Some(&levels[0].path));
+		// A level's max distance is still inside it
+		assert_eq!(selected(2.0), Some(&levels[0].path));
+		assert_eq!(selected(2.001), Some(&levels[1].path));
+		assert_eq!(selected(10.0), Some(&levels[1].path));
+		assert_eq!(selected(30.0), Some(&levels[2].path));
+		assert_eq!(selected(50.0), Some(&levels[2].path));
+		// Too far for every level draws nothing
+		assert_eq!(selected(50.001), None);
+		assert!(select_lod(1.0, &[]).is_none());
+	}
+
+	#[test]
+	fn normalizing_is_applied_to_the_local_transform_once() {
+		let local = Mat4::from_translation(Vec3::new(0.0, 1.0, 0.0));
+		let fit = fit_transform(
+			Some(1.0),
+			Vec3::new(0.0, 0.0, 2.0),
+			Vec3::new(4.0, 2.0, 1.0),
+		);
+		// The far corner of the bounds ends up half a meter out
+		let fitted = refit(local, Mat4::IDENTITY, fit);
+		let corner = fitted.transform_point3(Vec3::new(2.0, 1.0, 2.5));
+		assert!(corner.abs_diff_eq(Vec3::new(0.5, 1.25, 0.125), 1e-6));
+		// Reloading the same file doesn't scale it again, a changed one is refit from scratch
+		assert!(refit(fitted, fit, fit).abs_diff_eq(fitted, 1e-6));
+		let reloaded = fit_transform(Some(1.0), Vec3::ZERO, Vec3::splat(2.0));
+		let refitted = refit(fitted, fit, reloaded);
+		assert!(refitted.abs_diff_eq(local * reloaded, 1e-6));
+		// Nothing to scale
+		assert_eq!(
+			fit_transform(Some(1.0), Vec3::ONE, Vec3::ZERO),
+			Mat4::IDENTITY
+		);
+		assert_eq!(fit_transform(None, Vec3::ONE, Vec3::ONE), Mat4::IDENTITY);
+	}
+
+	#[test]
+	fn transparent_models_draw_farthest_first() {
+		let at = |distance| PreparedDraw {
+			transform: Mat4::IDENTITY,
+			visible: Some(true),
+			distance,
+		};
+		let mut draws = [1.0, f32::INFINITY, 3.0, 0.0, 3.0, 2.0].map(at);
+		draws.sort_by(farthest_first);
+		let distances = draws.map(|draw| draw.distance);
+		assert_eq!(distances, [f32::INFINITY, 3.0, 3.0, 2.0, 1.0, 0.0]);
+	}
+
+	#[test]
+	fn vector_components_keep_their_order() {
+		let int4 = Vector4 {
+			x: 1,
+			y: -2,
+			z: 3,
+			w: -4,
+		};
+		assert_eq!(components(&int4), [1, -2, 3, -4]);
+		let uint4 = Vector4 {
+			x: 5_u32,
+			y: 6,
+			z: 7,
+			w: 8,
+		};
+		assert_eq!(components(&uint4), [5, 6, 7, 8]);
+	}
+
+	#[test]
+	fn non_utf8_model_paths_fail_to_load() {
+		use std::os::unix::ffi::OsStrExt;
+		let path = Path::new(OsStr::from_bytes(b"/tmp/\xffmodel.glb"));
+		let Err(e) = ModelParse::start(path.to_path_buf()) else {
+			panic!("Non UTF-8 path was accepted");
+		};
+		assert!(e.to_string().contains("not valid UTF-8"), "{e}");
+	}
+
+	#[test]
+	fn replacements_wait_for_their_material() {
+		let mut replacements = FxHashMap::default();
+		replacements.insert(0, "first");
+		replacements.insert(2, "third");
+		replacements.insert(u32::MAX, "way out of range");
+		let mut applied = Vec::new();
+		apply_available(&mut replacements, 2, |idx, replacement| {
+			applied.push((idx, *replacement))
+		});
+		assert_eq!(applied, [(0, "first")]);
+		assert_eq!(replacements.len(), 2);
+		// Once the model has loaded with more materials the rest go through
+		apply_available(&mut replacements, 3, |idx, _| applied.push((idx, "")));
+		assert_eq!(applied, [(0, "first"), (2, "")]);
+		assert!(replacements.contains_key(&u32::MAX));
+	}
+
+	#[test]
+	fn overflowing_raw_texture_sizes_are_rejected() {
+		let raw = |width, height| MaterialParameter::TextureRaw {
+			width,
+			height,
+			format: RawTextureFormat::Rgba128,
+			data: vec![0; 2 * 3 * 16],
+		};
+		assert!(raw(2, 3).validate().is_ok());
+		assert!(raw(3, 2).validate().is_ok());
+		assert!(raw(2, 2).validate().is_err());
+		// Wraps around to a small length on 32 bit targets without the checks
+		assert!(raw(u32::MAX, u32::MAX).validate().is_err());
+		assert_eq!(RawTextureFormat::R8.texture_len(7, 5), Some(35));
+	}
+
+	#[test]
+	fn srgb_decodes_to_known_linear_values() {
+		assert_eq!(srgb_to_linear(0.0), 0.0);
+		// The linear segment ends where the curve starts
+		assert!((srgb_to_linear(0.04045) - 0.003_130_8).abs() < 1e-6);
+		assert!((srgb_to_linear(0.04046) - 0.003_131_6).abs() < 1e-6);
+		assert!((srgb_to_linear(0.5) - 0.214_041).abs() < 1e-5);
+		assert!((srgb_to_linear(1.0) - 1.0).abs() < 1e-6);
+	}
+
+	#[test]
+	fn material_colors_convert_only_srgb() {
+		let linear = MaterialColor::Rgba([0.5, 0.5, 0.5, 0.5]).to_linear();
+		assert_eq!([linear.r, linear.g, linear.b, linear.a], [0.5; 4]);
+		let linear =
+			MaterialColor::WithColorSpace([0.5, 0.5, 0.5, 0.5], ColorSpace::Linear).to_linear();
+		assert_eq!([linear.r, linear.g, linear.b, linear.a], [0.5; 4]);
+		// Alpha stays linear
+		let linear =
+			MaterialColor::WithColorSpace([1.0, 0.5, 0.0, 0.5], ColorSpace::Srgb).to_linear();
+		assert!((linear.r - 1.0).abs() < 1e-6);
+		assert!((linear.g - 0.214_041).abs() < 1e-5);
+		assert_eq!((linear.b, linear.a), (0.0, 0.5));
+	}
+
+	#[test]
+	fn resetting_one_parameter_keeps_the_others() {
+		let mut material_parameters = FxHashMap::default();
+		let mut dirty_materials = Vec::new();
+		merge_parameter_changes(
+			&mut material_parameters,
+			&mut dirty_materials,
+			Vec::new(),
+			vec![value(0, "metallic", 1.0), value(0, "roughness", 0.5)],
+			1,
+			|_| false,
+		);
+		assert_eq!(dirty_materials, [0]);
+
+		let mut dirty_materials = Vec::new();
+		merge_parameter_changes(
+			&mut material_parameters,
+			&mut dirty_materials,
+			vec![(0, "metallic".to_string())],
+			Vec::new(),
+			1,
+			|_| true,
+		);
+		// Rebuilt from the original material with only what's left
+		assert_eq!(dirty_materials, [0]);
+		let parameters = &material_parameters[&0];
+		assert!(!parameters.contains_key("metallic"));
+		assert!(matches!(parameters["roughness"], MaterialParameter::Float(v) if v == 0.5));
+	}
+
+	#[test]
+	fn repeated_values_only_copy_the_material_once() {
+		let mut material_parameters = FxHashMap::default();
+		let mut applied = false;
+		let mut copies = 0;
+		for frame in 0..100 {
+			let mut dirty_materials = Vec::new();
+			let updated = merge_parameter_changes(
+				&mut material_parameters,
+				&mut dirty_materials,
+				Vec::new(),
+				vec![value(0, "color_strength", frame as f32)],
+				1,
+				|_| applied,
+			);
+			copies += dirty_materials.len();
+			if !dirty_materials.is_empty() {
+				applied = true;
+			} else {
+				assert_eq!(updated[&0], ["color_strength"]);
+			}
+		}
+		assert_eq!(copies, 1);
+	}
+
+	/// A KTX2 header as written by `toktx`, for a 4x4 RGBA8 texture with one level
+	fn ktx2_header(supercompression: u32) -> Vec<u8> {
+		let mut header = KTX2_IDENTIFIER.to_vec();
+		// vkFormat R8G8B8A8_SRGB, typeSize, width, height, depth, layers, faces, levels
+		for field in [43_u32, 1, 4, 4, 0, 0, 1, 1] {
+			header.extend_from_slice(&field.to_le_bytes());
+		}
+		header.extend_from_slice(&supercompression.to_le_bytes());
+		header
+	}
+
+	#[test]
+	fn ktx2_headers_are_recognized() {
+		assert!(check_ktx2_header(&ktx2_header(0)).is_ok());
+		assert!(check_ktx2_header(&ktx2_header(1)).is_ok());
+		assert!(check_ktx2_header(&ktx2_header(2)).is_ok());
+		// ZLIB
+		assert!(check_ktx2_header(&ktx2_header(3)).is_err());
+		assert!(check_ktx2_header(b"\x89PNG\r\n\x1a\n").is_err());
+		assert!(check_ktx2_header(&ktx2_header(0)[..40]).is_err());
+	}
+
+	#[test]
+	fn all_materials_expands_to_every_material() {
+		let mut material_parameters = FxHashMap::default();
+		let mut dirty_materials = Vec::new();
+		merge_parameter_changes(
+			&mut material_parameters,
+			&mut dirty_materials,
+			Vec::new(),
+			vec![value(ALL_MATERIALS, "metallic", 1.0)],
+			3,
+			|_| false,
+		);
+		assert_eq!(dirty_materials, [0, 1, 2]);
+		assert!(!material_parameters.contains_key(&ALL_MATERIALS));
+	}
+
+	/// 90 degrees both ways, looking down -Z from the origin
+	fn head_frustum() -> ViewFrustum {
+		let projection = Mat4::perspective_rh(90.0_f32.to_radians(), 1.0, 0.02, 50.0);
+		ViewFrustum::new(projection, Mat4::IDENTITY)
+	}
+	fn head_views() -> FrameViews {
+		FrameViews {
+			origin: Vec3::ZERO,
+			frustums: vec![head_frustum()],
+		}
+	}
+
+	#[test]
+	fn frustum_culls_outside_the_view() {
+		let frustum = head_frustum();
+		assert!(frustum.intersects_sphere(Vec3::new(0.0, 0.0, -2.0), 0.1));
+		assert!(!frustum.intersects_sphere(Vec3::new(0.0, 0.0, 2.0), 0.1));
+		// Past the far plane
+		assert!(!frustum.intersects_sphere(Vec3::new(0.0, 0.0, -60.0), 1.0));
+		// Just past the left and top edges, then reaching back in
+		assert!(!frustum.intersects_sphere(Vec3::new(-2.5, 0.0, -2.0), 0.3));
+		assert!(!frustum.intersects_sphere(Vec3::new(0.0, 2.5, -2.0), 0.3));
+		assert!(frustum.intersects_sphere(Vec3::new(-2.5, 0.0, -2.0), 0.5));
+	}
+
+	#[test]
+	fn head_frustum_covers_both_eyes() {
+		// What an OpenXR runtime reports for a left eye, the right one sees further right
+		let fov = FovInfo {
+			left: -52.0,
+			right: 44.0,
+			top: 48.0,
+			bottom: -53.0,
+		};
+		let frustum = ViewFrustum::for_head(Mat4::IDENTITY, fov);
+		let right_eye = Vec3::new(0.032, 0.0, 0.0);
+		let (sin, cos) = 51.0_f32.to_radians().sin_cos();
+		assert!(frustum.intersects_sphere(right_eye + Vec3::new(sin, 0.0, -cos) * 3.0, 0.01));
+		let (sin, cos) = 60.0_f32.to_radians().sin_cos();
+		assert!(!frustum.intersects_sphere(Vec3::new(sin, 0.0, -cos) * 3.0, 0.01));
+		// It moves with the head
+		let turned = Mat4::from_rotation_y(std::f32::consts::PI);
+		let frustum = ViewFrustum::for_head(turned, fov);
+		assert!(frustum.intersects_sphere(Vec3::new(0.0, 0.0, 2.0), 0.1));
+		assert!(!frustum.intersects_sphere(Vec3::new(0.0, 0.0, -2.0), 0.1));
+	}
+
+	#[test]
+	fn other_views_keep_what_the_head_cant_see() {
+		let mut views = head_views();
+		let behind = Vec3::new(0.0, 0.0, 2.0);
+		assert!(!views.intersects_sphere(behind, 0.1));
+		// A render target at the origin looking backwards
+		let projection = Mat4::perspective_rh(60.0_f32.to_radians(), 1.0, 0.1, 10.0);
+		let camera = Mat4::from_rotation_y(std::f32::consts::PI);
+		views
+			.frustums
+			.push(ViewFrustum::new(projection, camera.inverse()));
+		assert!(views.intersects_sphere(behind, 0.1));
+		// Where neither of them is looking still gets culled
+		assert!(!views.intersects_sphere(Vec3::new(3.0, 0.0, 0.0), 0.1));
+	}
+
+	#[test]
+	fn frustum_bounds_follow_the_transform() {
+		let views = head_views();
+		let bounds = (Vec3::new(0.0, 0.0, 0.5), 0.1);
+		// Behind the head in local space, moved in front of it
+		assert!(!views.intersects_bounds(bounds, Mat4::IDENTITY));
+		let moved = Mat4::from_translation(Vec3::new(0.0, 0.0, -3.0));
+		assert!(views.intersects_bounds(bounds, moved));
+		// Off to the side, only a big enough scale makes it reach into view
+		let beside = Mat4::from_translation(Vec3::new(3.0, 0.0, 1.0));
+		assert!(!views.intersects_bounds((Vec3::ZERO, 0.5), beside));
+		let scaled = beside * Mat4::from_scale(Vec3::new(1.0, 8.0, 1.0));
+		assert!(views.intersects_bounds((Vec3::ZERO, 0.5), scaled));
+		// Mirrored scale still counts
+		let mirrored = beside * Mat4::from_scale(Vec3::new(-8.0, 1.0, 1.0));
+		assert!(views.intersects_bounds((Vec3::ZERO, 0.5), mirrored));
+	}
+
+	#[test]
+	fn prepare_phase_transforms_and_culls_every_spatial() {
+		let views = head_views();
+		let placement = ModelPlacement {
+			bounds: (Vec3::ZERO, 0.2),
+		};
+		// Rows of models in front of and behind the head, sharing ancestors so their cached global
+		// transforms get read from many threads at once
+		let root = Spatial::new(Weak::new(), None, Mat4::from_translation(Vec3::Y));
+		let mut items = Vec::new();
+		for row in 0..8 {
+			let z = if row % 2 == 0 { -4.0 } else { 4.0 };
+			let row_transform = Mat4::from_translation(Vec3::new(0.0, 0.0, z));
+			let parent = Spatial::new(Weak::new(), Some(root.clone()), row_transform);
+			for column in 0..64 {
+				let x = (column as f32 - 32.0) * 0.05;
+				let local = Mat4::from_translation(Vec3::new(x, 0.0, 0.0));
+				let child = Spatial::new(Weak::new(), Some(parent.clone()), local);
+				child.set_static(column % 3 == 0);
+				// Every fifth one is disabled and every seventh isn't loaded yet
+				let loaded = column % 7 != 0;
+				items.push((row * 64 + column, child, column % 5 != 0, loaded));
+			}
+		}
+
+		let prepared = prepare_draws(items, |(_, space, enabled, loaded)| {
+			enabled.then(|| {
+				let placement = loaded.then_some(placement);
+				PreparedDraw::new(space.global_transform(), placement, false, &views)
+			})
+		});
+		assert_eq!(prepared.len(), 8 * 64 - 8 * 13);
+		// Order is kept, so the draw pass sees models in registry order
+		assert!(prepared.windows(2).all(|pair| pair[0].0 .0 < pair[1].0 .0));
+		for ((idx, _, _, loaded), prepared) in &prepared {
+			let (row, column) = (idx / 64, idx % 64);
+			let z = if row % 2 == 0 { -4.0 } else { 4.0 };
+			let position = Vec3::new((column as f32 - 32.0) * 0.05, 1.0, z);
+			let expected = Mat4::from_translation(position);
+			assert!(prepared.transform.abs_diff_eq(expected, 1e-5));
+			assert!((prepared.distance - position.length()).abs() < 1e-4);
+			// Only the rows in front of the head are visible
+			let expected_visible = loaded.then_some(row % 2 == 0);
+			assert_eq!(prepared.visible, expected_visible);
+		}
+	}
+
+	#[test]
+	fn raycast_normals_stay_perpendicular_under_non_uniform_scale() {
+		// A 45 degree slope stretched to twice its width gets shallower
+		let model_to_reference = Mat4::from_scale(Vec3::new(2.0, 1.0, 1.0));
+		let normal = Vec3::new(1.0, 1.0, 0.0).normalize();
+		let hit = raycast_hit(
+			model_to_reference,
+			Vec3::new(1.0, 0.0, 0.0),
+			normal,
+			Vec3::new(2.0, 3.0, 0.0),
+		);
+		assert_eq!(Vec3::from(hit.point), Vec3::new(2.0, 0.0, 0.0));
+		assert!(Vec3::from(hit.normal).abs_diff_eq(Vec3::new(0.5, 1.0, 0.0).normalize(), 1e-6));
+		assert_eq!(hit.distance, 3.0);
+		// What transforming it like a direction would give, which isn't perpendicular anymore
+		let slope = model_to_reference.transform_vector3(Vec3::new(1.0, -1.0, 0.0));
+		assert!(Vec3::from(hit.normal).dot(slope).abs() < 1e-6);
+		let transformed_as_direction = model_to_reference.transform_vector3(normal);
+		assert!(transformed_as_direction.dot(slope).abs() > 0.1);
+	}
+}
@@ -0,0 +1,99 @@
+use super::Drawable;
+use crate::{
+	core::{client::Client, registry::Registry},
+	nodes::{
+		spatial::{find_spatial_parent, parse_transform, Spatial},
+		Node,
+	},
+};
+use color_eyre::eyre::{ensure, Result};
+use portable_atomic::{AtomicBool, Ordering};
+use serde::Deserialize;
+use stardust_xr::{schemas::flex::deserialize, values::Transform};
+use std::sync::{Arc, Weak};
+use stereokit::StereoKitDraw;
+
+static REFERENCE_REGISTRY: Registry<DrawableReference> = Registry::new();
+
+/// Draws another node's model, lines or text at this node's transform, as the original looks
+/// right now so material changes show up on every reference. Nothing is drawn while the original
+/// is disabled, and once it's destroyed the reference stays around drawing nothing until it's
+/// destroyed too.
+pub struct DrawableReference {
+	enabled: Arc<AtomicBool>,
+	space: Arc<Spatial>,
+	target: Weak<Node>,
+}
+impl DrawableReference {
+	fn add_to(node: &Arc<Node>, target: &Arc<Node>) -> Result<Arc<DrawableReference>> {
+		ensure!(
+			node.drawable.get().is_none(),
+			"Internal: Node already has a drawable attached!"
+		);
+		ensure!(
+			matches!(
+				target.drawable.get(),
+				Some(Drawable::Model(_) | Drawable::Lines(_) | Drawable::Text(_))
+			),
+			"Only models, lines and text can be referenced"
+		);
+
+		let reference = REFERENCE_REGISTRY.add(DrawableReference {
+			enabled: node.enabled.clone(),
+			space: node
+				.get_aspect("Reference", "spatial", |n| &n.spatial)?
+				.clone(),
+			target: Arc::downgrade(target),
+		});
+		let _ = node.drawable.set(Drawable::Reference(reference.clone()));
+
+		Ok(reference)
+	}
+
+	fn draw(&self, sk: &impl StereoKitDraw) {
+		let Some(target) = self.target.upgrade() else {return};
+		if !target.enabled.load(Ordering::Relaxed) {
+			return;
+		}
+		let transform = self.space.global_transform();
+		match target.drawable.get() {
+			Some(Drawable::Model(model)) => model.draw_at(sk, transform),
+			Some(Drawable::Lines(lines)) => lines.draw(sk, transform),
+			Some(Drawable::Text(text)) => text.draw(sk, transform),
+			_ => (),
+		}
+	}
+}
+impl Drop for DrawableReference {
+	fn drop(&mut self) {
+		REFERENCE_REGISTRY.remove(self);
+	}
+}
+
+pub fn draw_all(sk: &impl StereoKitDraw) {
+	for reference in REFERENCE_REGISTRY.get_valid_contents() {
+		if reference.enabled.load(Ordering::Relaxed) {
+			reference.draw(sk);
+		}
+	}
+}
+
+pub fn create_flex(_node: &Node, calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+	#[derive(Deserialize)]
+	struct CreateReferenceInfo<'a> {
+		name: &'a str,
+		parent_path: &'a str,
+		transform: Transform,
+		/// A model, lines or text node of the same client
+		target_path: &'a str,
+	}
+	let info: CreateReferenceInfo = deserialize(data)?;
+	let node = Node::create(&calling_client, "/drawable/reference", info.name, true);
+	let parent = find_spatial_parent(&calling_client, info.parent_path)?;
+	let target = calling_client.get_node("Reference target", info.target_path)?;
+	let transform = parse_transform(info.transform, true, true, true);
+	let node = node.add_to_scenegraph()?;
+	Spatial::add_to(&node, Some(parent), transform, false)?;
+	DrawableReference::add_to(&node, &target)?;
+	Ok(())
+}
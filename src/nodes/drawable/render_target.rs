@@ -0,0 +1,180 @@
+use crate::{
+	core::{client::Client, destroy_queue, registry::Registry},
+	nodes::{
+		drawable::model::{tex_set_colors, ViewFrustum},
+		spatial::{find_spatial_parent, parse_transform, Spatial},
+		Node,
+	},
+};
+use color_eyre::eyre::{bail, ensure, Result};
+use glam::Mat4;
+use once_cell::sync::OnceCell;
+use portable_atomic::{AtomicBool, Ordering};
+use send_wrapper::SendWrapper;
+use serde::Deserialize;
+use stardust_xr::{schemas::flex::deserialize, values::Transform};
+use std::sync::Arc;
+use stereokit::{
+	Rect, RenderClear, RenderLayer, StereoKitDraw, StereoKitMultiThread, Tex, TextureFormat,
+	TextureType,
+};
+
+use super::Drawable;
+
+static RENDER_TARGET_REGISTRY: Registry<RenderTarget> = Registry::new();
+/// Keeps a single render target from eating all the GPU memory
+const MAX_SIZE: u32 = 4096;
+
+/// Renders the scene from its spatial's point of view (looking down -Z) into a texture, which
+/// models can use through `MaterialParameter::RenderTarget`.
+pub struct RenderTarget {
+	enabled: Arc<AtomicBool>,
+	space: Arc<Spatial>,
+	width: u32,
+	height: u32,
+	/// Vertical, in degrees
+	fov: f32,
+	near: f32,
+	far: f32,
+	layers: RenderLayer,
+	tex: OnceCell<SendWrapper<Tex>>,
+}
+impl RenderTarget {
+	fn add_to(node: &Arc<Node>, info: &CreateRenderTargetInfo) -> Result<Arc<RenderTarget>> {
+		ensure!(
+			node.drawable.get().is_none(),
+			"Internal: Node already has a drawable attached!"
+		);
+		ensure!(
+			(1..=MAX_SIZE).contains(&info.width) && (1..=MAX_SIZE).contains(&info.height),
+			"Render target size must be between 1 and {MAX_SIZE}"
+		);
+		ensure!(
+			info.fov > 0.0 && info.fov < 180.0,
+			"Field of view must be between 0 and 180 degrees"
+		);
+		ensure!(
+			info.near > 0.0 && info.far > info.near,
+			"Clip planes must be positive with far past near"
+		);
+
+		let render_target = RENDER_TARGET_REGISTRY.add(RenderTarget {
+			enabled: node.enabled.clone(),
+			space: node
+				.get_aspect("Render target", "spatial", |n| &n.spatial)?
+				.clone(),
+			width: info.width,
+			height: info.height,
+			fov: info.fov,
+			near: info.near,
+			far: info.far,
+			layers: info
+				.layers
+				.map_or_else(RenderLayer::all, RenderLayer::from_bits_truncate),
+			tex: OnceCell::new(),
+		});
+		let _ = node
+			.drawable
+			.set(Drawable::RenderTarget(render_target.clone()));
+		Ok(render_target)
+	}
+
+	/// Created on first use, has to be called from the render thread.
+	pub fn texture(&self, sk: &impl StereoKitMultiThread) -> &Tex {
+		self.tex.get_or_init(|| {
+			let tex = sk.tex_create(TextureType::RENDER_TARGET, TextureFormat::RGBA32);
+			let blank = vec![0_u8; self.width as usize * self.height as usize * 4];
+			tex_set_colors(&tex, self.width as usize, self.height as usize, &blank);
+			sk.tex_add_zbuffer(&tex, TextureFormat::Depth16);
+			SendWrapper::new(tex)
+		})
+	}
+
+	fn projection(&self) -> Mat4 {
+		Mat4::perspective_rh(
+			self.fov.to_radians(),
+			self.width as f32 / self.height as f32,
+			self.near,
+			self.far,
+		)
+	}
+
+	fn draw(&self, sk: &impl StereoKitDraw) {
+		sk.render_to(
+			self.texture(sk),
+			self.space.global_transform(),
+			self.projection(),
+			self.layers,
+			RenderClear::All,
+			Rect {
+				x: 0.0,
+				y: 0.0,
+				w: 1.0,
+				h: 1.0,
+			},
+		);
+	}
+}
+impl Drop for RenderTarget {
+	fn drop(&mut self) {
+		if let Some(tex) = self.tex.take() {
+			destroy_queue::add(tex);
+		}
+		RENDER_TARGET_REGISTRY.remove(self);
+	}
+}
+
+/// What every enabled render target sees this frame.
+pub(super) fn view_frustums() -> Vec<ViewFrustum> {
+	RENDER_TARGET_REGISTRY
+		.get_valid_contents()
+		.iter()
+		.filter(|render_target| render_target.enabled.load(Ordering::Relaxed))
+		.map(|render_target| {
+			let view = render_target.space.global_transform().inverse();
+			ViewFrustum::new(render_target.projection(), view)
+		})
+		.collect()
+}
+
+/// Has to run before anything else draws so models using the textures get this frame's view.
+pub fn draw_all(sk: &impl StereoKitDraw) {
+	for render_target in RENDER_TARGET_REGISTRY.get_valid_contents() {
+		if render_target.enabled.load(Ordering::Relaxed) {
+			render_target.draw(sk);
+		}
+	}
+}
+
+#[derive(Deserialize)]
+struct CreateRenderTargetInfo<'a> {
+	name: &'a str,
+	parent_path: &'a str,
+	transform: Transform,
+	width: u32,
+	height: u32,
+	fov: f32,
+	near: f32,
+	far: f32,
+	/// Bitmask of the render layers to draw, defaults to all of them
+	#[serde(default)]
+	layers: Option<u32>,
+}
+
+pub fn create_flex(_node: &Node, calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+	let info: CreateRenderTargetInfo = deserialize(data)?;
+	let node = Node::create(&calling_client, "/drawable/render_target", info.name, true);
+	let parent = find_spatial_parent(&calling_client, info.parent_path)?;
+	let transform = parse_transform(info.transform, true, true, false);
+	let node = node.add_to_scenegraph()?;
+	Spatial::add_to(&node, Some(parent), transform, false)?;
+	RenderTarget::add_to(&node, &info)?;
+	Ok(())
+}
+
+/// The render target a node path points to, for binding its texture to materials.
+pub fn find_render_target(client: &Client, path: &str) -> Result<Arc<RenderTarget>> {
+	let node = client.get_node("Render target", path)?;
+	let Some(Drawable::RenderTarget(render_target)) = node.drawable.get() else {bail!("Node is not a render target")};
+	Ok(render_target.clone())
+}
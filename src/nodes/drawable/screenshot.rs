@@ -0,0 +1,168 @@
+use super::model::ViewFrustum;
+use super::CLIP_PLANES;
+use crate::core::client::Client;
+use crate::nodes::Node;
+use color_eyre::eyre::{ensure, eyre, Result};
+use directories::ProjectDirs;
+use glam::{Mat4, Vec3};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use stardust_xr::schemas::flex::deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use stereokit::{StereoKitDraw, StereoKitMultiThread};
+use tracing::{debug, error};
+
+/// How long StereoKit gets to write the screenshot before it's considered failed
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(5);
+/// For screenshots the client didn't name.
+static NEXT_SCREENSHOT: AtomicU64 = AtomicU64::new(0);
+
+pub(super) struct PendingScreenshot {
+	client: Weak<Client>,
+	path: PathBuf,
+	width: i32,
+	height: i32,
+	fov: f32,
+}
+static PENDING_SCREENSHOTS: Mutex<Vec<PendingScreenshot>> = Mutex::new(Vec::new());
+
+/// Clients only get to pick a name in here, prefixed with their ID, so they can't write
+/// anywhere else or over each other's screenshots.
+fn screenshot_path(client: &Client, name: Option<&str>) -> Result<PathBuf> {
+	let project_dirs =
+		ProjectDirs::from("", "", "stardust").ok_or_else(|| eyre!("No home directory"))?;
+	let directory = project_dirs.cache_dir().join("screenshots");
+	std::fs::create_dir_all(&directory)?;
+	let name = match name {
+		Some(name) => {
+			ensure!(
+				!name.is_empty()
+					&& name
+						.chars()
+						.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+				"Screenshot names can only have letters, numbers, - and _"
+			);
+			name.to_string()
+		}
+		None => NEXT_SCREENSHOT.fetch_add(1, Ordering::Relaxed).to_string(),
+	};
+	let path = directory.join(format!("{}-{name}.png", client.id));
+	// StereoKit only takes UTF-8 paths
+	ensure!(
+		path.to_str().is_some(),
+		"Screenshot directory {} is not valid UTF-8",
+		directory.display()
+	);
+	Ok(path)
+}
+
+/// The screenshot event has the path it was saved to.
+pub fn capture_screenshot_flex(
+	_node: &Node,
+	calling_client: Arc<Client>,
+	data: &[u8],
+) -> Result<()> {
+	#[derive(Deserialize)]
+	struct ScreenshotInfo<'a> {
+		/// Defaults to a number that's different for every screenshot
+		#[serde(default, borrow)]
+		name: Option<&'a str>,
+		#[serde(default)]
+		width: Option<u32>,
+		#[serde(default)]
+		height: Option<u32>,
+		/// Vertical, in degrees
+		#[serde(default)]
+		fov: Option<f32>,
+	}
+	let info: ScreenshotInfo = deserialize(data)?;
+	let width = info.width.unwrap_or(1920);
+	let height = info.height.unwrap_or(1080);
+	ensure!(
+		(1..=8192).contains(&width) && (1..=8192).contains(&height),
+		"Screenshot size must be between 1 and 8192"
+	);
+	PENDING_SCREENSHOTS.lock().push(PendingScreenshot {
+		client: Arc::downgrade(&calling_client),
+		path: screenshot_path(&calling_client, info.name)?,
+		width: width as i32,
+		height: height as i32,
+		fov: info.fov.unwrap_or(90.0),
+	});
+	Ok(())
+}
+
+impl PendingScreenshot {
+	/// StereoKit renders screenshots with its own clip planes.
+	pub(super) fn view_frustum(&self, sk: &impl StereoKitMultiThread) -> ViewFrustum {
+		let (from, at) = viewpoint(sk);
+		let (near, far) = CLIP_PLANES;
+		let aspect = self.width as f32 / self.height as f32;
+		let projection = Mat4::perspective_rh(self.fov.to_radians(), aspect, near, far);
+		ViewFrustum::new(projection, Mat4::look_at_rh(from, at, Vec3::Y))
+	}
+}
+
+/// Where screenshots are taken from and looking at, straight ahead from the head.
+fn viewpoint(sk: &impl StereoKitMultiThread) -> (Vec3, Vec3) {
+	let head = sk.input_head();
+	(
+		head.position,
+		head.position + head.orientation * Vec3::NEG_Z,
+	)
+}
+
+/// Taken before anything draws, so models know they'll be seen from the screenshot too.
+pub(super) fn take_pending() -> Vec<PendingScreenshot> {
+	std::mem::take(&mut *PENDING_SCREENSHOTS.lock())
+}
+
+/// StereoKit renders and saves screenshots as JPEG at the end of the frame, so this only queues
+/// them and a thread waits for the file to convert it to PNG and tell the client.
+pub(super) fn capture(sk: &impl StereoKitDraw, pending: Vec<PendingScreenshot>) {
+	for screenshot in pending {
+		let capture_path = screenshot.path.with_extension("capture.jpg");
+		let _ = std::fs::remove_file(&capture_path);
+		let (from, at) = viewpoint(sk);
+		sk.render_screenshot(
+			capture_path.to_string_lossy(),
+			from,
+			at,
+			screenshot.width,
+			screenshot.height,
+			screenshot.fov,
+		);
+		thread::spawn(move || {
+			let result = convert_capture(&capture_path, &screenshot.path);
+			let _ = std::fs::remove_file(&capture_path);
+			match &result {
+				Ok(()) => debug!(path = ?screenshot.path, "Saved screenshot"),
+				Err(e) => error!(path = ?screenshot.path, "Unable to save screenshot: {e}"),
+			}
+			let Some(client) = screenshot.client.upgrade() else {return};
+			let Some(root) = client.root.get() else {return};
+			root.send_screenshot_event(&screenshot.path, result.map_err(|e| e.to_string()));
+		});
+	}
+}
+
+fn convert_capture(capture_path: &Path, path: &Path) -> Result<()> {
+	let started = Instant::now();
+	// StereoKit doesn't say when it's done, so wait for a file that decodes
+	let image = loop {
+		if let Ok(image) = image::open(capture_path) {
+			break image;
+		}
+		ensure!(
+			started.elapsed() < CAPTURE_TIMEOUT,
+			"StereoKit didn't write the screenshot in time"
+		);
+		thread::sleep(Duration::from_millis(20));
+	};
+	image.into_rgb8().save(path)?;
+	Ok(())
+}
@@ -1,5 +1,5 @@
 use crate::{
-	core::{client::Client, destroy_queue, registry::Registry, resource::ResourceID},
+	core::{client::Client, destroy_queue, frame_stats, registry::Registry, resource::ResourceID},
 	nodes::{
 		drawable::Drawable,
 		spatial::{find_spatial_parent, parse_transform, Spatial},
@@ -90,7 +90,8 @@ impl Text {
 		Ok(text)
 	}
 
-	fn draw(&self, sk: &impl StereoKitDraw) {
+	/// At `transform` rather than its own, so references can draw it elsewhere.
+	pub(super) fn draw(&self, sk: &impl StereoKitDraw, transform: Mat4) {
 		let style = self.style.get_or_try_init(
 			|| -> Result<SendWrapper<TextStyle>, color_eyre::eyre::Error> {
 				let font = self
@@ -106,7 +107,7 @@ impl Text {
 
 		if let Ok(style) = style {
 			let data = self.data.lock();
-			let transform = self.space.global_transform()
+			let transform = transform
 				* Mat4::from_scale(vec3(
 					data.character_height,
 					data.character_height,
@@ -145,6 +146,7 @@ impl Text {
 					]),
 				);
 			}
+			frame_stats::count_draw_call();
 		}
 	}
 
@@ -178,7 +180,7 @@ impl Drop for Text {
 pub fn draw_all(sk: &impl StereoKitDraw) {
 	for text in TEXT_REGISTRY.get_valid_contents() {
 		if text.enabled.load(Ordering::Relaxed) {
-			text.draw(sk);
+			text.draw(sk, text.space.global_transform());
 		}
 	}
 }
@@ -219,3 +221,48 @@ pub fn create_flex(_node: &Node, calling_client: Arc<Client>, data: &[u8]) -> Re
 	)?;
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::core::client::INTERNAL_CLIENT;
+
+	#[test]
+	fn destroying_a_parent_destroys_its_child_text() {
+		let client = INTERNAL_CLIENT.clone();
+		let parent = Node::create(&client, "", "cascade_parent", true)
+			.add_to_scenegraph()
+			.unwrap();
+		let parent_spatial = Spatial::add_to(&parent, None, Mat4::IDENTITY, false).unwrap();
+		let child = Node::create(&client, "/cascade_parent", "text", true)
+			.add_to_scenegraph()
+			.unwrap();
+		Spatial::add_to(&child, Some(parent_spatial), Mat4::IDENTITY, false).unwrap();
+		let text = Text::add_to(
+			&child,
+			None,
+			"child".to_string(),
+			0.01,
+			TextAlign::Left,
+			None,
+			TextFit::Overflow,
+			TextAlign::Left,
+			Rgba::from_slice(&[1.0; 4]),
+		)
+		.unwrap();
+		// Stands in for the style StereoKit makes on the first draw
+		let _ = text.style.set(SendWrapper::new(TextStyle(0)));
+		let child_spatial = Arc::downgrade(child.spatial.get().unwrap());
+		let (weak_child, weak_text) = (Arc::downgrade(&child), Arc::downgrade(&text));
+		drop((child, text));
+		let queued = destroy_queue::len();
+
+		parent.destroy_cascading(false);
+		assert!(client.scenegraph.get_node("/cascade_parent").is_none());
+		assert!(client.scenegraph.get_node("/cascade_parent/text").is_none());
+		assert!(weak_child.upgrade().is_none());
+		assert!(child_spatial.upgrade().is_none());
+		assert!(weak_text.upgrade().is_none());
+		assert!(destroy_queue::len() > queued);
+	}
+}
@@ -27,11 +27,11 @@ pub fn frame(sk: &impl StereoKitMultiThread) {
 		.get()
 		.expect("Unable to get spatial to update HMD");
 	let hmd_pose = sk.input_head();
-	*spatial.transform.lock() = Mat4::from_scale_rotation_translation(
+	spatial.set_local_transform(Mat4::from_scale_rotation_translation(
 		vec3(1.0, 1.0, 1.0),
-		hmd_pose.orientation.into(),
-		hmd_pose.position.into(),
-	);
+		hmd_pose.orientation,
+		hmd_pose.position,
+	));
 }
 
 pub fn make_alias(client: &Arc<Client>) -> Result<Arc<Node>> {
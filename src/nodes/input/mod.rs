@@ -99,7 +99,7 @@ impl InputMethod {
 		let handler =
 			handler_node.get_aspect("Input Handler", "input handler", |n| &n.input_handler)?;
 
-		method.captures.add_raw(&handler);
+		method.captures.add_raw(handler);
 		Ok(())
 	}
 	fn set_datamap_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
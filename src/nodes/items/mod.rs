@@ -29,6 +29,7 @@ lazy_static! {
 		"set_transform",
 		"set_spatial_parent",
 		"set_spatial_parent_in_place",
+		"set_parent",
 		"set_zoneable",
 		"release",
 	];
@@ -52,7 +53,7 @@ fn release(item: &Item, acceptor: Option<&ItemAcceptor>) {
 		*captured_acceptor = Weak::default();
 		acceptor.handle_release(item);
 		if let Some(ui) = item.type_info.ui.lock().upgrade() {
-			ui.handle_release_item(item, &acceptor);
+			ui.handle_release_item(item, acceptor);
 		}
 	}
 }
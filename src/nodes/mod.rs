@@ -18,21 +18,23 @@ use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
 use portable_atomic::{AtomicBool, Ordering};
 use rustc_hash::FxHasher;
+use serde::{Deserialize, Serialize};
 use stardust_xr::messenger::MessageSenderHandle;
 use stardust_xr::scenegraph::ScenegraphError;
-use stardust_xr::schemas::flex::deserialize;
+use stardust_xr::schemas::flex::{deserialize, serialize};
 use std::fmt::Debug;
 use std::sync::{Arc, Weak};
 use std::vec::Vec;
 use tracing::{debug_span, instrument};
 
 use crate::core::client::Client;
+use crate::core::quota::{QuotaKind, QuotaReservation};
 use crate::core::registry::Registry;
 
 use self::alias::Alias;
 use self::audio::Sound;
 use self::data::{PulseReceiver, PulseSender};
-use self::drawable::Drawable;
+use self::drawable::{model::ModelIntrospection, Drawable};
 use self::fields::Field;
 use self::input::{InputHandler, InputMethod};
 use self::items::{Item, ItemAcceptor, ItemUI};
@@ -40,6 +42,18 @@ use self::spatial::zone::Zone;
 use self::spatial::Spatial;
 use self::startup::StartupSettings;
 
+/// A read-only snapshot of a node, for debugging clients.
+#[derive(Debug, Serialize)]
+pub struct NodeIntrospection {
+	pub path: String,
+	pub enabled: bool,
+	/// Like "spatial" or "model"
+	pub aspects: Vec<&'static str>,
+	/// The path of the node this one is an alias of
+	pub alias_of: Option<String>,
+	pub model: Option<ModelIntrospection>,
+}
+
 pub type Signal = fn(&Node, Arc<Client>, &[u8]) -> Result<()>;
 pub type Method = fn(&Node, Arc<Client>, &[u8]) -> Result<Vec<u8>>;
 
@@ -53,6 +67,7 @@ pub struct Node {
 	local_signals: DashMap<String, Signal, BuildHasherDefault<FxHasher>>,
 	local_methods: DashMap<String, Method, BuildHasherDefault<FxHasher>>,
 	destroyable: bool,
+	_quota_reservation: Option<QuotaReservation>,
 
 	pub alias: OnceCell<Arc<Alias>>,
 	aliases: Registry<Alias>,
@@ -95,6 +110,67 @@ impl Node {
 		self.path.as_str()
 	}
 
+	pub fn introspect(&self) -> NodeIntrospection {
+		let mut aspects = Vec::new();
+		let mut model = None;
+		if self.spatial.get().is_some() {
+			aspects.push("spatial");
+		}
+		if self.field.get().is_some() {
+			aspects.push("field");
+		}
+		if self.zone.get().is_some() {
+			aspects.push("zone");
+		}
+		if self.pulse_sender.get().is_some() {
+			aspects.push("pulse_sender");
+		}
+		if self.pulse_receiver.get().is_some() {
+			aspects.push("pulse_receiver");
+		}
+		match self.drawable.get() {
+			Some(Drawable::Lines(_)) => aspects.push("lines"),
+			Some(Drawable::Model(drawable_model)) => {
+				aspects.push("model");
+				model = Some(drawable_model.introspect());
+			}
+			Some(Drawable::Text(_)) => aspects.push("text"),
+			Some(Drawable::RenderTarget(_)) => aspects.push("render_target"),
+			Some(Drawable::Material(_)) => aspects.push("material"),
+			Some(Drawable::Reference(_)) => aspects.push("reference"),
+			None => (),
+		}
+		if self.input_method.get().is_some() {
+			aspects.push("input_method");
+		}
+		if self.input_handler.get().is_some() {
+			aspects.push("input_handler");
+		}
+		if self.item.get().is_some() {
+			aspects.push("item");
+		}
+		if self.item_acceptor.get().is_some() {
+			aspects.push("item_acceptor");
+		}
+		if self.item_ui.get().is_some() {
+			aspects.push("item_ui");
+		}
+		if self.sound.get().is_some() {
+			aspects.push("sound");
+		}
+		NodeIntrospection {
+			path: self.path.clone(),
+			enabled: self.enabled.load(Ordering::Relaxed),
+			aspects,
+			alias_of: self
+				.alias
+				.get()
+				.and_then(|alias| alias.original.upgrade())
+				.map(|original| original.path.clone()),
+			model,
+		}
+	}
+
 	pub fn create(client: &Arc<Client>, parent: &str, name: &str, destroyable: bool) -> Self {
 		let mut path = parent.to_string();
 		path.push('/');
@@ -109,6 +185,7 @@ impl Node {
 			local_signals: Default::default(),
 			local_methods: Default::default(),
 			destroyable,
+			_quota_reservation: None,
 
 			alias: OnceCell::new(),
 			aliases: Registry::new(),
@@ -128,15 +205,17 @@ impl Node {
 			startup_settings: OnceCell::new(),
 		};
 		node.add_local_signal("set_enabled", Node::set_enabled_flex);
+		node.add_local_method("set_enabled", Node::set_enabled_confirmed_flex);
+		node.add_local_method("get_enabled", Node::get_enabled_flex);
 		node.add_local_signal("destroy", Node::destroy_flex);
 		node
 	}
-	pub fn add_to_scenegraph(self) -> Result<Arc<Node>> {
-		Ok(self
+	pub fn add_to_scenegraph(mut self) -> Result<Arc<Node>> {
+		let client = self
 			.get_client()
-			.ok_or_else(|| eyre!("Internal: Unable to get client"))?
-			.scenegraph
-			.add_node(self))
+			.ok_or_else(|| eyre!("Internal: Unable to get client"))?;
+		self._quota_reservation = Some(QuotaReservation::reserve(&client, QuotaKind::Nodes, 1)?);
+		Ok(client.scenegraph.add_node(self))
 	}
 	pub fn destroy(&self) {
 		if let Some(client) = self.get_client() {
@@ -145,12 +224,67 @@ impl Node {
 	}
 
 	pub fn set_enabled_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		if let Some(Drawable::Model(model)) = node.drawable.get() {
+			model.cancel_fade();
+		}
 		node.enabled.store(deserialize(data)?, Ordering::Relaxed);
 		Ok(())
 	}
-	pub fn destroy_flex(node: &Node, _calling_client: Arc<Client>, _data: &[u8]) -> Result<()> {
+	/// Same as the signal but answers with the state it ended up in, which shows up in the next
+	/// frame drawn.
+	pub fn set_enabled_confirmed_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<Vec<u8>> {
+		if let Some(Drawable::Model(model)) = node.drawable.get() {
+			model.cancel_fade();
+		}
+		node.enabled.store(deserialize(data)?, Ordering::Relaxed);
+		Ok(serialize(node.enabled.load(Ordering::Relaxed))?)
+	}
+	pub fn get_enabled_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		_data: &[u8],
+	) -> Result<Vec<u8>> {
+		Ok(serialize(node.enabled.load(Ordering::Relaxed))?)
+	}
+	/// Takes the spatial children of the node along with it. Children of other clients, ones
+	/// that can't be destroyed and all of them with `reparent_children` set get moved to the
+	/// node's parent in place instead.
+	pub fn destroy_cascading(&self, reparent_children: bool) {
+		if let Some(spatial) = self.spatial.get() {
+			let grandparent = spatial.get_parent();
+			for child in spatial.children() {
+				let Some(child_node) = child.node.upgrade() else {continue};
+				if !reparent_children
+					&& child_node.destroyable
+					&& child_node.client.ptr_eq(&self.client)
+				{
+					child_node.destroy_cascading(false);
+				} else {
+					let _ = child.set_spatial_parent_in_place(grandparent.clone());
+				}
+			}
+		}
+		self.destroy();
+	}
+
+	pub fn destroy_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		#[derive(Deserialize, Default)]
+		struct DestroyInfo {
+			#[serde(default)]
+			reparent_children: bool,
+		}
+		// Older clients send nothing at all
+		let info: DestroyInfo = if data.is_empty() {
+			DestroyInfo::default()
+		} else {
+			deserialize(data)?
+		};
 		if node.destroyable {
-			node.destroy();
+			node.destroy_cascading(info.reparent_children);
 		}
 		Ok(())
 	}
@@ -242,10 +376,11 @@ impl Node {
 		let path = self.path.clone();
 		let method = method.to_string();
 		let data = data.to_vec();
-		self.message_sender_handle
-			.as_ref()
-			.map(|handle| handle.signal(path.as_str(), method.as_str(), data.as_slice()))
-			.transpose()?;
+		let Some(handle) = self.message_sender_handle.as_ref() else {return Ok(())};
+		handle.signal(path.as_str(), method.as_str(), data.as_slice())?;
+		if let Some(client) = self.client.upgrade() {
+			client.traffic.count_sent(data.len());
+		}
 		Ok(())
 	}
 	// #[instrument(level = "debug", skip_all)]
@@ -1,12 +1,19 @@
 use super::spatial::Spatial;
 use super::Node;
-use crate::core::client::Client;
+use crate::core::client::{Client, ClientId};
+use crate::core::frame_stats;
+use crate::core::latency;
 use crate::core::registry::Registry;
-use color_eyre::eyre::Result;
+use crate::core::resource::{ResourceID, ResourceState};
+use crate::core::traffic;
+use color_eyre::eyre::{ensure, Result};
 use glam::Mat4;
+use serde::Serialize;
 use stardust_xr::schemas::flex::{deserialize, serialize};
 use tracing::instrument;
 
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
@@ -21,6 +28,14 @@ impl Root {
 		let node = Node::create(client, "", "", false);
 		node.add_local_signal("subscribe_frame", Root::subscribe_frame_flex);
 		node.add_local_signal("set_base_prefixes", Root::set_base_prefixes_flex);
+		node.add_local_signal("add_resource_prefix", Root::add_resource_prefix_flex);
+		node.add_local_signal("remove_resource_prefix", Root::remove_resource_prefix_flex);
+		node.add_local_signal("set_render_layer_mask", Root::set_render_layer_mask_flex);
+		node.add_local_method("resolve_resource", Root::resolve_resource_flex);
+		node.add_local_method("get_frame_stats", Root::get_frame_stats_flex);
+		node.add_local_method("get_client_traffic", Root::get_client_traffic_flex);
+		node.add_local_method("get_handler_latency", Root::get_handler_latency_flex);
+		node.add_local_method("introspect_scenegraph", Root::introspect_scenegraph_flex);
 		let node = node.add_to_scenegraph()?;
 		let _ = Spatial::add_to(
 			&node,
@@ -53,6 +68,7 @@ impl Root {
 	pub fn send_frame_events(delta: f64) {
 		if let Ok(data) = serialize((delta, 0.0)) {
 			for root in ROOT_REGISTRY.get_valid_contents() {
+				// A client that's behind has its older frame events dropped by the relay
 				if root.send_frame_event.load(Ordering::Relaxed) {
 					let _ = root.node.send_remote_signal("frame", &data);
 				}
@@ -60,6 +76,85 @@ impl Root {
 		}
 	}
 
+	/// Lets clients save state and close on their own before the server goes away.
+	pub fn send_disconnect_events() {
+		if let Ok(data) = serialize(()) {
+			for root in ROOT_REGISTRY.get_valid_contents() {
+				let _ = root.node.send_remote_signal("disconnect", &data);
+			}
+		}
+	}
+
+	fn get_frame_stats_flex(
+		_node: &Node,
+		_calling_client: Arc<Client>,
+		_data: &[u8],
+	) -> Result<Vec<u8>> {
+		Ok(serialize(frame_stats::last_frame())?)
+	}
+	/// Message and byte totals of every connected client by ID along with how much is waiting for
+	/// it to read, to find one flooding the server or falling behind.
+	fn get_client_traffic_flex(
+		_node: &Node,
+		_calling_client: Arc<Client>,
+		_data: &[u8],
+	) -> Result<Vec<u8>> {
+		Ok(serialize(traffic::all_totals())?)
+	}
+
+	/// How long each signal and method handler has taken, slowest in total first.
+	fn get_handler_latency_flex(
+		_node: &Node,
+		_calling_client: Arc<Client>,
+		_data: &[u8],
+	) -> Result<Vec<u8>> {
+		ensure!(
+			latency::enabled(),
+			"Handler latency isn't being measured, start the server with --measure-latency"
+		);
+		Ok(serialize(latency::summaries())?)
+	}
+
+	/// Every node of the calling client's scenegraph sorted by path. Other clients' scenegraphs
+	/// can have paths and names they wouldn't want shared, so those can't be looked at.
+	fn introspect_scenegraph_flex(
+		_node: &Node,
+		calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<Vec<u8>> {
+		let client_id: Option<ClientId> = deserialize(data)?;
+		ensure!(
+			client_id.is_none_or(|client_id| client_id == calling_client.id),
+			"Clients can only introspect their own scenegraph"
+		);
+		let mut nodes: Vec<_> = calling_client
+			.scenegraph
+			.nodes()
+			.iter()
+			.map(|node| node.introspect())
+			.collect();
+		nodes.sort_by(|a, b| a.path.cmp(&b.path));
+		Ok(serialize(nodes)?)
+	}
+
+	/// Tells the client a screenshot it asked for was saved, or why it wasn't.
+	pub fn send_screenshot_event(&self, path: &Path, result: Result<(), String>) {
+		if let Ok(data) = serialize((path, result.err())) {
+			let _ = self.node.send_remote_signal("screenshot", &data);
+		}
+	}
+
+	/// A PNG of a panel item's surface the client asked for, or why it couldn't be captured.
+	pub fn send_surface_captured_event(
+		&self,
+		surface_id: &impl Serialize,
+		result: Result<Vec<u8>, String>,
+	) {
+		if let Ok(data) = serialize((surface_id, result)) {
+			let _ = self.node.send_remote_signal("surface_captured", &data);
+		}
+	}
+
 	fn set_base_prefixes_flex(
 		_node: &Node,
 		calling_client: Arc<Client>,
@@ -68,6 +163,68 @@ impl Root {
 		*calling_client.base_resource_prefixes.lock() = deserialize(data)?;
 		Ok(())
 	}
+	/// Prefixes are searched in the order they were added, so this one gets checked last.
+	fn add_resource_prefix_flex(
+		_node: &Node,
+		calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let prefix: PathBuf = deserialize(data)?;
+		ensure!(
+			prefix.is_dir(),
+			"Resource prefix {} is not a directory",
+			prefix.display()
+		);
+		let mut prefixes = calling_client.base_resource_prefixes.lock();
+		if !prefixes.contains(&prefix) {
+			prefixes.push(prefix);
+		}
+		Ok(())
+	}
+	/// Models the client owns are only drawn on the layers in both their own layer and this mask,
+	/// so things like debug overlays can be kept off layers other users' views render.
+	fn set_render_layer_mask_flex(
+		_node: &Node,
+		calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let mask: u32 = deserialize(data)?;
+		calling_client
+			.render_layer_mask
+			.store(mask, Ordering::Relaxed);
+		Ok(())
+	}
+	/// Resolves a resource the same way loading it would, without loading it. URLs are never
+	/// downloaded for this, they're only looked up in the cache, so along with the path it
+	/// returns one of "found", "downloading", "not_cached" or "not_found".
+	fn resolve_resource_flex(
+		_node: &Node,
+		calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<Vec<u8>> {
+		let (resource, extensions): (ResourceID, Vec<String>) = deserialize(data)?;
+		let extensions: Vec<&OsStr> = extensions.iter().map(OsStr::new).collect();
+		let prefixes = calling_client.base_resource_prefixes.lock().clone();
+		let (path, state) = match resource.resolve_cached(&prefixes, &extensions) {
+			ResourceState::Found(path) => (Some(path), "found"),
+			ResourceState::Pending => (None, "downloading"),
+			ResourceState::Uncached => (None, "not_cached"),
+			ResourceState::NotFound => (None, "not_found"),
+		};
+		Ok(serialize((path.is_some(), path, state))?)
+	}
+	fn remove_resource_prefix_flex(
+		_node: &Node,
+		calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let prefix: PathBuf = deserialize(data)?;
+		calling_client
+			.base_resource_prefixes
+			.lock()
+			.retain(|existing| existing != &prefix);
+		Ok(())
+	}
 }
 
 impl Drop for Root {
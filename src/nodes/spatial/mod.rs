@@ -5,7 +5,7 @@ use super::Node;
 use crate::core::client::Client;
 use crate::core::registry::Registry;
 use color_eyre::eyre::{ensure, eyre, Result};
-use glam::{vec3a, Mat4, Quat};
+use glam::{vec3a, Mat3, Mat4, Quat, Vec3, Vec3A};
 use mint::Vector3;
 use nanoid::nanoid;
 use parking_lot::Mutex;
@@ -14,10 +14,128 @@ use stardust_xr::schemas::flex::{deserialize, serialize};
 use stardust_xr::values::Transform;
 use std::fmt::Debug;
 use std::ptr;
-use std::sync::{Arc, Weak};
+use std::sync::{
+	atomic::{AtomicBool, AtomicU64, Ordering},
+	Arc, Weak,
+};
 use tracing::instrument;
 
 static ZONEABLE_REGISTRY: Registry<Spatial> = Registry::new();
+static TRANSFORM_WATCH_REGISTRY: Registry<Spatial> = Registry::new();
+
+/// Default for how far any element of the transform matrix has to move before it's sent again.
+const DEFAULT_TRANSFORM_THRESHOLD: f32 = 0.001;
+
+static SMOOTHING_REGISTRY: Registry<Spatial> = Registry::new();
+/// Goes up every time any spatial moves. Static spatials' cached transforms are stamped with it,
+/// so one that moves while its transform is being computed doesn't keep the stale result.
+static TRANSFORM_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+pub enum Easing {
+	#[default]
+	Linear,
+	EaseIn,
+	EaseOut,
+	EaseInOut,
+}
+impl Easing {
+	fn apply(self, t: f32) -> f32 {
+		match self {
+			Easing::Linear => t,
+			Easing::EaseIn => t * t,
+			Easing::EaseOut => t * (2.0 - t),
+			Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+		}
+	}
+}
+
+/// Eases the local transform from where it was towards a target.
+struct TransformSmoothing {
+	start: Mat4,
+	target: Mat4,
+	duration: f32,
+	elapsed: f32,
+	easing: Easing,
+}
+impl TransformSmoothing {
+	fn current(&self) -> Mat4 {
+		let t = self
+			.easing
+			.apply((self.elapsed / self.duration).clamp(0.0, 1.0));
+		let (start_scl, start_rot, start_pos) = self.start.to_scale_rotation_translation();
+		let (target_scl, target_rot, target_pos) = self.target.to_scale_rotation_translation();
+		Mat4::from_scale_rotation_translation(
+			start_scl.lerp(target_scl, t),
+			start_rot.slerp(target_rot, t),
+			start_pos.lerp(target_pos, t),
+		)
+	}
+	fn finished(&self) -> bool {
+		self.elapsed >= self.duration
+	}
+}
+
+static VELOCITY_REGISTRY: Registry<Spatial> = Registry::new();
+
+/// How much of each frame's velocity goes into the estimate, lower is smoother but lags more.
+const VELOCITY_SMOOTHING: f32 = 0.3;
+
+/// Velocity in world space, estimated from how the global transform changed between frames.
+struct VelocityTracking {
+	last_position: Vec3,
+	last_rotation: Quat,
+	/// Meters per second
+	linear: Vec3,
+	/// Axis scaled by radians per second
+	angular: Vec3,
+}
+impl VelocityTracking {
+	fn new(transform: Mat4) -> Self {
+		let (_, rotation, position) = transform.to_scale_rotation_translation();
+		VelocityTracking {
+			last_position: position,
+			last_rotation: rotation,
+			linear: Vec3::ZERO,
+			angular: Vec3::ZERO,
+		}
+	}
+	fn update(&mut self, transform: Mat4, delta: f32) {
+		let (_, rotation, position) = transform.to_scale_rotation_translation();
+		if delta > 0.0 {
+			let linear = (position - self.last_position) / delta;
+			let mut rotation_delta = rotation * self.last_rotation.inverse();
+			// Go the short way around
+			if rotation_delta.w < 0.0 {
+				rotation_delta = -rotation_delta;
+			}
+			let (axis, angle) = rotation_delta.to_axis_angle();
+			let angular = axis * angle / delta;
+			self.linear = self.linear.lerp(linear, VELOCITY_SMOOTHING);
+			self.angular = self.angular.lerp(angular, VELOCITY_SMOOTHING);
+		}
+		self.last_position = position;
+		self.last_rotation = rotation;
+	}
+}
+
+static BILLBOARD_REGISTRY: Registry<Spatial> = Registry::new();
+
+/// Keeps a spatial's +Z side, the one its contents face out of, turned towards the head.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Billboard {
+	#[default]
+	Off,
+	Full,
+	/// Only turns around the world's up axis, so it stays upright
+	Yaw,
+}
+
+struct TransformWatch {
+	reference_space: Weak<Spatial>,
+	threshold: f32,
+	last_sent: Option<Mat4>,
+}
 
 pub struct Spatial {
 	uid: String,
@@ -25,14 +143,28 @@ pub struct Spatial {
 	self_ref: Weak<Spatial>,
 	parent: Mutex<Option<Arc<Spatial>>>,
 	old_parent: Mutex<Option<Arc<Spatial>>>,
-	pub(super) transform: Mutex<Mat4>,
+	/// Only written through `set_local_transform`, so static descendants get marked dirty
+	transform: Mutex<Mat4>,
 	zone: Mutex<Weak<Zone>>,
 	children: Registry<Spatial>,
+	transform_watch: Mutex<Option<TransformWatch>>,
+	/// The last global transform that got inverted along with its inverse
+	inverse_cache: Mutex<Option<(Mat4, Mat4)>>,
+	smoothing: Mutex<Option<TransformSmoothing>>,
+	billboard: Mutex<Billboard>,
+	/// Only tracked once a client asks for it
+	velocity: Mutex<Option<VelocityTracking>>,
+	/// Static spatials keep their global transform until they or an ancestor are marked dirty
+	is_static: AtomicBool,
+	/// `TRANSFORM_GENERATION` when this or an ancestor was last moved or marked dirty
+	moved_generation: AtomicU64,
+	/// Along with the `TRANSFORM_GENERATION` from before it was computed
+	global_transform_cache: Mutex<Option<(u64, Mat4)>>,
 }
 
 impl Spatial {
 	pub fn new(node: Weak<Node>, parent: Option<Arc<Spatial>>, transform: Mat4) -> Arc<Self> {
-		Arc::new_cyclic(|self_ref| Spatial {
+		let spatial = Arc::new_cyclic(|self_ref| Spatial {
 			uid: nanoid!(),
 			node,
 			self_ref: self_ref.clone(),
@@ -41,7 +173,20 @@ impl Spatial {
 			transform: Mutex::new(transform),
 			zone: Mutex::new(Weak::new()),
 			children: Registry::new(),
-		})
+			transform_watch: Mutex::new(None),
+			inverse_cache: Mutex::new(None),
+			smoothing: Mutex::new(None),
+			billboard: Mutex::new(Billboard::Off),
+			velocity: Mutex::new(None),
+			is_static: AtomicBool::new(false),
+			moved_generation: AtomicU64::new(0),
+			global_transform_cache: Mutex::new(None),
+		});
+		// Destroying the parent has to find it to take it along
+		if let Some(parent) = spatial.get_parent() {
+			parent.children.add_raw(&spatial);
+		}
+		spatial
 	}
 	pub fn add_to(
 		node: &Arc<Node>,
@@ -56,12 +201,28 @@ impl Spatial {
 		let spatial = Spatial::new(Arc::downgrade(node), parent, transform);
 		node.add_local_method("get_transform", Spatial::get_transform_flex);
 		node.add_local_signal("set_transform", Spatial::set_transform_flex);
+		node.add_local_signal(
+			"set_transform_smoothed",
+			Spatial::set_transform_smoothed_flex,
+		);
 		node.add_local_signal("set_spatial_parent", Spatial::set_spatial_parent_flex);
 		node.add_local_signal(
 			"set_spatial_parent_in_place",
 			Spatial::set_spatial_parent_in_place_flex,
 		);
+		node.add_local_signal("set_parent", Spatial::set_parent_flex);
+		node.add_local_signal("set_static", Spatial::set_static_flex);
+		node.add_local_signal("mark_dirty", Spatial::mark_dirty_flex);
 		node.add_local_signal("set_zoneable", Spatial::set_zoneable_flex);
+		node.add_local_signal("set_billboard", Spatial::set_billboard_flex);
+		node.add_local_signal("look_at", Spatial::look_at_flex);
+		node.add_local_signal("watch_transform", Spatial::watch_transform_flex);
+		node.add_local_signal("unwatch_transform", Spatial::unwatch_transform_flex);
+		node.add_local_method("get_velocity", Spatial::get_velocity_flex);
+		node.add_local_method("transform_point_to_local", Spatial::point_to_local_flex);
+		node.add_local_method("transform_point_to_world", Spatial::point_to_world_flex);
+		node.add_local_method("transform_pose_to_local", Spatial::pose_to_local_flex);
+		node.add_local_method("transform_pose_to_world", Spatial::pose_to_world_flex);
 		node.add_local_method("field_distance", Spatial::field_distance_flex);
 		node.add_local_method("field_normal", Spatial::field_normal_flex);
 		node.add_local_method("field_closest_point", Spatial::field_closest_point_flex);
@@ -84,14 +245,83 @@ impl Spatial {
 		*self.transform.lock()
 	}
 	pub fn global_transform(&self) -> Mat4 {
+		if !self.is_static.load(Ordering::Relaxed) {
+			return self.compute_global_transform();
+		}
+		// Taken before computing, so a move that happens meanwhile leaves the cache stale
+		let generation = TRANSFORM_GENERATION.load(Ordering::Acquire);
+		if let Some((cached_generation, global_transform)) = *self.global_transform_cache.lock() {
+			if self.moved_generation.load(Ordering::Acquire) <= cached_generation {
+				return global_transform;
+			}
+		}
+		let global_transform = self.compute_global_transform();
+		*self.global_transform_cache.lock() = Some((generation, global_transform));
+		global_transform
+	}
+	fn compute_global_transform(&self) -> Mat4 {
 		match self.get_parent() {
 			Some(value) => value.global_transform() * *self.transform.lock(),
 			None => *self.transform.lock(),
 		}
 	}
+	/// Dynamic by default, static spatials are for the parts of a scene that don't move.
+	pub fn set_static(&self, is_static: bool) {
+		self.is_static.store(is_static, Ordering::Relaxed);
+		if !is_static {
+			*self.global_transform_cache.lock() = None;
+		}
+	}
+	/// Makes this spatial and everything under it recompute their global transforms. Anything
+	/// that moves a spatial does this already, after the move.
+	pub fn mark_dirty(&self) {
+		let generation = TRANSFORM_GENERATION.fetch_add(1, Ordering::AcqRel) + 1;
+		self.moved_generation
+			.fetch_max(generation, Ordering::Release);
+		// Paid once per move instead of every static descendant walking its ancestors on every read
+		let mut descendants = self.children();
+		while let Some(descendant) = descendants.pop() {
+			descendant
+				.moved_generation
+				.fetch_max(generation, Ordering::Release);
+			descendants.extend(descendant.children());
+		}
+	}
+	/// Inverse of `global_transform`, only recomputed when the global transform changed.
+	pub fn global_transform_inverse(&self) -> Mat4 {
+		let global_transform = self.global_transform();
+		let mut inverse_cache = self.inverse_cache.lock();
+		match *inverse_cache {
+			Some((cached_transform, inverse)) if cached_transform == global_transform => inverse,
+			_ => {
+				let inverse = global_transform.inverse();
+				*inverse_cache = Some((global_transform, inverse));
+				inverse
+			}
+		}
+	}
+	pub fn transform_point_to_local(&self, point: Vec3A) -> Vec3A {
+		self.global_transform_inverse().transform_point3a(point)
+	}
+	pub fn transform_point_to_world(&self, point: Vec3A) -> Vec3A {
+		self.global_transform().transform_point3a(point)
+	}
+	pub fn transform_pose_to_local(&self, position: Vec3A, rotation: Quat) -> (Vec3A, Quat) {
+		Self::transform_pose(self.global_transform_inverse(), position, rotation)
+	}
+	pub fn transform_pose_to_world(&self, position: Vec3A, rotation: Quat) -> (Vec3A, Quat) {
+		Self::transform_pose(self.global_transform(), position, rotation)
+	}
+	fn transform_pose(transform: Mat4, position: Vec3A, rotation: Quat) -> (Vec3A, Quat) {
+		let pose = transform * Mat4::from_rotation_translation(rotation, position.into());
+		let (_, rotation, position) = pose.to_scale_rotation_translation();
+		(position.into(), rotation)
+	}
+
 	#[instrument]
 	pub fn set_local_transform(&self, transform: Mat4) {
 		*self.transform.lock() = transform;
+		self.mark_dirty();
 	}
 	#[instrument(level = "debug", skip(self, reference_space))]
 	pub fn set_local_transform_components(
@@ -153,9 +383,12 @@ impl Spatial {
 		}
 	}
 
-	fn get_parent(&self) -> Option<Arc<Spatial>> {
+	pub(super) fn get_parent(&self) -> Option<Arc<Spatial>> {
 		self.parent.lock().clone()
 	}
+	pub(super) fn children(&self) -> Vec<Arc<Spatial>> {
+		self.children.get_valid_contents()
+	}
 	fn set_parent(&self, new_parent: Option<Arc<Spatial>>) {
 		if let Some(parent) = self.get_parent() {
 			parent.children.remove(self);
@@ -167,6 +400,7 @@ impl Spatial {
 		}
 
 		*self.parent.lock() = new_parent;
+		self.mark_dirty();
 	}
 
 	#[instrument(level = "debug", skip_all)]
@@ -193,6 +427,8 @@ impl Spatial {
 			return Err(eyre!("Setting spatial parent would cause a loop"));
 		}
 
+		// A smoothed transform would keep heading for where it was going in the old parent
+		self.stop_smoothing();
 		self.set_local_transform(Spatial::space_to_space_matrix(
 			Some(self),
 			parent.as_deref(),
@@ -226,6 +462,184 @@ impl Spatial {
 		))
 		.map_err(|e| e.into())
 	}
+	/// Linear velocity in meters per second and angular velocity as an axis scaled by radians per
+	/// second, rotated into the reference space. Tracking starts on the first call, so that one
+	/// and the next few are low until the estimate settles.
+	pub fn get_velocity_flex(
+		node: &Node,
+		calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<Vec<u8>> {
+		let this_spatial = node
+			.spatial
+			.get()
+			.ok_or_else(|| eyre!("Node doesn't have a spatial?"))?;
+		let reference_space = find_reference_space(&calling_client, deserialize(data)?)?;
+
+		let (linear, angular) = {
+			let mut velocity = this_spatial.velocity.lock();
+			let velocity = velocity.get_or_insert_with(|| {
+				VELOCITY_REGISTRY.add_raw(this_spatial);
+				VelocityTracking::new(this_spatial.global_transform())
+			});
+			(velocity.linear, velocity.angular)
+		};
+		let world_to_reference = reference_space.global_transform_inverse();
+		let (_, reference_rotation, _) = world_to_reference.to_scale_rotation_translation();
+		serialize((
+			mint::Vector3::from(world_to_reference.transform_vector3(linear)),
+			mint::Vector3::from(reference_rotation * angular),
+		))
+		.map_err(|e| e.into())
+	}
+	/// Updates the velocity of every spatial a client asked about, meant to be called once per
+	/// frame after anything else that moves spatials.
+	pub fn update_velocities(delta: f32) {
+		for spatial in VELOCITY_REGISTRY.get_valid_contents() {
+			let transform = spatial.global_transform();
+			let mut velocity = spatial.velocity.lock();
+			let Some(velocity) = velocity.as_mut() else {
+				VELOCITY_REGISTRY.remove(&spatial);
+				continue;
+			};
+			velocity.update(transform, delta);
+		}
+	}
+	/// Moves every smoothed spatial along by `delta` seconds, meant to be called once per frame.
+	pub fn update_smoothed_transforms(delta: f32) {
+		for spatial in SMOOTHING_REGISTRY.get_valid_contents() {
+			let mut smoothing = spatial.smoothing.lock();
+			let Some(current_smoothing) = smoothing.as_mut() else {
+				SMOOTHING_REGISTRY.remove(&spatial);
+				continue;
+			};
+			current_smoothing.elapsed += delta;
+			spatial.set_local_transform(current_smoothing.current());
+			if current_smoothing.finished() {
+				*smoothing = None;
+				SMOOTHING_REGISTRY.remove(&spatial);
+			}
+		}
+	}
+	/// Turns every billboarded spatial towards `head_position`, meant to be called once per frame
+	/// after anything else that moves spatials.
+	pub fn update_billboards(head_position: Vec3) {
+		let mut spatials = BILLBOARD_REGISTRY.get_valid_contents();
+		// Parents go first so nested billboards are turned relative to where the parent ends up
+		spatials.sort_by_cached_key(|spatial| spatial.depth());
+		for spatial in spatials {
+			let billboard = *spatial.billboard.lock();
+			let position = spatial.global_transform().transform_point3(Vec3::ZERO);
+			let mut forward = position - head_position;
+			if billboard == Billboard::Yaw {
+				forward.y = 0.0;
+			}
+			let Some(rotation) = look_rotation(forward, Vec3::Y) else {continue};
+			spatial.set_global_rotation(rotation);
+		}
+	}
+	fn depth(&self) -> usize {
+		let mut depth = 0;
+		let mut parent = self.get_parent();
+		while let Some(ancestor) = parent {
+			depth += 1;
+			parent = ancestor.get_parent();
+		}
+		depth
+	}
+	/// Turns the spatial so -Z points at `target` with +Y towards `up`, both in world space.
+	pub fn look_at(&self, target: Vec3, up: Vec3) -> Result<()> {
+		let position = self.global_transform().transform_point3(Vec3::ZERO);
+		let rotation = look_rotation(target - position, up)
+			.ok_or_else(|| eyre!("Target is on top of the spatial or straight along up"))?;
+		self.set_global_rotation(rotation);
+		Ok(())
+	}
+	/// Replaces only the rotation of the local transform so it ends up at `rotation` in world
+	/// space, keeping the position and scale.
+	fn set_global_rotation(&self, rotation: Quat) {
+		let parent_rotation = self.get_parent().map_or(Quat::IDENTITY, |parent| {
+			parent.global_transform().to_scale_rotation_translation().1
+		});
+		let (scale, _, position) = self.local_transform().to_scale_rotation_translation();
+		let local_rotation = parent_rotation.inverse() * rotation;
+		self.set_local_transform(Mat4::from_scale_rotation_translation(
+			scale,
+			local_rotation,
+			position,
+		));
+	}
+	fn stop_smoothing(&self) {
+		if self.smoothing.lock().take().is_some() {
+			SMOOTHING_REGISTRY.remove(self);
+		}
+	}
+
+	/// Sends `transform_changed` to every watching client whose spatial moved enough since the
+	/// last time it was sent, meant to be called once per frame.
+	pub fn send_transform_change_events() {
+		for spatial in TRANSFORM_WATCH_REGISTRY.get_valid_contents() {
+			spatial.send_transform_change();
+		}
+	}
+	fn send_transform_change(&self) {
+		let mut transform_watch = self.transform_watch.lock();
+		let Some(watch) = transform_watch.as_mut() else {return};
+		let Some(reference_space) = watch.reference_space.upgrade() else {return};
+		let transform = Spatial::space_to_space_matrix(Some(self), Some(reference_space.as_ref()));
+		if let Some(last_sent) = watch.last_sent {
+			if last_sent.abs_diff_eq(transform, watch.threshold) {
+				return;
+			}
+		}
+		let Some(node) = self.node.upgrade() else {return};
+		// A client that's behind has the older ones dropped by the relay, so it gets the latest
+		// transform once it catches up instead of every one in between
+		watch.last_sent = Some(transform);
+		drop(transform_watch);
+
+		let (scale, rotation, position) = transform.to_scale_rotation_translation();
+		let Ok(data) = serialize((
+			mint::Vector3::from(position),
+			mint::Quaternion::from(rotation),
+			mint::Vector3::from(scale),
+		)) else {return};
+		let _ = node.send_remote_signal("transform_changed", &data);
+	}
+	pub fn watch_transform_flex(
+		node: &Node,
+		calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		#[derive(Deserialize)]
+		struct WatchTransformArgs<'a> {
+			reference_space_path: &'a str,
+			threshold: Option<f32>,
+		}
+		let args: WatchTransformArgs = deserialize(data)?;
+		let reference_space = find_reference_space(&calling_client, args.reference_space_path)?;
+		let threshold = args.threshold.unwrap_or(DEFAULT_TRANSFORM_THRESHOLD);
+		ensure!(threshold >= 0.0, "Transform threshold can't be negative");
+
+		let spatial = node.spatial.get().unwrap();
+		*spatial.transform_watch.lock() = Some(TransformWatch {
+			reference_space: Arc::downgrade(&reference_space),
+			threshold,
+			last_sent: None,
+		});
+		TRANSFORM_WATCH_REGISTRY.add_raw(spatial);
+		Ok(())
+	}
+	pub fn unwatch_transform_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		_data: &[u8],
+	) -> Result<()> {
+		let spatial = node.spatial.get().unwrap();
+		TRANSFORM_WATCH_REGISTRY.remove(spatial);
+		*spatial.transform_watch.lock() = None;
+		Ok(())
+	}
 	pub fn set_transform_flex(node: &Node, calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
 		#[derive(Deserialize)]
 		struct TransformArgs<'a> {
@@ -238,12 +652,54 @@ impl Spatial {
 			.map(|path| find_reference_space(&calling_client, path))
 			.transpose()?;
 
-		node.spatial.get().unwrap().set_local_transform_components(
+		let spatial = node.spatial.get().unwrap();
+		spatial.stop_smoothing();
+		spatial.set_local_transform_components(
 			reference_space_transform.as_deref(),
 			transform_args.transform,
 		);
 		Ok(())
 	}
+	pub fn set_transform_smoothed_flex(
+		node: &Node,
+		calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		#[derive(Deserialize)]
+		struct SmoothedTransformArgs<'a> {
+			reference_space_path: Option<&'a str>,
+			transform: Transform,
+			duration: f32,
+			#[serde(default)]
+			easing: Easing,
+		}
+		let args: SmoothedTransformArgs = deserialize(data)?;
+		let reference_space_transform = args
+			.reference_space_path
+			.map(|path| find_reference_space(&calling_client, path))
+			.transpose()?;
+
+		let spatial = node.spatial.get().unwrap();
+		spatial.stop_smoothing();
+		let start = spatial.local_transform();
+		spatial
+			.set_local_transform_components(reference_space_transform.as_deref(), args.transform);
+		// A duration of 0 snaps straight to the target
+		if args.duration <= 0.0 || !args.duration.is_finite() {
+			return Ok(());
+		}
+		let target = spatial.local_transform();
+		spatial.set_local_transform(start);
+		*spatial.smoothing.lock() = Some(TransformSmoothing {
+			start,
+			target,
+			duration: args.duration,
+			elapsed: 0.0,
+			easing: args.easing,
+		});
+		SMOOTHING_REGISTRY.add_raw(spatial);
+		Ok(())
+	}
 	pub fn set_spatial_parent_flex(
 		node: &Node,
 		calling_client: Arc<Client>,
@@ -264,6 +720,75 @@ impl Spatial {
 			.set_spatial_parent_in_place(Some(parent))?;
 		Ok(())
 	}
+	/// Like `set_spatial_parent_in_place` unless `keep_local_transform` is set, and without a
+	/// parent it's detached into world space. For picking things up into a hand and dropping them
+	/// back into the world.
+	pub fn set_parent_flex(node: &Node, calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		#[derive(Deserialize)]
+		struct SetParentInfo<'a> {
+			#[serde(borrow)]
+			parent_path: Option<&'a str>,
+			#[serde(default)]
+			keep_local_transform: bool,
+		}
+		let info: SetParentInfo = deserialize(data)?;
+		let parent = info
+			.parent_path
+			.map(|parent_path| find_spatial_parent(&calling_client, parent_path))
+			.transpose()?;
+		let spatial = node.spatial.get().unwrap();
+		if info.keep_local_transform {
+			spatial.set_spatial_parent(parent)
+		} else {
+			spatial.set_spatial_parent_in_place(parent)
+		}
+	}
+	pub fn set_static_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		node.spatial.get().unwrap().set_static(deserialize(data)?);
+		Ok(())
+	}
+	pub fn mark_dirty_flex(node: &Node, _calling_client: Arc<Client>, _data: &[u8]) -> Result<()> {
+		node.spatial.get().unwrap().mark_dirty();
+		Ok(())
+	}
+	pub fn set_billboard_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let billboard: Billboard = deserialize(data)?;
+		let spatial = node.spatial.get().unwrap();
+		*spatial.billboard.lock() = billboard;
+		if billboard == Billboard::Off {
+			BILLBOARD_REGISTRY.remove(spatial);
+		} else {
+			BILLBOARD_REGISTRY.add_raw(spatial);
+		}
+		Ok(())
+	}
+	pub fn look_at_flex(node: &Node, calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		#[derive(Deserialize)]
+		struct LookAtArgs<'a> {
+			/// Space the target and up are in, defaults to world space
+			#[serde(default)]
+			reference_space_path: Option<&'a str>,
+			target: Vector3<f32>,
+			/// Defaults to +Y
+			#[serde(default)]
+			up: Option<Vector3<f32>>,
+		}
+		let args: LookAtArgs = deserialize(data)?;
+		let reference_transform = match args.reference_space_path {
+			Some(path) => find_reference_space(&calling_client, path)?.global_transform(),
+			None => Mat4::IDENTITY,
+		};
+		let target = reference_transform.transform_point3(args.target.into());
+		let up = reference_transform.transform_vector3(args.up.map_or(Vec3::Y, Vec3::from));
+
+		let spatial = node.spatial.get().unwrap();
+		spatial.stop_smoothing();
+		spatial.look_at(target, up)
+	}
 	pub fn set_zoneable_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
 		let zoneable: bool = deserialize(data)?;
 		let spatial = node.spatial.get().unwrap();
@@ -276,6 +801,57 @@ impl Spatial {
 		Ok(())
 	}
 
+	pub fn point_to_local_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<Vec<u8>> {
+		let point: Vector3<f32> = deserialize(data)?;
+		let spatial = node.spatial.get().unwrap();
+		Ok(serialize(Vector3::from(
+			spatial.transform_point_to_local(point.into()),
+		))?)
+	}
+	pub fn point_to_world_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<Vec<u8>> {
+		let point: Vector3<f32> = deserialize(data)?;
+		let spatial = node.spatial.get().unwrap();
+		Ok(serialize(Vector3::from(
+			spatial.transform_point_to_world(point.into()),
+		))?)
+	}
+	pub fn pose_to_local_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<Vec<u8>> {
+		let (position, rotation): (Vector3<f32>, mint::Quaternion<f32>) = deserialize(data)?;
+		let spatial = node.spatial.get().unwrap();
+		let (position, rotation) =
+			spatial.transform_pose_to_local(position.into(), rotation.into());
+		Ok(serialize((
+			Vector3::from(position),
+			mint::Quaternion::from(rotation),
+		))?)
+	}
+	pub fn pose_to_world_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<Vec<u8>> {
+		let (position, rotation): (Vector3<f32>, mint::Quaternion<f32>) = deserialize(data)?;
+		let spatial = node.spatial.get().unwrap();
+		let (position, rotation) =
+			spatial.transform_pose_to_world(position.into(), rotation.into());
+		Ok(serialize((
+			Vector3::from(position),
+			mint::Quaternion::from(rotation),
+		))?)
+	}
+
 	pub fn field_distance_flex(
 		node: &Node,
 		calling_client: Arc<Client>,
@@ -374,10 +950,23 @@ impl Debug for Spatial {
 impl Drop for Spatial {
 	fn drop(&mut self) {
 		ZONEABLE_REGISTRY.remove(self);
+		TRANSFORM_WATCH_REGISTRY.remove(self);
+		SMOOTHING_REGISTRY.remove(self);
+		BILLBOARD_REGISTRY.remove(self);
+		VELOCITY_REGISTRY.remove(self);
 		zone::release(self);
 	}
 }
 
+/// The rotation that points -Z (StereoKit's forward) along `forward` with +Y as close to `up` as
+/// it can get, `None` if either is zero or they're parallel.
+fn look_rotation(forward: Vec3, up: Vec3) -> Option<Quat> {
+	let back = -forward.try_normalize()?;
+	let right = up.cross(back).try_normalize()?;
+	let up = back.cross(right);
+	Some(Quat::from_mat3(&Mat3::from_cols(right, up, back)))
+}
+
 pub fn parse_transform(transform: Transform, position: bool, rotation: bool, scale: bool) -> Mat4 {
 	let position = position
 		.then_some(transform.position)
@@ -441,3 +1030,140 @@ pub fn create_spatial_flex(_node: &Node, calling_client: Arc<Client>, data: &[u8
 	Spatial::add_to(&node, Some(parent), transform, info.zoneable)?;
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn static_spatials_follow_moved_ancestors() {
+		let root = Spatial::new(Weak::new(), None, Mat4::IDENTITY);
+		let parent = Spatial::new(Weak::new(), Some(root.clone()), Mat4::IDENTITY);
+		let child = Spatial::new(Weak::new(), Some(parent.clone()), Mat4::IDENTITY);
+		child.set_static(true);
+		assert_eq!(child.global_transform(), Mat4::IDENTITY);
+
+		let moved = Mat4::from_translation(Vec3::X);
+		root.set_local_transform(moved);
+		assert_eq!(child.global_transform(), moved);
+		// A second read comes from the cache
+		assert_eq!(
+			child.global_transform_cache.lock().map(|(_, t)| t),
+			Some(moved)
+		);
+		assert_eq!(child.global_transform(), moved);
+
+		// Reparenting moves the whole subtree along with it
+		let grandchild = Spatial::new(Weak::new(), Some(child.clone()), Mat4::IDENTITY);
+		grandchild.set_static(true);
+		assert_eq!(grandchild.global_transform(), moved);
+		let other = Spatial::new(Weak::new(), None, Mat4::from_translation(Vec3::Y));
+		parent.set_parent(Some(other));
+		assert_eq!(
+			grandchild.global_transform(),
+			Mat4::from_translation(Vec3::Y)
+		);
+	}
+
+	#[test]
+	fn easings_start_at_zero_and_end_at_one() {
+		for easing in [
+			Easing::Linear,
+			Easing::EaseIn,
+			Easing::EaseOut,
+			Easing::EaseInOut,
+		] {
+			assert_eq!(easing.apply(0.0), 0.0);
+			assert_eq!(easing.apply(1.0), 1.0);
+		}
+		assert_eq!(Easing::Linear.apply(0.25), 0.25);
+		assert_eq!(Easing::EaseIn.apply(0.5), 0.25);
+		assert_eq!(Easing::EaseOut.apply(0.5), 0.75);
+		assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+		assert!(Easing::EaseInOut.apply(0.25) < 0.25);
+	}
+
+	#[test]
+	fn smoothing_interpolates_each_component_and_stops_at_the_target() {
+		let target = Mat4::from_scale_rotation_translation(
+			Vec3::splat(3.0),
+			Quat::from_rotation_z(std::f32::consts::FRAC_PI_2),
+			Vec3::new(2.0, 0.0, 0.0),
+		);
+		let mut smoothing = TransformSmoothing {
+			start: Mat4::IDENTITY,
+			target,
+			duration: 2.0,
+			elapsed: 1.0,
+			easing: Easing::Linear,
+		};
+		let (scale, rotation, position) = smoothing.current().to_scale_rotation_translation();
+		assert!(scale.abs_diff_eq(Vec3::splat(2.0), 1e-5));
+		assert!(rotation.abs_diff_eq(Quat::from_rotation_z(std::f32::consts::FRAC_PI_4), 1e-5));
+		assert!(position.abs_diff_eq(Vec3::new(1.0, 0.0, 0.0), 1e-5));
+		assert!(!smoothing.finished());
+		// Overshooting the duration doesn't go past the target
+		smoothing.elapsed = 3.0;
+		assert!(smoothing.current().abs_diff_eq(target, 1e-5));
+		assert!(smoothing.finished());
+	}
+
+	#[test]
+	fn look_rotation_points_forward_and_keeps_up() {
+		let rotation = look_rotation(Vec3::new(1.0, 0.0, 0.0), Vec3::Y).unwrap();
+		assert!((rotation * Vec3::NEG_Z).abs_diff_eq(Vec3::X, 1e-6));
+		assert!((rotation * Vec3::Y).abs_diff_eq(Vec3::Y, 1e-6));
+		// Up only has to be roughly right, it gets straightened out
+		let rotation = look_rotation(Vec3::new(0.0, 0.0, -2.0), Vec3::new(0.0, 1.0, 1.0)).unwrap();
+		assert!(rotation.abs_diff_eq(Quat::IDENTITY, 1e-6));
+		assert!(look_rotation(Vec3::ZERO, Vec3::Y).is_none());
+		assert!(look_rotation(Vec3::Y, Vec3::Y).is_none());
+	}
+
+	#[test]
+	fn look_at_turns_only_the_rotation_under_a_turned_parent() {
+		let parent_rotation = Quat::from_rotation_y(std::f32::consts::FRAC_PI_2);
+		let parent = Spatial::new(
+			Weak::new(),
+			None,
+			Mat4::from_rotation_translation(parent_rotation, Vec3::new(0.0, 0.0, -1.0)),
+		);
+		let local = Mat4::from_scale_rotation_translation(
+			Vec3::splat(2.0),
+			Quat::from_rotation_x(1.0),
+			Vec3::new(0.0, 1.0, 0.0),
+		);
+		let spatial = Spatial::new(Weak::new(), Some(parent), local);
+		spatial.look_at(Vec3::new(5.0, 1.0, -1.0), Vec3::Y).unwrap();
+		let (scale, rotation, position) =
+			spatial.global_transform().to_scale_rotation_translation();
+		assert!(scale.abs_diff_eq(Vec3::splat(2.0), 1e-5));
+		assert!(position.abs_diff_eq(Vec3::new(0.0, 1.0, -1.0), 1e-5));
+		assert!((rotation * Vec3::NEG_Z).abs_diff_eq(Vec3::X, 1e-5));
+		assert!((rotation * Vec3::Y).abs_diff_eq(Vec3::Y, 1e-5));
+		// Looking at itself has no direction to turn to
+		assert!(spatial.look_at(position, Vec3::Y).is_err());
+	}
+
+	#[test]
+	fn velocity_is_smoothed_and_takes_the_short_way_around() {
+		let mut velocity = VelocityTracking::new(Mat4::IDENTITY);
+		velocity.update(Mat4::from_translation(Vec3::new(0.5, 0.0, 0.0)), 0.5);
+		assert!(velocity
+			.linear
+			.abs_diff_eq(Vec3::new(VELOCITY_SMOOTHING, 0.0, 0.0), 1e-6));
+		// A paused frame keeps the estimate instead of dividing by zero
+		let estimate = velocity.linear;
+		velocity.update(Mat4::from_translation(Vec3::new(0.6, 0.0, 0.0)), 0.0);
+		assert_eq!(velocity.linear, estimate);
+
+		// Going from just short of a half turn to just past it is a small step, not nearly a
+		// full turn back
+		let almost_half_turn = Quat::from_rotation_y(std::f32::consts::PI - 0.05);
+		let mut velocity = VelocityTracking::new(Mat4::from_quat(almost_half_turn));
+		let past_half_turn = Quat::from_rotation_y(std::f32::consts::PI + 0.05);
+		velocity.update(Mat4::from_quat(past_half_turn), 0.1);
+		let expected = Vec3::new(0.0, 1.0, 0.0) * VELOCITY_SMOOTHING;
+		assert!(velocity.angular.abs_diff_eq(expected, 1e-3));
+	}
+}
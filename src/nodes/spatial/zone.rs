@@ -4,12 +4,13 @@ use crate::{
 	nodes::{
 		alias::{Alias, AliasInfo},
 		fields::{find_field, Field},
-		spatial::{find_spatial_parent, parse_transform},
+		spatial::{find_reference_space, find_spatial_parent, parse_transform},
 		Node,
 	},
 };
 use color_eyre::eyre::Result;
-use glam::vec3a;
+use glam::{vec3a, Vec3A};
+use mint::Vector3;
 use parking_lot::Mutex;
 use rustc_hash::FxHashMap;
 use serde::Deserialize;
@@ -46,26 +47,68 @@ pub fn release(spatial: &Spatial) {
 	*spatial_zone = Weak::new();
 }
 
+/// Shape of a zone in its own space, centered on its origin.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum ZoneBounds {
+	Box(Vector3<f32>),
+	Sphere(f32),
+}
+impl ZoneBounds {
+	fn contains_local(&self, point: Vec3A) -> bool {
+		match self {
+			ZoneBounds::Box(size) => {
+				let half_size = Vec3A::from(*size) * 0.5;
+				point.abs().cmple(half_size).all()
+			}
+			ZoneBounds::Sphere(radius) => point.length() <= *radius,
+		}
+	}
+}
+
 pub struct Zone {
 	spatial: Arc<Spatial>,
 	pub field: Weak<Field>,
+	bounds: Option<ZoneBounds>,
 	zoneables: Mutex<FxHashMap<String, Arc<Node>>>,
 	captured: Registry<Spatial>,
 }
 impl Zone {
-	pub fn add_to(node: &Arc<Node>, spatial: Arc<Spatial>, field: &Arc<Field>) -> Arc<Zone> {
+	pub fn add_to(
+		node: &Arc<Node>,
+		spatial: Arc<Spatial>,
+		field: &Arc<Field>,
+		bounds: Option<ZoneBounds>,
+	) -> Arc<Zone> {
 		let zone = Arc::new(Zone {
 			spatial,
 			field: Arc::downgrade(field),
+			bounds,
 			zoneables: Mutex::new(FxHashMap::default()),
 			captured: Registry::new(),
 		});
 		node.add_local_signal("capture", Zone::capture_flex);
 		node.add_local_signal("release", Zone::release_flex);
 		node.add_local_signal("update", Zone::update);
+		node.add_local_method("contains", Zone::contains_flex);
 		let _ = node.zone.set(zone.clone());
 		zone
 	}
+	/// Whether a world space point is inside the zone's bounds, taking its scale and rotation into
+	/// account. Zones created without bounds contain nothing.
+	pub fn contains(&self, point: Vec3A) -> bool {
+		let Some(bounds) = &self.bounds else {return false};
+		let world_to_local = self.spatial.global_transform_inverse();
+		bounds.contains_local(world_to_local.transform_point3a(point))
+	}
+	fn contains_flex(node: &Node, calling_client: Arc<Client>, data: &[u8]) -> Result<Vec<u8>> {
+		let zone = node.zone.get().unwrap();
+		let (reference_space_path, point): (&str, Vector3<f32>) = deserialize(data)?;
+		let reference_space = find_reference_space(&calling_client, reference_space_path)?;
+		let world_point = reference_space
+			.global_transform()
+			.transform_point3a(point.into());
+		Ok(serialize(zone.contains(world_point))?)
+	}
 	fn capture_flex(node: &Node, calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
 		let zone = node.zone.get().unwrap();
 		let capture_path: &str = deserialize(data)?;
@@ -118,6 +161,7 @@ impl Zone {
 							"set_transform",
 							"set_spatial_parent",
 							"set_spatial_parent_in_place",
+							"set_parent",
 						],
 						local_methods: vec!["get_transform"],
 						..Default::default()
@@ -155,6 +199,8 @@ pub fn create_zone_flex(_node: &Node, calling_client: Arc<Client>, data: &[u8])
 		parent_path: &'a str,
 		transform: Transform,
 		field_path: &'a str,
+		#[serde(default)]
+		bounds: Option<ZoneBounds>,
 	}
 	let info: CreateZoneInfo = deserialize(data)?;
 	let parent = find_spatial_parent(&calling_client, info.parent_path)?;
@@ -164,6 +210,47 @@ pub fn create_zone_flex(_node: &Node, calling_client: Arc<Client>, data: &[u8])
 	let node =
 		Node::create(&calling_client, "/spatial/zone", info.name, true).add_to_scenegraph()?;
 	let space = Spatial::add_to(&node, Some(parent), transform, false)?;
-	Zone::add_to(&node, space, &field);
+	Zone::add_to(&node, space, &field, info.bounds);
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use glam::{Mat4, Quat, Vec3};
+	use std::sync::Weak;
+
+	#[test]
+	fn bounds_include_their_surface() {
+		let cube = ZoneBounds::Box([2.0, 1.0, 4.0].into());
+		assert!(cube.contains_local(Vec3A::ZERO));
+		assert!(cube.contains_local(vec3a(1.0, -0.5, 2.0)));
+		assert!(!cube.contains_local(vec3a(1.0, 0.51, 0.0)));
+		assert!(!cube.contains_local(vec3a(0.0, 0.0, -2.01)));
+		let sphere = ZoneBounds::Sphere(0.5);
+		assert!(sphere.contains_local(vec3a(0.0, 0.5, 0.0)));
+		assert!(!sphere.contains_local(vec3a(0.3, 0.3, 0.3)));
+	}
+
+	#[test]
+	fn world_points_are_checked_in_the_zones_space() {
+		let transform = Mat4::from_scale_rotation_translation(
+			Vec3::splat(2.0),
+			Quat::from_rotation_y(std::f32::consts::FRAC_PI_2),
+			Vec3::new(0.0, 0.0, -5.0),
+		);
+		let zone = |bounds| Zone {
+			spatial: Spatial::new(Weak::new(), None, transform),
+			field: Weak::new(),
+			bounds,
+			zoneables: Mutex::new(FxHashMap::default()),
+			captured: Registry::new(),
+		};
+		let thin = zone(Some(ZoneBounds::Box([1.0, 1.0, 0.2].into())));
+		// Turned so the thin side runs along world X, then scaled up
+		assert!(thin.contains(vec3a(0.15, 0.0, -5.0)));
+		assert!(!thin.contains(vec3a(0.25, 0.0, -5.0)));
+		assert!(thin.contains(vec3a(0.0, 0.9, -4.1)));
+		assert!(!zone(None).contains(vec3a(0.0, 0.0, -5.0)));
+	}
+}
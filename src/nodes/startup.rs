@@ -75,8 +75,8 @@ impl Debug for StartupSettings {
 				"acceptors",
 				&self
 					.acceptors
-					.iter()
-					.map(|(k, _)| k.type_name)
+					.keys()
+					.map(|k| k.type_name)
 					.collect::<Vec<_>>(),
 			)
 			.finish()
@@ -9,7 +9,7 @@ use crate::{
 	},
 };
 use color_eyre::eyre::Result;
-use glam::{vec3, Mat4, Vec3};
+use glam::{vec3, Mat4};
 use nanoid::nanoid;
 use serde::Serialize;
 use stardust_xr::schemas::{flat::Datamap, flex::flexbuffers};
@@ -62,7 +62,7 @@ impl MousePointer {
 
 		let ray = ray_from_mouse(mouse.pos).unwrap();
 		self.spatial.set_local_transform(
-			Mat4::look_to_rh(ray.pos.into(), -Vec3::from(ray.dir), vec3(0.0, 1.0, 0.0)).inverse(),
+			Mat4::look_to_rh(ray.pos, -ray.dir, vec3(0.0, 1.0, 0.0)).inverse(),
 		);
 		{
 			// Set pointer input datamap
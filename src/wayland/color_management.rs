@@ -0,0 +1,497 @@
+use super::state::WaylandState;
+use parking_lot::Mutex;
+use portable_atomic::{AtomicU32, Ordering};
+use smithay::{
+	reexports::{
+		wayland_protocols::wp::color_management::v1::server::{
+			wp_color_management_output_v1::{self, WpColorManagementOutputV1},
+			wp_color_management_surface_feedback_v1::{self, WpColorManagementSurfaceFeedbackV1},
+			wp_color_management_surface_v1::{self, WpColorManagementSurfaceV1},
+			wp_color_manager_v1::{
+				self, Feature, Primaries, RenderIntent, TransferFunction, WpColorManagerV1,
+			},
+			wp_image_description_creator_icc_v1::{self, WpImageDescriptionCreatorIccV1},
+			wp_image_description_creator_params_v1::{self, WpImageDescriptionCreatorParamsV1},
+			wp_image_description_info_v1::{self, WpImageDescriptionInfoV1},
+			wp_image_description_v1::{self, Cause, WpImageDescriptionV1},
+		},
+		wayland_server::{
+			protocol::wl_surface::WlSurface, Client, DataInit, Dispatch, DisplayHandle,
+			GlobalDispatch, New, Resource, WEnum, Weak as WlWeak,
+		},
+	},
+	wayland::compositor,
+};
+use tracing::debug;
+
+/// Every image description needs an identity that's unique for as long as the server runs.
+static IMAGE_DESCRIPTION_IDENTITY: AtomicU32 = AtomicU32::new(1);
+
+const SUPPORTED_TRANSFER_FUNCTIONS: [TransferFunction; 4] = [
+	TransferFunction::Srgb,
+	TransferFunction::Gamma22,
+	TransferFunction::ExtLinear,
+	TransferFunction::St2084Pq,
+];
+const SUPPORTED_PRIMARIES: [Primaries; 2] = [Primaries::Srgb, Primaries::Bt2020];
+
+/// How a surface's content is encoded, StereoKit renders everything as sRGB so this is what the
+/// panel shader converts from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorDescription {
+	pub transfer_function: TransferFunction,
+	pub primaries: Primaries,
+}
+impl ColorDescription {
+	pub const SRGB: Self = ColorDescription {
+		transfer_function: TransferFunction::Srgb,
+		primaries: Primaries::Srgb,
+	};
+
+	/// The `transfer_function` value the panel shader switches on.
+	pub fn shader_transfer_function(&self) -> f32 {
+		match self.transfer_function {
+			TransferFunction::ExtLinear => 1.0,
+			TransferFunction::St2084Pq => 2.0,
+			_ => 0.0,
+		}
+	}
+	/// The `bt2020` value the panel shader uses to convert wide gamut primaries to sRGB ones.
+	pub fn shader_bt2020(&self) -> f32 {
+		match self.primaries {
+			Primaries::Bt2020 => 1.0,
+			_ => 0.0,
+		}
+	}
+
+	fn send_information(&self, info: &WpImageDescriptionInfoV1) {
+		// Chromaticity coordinates are sent multiplied by a million
+		let [r_x, r_y, g_x, g_y, b_x, b_y, w_x, w_y] = match self.primaries {
+			Primaries::Bt2020 => [
+				708000, 292000, 170000, 797000, 131000, 46000, 312700, 329000,
+			],
+			_ => [
+				640000, 330000, 300000, 600000, 150000, 60000, 312700, 329000,
+			],
+		};
+		info.primaries(r_x, r_y, g_x, g_y, b_x, b_y, w_x, w_y);
+		info.primaries_named(self.primaries);
+		info.tf_named(self.transfer_function);
+		// Minimum luminance is sent in units of 0.0001 cd/m²
+		match self.transfer_function {
+			TransferFunction::St2084Pq => info.luminances(50, 10000, 203),
+			_ => info.luminances(2000, 80, 80),
+		}
+		info.done();
+	}
+}
+
+struct PendingColorDescription(Mutex<Option<ColorDescription>>);
+/// A surface can only have one color management object at a time.
+struct ColorManagementSurface(Mutex<Option<WpColorManagementSurfaceV1>>);
+
+fn with_color_surface<T>(
+	surface: &WlSurface,
+	f: impl FnOnce(&mut Option<WpColorManagementSurfaceV1>) -> T,
+) -> T {
+	compositor::with_states(surface, |data| {
+		data.data_map
+			.insert_if_missing_threadsafe(|| ColorManagementSurface(Mutex::new(None)));
+		let mut color_surface = data
+			.data_map
+			.get::<ColorManagementSurface>()
+			.unwrap()
+			.0
+			.lock();
+		f(&mut color_surface)
+	})
+}
+
+/// Takes the description set since the last commit, `None` if it didn't change.
+pub fn take_pending(surface: &WlSurface) -> Option<ColorDescription> {
+	compositor::with_states(surface, |data| {
+		data.data_map
+			.get::<PendingColorDescription>()
+			.and_then(|pending| pending.0.lock().take())
+	})
+}
+fn set_pending(surface: &WlSurface, description: ColorDescription) {
+	compositor::with_states(surface, |data| {
+		data.data_map
+			.insert_if_missing_threadsafe(|| PendingColorDescription(Mutex::new(None)));
+		*data
+			.data_map
+			.get::<PendingColorDescription>()
+			.unwrap()
+			.0
+			.lock() = Some(description);
+	});
+}
+
+fn ready_description(
+	data_init: &mut DataInit<'_, WaylandState>,
+	id: New<WpImageDescriptionV1>,
+	description: ColorDescription,
+) {
+	let image_description = data_init.init(id, Some(description));
+	image_description.ready(IMAGE_DESCRIPTION_IDENTITY.fetch_add(1, Ordering::Relaxed));
+}
+fn failed_description(
+	data_init: &mut DataInit<'_, WaylandState>,
+	id: New<WpImageDescriptionV1>,
+	message: &str,
+) {
+	let image_description = data_init.init(id, None);
+	image_description.failed(Cause::Unsupported, message.to_string());
+}
+
+impl GlobalDispatch<WpColorManagerV1, (), WaylandState> for WaylandState {
+	fn bind(
+		_state: &mut WaylandState,
+		_handle: &DisplayHandle,
+		_client: &Client,
+		resource: New<WpColorManagerV1>,
+		_global_data: &(),
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		let manager = data_init.init(resource, ());
+		manager.supported_intent(RenderIntent::Perceptual);
+		manager.supported_feature(Feature::Parametric);
+		for transfer_function in SUPPORTED_TRANSFER_FUNCTIONS {
+			manager.supported_tf_named(transfer_function);
+		}
+		for primaries in SUPPORTED_PRIMARIES {
+			manager.supported_primaries_named(primaries);
+		}
+		manager.done();
+	}
+}
+
+impl Dispatch<WpColorManagerV1, (), WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		resource: &WpColorManagerV1,
+		request: wp_color_manager_v1::Request,
+		_data: &(),
+		_dhandle: &DisplayHandle,
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			wp_color_manager_v1::Request::GetOutput { id, output: _ } => {
+				data_init.init(id, ());
+			}
+			wp_color_manager_v1::Request::GetSurface { id, surface } => {
+				let color_surface = data_init.init(id, surface.downgrade());
+				let exists = with_color_surface(&surface, |existing| {
+					if existing.as_ref().is_some_and(Resource::is_alive) {
+						return true;
+					}
+					*existing = Some(color_surface.clone());
+					false
+				});
+				if exists {
+					resource.post_error(
+						wp_color_manager_v1::Error::SurfaceExists,
+						"Surface already has a color management object",
+					);
+				}
+			}
+			wp_color_manager_v1::Request::GetSurfaceFeedback { id, surface: _ } => {
+				data_init.init(id, ());
+			}
+			wp_color_manager_v1::Request::CreateIccCreator { obj } => {
+				data_init.init(obj, ());
+				resource.post_error(
+					wp_color_manager_v1::Error::UnsupportedFeature,
+					"ICC profiles aren't supported",
+				);
+			}
+			wp_color_manager_v1::Request::CreateParametricCreator { obj } => {
+				data_init.init(obj, Mutex::new(ParametricParams::default()));
+			}
+			wp_color_manager_v1::Request::CreateWindowsScrgb { image_description } => {
+				failed_description(data_init, image_description, "scRGB isn't supported");
+			}
+			wp_color_manager_v1::Request::Destroy => (),
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Dispatch<WpColorManagementOutputV1, (), WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		_resource: &WpColorManagementOutputV1,
+		request: wp_color_management_output_v1::Request,
+		_data: &(),
+		_dhandle: &DisplayHandle,
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			// StereoKit's swapchain is always sRGB
+			wp_color_management_output_v1::Request::GetImageDescription { image_description } => {
+				ready_description(data_init, image_description, ColorDescription::SRGB);
+			}
+			wp_color_management_output_v1::Request::Destroy => (),
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Dispatch<WpColorManagementSurfaceFeedbackV1, (), WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		_resource: &WpColorManagementSurfaceFeedbackV1,
+		request: wp_color_management_surface_feedback_v1::Request,
+		_data: &(),
+		_dhandle: &DisplayHandle,
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			wp_color_management_surface_feedback_v1::Request::GetPreferred {
+				image_description,
+			}
+			| wp_color_management_surface_feedback_v1::Request::GetPreferredParametric {
+				image_description,
+			} => {
+				ready_description(data_init, image_description, ColorDescription::SRGB);
+			}
+			wp_color_management_surface_feedback_v1::Request::Destroy => (),
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Dispatch<WpColorManagementSurfaceV1, WlWeak<WlSurface>, WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		resource: &WpColorManagementSurfaceV1,
+		request: wp_color_management_surface_v1::Request,
+		surface: &WlWeak<WlSurface>,
+		_dhandle: &DisplayHandle,
+		_data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		let Ok(surface) = surface.upgrade() else {
+			if !matches!(request, wp_color_management_surface_v1::Request::Destroy) {
+				resource.post_error(
+					wp_color_management_surface_v1::Error::Inert,
+					"Surface was destroyed",
+				);
+			}
+			return;
+		};
+		match request {
+			wp_color_management_surface_v1::Request::SetImageDescription {
+				image_description,
+				render_intent,
+			} => {
+				if render_intent != WEnum::Value(RenderIntent::Perceptual) {
+					resource.post_error(
+						wp_color_management_surface_v1::Error::RenderIntent,
+						"Only the perceptual render intent is supported",
+					);
+					return;
+				}
+				let Some(Some(description)) = image_description.data::<Option<ColorDescription>>()
+				else {
+					resource.post_error(
+						wp_color_management_surface_v1::Error::ImageDescription,
+						"Image description isn't ready",
+					);
+					return;
+				};
+				debug!(?surface, ?description, "Set surface image description");
+				set_pending(&surface, *description);
+			}
+			wp_color_management_surface_v1::Request::UnsetImageDescription => {
+				set_pending(&surface, ColorDescription::SRGB);
+			}
+			wp_color_management_surface_v1::Request::Destroy => {
+				set_pending(&surface, ColorDescription::SRGB);
+				// Lets another color management object be made for the surface
+				with_color_surface(&surface, |existing| *existing = None);
+			}
+			_ => unreachable!(),
+		}
+	}
+}
+
+#[derive(Default)]
+struct ParametricParams {
+	transfer_function: Option<TransferFunction>,
+	primaries: Option<Primaries>,
+}
+
+impl Dispatch<WpImageDescriptionCreatorParamsV1, Mutex<ParametricParams>, WaylandState>
+	for WaylandState
+{
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		resource: &WpImageDescriptionCreatorParamsV1,
+		request: wp_image_description_creator_params_v1::Request,
+		params: &Mutex<ParametricParams>,
+		_dhandle: &DisplayHandle,
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		use wp_image_description_creator_params_v1::Error;
+		let mut params = params.lock();
+		match request {
+			wp_image_description_creator_params_v1::Request::SetTfNamed { tf } => {
+				let WEnum::Value(tf) = tf else {
+					resource.post_error(Error::InvalidTf, "Unknown transfer function");
+					return;
+				};
+				if !SUPPORTED_TRANSFER_FUNCTIONS.contains(&tf) {
+					resource.post_error(Error::InvalidTf, "Unsupported transfer function");
+				} else if params.transfer_function.replace(tf).is_some() {
+					resource.post_error(Error::AlreadySet, "Transfer function already set");
+				}
+			}
+			wp_image_description_creator_params_v1::Request::SetPrimariesNamed { primaries } => {
+				let WEnum::Value(primaries) = primaries else {
+					resource.post_error(Error::InvalidPrimariesNamed, "Unknown primaries");
+					return;
+				};
+				if !SUPPORTED_PRIMARIES.contains(&primaries) {
+					resource.post_error(Error::InvalidPrimariesNamed, "Unsupported primaries");
+				} else if params.primaries.replace(primaries).is_some() {
+					resource.post_error(Error::AlreadySet, "Primaries already set");
+				}
+			}
+			// Mastering metadata is only a hint for tone mapping, the fixed curve ignores it
+			wp_image_description_creator_params_v1::Request::SetLuminances { .. }
+			| wp_image_description_creator_params_v1::Request::SetMasteringDisplayPrimaries {
+				..
+			}
+			| wp_image_description_creator_params_v1::Request::SetMasteringLuminance { .. }
+			| wp_image_description_creator_params_v1::Request::SetMaxCll { .. }
+			| wp_image_description_creator_params_v1::Request::SetMaxFall { .. } => (),
+			wp_image_description_creator_params_v1::Request::SetTfPower { .. }
+			| wp_image_description_creator_params_v1::Request::SetPrimaries { .. } => {
+				resource.post_error(
+					Error::UnsupportedFeature,
+					"Only named transfer functions and primaries are supported",
+				);
+			}
+			wp_image_description_creator_params_v1::Request::Create { image_description } => {
+				let (Some(transfer_function), Some(primaries)) =
+					(params.transfer_function, params.primaries)
+				else {
+					data_init.init(image_description, None);
+					resource.post_error(
+						Error::IncompleteSet,
+						"Transfer function and primaries must both be set",
+					);
+					return;
+				};
+				let description = ColorDescription {
+					transfer_function,
+					primaries,
+				};
+				ready_description(data_init, image_description, description);
+			}
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Dispatch<WpImageDescriptionCreatorIccV1, (), WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		_resource: &WpImageDescriptionCreatorIccV1,
+		request: wp_image_description_creator_icc_v1::Request,
+		_data: &(),
+		_dhandle: &DisplayHandle,
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		// The client already got an error when making this, so it's just cleaned up
+		if let wp_image_description_creator_icc_v1::Request::Create { image_description } = request
+		{
+			failed_description(
+				data_init,
+				image_description,
+				"ICC profiles aren't supported",
+			);
+		}
+	}
+}
+
+impl Dispatch<WpImageDescriptionV1, Option<ColorDescription>, WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		resource: &WpImageDescriptionV1,
+		request: wp_image_description_v1::Request,
+		description: &Option<ColorDescription>,
+		_dhandle: &DisplayHandle,
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			wp_image_description_v1::Request::GetInformation { information } => {
+				let info = data_init.init(information, ());
+				let Some(description) = description else {
+					resource.post_error(
+						wp_image_description_v1::Error::NotReady,
+						"Image description failed",
+					);
+					return;
+				};
+				description.send_information(&info);
+			}
+			wp_image_description_v1::Request::Destroy => (),
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Dispatch<WpImageDescriptionInfoV1, (), WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		_resource: &WpImageDescriptionInfoV1,
+		_request: wp_image_description_info_v1::Request,
+		_data: &(),
+		_dhandle: &DisplayHandle,
+		_data_init: &mut DataInit<'_, WaylandState>,
+	) {
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn shader_values_match_the_panel_shader() {
+		let description = |transfer_function, primaries| ColorDescription {
+			transfer_function,
+			primaries,
+		};
+		// The shader treats anything under 0.5 as sRGB, and gamma 2.2 is drawn the same way
+		assert_eq!(ColorDescription::SRGB.shader_transfer_function(), 0.0);
+		assert_eq!(
+			description(TransferFunction::Gamma22, Primaries::Srgb).shader_transfer_function(),
+			0.0
+		);
+		assert_eq!(
+			description(TransferFunction::ExtLinear, Primaries::Srgb).shader_transfer_function(),
+			1.0
+		);
+		assert_eq!(
+			description(TransferFunction::St2084Pq, Primaries::Srgb).shader_transfer_function(),
+			2.0
+		);
+
+		assert_eq!(ColorDescription::SRGB.shader_bt2020(), 0.0);
+		assert_eq!(
+			description(TransferFunction::St2084Pq, Primaries::Bt2020).shader_bt2020(),
+			1.0
+		);
+	}
+}
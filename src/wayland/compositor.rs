@@ -1,11 +1,11 @@
-use crate::wayland::surface::CoreSurface;
+use crate::wayland::{color_management, surface::CoreSurface};
 
-use super::state::WaylandState;
+use super::state::{ClientState, WaylandState};
 use portable_atomic::{AtomicU32, Ordering};
 use smithay::{
 	delegate_compositor,
-	reexports::wayland_server::protocol::wl_surface::WlSurface,
-	wayland::compositor::{self, CompositorHandler, CompositorState},
+	reexports::wayland_server::{protocol::wl_surface::WlSurface, Client},
+	wayland::compositor::{self, CompositorClientState, CompositorHandler, CompositorState},
 };
 use std::sync::Arc;
 use tracing::debug;
@@ -14,6 +14,9 @@ impl CompositorHandler for WaylandState {
 	fn compositor_state(&mut self) -> &mut CompositorState {
 		&mut self.compositor_state
 	}
+	fn client_compositor_state<'a>(&self, client: &'a Client) -> &'a CompositorClientState {
+		&client.get_data::<ClientState>().unwrap().compositor_state
+	}
 
 	fn commit(&mut self, surface: &WlSurface) {
 		debug!(?surface, "Surface commit");
@@ -33,7 +36,19 @@ impl CompositorHandler for WaylandState {
 			data.data_map.get::<Arc<CoreSurface>>().cloned()
 		});
 		if let Some(core_surface) = core_surface {
+			if let Some(color_description) = color_management::take_pending(surface) {
+				core_surface.set_color_description(color_description);
+			}
 			core_surface.commit(count);
+		} else {
+			// Subsurfaces don't get their own core surface but get composited into the root's
+			let mut root = surface.clone();
+			while let Some(parent) = compositor::get_parent(&root) {
+				root = parent;
+			}
+			if let Some(root_surface) = CoreSurface::from_wl_surface(&root) {
+				root_surface.mark_committed();
+			}
 		}
 	}
 }
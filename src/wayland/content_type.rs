@@ -0,0 +1,103 @@
+use super::state::WaylandState;
+use parking_lot::Mutex;
+use smithay::{
+	reexports::{
+		wayland_protocols::wp::content_type::v1::server::{
+			wp_content_type_manager_v1::{self, WpContentTypeManagerV1},
+			wp_content_type_v1::{self, Type, WpContentTypeV1},
+		},
+		wayland_server::{
+			protocol::wl_surface::WlSurface, Client, DataInit, Dispatch, DisplayHandle,
+			GlobalDispatch, New, Resource, WEnum, Weak as WlWeak,
+		},
+	},
+	wayland::compositor,
+};
+use tracing::debug;
+
+struct ContentTypeHint(Mutex<Type>);
+
+/// What kind of content the client says is on the surface, `Type::None` if it didn't say.
+pub fn content_type(surface: &WlSurface) -> Type {
+	compositor::with_states(surface, |data| {
+		data.data_map
+			.get::<ContentTypeHint>()
+			.map(|hint| *hint.0.lock())
+			.unwrap_or(Type::None)
+	})
+}
+fn set_content_type(surface: &WlSurface, new_type: Type) {
+	compositor::with_states(surface, |data| {
+		data.data_map
+			.insert_if_missing_threadsafe(|| ContentTypeHint(Mutex::new(Type::None)));
+		*data.data_map.get::<ContentTypeHint>().unwrap().0.lock() = new_type;
+	});
+}
+/// The protocol's name for it, to send to clients.
+pub fn content_type_name(content_type: Type) -> &'static str {
+	match content_type {
+		Type::Photo => "photo",
+		Type::Video => "video",
+		Type::Game => "game",
+		_ => "none",
+	}
+}
+
+impl GlobalDispatch<WpContentTypeManagerV1, (), WaylandState> for WaylandState {
+	fn bind(
+		_state: &mut WaylandState,
+		_handle: &DisplayHandle,
+		_client: &Client,
+		resource: New<WpContentTypeManagerV1>,
+		_global_data: &(),
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		data_init.init(resource, ());
+	}
+}
+
+impl Dispatch<WpContentTypeManagerV1, (), WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		_resource: &WpContentTypeManagerV1,
+		request: wp_content_type_manager_v1::Request,
+		_data: &(),
+		_dhandle: &DisplayHandle,
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			wp_content_type_manager_v1::Request::GetSurfaceContentType { id, surface } => {
+				data_init.init(id, surface.downgrade());
+			}
+			wp_content_type_manager_v1::Request::Destroy => (),
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Dispatch<WpContentTypeV1, WlWeak<WlSurface>, WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		_resource: &WpContentTypeV1,
+		request: wp_content_type_v1::Request,
+		surface: &WlWeak<WlSurface>,
+		_dhandle: &DisplayHandle,
+		_data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		let Ok(surface) = surface.upgrade() else {return};
+		match request {
+			wp_content_type_v1::Request::SetContentType { content_type } => {
+				let WEnum::Value(content_type) = content_type else {return};
+				debug!(?surface, ?content_type, "Set content type");
+				set_content_type(&surface, content_type);
+			}
+			// Destroying the object resets the surface back to the default
+			wp_content_type_v1::Request::Destroy => {
+				set_content_type(&surface, Type::None);
+			}
+			_ => unreachable!(),
+		}
+	}
+}
@@ -0,0 +1,132 @@
+use super::{seat::SeatData, state::WaylandState};
+use smithay::reexports::{
+	wayland_protocols::wp::cursor_shape::v1::server::{
+		wp_cursor_shape_device_v1::{self, Shape, WpCursorShapeDeviceV1},
+		wp_cursor_shape_manager_v1::{self, WpCursorShapeManagerV1},
+	},
+	wayland_server::{
+		Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+	},
+};
+use std::sync::Arc;
+use tracing::warn;
+
+/// The CSS cursor name for each shape, which is also what cursor themes name them.
+fn shape_name(shape: Shape) -> Option<&'static str> {
+	Some(match shape {
+		Shape::Default => "default",
+		Shape::ContextMenu => "context-menu",
+		Shape::Help => "help",
+		Shape::Pointer => "pointer",
+		Shape::Progress => "progress",
+		Shape::Wait => "wait",
+		Shape::Cell => "cell",
+		Shape::Crosshair => "crosshair",
+		Shape::Text => "text",
+		Shape::VerticalText => "vertical-text",
+		Shape::Alias => "alias",
+		Shape::Copy => "copy",
+		Shape::Move => "move",
+		Shape::NoDrop => "no-drop",
+		Shape::NotAllowed => "not-allowed",
+		Shape::Grab => "grab",
+		Shape::Grabbing => "grabbing",
+		Shape::EResize => "e-resize",
+		Shape::NResize => "n-resize",
+		Shape::NeResize => "ne-resize",
+		Shape::NwResize => "nw-resize",
+		Shape::SResize => "s-resize",
+		Shape::SeResize => "se-resize",
+		Shape::SwResize => "sw-resize",
+		Shape::WResize => "w-resize",
+		Shape::EwResize => "ew-resize",
+		Shape::NsResize => "ns-resize",
+		Shape::NeswResize => "nesw-resize",
+		Shape::NwseResize => "nwse-resize",
+		Shape::ColResize => "col-resize",
+		Shape::RowResize => "row-resize",
+		Shape::AllScroll => "all-scroll",
+		Shape::ZoomIn => "zoom-in",
+		Shape::ZoomOut => "zoom-out",
+		_ => return None,
+	})
+}
+
+impl GlobalDispatch<WpCursorShapeManagerV1, (), WaylandState> for WaylandState {
+	fn bind(
+		_state: &mut WaylandState,
+		_handle: &DisplayHandle,
+		_client: &Client,
+		resource: New<WpCursorShapeManagerV1>,
+		_global_data: &(),
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		data_init.init(resource, ());
+	}
+}
+
+impl Dispatch<WpCursorShapeManagerV1, (), WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		_resource: &WpCursorShapeManagerV1,
+		request: wp_cursor_shape_manager_v1::Request,
+		_data: &(),
+		_dhandle: &DisplayHandle,
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			wp_cursor_shape_manager_v1::Request::GetPointer {
+				cursor_shape_device,
+				pointer,
+			} => {
+				let seat_data = pointer.data::<Arc<SeatData>>().cloned();
+				data_init.init(cursor_shape_device, seat_data);
+			}
+			// Tablets aren't supported so the device never gets any events to set shapes for
+			wp_cursor_shape_manager_v1::Request::GetTabletToolV2 {
+				cursor_shape_device,
+				tablet_tool: _,
+			} => {
+				data_init.init(cursor_shape_device, None);
+			}
+			wp_cursor_shape_manager_v1::Request::Destroy => (),
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Dispatch<WpCursorShapeDeviceV1, Option<Arc<SeatData>>, WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		resource: &WpCursorShapeDeviceV1,
+		request: wp_cursor_shape_device_v1::Request,
+		seat_data: &Option<Arc<SeatData>>,
+		_dhandle: &DisplayHandle,
+		_data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			wp_cursor_shape_device_v1::Request::SetShape { serial: _, shape } => {
+				let Some(name) = (match shape {
+					WEnum::Value(shape) => shape_name(shape),
+					WEnum::Unknown(_) => None,
+				}) else {
+					resource.post_error(
+						wp_cursor_shape_device_v1::Error::InvalidShape,
+						"Unknown cursor shape",
+					);
+					return;
+				};
+				let Some(seat_data) = seat_data else {return};
+				let Some(panel_item) = seat_data.pointer_focus_panel_item() else {
+					warn!(name, "Cursor shape set without pointer focus");
+					return;
+				};
+				panel_item.set_cursor_shape(name);
+			}
+			wp_cursor_shape_device_v1::Request::Destroy => (),
+			_ => unreachable!(),
+		}
+	}
+}
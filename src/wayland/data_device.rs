@@ -1,4 +1,6 @@
+use parking_lot::Mutex;
 use smithay::reexports::wayland_server::{
+	backend::ClientId,
 	protocol::{
 		wl_data_device::{
 			Request::{Release, SetSelection, StartDrag},
@@ -8,6 +10,7 @@ use smithay::reexports::wayland_server::{
 			Request::{CreateDataSource, GetDataDevice},
 			WlDataDeviceManager,
 		},
+		wl_data_offer::{self, WlDataOffer},
 		wl_data_source::{
 			Request::{Destroy, Offer, SetActions},
 			WlDataSource,
@@ -15,9 +18,86 @@ use smithay::reexports::wayland_server::{
 	},
 	Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
 };
+use std::os::fd::AsFd;
+use tracing::debug;
 
 use super::state::WaylandState;
 
+/// MIME types a source offers, used for both the clipboard and the primary selection.
+#[derive(Default)]
+pub struct DataSourceData {
+	mime_types: Mutex<Vec<String>>,
+}
+impl DataSourceData {
+	pub fn add_mime_type(&self, mime_type: String) {
+		self.mime_types.lock().push(mime_type);
+	}
+	pub fn mime_types(&self) -> Vec<String> {
+		self.mime_types.lock().clone()
+	}
+}
+
+/// The clipboard lives out here since keyboard focus changes outside of Wayland dispatch.
+static SELECTION: Mutex<Selection> = Mutex::new(Selection {
+	source: None,
+	devices: Vec::new(),
+	focus: None,
+	dh: None,
+});
+
+struct Selection {
+	/// The data source for the clipboard contents, shared between all clients
+	source: Option<WlDataSource>,
+	devices: Vec<WlDataDevice>,
+	/// Only the client with keyboard focus gets offered the selection
+	focus: Option<ClientId>,
+	/// From the first data device, needed to make offers when focus changes
+	dh: Option<DisplayHandle>,
+}
+impl Selection {
+	/// Tell the focused client's data devices about the current selection with a fresh offer.
+	fn offer_to_focus(&mut self) {
+		self.devices.retain(|device| device.is_alive());
+		let (Some(focus), Some(dh)) = (&self.focus, &self.dh) else {return};
+		for device in &self.devices {
+			let Ok(client) = dh.get_client(device.id()) else {continue};
+			if &client.id() == focus {
+				offer_selection(dh, device, self.source.as_ref());
+			}
+		}
+	}
+}
+
+/// Called whenever a client's surface gets keyboard focus, so it can paste what's been copied.
+pub fn set_focus(client: &ClientId) {
+	let mut selection = SELECTION.lock();
+	if selection.focus.as_ref() == Some(client) {
+		return;
+	}
+	selection.focus = Some(client.clone());
+	selection.offer_to_focus();
+}
+
+fn offer_selection(dh: &DisplayHandle, device: &WlDataDevice, source: Option<&WlDataSource>) {
+	let Some(source) = source else {
+		device.selection(None);
+		return;
+	};
+	let Ok(client) = dh.get_client(device.id()) else {return};
+	let Ok(offer) = client.create_resource::<WlDataOffer, _, WaylandState>(
+		dh,
+		device.version(),
+		source.clone(),
+	) else {return};
+	device.data_offer(&offer);
+	if let Some(source_data) = source.data::<DataSourceData>() {
+		for mime_type in source_data.mime_types() {
+			offer.offer(mime_type);
+		}
+	}
+	device.selection(Some(&offer));
+}
+
 impl GlobalDispatch<WlDataDeviceManager, (), WaylandState> for WaylandState {
 	fn bind(
 		_state: &mut WaylandState,
@@ -34,49 +114,92 @@ impl GlobalDispatch<WlDataDeviceManager, (), WaylandState> for WaylandState {
 impl Dispatch<WlDataDeviceManager, (), WaylandState> for WaylandState {
 	fn request(
 		_state: &mut WaylandState,
-		_client: &Client,
+		client: &Client,
 		_resource: &WlDataDeviceManager,
 		request: <WlDataDeviceManager as Resource>::Request,
 		_data: &(),
-		_dhandle: &DisplayHandle,
+		dhandle: &DisplayHandle,
 		data_init: &mut DataInit<'_, WaylandState>,
 	) {
 		match request {
 			CreateDataSource { id } => {
-				data_init.init(id, ());
+				data_init.init(id, DataSourceData::default());
 			}
 			GetDataDevice { id, seat: _ } => {
-				data_init.init(id, ());
+				let device = data_init.init(id, ());
+				let mut selection = SELECTION.lock();
+				// New devices should be able to paste what was copied before they existed
+				if selection.focus.as_ref() == Some(&client.id()) {
+					offer_selection(dhandle, &device, selection.source.as_ref());
+				}
+				selection.devices.push(device);
+				selection.dh.get_or_insert_with(|| dhandle.clone());
 			}
 			_ => unreachable!(),
 		}
 	}
 }
 
-impl Dispatch<WlDataSource, (), WaylandState> for WaylandState {
+impl Dispatch<WlDataSource, DataSourceData, WaylandState> for WaylandState {
 	fn request(
 		_state: &mut WaylandState,
 		_client: &Client,
-		_resource: &WlDataSource,
+		resource: &WlDataSource,
 		request: <WlDataSource as Resource>::Request,
-		_data: &(),
+		data: &DataSourceData,
 		_dhandle: &DisplayHandle,
 		_data_init: &mut DataInit<'_, WaylandState>,
 	) {
 		match request {
-			Offer { mime_type: _ } => {}
-			Destroy => {}
+			Offer { mime_type } => {
+				data.add_mime_type(mime_type);
+			}
+			Destroy => {
+				let mut selection = SELECTION.lock();
+				if selection.source.as_ref() == Some(resource) {
+					selection.source = None;
+					selection.offer_to_focus();
+				}
+			}
 			SetActions { dnd_actions: _ } => {}
 			_ => unreachable!(),
 		}
 	}
 }
 
+impl Dispatch<WlDataOffer, WlDataSource, WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		_resource: &WlDataOffer,
+		request: <WlDataOffer as Resource>::Request,
+		source: &WlDataSource,
+		_dhandle: &DisplayHandle,
+		_data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			wl_data_offer::Request::Receive { mime_type, fd } => {
+				debug!(mime_type, "Clipboard transfer");
+				// The fd gets closed on our side once dropped, the source client has its own copy
+				if source.is_alive() {
+					source.send(mime_type, fd.as_fd());
+				}
+			}
+			// Only drag and drop uses these and that's not supported
+			wl_data_offer::Request::Accept { .. } => {}
+			wl_data_offer::Request::Finish => {}
+			wl_data_offer::Request::SetActions { .. } => {}
+			wl_data_offer::Request::Destroy => {}
+			_ => unreachable!(),
+		}
+	}
+}
+
 impl Dispatch<WlDataDevice, (), WaylandState> for WaylandState {
 	fn request(
 		_state: &mut WaylandState,
 		_client: &Client,
-		_resource: &WlDataDevice,
+		resource: &WlDataDevice,
 		request: <WlDataDevice as Resource>::Request,
 		_data: &(),
 		_dhandle: &DisplayHandle,
@@ -89,11 +212,19 @@ impl Dispatch<WlDataDevice, (), WaylandState> for WaylandState {
 				icon: _,
 				serial: _,
 			} => {}
-			SetSelection {
-				source: _,
-				serial: _,
-			} => {}
-			Release => {}
+			SetSelection { source, serial: _ } => {
+				let mut selection = SELECTION.lock();
+				if let Some(old_source) = selection.source.take() {
+					if Some(&old_source) != source.as_ref() {
+						old_source.cancelled();
+					}
+				}
+				selection.source = source;
+				selection.offer_to_focus();
+			}
+			Release => {
+				SELECTION.lock().devices.retain(|device| device != resource);
+			}
 			_ => unreachable!(),
 		}
 	}
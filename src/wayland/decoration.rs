@@ -1,4 +1,4 @@
-use super::state::WaylandState;
+use super::{state::WaylandState, xdg_shell::ToplevelData};
 use smithay::{
 	delegate_kde_decoration,
 	reexports::{
@@ -19,6 +19,7 @@ use smithay::{
 	},
 	wayland::shell::{self, kde::decoration::KdeDecorationHandler},
 };
+use tracing::debug;
 
 impl GlobalDispatch<ZxdgDecorationManagerV1, (), WaylandState> for WaylandState {
 	fn bind(
@@ -46,7 +47,19 @@ impl Dispatch<ZxdgDecorationManagerV1, (), WaylandState> for WaylandState {
 		match request {
 			zxdg_decoration_manager_v1::Request::Destroy => (),
 			zxdg_decoration_manager_v1::Request::GetToplevelDecoration { id, toplevel } => {
-				data_init.init(id, toplevel.downgrade());
+				let decoration = data_init.init(id, toplevel.downgrade());
+				let mut toplevel_data = ToplevelData::get(&toplevel).lock();
+				if toplevel_data.decoration_mode().is_some() {
+					decoration.post_error(
+						zxdg_toplevel_decoration_v1::Error::AlreadyConstructed,
+						"Toplevel already has a decoration object",
+					);
+					return;
+				}
+				// Panels float in space with nothing to attach a titlebar to, so always ask the
+				// client not to draw its own
+				decoration.configure(Mode::ServerSide);
+				toplevel_data.set_decoration_mode(Some(Mode::ServerSide));
 			}
 			_ => unreachable!(),
 		}
@@ -58,18 +71,24 @@ impl Dispatch<ZxdgToplevelDecorationV1, Weak<XdgToplevel>, WaylandState> for Way
 		_client: &Client,
 		resource: &ZxdgToplevelDecorationV1,
 		request: zxdg_toplevel_decoration_v1::Request,
-		_data: &Weak<XdgToplevel>,
+		toplevel: &Weak<XdgToplevel>,
 		_dhandle: &DisplayHandle,
 		_data_init: &mut DataInit<'_, WaylandState>,
 	) {
 		match request {
-			zxdg_toplevel_decoration_v1::Request::SetMode { mode: _ } => {
+			zxdg_toplevel_decoration_v1::Request::SetMode { mode } => {
+				debug!(?mode, "Toplevel requested decoration mode");
 				resource.configure(Mode::ServerSide);
 			}
 			zxdg_toplevel_decoration_v1::Request::UnsetMode => {
 				resource.configure(Mode::ServerSide);
 			}
-			zxdg_toplevel_decoration_v1::Request::Destroy => (),
+			zxdg_toplevel_decoration_v1::Request::Destroy => {
+				let Ok(toplevel) = toplevel.upgrade() else { return };
+				ToplevelData::get(&toplevel)
+					.lock()
+					.set_decoration_mode(None);
+			}
 			_ => unreachable!(),
 		}
 	}
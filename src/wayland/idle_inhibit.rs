@@ -0,0 +1,106 @@
+use super::state::WaylandState;
+use parking_lot::Mutex;
+use smithay::{
+	backend::renderer::utils::RendererSurfaceStateUserData,
+	reexports::{
+		wayland_protocols::wp::idle_inhibit::zv1::server::{
+			zwp_idle_inhibit_manager_v1::{self, ZwpIdleInhibitManagerV1},
+			zwp_idle_inhibitor_v1::{self, ZwpIdleInhibitorV1},
+		},
+		wayland_server::{
+			protocol::wl_surface::WlSurface, Client, DataInit, Dispatch, DisplayHandle,
+			GlobalDispatch, New, Resource, Weak as WlWeak,
+		},
+	},
+	wayland::compositor,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{debug, info};
+
+static IDLE_INHIBITORS: Mutex<Vec<ZwpIdleInhibitorV1>> = Mutex::new(Vec::new());
+/// If any mapped surface is keeping the session from idling, updated every frame.
+pub static IDLE_INHIBITED: AtomicBool = AtomicBool::new(false);
+
+fn is_mapped(surface: &WlSurface) -> bool {
+	compositor::with_states(surface, |data| {
+		data.data_map
+			.get::<RendererSurfaceStateUserData>()
+			.map(|surface_states| surface_states.lock().unwrap().buffer().is_some())
+			.unwrap_or(false)
+	})
+}
+
+/// Drops inhibitors whose surface is gone and recomputes `IDLE_INHIBITED`, unmapped surfaces
+/// don't count until they're mapped again.
+pub fn update() {
+	let mut inhibitors = IDLE_INHIBITORS.lock();
+	inhibitors.retain(|inhibitor| {
+		inhibitor.is_alive()
+			&& inhibitor
+				.data::<WlWeak<WlSurface>>()
+				.is_some_and(|surface| surface.upgrade().is_ok())
+	});
+	let inhibited = inhibitors
+		.iter()
+		.filter_map(|inhibitor| inhibitor.data::<WlWeak<WlSurface>>()?.upgrade().ok())
+		.any(|surface| is_mapped(&surface));
+	if IDLE_INHIBITED.swap(inhibited, Ordering::Relaxed) != inhibited {
+		info!(inhibited, "Idle inhibition changed");
+	}
+}
+
+impl GlobalDispatch<ZwpIdleInhibitManagerV1, (), WaylandState> for WaylandState {
+	fn bind(
+		_state: &mut WaylandState,
+		_handle: &DisplayHandle,
+		_client: &Client,
+		resource: New<ZwpIdleInhibitManagerV1>,
+		_global_data: &(),
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		data_init.init(resource, ());
+	}
+}
+
+impl Dispatch<ZwpIdleInhibitManagerV1, (), WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		_resource: &ZwpIdleInhibitManagerV1,
+		request: zwp_idle_inhibit_manager_v1::Request,
+		_data: &(),
+		_dhandle: &DisplayHandle,
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			zwp_idle_inhibit_manager_v1::Request::CreateInhibitor { id, surface } => {
+				debug!(?surface, "New idle inhibitor");
+				let inhibitor = data_init.init(id, surface.downgrade());
+				IDLE_INHIBITORS.lock().push(inhibitor);
+			}
+			zwp_idle_inhibit_manager_v1::Request::Destroy => (),
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Dispatch<ZwpIdleInhibitorV1, WlWeak<WlSurface>, WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		resource: &ZwpIdleInhibitorV1,
+		request: zwp_idle_inhibitor_v1::Request,
+		_surface: &WlWeak<WlSurface>,
+		_dhandle: &DisplayHandle,
+		_data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			zwp_idle_inhibitor_v1::Request::Destroy => {
+				IDLE_INHIBITORS
+					.lock()
+					.retain(|inhibitor| inhibitor != resource);
+			}
+			_ => unreachable!(),
+		}
+	}
+}
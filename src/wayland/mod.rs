@@ -1,35 +1,57 @@
+mod color_management;
 mod compositor;
+mod content_type;
+mod cursor_shape;
 mod data_device;
 mod decoration;
+mod idle_inhibit;
 pub mod panel_item;
+mod pointer_constraints;
+mod pointer_gestures;
+mod primary_selection;
 mod seat;
+mod security_context;
 mod shaders;
 mod state;
 mod surface;
+mod tearing_control;
+mod text_input;
 // mod xdg_activation;
 mod xdg_shell;
 
-use self::{state::WaylandState, surface::CORE_SURFACES};
-use crate::{core::task, wayland::state::ClientState};
+pub use self::{
+	seat::KeyboardConfig,
+	surface::{set_debug_damage, set_panel_shader},
+};
+use self::{
+	seat::KEYBOARD_CONFIG,
+	state::{update_output_modes, WaylandState, OUTPUTS},
+	surface::{assign_queue_offsets, CORE_SURFACES},
+};
+use crate::{
+	core::{frame_stats, task},
+	wayland::state::ClientState,
+};
 use color_eyre::eyre::{ensure, Result};
 use global_counter::primitive::exact::CounterU32;
 use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
 use sk::StereoKitDraw;
 use smithay::backend::egl::EGLContext;
-use smithay::backend::renderer::gles::GlesRenderer;
+use smithay::backend::renderer::{gles::GlesRenderer, ImportDma};
 use smithay::reexports::wayland_server::{backend::GlobalId, Display, ListeningSocket};
 use std::os::unix::prelude::AsRawFd;
 use std::{
 	ffi::c_void,
 	os::unix::{net::UnixListener, prelude::FromRawFd},
 	sync::Arc,
+	time::Duration,
 };
 use stereokit as sk;
 use tokio::{
 	io::unix::AsyncFd, net::UnixListener as AsyncUnixListener, sync::mpsc, task::JoinHandle,
 };
-use tracing::{debug, debug_span, info, instrument};
+use tracing::{debug, debug_span, info, instrument, warn};
 
 pub static WAYLAND_DISPLAY: OnceCell<String> = OnceCell::new();
 
@@ -64,9 +86,15 @@ pub struct Wayland {
 	join_handle: JoinHandle<Result<()>>,
 	renderer: GlesRenderer,
 	state: Arc<Mutex<WaylandState>>,
+	/// Monotonic time when StereoKit's clock started, puts its frame times on the clients' clock
+	monotonic_base: Option<Duration>,
+	presentation_seq: u64,
+	/// Smoothed seconds per frame, the outputs' refresh rate comes from it
+	frame_time: Option<f64>,
 }
 impl Wayland {
-	pub fn new() -> Result<Self> {
+	pub fn new(output_count: u32, keyboard_config: KeyboardConfig) -> Result<Self> {
+		let _ = KEYBOARD_CONFIG.set(keyboard_config);
 		let egl_raw_handles = get_sk_egl()?;
 		let renderer = unsafe {
 			GlesRenderer::new(EGLContext::from_raw(
@@ -80,7 +108,7 @@ impl Wayland {
 		let display_handle = display.handle();
 
 		let display = Arc::new(Mutex::new(display));
-		let state = WaylandState::new(display.clone(), display_handle, &renderer);
+		let state = WaylandState::new(display.clone(), display_handle, &renderer, output_count);
 
 		let (global_destroy_queue_in, global_destroy_queue) = mpsc::channel(8);
 		GLOBAL_DESTROY_QUEUE.set(global_destroy_queue_in).unwrap();
@@ -101,6 +129,9 @@ impl Wayland {
 			join_handle,
 			renderer,
 			state,
+			monotonic_base: None,
+			presentation_seq: 0,
+			frame_time: None,
 		})
 	}
 
@@ -119,7 +150,7 @@ impl Wayland {
 		let dh1 = display.lock().handle();
 		let mut dh2 = dh1.clone();
 
-		Ok(task::new(|| "wayland loop", async move {
+		task::new(|| "wayland loop", async move {
 			let _socket = socket; // Keep the socket alive
 			loop {
 				tokio::select! {
@@ -129,7 +160,11 @@ impl Wayland {
 					}
 					acc = listen_async.accept() => { // New client connected
 						let (stream, _) = acc?;
-						let client = dh2.insert_client(stream.into_std()?, Arc::new(ClientState))?;
+						let client_state = Arc::new(ClientState {
+							compositor_state: Default::default(),
+							security_context: None,
+						});
+						let client = dh2.insert_client(stream.into_std()?, client_state)?;
 
 						state.lock().new_client(client.id(), &dh2);
 					}
@@ -145,23 +180,67 @@ impl Wayland {
 					}
 				}
 			}
-		})?)
+		})
 	}
 
 	#[instrument(level = "debug", name = "Wayland frame", skip(self, sk))]
 	pub fn update(&mut self, sk: &impl StereoKitDraw) {
-		for core_surface in CORE_SURFACES.get_valid_contents() {
-			core_surface.process(sk, &mut self.renderer);
+		// Import new dmabufs ahead of time so the GPU buffers are ready when surfaces commit them
+		let pending_dmabufs = std::mem::take(&mut self.state.lock().pending_dmabufs);
+		for (dmabuf, notifier) in pending_dmabufs {
+			match self.renderer.import_dmabuf(&dmabuf, None) {
+				Ok(_) => {
+					let _ = notifier.successful::<WaylandState>();
+				}
+				Err(e) => {
+					warn!(?e, "Unable to import dmabuf");
+					notifier.failed();
+				}
+			}
 		}
 
-		self.display.lock().flush_clients().unwrap();
+		let default_output = &OUTPUTS.get().unwrap()[0];
+		let core_surfaces = CORE_SURFACES.get_valid_contents();
+		assign_queue_offsets(&core_surfaces);
+		for core_surface in core_surfaces {
+			if !core_surface.powered() {
+				continue;
+			}
+			core_surface.process(sk, &mut self.renderer, default_output);
+			frame_stats::count_surface_processed();
+		}
+		idle_inhibit::update();
+
+		if let Err(e) = self.display.lock().flush_clients() {
+			warn!(?e, "Unable to flush Wayland clients");
+		}
 	}
 
-	pub fn frame_event(&self, sk: &impl StereoKitDraw) {
+	pub fn frame_event(&mut self, sk: &impl StereoKitDraw) {
 		let state = self.state.lock();
 
+		// Everything processed last step has been drawn by now
+		let sk_time = Duration::from_secs_f64(sk.time_get());
+		let monotonic_base = *self
+			.monotonic_base
+			.get_or_insert_with(|| Duration::from(state.clock.now()).saturating_sub(sk_time));
+		self.presentation_seq += 1;
+
+		let frame_delta = sk.time_elapsed_unscaled();
+		let frame_time = match self.frame_time {
+			Some(frame_time) => frame_time * 0.95 + frame_delta * 0.05,
+			None => frame_delta,
+		};
+		self.frame_time = Some(frame_time);
+		let system_info = sk.system_info();
+		let display_size = (system_info.display_width, system_info.display_height);
+		update_output_modes(display_size, frame_time);
+
 		for core_surface in CORE_SURFACES.get_valid_contents() {
-			core_surface.frame(sk, state.output.clone());
+			core_surface.presented(monotonic_base + sk_time, self.presentation_seq);
+			if core_surface.powered() {
+				core_surface.frame(sk);
+			}
 		}
 	}
 
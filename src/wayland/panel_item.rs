@@ -1,6 +1,9 @@
 use super::{
+	content_type,
 	seat::{Cursor, SeatData},
+	state::{set_output_powered, set_output_transform, OutputTransform, OUTPUTS},
 	surface::CoreSurface,
+	text_input,
 	xdg_shell::{PopupData, ToplevelData, XdgSurfaceData},
 	SERIAL_COUNTER,
 };
@@ -8,6 +11,8 @@ use crate::{
 	core::{
 		client::{get_env, startup_settings, Client, INTERNAL_CLIENT},
 		registry::Registry,
+		resource::ResourceID,
+		task,
 	},
 	nodes::{
 		drawable::Drawable,
@@ -15,7 +20,10 @@ use crate::{
 		spatial::Spatial,
 		Node,
 	},
-	wayland::seat::{KeyboardEvent, PointerEvent},
+	wayland::{
+		pointer_gestures::GestureEvent,
+		seat::{KeyboardEvent, PointerEvent},
+	},
 };
 use color_eyre::eyre::{bail, eyre, Result};
 use glam::Mat4;
@@ -43,8 +51,11 @@ use smithay::{
 	wayland::compositor,
 };
 use stardust_xr::schemas::flex::{deserialize, serialize};
-use std::sync::{Arc, Weak};
-use tracing::debug;
+use std::{
+	ffi::OsStr,
+	sync::{Arc, Weak},
+};
+use tracing::{debug, warn};
 use xkbcommon::xkb::{self, ffi::XKB_KEYMAP_FORMAT_TEXT_V1, Keymap};
 
 lazy_static! {
@@ -52,21 +63,49 @@ lazy_static! {
 		type_name: "panel",
 		aliased_local_signals: vec![
 			"apply_surface_material",
+			"apply_surface_texture",
+			"set_opacity",
+			"set_corner_radius",
+			"set_shader",
+			"set_mipmaps",
+			"set_anisotropy",
+			"set_output",
+			"set_output_transform",
+			"set_output_powered",
+			"capture_surface",
 			"configure_toplevel",
 			"set_toplevel_capabilities",
 			"pointer_scroll",
 			"pointer_button",
 			"pointer_motion",
+			"pointer_motion_uv",
+			"pointer_relative_motion",
+			"pointer_swipe_begin",
+			"pointer_swipe_update",
+			"pointer_swipe_end",
+			"pointer_pinch_begin",
+			"pointer_pinch_update",
+			"pointer_pinch_end",
+			"touch_down",
+			"touch_move",
+			"touch_up",
 			"keyboard_key",
+			"keyboard_focus",
 			"keyboard_set_keymap_names",
 			"keyboard_set_keymap_string",
+			"text_input_preedit",
+			"text_input_commit",
 			"close",
 		],
-		aliased_local_methods: vec![],
+		aliased_local_methods: vec!["get_title", "get_app_id", "get_content_type"],
 		aliased_remote_signals: vec![
 			"commit_toplevel",
 			"recommend_toplevel_state",
 			"set_cursor",
+			"set_cursor_shape",
+			"set_text_input_enabled",
+			"set_toplevel_title",
+			"set_toplevel_app_id",
 			"new_popup",
 			"reposition_popup",
 			"drop_popup",
@@ -78,18 +117,14 @@ lazy_static! {
 }
 
 /// An ID for a surface inside this panel item
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 #[allow(dead_code)]
 pub enum SurfaceID {
 	Cursor,
+	#[default]
 	Toplevel,
 	Popup(String),
 }
-impl Default for SurfaceID {
-	fn default() -> Self {
-		Self::Toplevel
-	}
-}
 
 impl<'de> serde::Deserialize<'de> for SurfaceID {
 	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
@@ -223,6 +258,18 @@ impl PanelItem {
 			"apply_surface_material",
 			PanelItem::apply_surface_material_flex,
 		);
+		node.add_local_signal(
+			"apply_surface_texture",
+			PanelItem::apply_surface_texture_flex,
+		);
+		node.add_local_signal("set_opacity", PanelItem::set_opacity_flex);
+		node.add_local_signal("set_corner_radius", PanelItem::set_corner_radius_flex);
+		node.add_local_signal("set_shader", PanelItem::set_shader_flex);
+		node.add_local_signal("set_mipmaps", PanelItem::set_mipmaps_flex);
+		node.add_local_signal("set_anisotropy", PanelItem::set_anisotropy_flex);
+		node.add_local_signal("set_output", PanelItem::set_output_flex);
+		node.add_local_signal("set_output_transform", PanelItem::set_output_transform_flex);
+		node.add_local_signal("set_output_powered", PanelItem::set_output_powered_flex);
 		node.add_local_signal("configure_toplevel", PanelItem::configure_toplevel_flex);
 		node.add_local_signal(
 			"set_toplevel_capabilities",
@@ -231,6 +278,20 @@ impl PanelItem {
 		node.add_local_signal("pointer_scroll", PanelItem::pointer_scroll_flex);
 		node.add_local_signal("pointer_button", PanelItem::pointer_button_flex);
 		node.add_local_signal("pointer_motion", PanelItem::pointer_motion_flex);
+		node.add_local_signal("pointer_motion_uv", PanelItem::pointer_motion_uv_flex);
+		node.add_local_signal(
+			"pointer_relative_motion",
+			PanelItem::pointer_relative_motion_flex,
+		);
+		node.add_local_signal("pointer_swipe_begin", PanelItem::pointer_swipe_begin_flex);
+		node.add_local_signal("pointer_swipe_update", PanelItem::pointer_swipe_update_flex);
+		node.add_local_signal("pointer_swipe_end", PanelItem::pointer_swipe_end_flex);
+		node.add_local_signal("pointer_pinch_begin", PanelItem::pointer_pinch_begin_flex);
+		node.add_local_signal("pointer_pinch_update", PanelItem::pointer_pinch_update_flex);
+		node.add_local_signal("pointer_pinch_end", PanelItem::pointer_pinch_end_flex);
+		node.add_local_signal("touch_down", PanelItem::touch_down_flex);
+		node.add_local_signal("touch_move", PanelItem::touch_move_flex);
+		node.add_local_signal("touch_up", PanelItem::touch_up_flex);
 
 		node.add_local_signal(
 			"keyboard_set_keymap_string",
@@ -241,6 +302,13 @@ impl PanelItem {
 			PanelItem::keyboard_set_keymap_names_flex,
 		);
 		node.add_local_signal("keyboard_key", PanelItem::keyboard_key_flex);
+		node.add_local_method("get_title", PanelItem::get_title_flex);
+		node.add_local_method("get_app_id", PanelItem::get_app_id_flex);
+		node.add_local_method("get_content_type", PanelItem::get_content_type_flex);
+		node.add_local_signal("capture_surface", PanelItem::capture_surface_flex);
+		node.add_local_signal("keyboard_focus", PanelItem::keyboard_focus_flex);
+		node.add_local_signal("text_input_preedit", PanelItem::text_input_preedit_flex);
+		node.add_local_signal("text_input_commit", PanelItem::text_input_commit_flex);
 
 		(node, panel_item)
 	}
@@ -290,6 +358,54 @@ impl PanelItem {
 			.ok_or(eyre!("Surface with ID not found"))
 	}
 
+	fn get_title_flex(node: &Node, _calling_client: Arc<Client>, _data: &[u8]) -> Result<Vec<u8>> {
+		let Some(panel_item) = PanelItem::from_node(node) else { bail!("Not a panel item") };
+		let title = ToplevelData::get(&panel_item.toplevel()).lock().title();
+		serialize(title).map_err(|e| e.into())
+	}
+	fn get_app_id_flex(node: &Node, _calling_client: Arc<Client>, _data: &[u8]) -> Result<Vec<u8>> {
+		let Some(panel_item) = PanelItem::from_node(node) else { bail!("Not a panel item") };
+		let app_id = ToplevelData::get(&panel_item.toplevel()).lock().app_id();
+		serialize(app_id).map_err(|e| e.into())
+	}
+	/// "none", "photo", "video" or "game", from the client's content type hint.
+	fn get_content_type_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<Vec<u8>> {
+		let Some(panel_item) = PanelItem::from_node(node) else { bail!("Not a panel item") };
+		let surface_id: SurfaceID = deserialize(data)?;
+		let wl_surface = panel_item.wl_surface_from_id_result(&surface_id)?;
+		let content_type = content_type::content_type(&wl_surface);
+		serialize(content_type::content_type_name(content_type)).map_err(|e| e.into())
+	}
+
+	/// The PNG comes back in a `surface_captured` event on the client's root, along with the
+	/// surface ID, since the render thread has to read the texture back first.
+	fn capture_surface_flex(node: &Node, calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { bail!("Not a panel item") };
+
+		let surface_id: SurfaceID = deserialize(data)?;
+		let wl_surface = panel_item.wl_surface_from_id_result(&surface_id)?;
+		let core_surface = CoreSurface::from_wl_surface(&wl_surface)
+			.ok_or_else(|| eyre!("Surface has no texture"))?;
+		debug!(?surface_id, "Capture surface");
+
+		let capture = core_surface.capture_png()?;
+		let client = Arc::downgrade(&calling_client);
+		task::new(|| "surface capture", async move {
+			let result = capture.await;
+			if let Err(e) = &result {
+				warn!(?surface_id, "Unable to capture surface: {e}");
+			}
+			let Some(client) = client.upgrade() else { return };
+			let Some(root) = client.root.get() else { return };
+			root.send_surface_captured_event(&surface_id, result.map_err(|e| e.to_string()));
+		})?;
+		Ok(())
+	}
+
 	fn apply_surface_material_flex(
 		node: &Node,
 		calling_client: Arc<Client>,
@@ -321,6 +437,181 @@ impl PanelItem {
 		Ok(())
 	}
 
+	/// Binds only the surface's texture to a parameter of one of the model's own materials, so
+	/// it can feed something like an emissive map.
+	fn apply_surface_texture_flex(
+		node: &Node,
+		calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
+
+		#[derive(Debug, Deserialize)]
+		struct SurfaceTextureInfo<'a> {
+			surface: SurfaceID,
+			model_path: &'a str,
+			idx: i32,
+			parameter_name: String,
+		}
+
+		let info: SurfaceTextureInfo = deserialize(data)?;
+
+		let wl_surface = panel_item.wl_surface_from_id_result(&info.surface)?;
+		let core_surface = CoreSurface::from_wl_surface(&wl_surface)
+			.ok_or_else(|| eyre!("Surface has no texture"))?;
+		let texture = core_surface
+			.shared_texture()
+			.ok_or_else(|| eyre!("Surface hasn't been drawn yet"))?;
+
+		let model_node = calling_client
+			.scenegraph
+			.get_node(info.model_path)
+			.ok_or_else(|| eyre!("Model node not found"))?;
+		let Some(Drawable::Model(model)) = model_node.drawable.get() else {bail!("Node is not a model")};
+		debug!(?info, "Apply surface texture");
+
+		model.set_shared_texture(info.idx, info.parameter_name, texture)
+	}
+
+	fn set_opacity_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
+
+		let (surface_id, opacity): (SurfaceID, f32) = deserialize(data)?;
+		let wl_surface = panel_item.wl_surface_from_id_result(&surface_id)?;
+		let Some(core_surface) = CoreSurface::from_wl_surface(&wl_surface) else { return Ok(()) };
+		debug!(?surface_id, opacity, "Set surface opacity");
+
+		core_surface.set_opacity(opacity);
+
+		Ok(())
+	}
+
+	/// `None` goes back to the default panel shader.
+	fn set_shader_flex(node: &Node, calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
+
+		let (surface_id, shader): (SurfaceID, Option<ResourceID>) = deserialize(data)?;
+		let wl_surface = panel_item.wl_surface_from_id_result(&surface_id)?;
+		let Some(core_surface) = CoreSurface::from_wl_surface(&wl_surface) else { return Ok(()) };
+		let shader_path = shader
+			.map(|shader| {
+				shader
+					.get_file(
+						&calling_client.base_resource_prefixes.lock().clone(),
+						&[OsStr::new("sks")],
+					)
+					.ok_or_else(|| eyre!("Shader resource not found"))
+			})
+			.transpose()?;
+		debug!(?surface_id, ?shader_path, "Set surface shader");
+
+		core_surface.set_shader(shader_path);
+
+		Ok(())
+	}
+
+	fn set_corner_radius_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
+
+		let (surface_id, radius): (SurfaceID, f32) = deserialize(data)?;
+		let wl_surface = panel_item.wl_surface_from_id_result(&surface_id)?;
+		let Some(core_surface) = CoreSurface::from_wl_surface(&wl_surface) else { return Ok(()) };
+		debug!(?surface_id, radius, "Set surface corner radius");
+
+		core_surface.set_corner_radius(radius);
+
+		Ok(())
+	}
+
+	fn set_mipmaps_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
+
+		let (surface_id, mipmaps): (SurfaceID, bool) = deserialize(data)?;
+		let wl_surface = panel_item.wl_surface_from_id_result(&surface_id)?;
+		let Some(core_surface) = CoreSurface::from_wl_surface(&wl_surface) else { return Ok(()) };
+		debug!(?surface_id, mipmaps, "Set surface mipmaps");
+
+		core_surface.set_mipmaps(mipmaps);
+
+		Ok(())
+	}
+
+	fn set_anisotropy_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
+
+		let (surface_id, anisotropy): (SurfaceID, u32) = deserialize(data)?;
+		let wl_surface = panel_item.wl_surface_from_id_result(&surface_id)?;
+		let Some(core_surface) = CoreSurface::from_wl_surface(&wl_surface) else { return Ok(()) };
+		debug!(?surface_id, anisotropy, "Set surface anisotropy");
+
+		core_surface.set_anisotropy(anisotropy);
+
+		Ok(())
+	}
+
+	fn set_output_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
+		let Some(core_surface) = panel_item.core_surface() else { return Ok(()) };
+
+		let output_name: &str = deserialize(data)?;
+		let output = OUTPUTS
+			.get()
+			.and_then(|outputs| outputs.iter().find(|output| output.name() == output_name))
+			.ok_or_else(|| eyre!("Output not found"))?;
+		debug!(output_name, "Set panel output");
+
+		core_surface.set_outputs(vec![output.clone()]);
+		panel_item.flush_clients();
+
+		Ok(())
+	}
+
+	/// Outputs are shared by every panel, so this rotates all the panels on it.
+	fn set_output_transform_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
+
+		let (output_name, transform): (&str, OutputTransform) = deserialize(data)?;
+		let output = OUTPUTS
+			.get()
+			.and_then(|outputs| outputs.iter().find(|output| output.name() == output_name))
+			.ok_or_else(|| eyre!("Output not found"))?;
+		debug!(output_name, ?transform, "Set output transform");
+
+		set_output_transform(output, transform);
+		panel_item.flush_clients();
+
+		Ok(())
+	}
+
+	/// Outputs are shared by every panel, so this stops or resumes all the panels only on it.
+	fn set_output_powered_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
+
+		let (output_name, powered): (&str, bool) = deserialize(data)?;
+		let output = OUTPUTS
+			.get()
+			.and_then(|outputs| outputs.iter().find(|output| output.name() == output_name))
+			.ok_or_else(|| eyre!("Output not found"))?;
+		debug!(output_name, powered, "Set output power");
+
+		set_output_powered(output, powered);
+		panel_item.flush_clients();
+
+		Ok(())
+	}
+
 	fn pointer_motion_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
 		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
 
@@ -335,6 +626,85 @@ impl PanelItem {
 
 		Ok(())
 	}
+	/// Same as `pointer_motion` but with the UV coordinate of wherever a ray hit the panel's
+	/// model, so clients don't have to know the surface size.
+	fn pointer_motion_uv_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
+
+		let (surface_id, uv): (SurfaceID, Vector2<f32>) = deserialize(data)?;
+		let wl_surface = panel_item.wl_surface_from_id_result(&surface_id)?;
+		let Some(core_surface) = CoreSurface::from_wl_surface(&wl_surface) else { return Ok(()) };
+		// Hits on the model outside of the texture don't move the pointer
+		let Some(position) = core_surface.uv_to_surface(uv) else { return Ok(()) };
+		debug!(?surface_id, ?uv, ?position, "Pointer motion from UV");
+
+		panel_item
+			.seat_data
+			.pointer_event(&wl_surface, PointerEvent::Motion(position));
+		panel_item.flush_clients();
+
+		Ok(())
+	}
+	/// The raw motion of whatever's driving the pointer, like a mouse or thumbstick, for clients
+	/// with a relative pointer. It's never mapped onto the surface, so it keeps working while the
+	/// pointer is locked or stuck at an edge.
+	fn pointer_relative_motion_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
+
+		let (surface_id, delta): (SurfaceID, Vector2<f64>) = deserialize(data)?;
+		let wl_surface = panel_item.wl_surface_from_id_result(&surface_id)?;
+		debug!(?surface_id, ?delta, "Pointer relative motion");
+
+		panel_item
+			.seat_data
+			.pointer_event(&wl_surface, PointerEvent::RelativeMotion(delta));
+		panel_item.flush_clients();
+
+		Ok(())
+	}
+	/// Touch points are tracked by the ID the client picks, so any number can be down at once.
+	fn touch_down_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
+
+		let (surface_id, id, position): (SurfaceID, i32, Vector2<f64>) = deserialize(data)?;
+		let wl_surface = panel_item.wl_surface_from_id_result(&surface_id)?;
+		debug!(?surface_id, id, ?position, "Touch down");
+
+		panel_item.seat_data.touch_down(&wl_surface, id, position);
+		panel_item.flush_clients();
+
+		Ok(())
+	}
+	fn touch_move_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
+
+		let (id, position): (i32, Vector2<f64>) = deserialize(data)?;
+		debug!(id, ?position, "Touch move");
+
+		panel_item.seat_data.touch_motion(id, position);
+		panel_item.flush_clients();
+
+		Ok(())
+	}
+	fn touch_up_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
+
+		let id: i32 = deserialize(data)?;
+		debug!(id, "Touch up");
+
+		panel_item.seat_data.touch_up(id);
+		panel_item.flush_clients();
+
+		Ok(())
+	}
 	fn pointer_button_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
 		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
 
@@ -356,6 +726,7 @@ impl PanelItem {
 		struct PointerScrollInfo {
 			surface_id: SurfaceID,
 			axis_continuous: Option<Vector2<f32>>,
+			/// In wheel steps, fractions of a step are kept for high resolution scrolling
 			axis_discrete: Option<Vector2<f32>>,
 		}
 		let info: PointerScrollInfo = deserialize(data)?;
@@ -375,6 +746,76 @@ impl PanelItem {
 		Ok(())
 	}
 
+	fn pointer_gesture(node: &Node, surface_id: &SurfaceID, event: GestureEvent) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
+		let wl_surface = panel_item.wl_surface_from_id_result(surface_id)?;
+		debug!(?surface_id, ?event, "Pointer gesture");
+
+		panel_item
+			.seat_data
+			.pointer_event(&wl_surface, PointerEvent::Gesture(event));
+		panel_item.flush_clients();
+
+		Ok(())
+	}
+	fn pointer_swipe_begin_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let (surface_id, fingers): (SurfaceID, u32) = deserialize(data)?;
+		PanelItem::pointer_gesture(node, &surface_id, GestureEvent::SwipeBegin { fingers })
+	}
+	fn pointer_swipe_update_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let (surface_id, delta): (SurfaceID, Vector2<f64>) = deserialize(data)?;
+		PanelItem::pointer_gesture(node, &surface_id, GestureEvent::SwipeUpdate { delta })
+	}
+	fn pointer_swipe_end_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let (surface_id, cancelled): (SurfaceID, bool) = deserialize(data)?;
+		PanelItem::pointer_gesture(node, &surface_id, GestureEvent::SwipeEnd { cancelled })
+	}
+	fn pointer_pinch_begin_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let (surface_id, fingers): (SurfaceID, u32) = deserialize(data)?;
+		PanelItem::pointer_gesture(node, &surface_id, GestureEvent::PinchBegin { fingers })
+	}
+	fn pointer_pinch_update_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let (surface_id, delta, scale, rotation): (SurfaceID, Vector2<f64>, f64, f64) =
+			deserialize(data)?;
+		PanelItem::pointer_gesture(
+			node,
+			&surface_id,
+			GestureEvent::PinchUpdate {
+				delta,
+				scale,
+				rotation,
+			},
+		)
+	}
+	fn pointer_pinch_end_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let (surface_id, cancelled): (SurfaceID, bool) = deserialize(data)?;
+		PanelItem::pointer_gesture(node, &surface_id, GestureEvent::PinchEnd { cancelled })
+	}
+
 	fn keyboard_set_keymap_string_flex(
 		node: &Node,
 		_calling_client: Arc<Client>,
@@ -447,6 +888,66 @@ impl PanelItem {
 		Ok(())
 	}
 
+	fn keyboard_focus_flex(node: &Node, _calling_client: Arc<Client>, data: &[u8]) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
+		let surface_id: Option<SurfaceID> = deserialize(data)?;
+		let wl_surface = surface_id
+			.as_ref()
+			.map(|surface_id| panel_item.wl_surface_from_id_result(surface_id))
+			.transpose()?;
+		debug!(?surface_id, "Set keyboard focus");
+
+		panel_item.seat_data.set_keyboard_focus(wl_surface.as_ref());
+		panel_item.flush_clients();
+
+		Ok(())
+	}
+
+	fn text_input_preedit_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
+
+		#[derive(Debug, Deserialize)]
+		struct PreeditInfo {
+			surface_id: SurfaceID,
+			text: Option<String>,
+			cursor_begin: i32,
+			cursor_end: i32,
+		}
+		let info: PreeditInfo = deserialize(data)?;
+		let wl_surface = panel_item.wl_surface_from_id_result(&info.surface_id)?;
+		debug!(?info, "Text input preedit");
+
+		text_input::preedit(
+			&panel_item.seat_data,
+			&wl_surface,
+			info.text,
+			info.cursor_begin,
+			info.cursor_end,
+		);
+		panel_item.flush_clients();
+
+		Ok(())
+	}
+	fn text_input_commit_flex(
+		node: &Node,
+		_calling_client: Arc<Client>,
+		data: &[u8],
+	) -> Result<()> {
+		let Some(panel_item) = PanelItem::from_node(node) else { return Ok(()) };
+		let (surface_id, text): (SurfaceID, String) = deserialize(data)?;
+		let wl_surface = panel_item.wl_surface_from_id_result(&surface_id)?;
+		debug!(?surface_id, text, "Text input commit");
+
+		text_input::commit(&panel_item.seat_data, &wl_surface, text);
+		panel_item.flush_clients();
+
+		Ok(())
+	}
+
 	fn configure_toplevel_flex(
 		node: &Node,
 		_calling_client: Arc<Client>,
@@ -541,7 +1042,7 @@ impl PanelItem {
 		self.popups.lock().insert(uid.clone(), popup.downgrade());
 
 		let Some(node) = self.node.upgrade() else { return };
-		let _ = node.send_remote_signal("new_popup", &serialize(&(&uid, data)).unwrap());
+		let _ = node.send_remote_signal("new_popup", &serialize((&uid, data)).unwrap());
 	}
 	// pub fn commit_popup(&self, data: &PopupData) {
 	// 	let xdg_surf = data.xdg_surface.upgrade().unwrap();
@@ -591,7 +1092,7 @@ impl PanelItem {
 		let mut data = serialize(()).unwrap();
 
 		let cursor_size = surface
-			.and_then(|c| CoreSurface::from_wl_surface(c))
+			.and_then(CoreSurface::from_wl_surface)
 			.and_then(|c| c.size());
 
 		if let Some(size) = cursor_size {
@@ -602,6 +1103,33 @@ impl PanelItem {
 		*self.cursor.lock() = surface.map(|surf| surf.downgrade());
 	}
 
+	/// Named cursor from the cursor shape protocol (CSS cursor names like `text` or `pointer`),
+	/// replaces any cursor surface.
+	pub fn set_cursor_shape(&self, shape: &str) {
+		let Some(node) = self.node.upgrade() else { return };
+		debug!(shape, "Set cursor shape");
+
+		*self.cursor.lock() = None;
+		let _ = node.send_remote_signal("set_cursor_shape", &serialize(shape).unwrap());
+	}
+
+	pub fn set_toplevel_title(&self, title: Option<String>) {
+		let Some(node) = self.node.upgrade() else { return };
+
+		let _ = node.send_remote_signal("set_toplevel_title", &serialize(title).unwrap());
+	}
+	pub fn set_toplevel_app_id(&self, app_id: Option<String>) {
+		let Some(node) = self.node.upgrade() else { return };
+
+		let _ = node.send_remote_signal("set_toplevel_app_id", &serialize(app_id).unwrap());
+	}
+
+	pub fn set_text_input_enabled(&self, enabled: bool) {
+		let Some(node) = self.node.upgrade() else { return };
+
+		let _ = node.send_remote_signal("set_text_input_enabled", &serialize(enabled).unwrap());
+	}
+
 	pub fn on_drop(&self) {
 		let toplevel = self.toplevel_wl_surface();
 		self.seat_data.drop_surface(&toplevel);
@@ -614,7 +1142,7 @@ impl ItemSpecialization for PanelItem {
 		let cursor = self.cursor.lock().as_ref().and_then(|c| c.upgrade().ok());
 		let cursor_size = cursor
 			.as_ref()
-			.and_then(|c| CoreSurface::from_wl_surface(&c))
+			.and_then(CoreSurface::from_wl_surface)
 			.and_then(|c| c.size());
 		let cursor_hotspot = cursor
 			.and_then(|c| {
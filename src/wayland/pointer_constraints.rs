@@ -0,0 +1,327 @@
+use super::state::WaylandState;
+use mint::Vector2;
+use parking_lot::Mutex;
+use smithay::{
+	reexports::{
+		wayland_protocols::wp::pointer_constraints::zv1::server::{
+			zwp_confined_pointer_v1::{self, ZwpConfinedPointerV1},
+			zwp_locked_pointer_v1::{self, ZwpLockedPointerV1},
+			zwp_pointer_constraints_v1::{self, Lifetime, ZwpPointerConstraintsV1},
+		},
+		wayland_server::{
+			backend::ObjectId,
+			protocol::{wl_region::WlRegion, wl_surface::WlSurface},
+			Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+			Weak as WlWeak,
+		},
+	},
+	utils::{Logical, Rectangle},
+	wayland::compositor::{self, RectangleKind},
+};
+use tracing::debug;
+
+#[derive(Debug)]
+enum ConstraintKind {
+	Locked(ZwpLockedPointerV1),
+	Confined(ZwpConfinedPointerV1),
+}
+
+#[derive(Debug)]
+pub struct PointerConstraint {
+	kind: ConstraintKind,
+	/// Bounding box of the region the pointer is confined to, see `region_bounds`. `None` means
+	/// the whole surface
+	region: Option<Rectangle<i32, Logical>>,
+	lifetime: Lifetime,
+	active: bool,
+}
+impl PointerConstraint {
+	fn id(&self) -> ObjectId {
+		match &self.kind {
+			ConstraintKind::Locked(locked) => locked.id(),
+			ConstraintKind::Confined(confined) => confined.id(),
+		}
+	}
+
+	fn activate(&mut self) {
+		if self.active {
+			return;
+		}
+		self.active = true;
+		match &self.kind {
+			ConstraintKind::Locked(locked) => locked.locked(),
+			ConstraintKind::Confined(confined) => confined.confined(),
+		}
+	}
+	/// Returns if the constraint should stick around to be activated again.
+	fn deactivate(&mut self) -> bool {
+		if self.active {
+			self.active = false;
+			match &self.kind {
+				ConstraintKind::Locked(locked) => locked.unlocked(),
+				ConstraintKind::Confined(confined) => confined.unconfined(),
+			}
+		}
+		self.lifetime == Lifetime::Persistent
+	}
+
+	fn constrain(&self, position: Vector2<f64>) -> Option<Vector2<f64>> {
+		if !self.active {
+			return Some(position);
+		}
+		match (&self.kind, &self.region) {
+			(ConstraintKind::Locked(_), _) => None,
+			(ConstraintKind::Confined(_), None) => Some(position),
+			(ConstraintKind::Confined(_), Some(region)) => Some(confine(position, *region)),
+		}
+	}
+}
+
+fn confine(position: Vector2<f64>, region: Rectangle<i32, Logical>) -> Vector2<f64> {
+	// `max` then `min` instead of `clamp`, which panics if a bad rect gets its bounds backwards
+	let (min_x, max_x) = (region.loc.x as f64, (region.loc.x + region.size.w) as f64);
+	let (min_y, max_y) = (region.loc.y as f64, (region.loc.y + region.size.h) as f64);
+	Vector2::from([
+		position.x.max(min_x).min(max_x),
+		position.y.max(min_y).min(max_y),
+	])
+}
+
+fn region_bounds(region: Option<&WlRegion>) -> Option<Rectangle<i32, Logical>> {
+	rects_bounds(compositor::get_region_attributes(region?).rects)
+}
+/// Only the bounding box of the added rects is used, so subtracted rects are ignored and the
+/// pointer can reach gaps between added ones. Rects with no area add nothing, which also covers
+/// the negative sizes smithay passes through from clients in release builds.
+fn rects_bounds(
+	rects: impl IntoIterator<Item = (RectangleKind, Rectangle<i32, Logical>)>,
+) -> Option<Rectangle<i32, Logical>> {
+	rects
+		.into_iter()
+		.filter(|(kind, _)| matches!(kind, RectangleKind::Add))
+		.map(|(_, rect)| rect)
+		.filter(|rect| rect.size.w > 0 && rect.size.h > 0)
+		.reduce(|bounds, rect| bounds.merge(rect))
+}
+
+fn with_constraint<T>(
+	surface: &WlSurface,
+	f: impl FnOnce(&mut Option<PointerConstraint>) -> T,
+) -> T {
+	compositor::with_states(surface, |data| {
+		data.data_map
+			.insert_if_missing_threadsafe(|| Mutex::new(None::<PointerConstraint>));
+		let mut constraint = data
+			.data_map
+			.get::<Mutex<Option<PointerConstraint>>>()
+			.unwrap()
+			.lock();
+		f(&mut constraint)
+	})
+}
+
+/// Call when the surface gets pointer focus.
+pub fn activate(surface: &WlSurface) {
+	with_constraint(surface, |constraint| {
+		if let Some(constraint) = constraint {
+			constraint.activate();
+		}
+	});
+}
+/// Call when the surface loses pointer focus, oneshot constraints are gone after this.
+pub fn deactivate(surface: &WlSurface) {
+	with_constraint(surface, |constraint| {
+		let keep = constraint
+			.as_mut()
+			.is_none_or(PointerConstraint::deactivate);
+		if !keep {
+			*constraint = None;
+		}
+	});
+}
+/// Where the pointer should be moved to, or `None` if it's locked and shouldn't move at all.
+pub fn constrain(surface: &WlSurface, position: Vector2<f64>) -> Option<Vector2<f64>> {
+	with_constraint(surface, |constraint| match constraint {
+		Some(constraint) => constraint.constrain(position),
+		None => Some(position),
+	})
+}
+
+fn remove_constraint(surface: &WlWeak<WlSurface>, id: ObjectId) {
+	let Ok(surface) = surface.upgrade() else {return};
+	with_constraint(&surface, |constraint| {
+		if constraint.as_ref().map(PointerConstraint::id) == Some(id) {
+			*constraint = None;
+		}
+	});
+}
+fn set_region(surface: &WlWeak<WlSurface>, id: ObjectId, region: Option<&WlRegion>) {
+	let Ok(surface) = surface.upgrade() else {return};
+	let region = region_bounds(region);
+	with_constraint(&surface, |constraint| {
+		let Some(constraint) = constraint.as_mut() else {return};
+		if constraint.id() == id {
+			constraint.region = region;
+		}
+	});
+}
+
+impl GlobalDispatch<ZwpPointerConstraintsV1, (), WaylandState> for WaylandState {
+	fn bind(
+		_state: &mut WaylandState,
+		_handle: &DisplayHandle,
+		_client: &Client,
+		resource: New<ZwpPointerConstraintsV1>,
+		_global_data: &(),
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		data_init.init(resource, ());
+	}
+}
+
+impl Dispatch<ZwpPointerConstraintsV1, (), WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		resource: &ZwpPointerConstraintsV1,
+		request: zwp_pointer_constraints_v1::Request,
+		_data: &(),
+		_dhandle: &DisplayHandle,
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		let (surface, region, lifetime, kind) = match request {
+			zwp_pointer_constraints_v1::Request::LockPointer {
+				id,
+				surface,
+				pointer: _,
+				region,
+				lifetime,
+			} => {
+				let locked = data_init.init(id, surface.downgrade());
+				(surface, region, lifetime, ConstraintKind::Locked(locked))
+			}
+			zwp_pointer_constraints_v1::Request::ConfinePointer {
+				id,
+				surface,
+				pointer: _,
+				region,
+				lifetime,
+			} => {
+				let confined = data_init.init(id, surface.downgrade());
+				(
+					surface,
+					region,
+					lifetime,
+					ConstraintKind::Confined(confined),
+				)
+			}
+			zwp_pointer_constraints_v1::Request::Destroy => return,
+			_ => unreachable!(),
+		};
+		let lifetime = match lifetime {
+			WEnum::Value(lifetime) => lifetime,
+			WEnum::Unknown(_) => Lifetime::Oneshot,
+		};
+		debug!(?surface, ?kind, ?lifetime, "New pointer constraint");
+
+		let new_constraint = PointerConstraint {
+			kind,
+			region: region_bounds(region.as_ref()),
+			lifetime,
+			active: false,
+		};
+		with_constraint(&surface, |constraint| {
+			if constraint.is_some() {
+				resource.post_error(
+					zwp_pointer_constraints_v1::Error::AlreadyConstrained,
+					"Surface already has a pointer constraint",
+				);
+				return;
+			}
+			*constraint = Some(new_constraint);
+		});
+	}
+}
+
+impl Dispatch<ZwpLockedPointerV1, WlWeak<WlSurface>, WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		resource: &ZwpLockedPointerV1,
+		request: zwp_locked_pointer_v1::Request,
+		surface: &WlWeak<WlSurface>,
+		_dhandle: &DisplayHandle,
+		_data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			// The pointer isn't drawn by us so there's nowhere to warp it to on unlock
+			zwp_locked_pointer_v1::Request::SetCursorPositionHint { .. } => (),
+			zwp_locked_pointer_v1::Request::SetRegion { region } => {
+				set_region(surface, resource.id(), region.as_ref());
+			}
+			zwp_locked_pointer_v1::Request::Destroy => remove_constraint(surface, resource.id()),
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Dispatch<ZwpConfinedPointerV1, WlWeak<WlSurface>, WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		resource: &ZwpConfinedPointerV1,
+		request: zwp_confined_pointer_v1::Request,
+		surface: &WlWeak<WlSurface>,
+		_dhandle: &DisplayHandle,
+		_data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			zwp_confined_pointer_v1::Request::SetRegion { region } => {
+				set_region(surface, resource.id(), region.as_ref());
+			}
+			zwp_confined_pointer_v1::Request::Destroy => remove_constraint(surface, resource.id()),
+			_ => unreachable!(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn rect(x: i32, y: i32, w: i32, h: i32) -> Rectangle<i32, Logical> {
+		Rectangle::new((x, y).into(), (w, h).into())
+	}
+
+	#[test]
+	fn region_bounds_cover_added_rects() {
+		let rects = [
+			(RectangleKind::Add, rect(0, 0, 10, 10)),
+			(RectangleKind::Add, rect(20, 5, 10, 10)),
+			// Subtracting doesn't shrink the bounds
+			(RectangleKind::Subtract, rect(0, 0, 30, 15)),
+			// Neither do rects without area
+			(RectangleKind::Add, rect(100, 100, 0, 0)),
+			(RectangleKind::Add, rect(-100, 0, 0, 10)),
+		];
+		assert_eq!(rects_bounds(rects), Some(rect(0, 0, 30, 15)));
+		assert_eq!(rects_bounds([(RectangleKind::Add, rect(0, 0, 0, 5))]), None);
+		assert_eq!(rects_bounds([]), None);
+	}
+
+	#[test]
+	fn confined_positions_stay_in_the_region() {
+		let region = rect(10, 20, 100, 50);
+		let inside = Vector2::from([50.0, 40.0]);
+		assert_eq!(confine(inside, region), inside);
+		assert_eq!(
+			confine(Vector2::from([0.0, 100.0]), region),
+			Vector2::from([10.0, 70.0])
+		);
+		// A region with no area pins the pointer to it
+		assert_eq!(
+			confine(inside, rect(10, 20, 0, 0)),
+			Vector2::from([10.0, 20.0])
+		);
+	}
+}
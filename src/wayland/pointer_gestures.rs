@@ -0,0 +1,204 @@
+use super::{
+	seat::{event_time, SeatData},
+	state::WaylandState,
+	SERIAL_COUNTER,
+};
+use mint::Vector2;
+use smithay::reexports::{
+	wayland_protocols::wp::pointer_gestures::zv1::server::{
+		zwp_pointer_gesture_pinch_v1::{self, ZwpPointerGesturePinchV1},
+		zwp_pointer_gesture_swipe_v1::{self, ZwpPointerGestureSwipeV1},
+		zwp_pointer_gestures_v1::{self, ZwpPointerGesturesV1},
+	},
+	wayland_server::{
+		protocol::wl_surface::WlSurface, Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch,
+		New, Resource,
+	},
+};
+use std::sync::Arc;
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy)]
+pub enum GestureEvent {
+	SwipeBegin {
+		fingers: u32,
+	},
+	SwipeUpdate {
+		delta: Vector2<f64>,
+	},
+	SwipeEnd {
+		cancelled: bool,
+	},
+	PinchBegin {
+		fingers: u32,
+	},
+	/// `scale` is relative to when the pinch began, `rotation` is in degrees since the last update
+	PinchUpdate {
+		delta: Vector2<f64>,
+		scale: f64,
+		rotation: f64,
+	},
+	PinchEnd {
+		cancelled: bool,
+	},
+}
+
+/// A seat's gesture objects and which gestures are in progress, at most one of each type.
+#[derive(Default)]
+pub struct PointerGestures {
+	swipes: Vec<ZwpPointerGestureSwipeV1>,
+	pinches: Vec<ZwpPointerGesturePinchV1>,
+	swipe_active: bool,
+	pinch_active: bool,
+}
+impl PointerGestures {
+	/// Only call while the pointer is on `focus`.
+	pub fn handle(&mut self, focus: &WlSurface, event: GestureEvent) {
+		let time = event_time();
+		match event {
+			GestureEvent::SwipeBegin { fingers } => {
+				self.end_swipe(true);
+				let serial = SERIAL_COUNTER.inc();
+				for swipe in &self.swipes {
+					swipe.begin(serial, time, focus, fingers);
+				}
+				self.swipe_active = true;
+			}
+			GestureEvent::SwipeUpdate { delta } if self.swipe_active => {
+				for swipe in &self.swipes {
+					swipe.update(time, delta.x, delta.y);
+				}
+			}
+			GestureEvent::SwipeEnd { cancelled } => self.end_swipe(cancelled),
+			GestureEvent::PinchBegin { fingers } => {
+				self.end_pinch(true);
+				let serial = SERIAL_COUNTER.inc();
+				for pinch in &self.pinches {
+					pinch.begin(serial, time, focus, fingers);
+				}
+				self.pinch_active = true;
+			}
+			GestureEvent::PinchUpdate {
+				delta,
+				scale,
+				rotation,
+			} if self.pinch_active => {
+				for pinch in &self.pinches {
+					pinch.update(time, delta.x, delta.y, scale, rotation);
+				}
+			}
+			GestureEvent::PinchEnd { cancelled } => self.end_pinch(cancelled),
+			event => warn!(?event, "Gesture update without a gesture in progress"),
+		}
+	}
+
+	/// Call before the pointer leaves the surface the gestures began on.
+	pub fn cancel(&mut self) {
+		self.end_swipe(true);
+		self.end_pinch(true);
+	}
+
+	fn end_swipe(&mut self, cancelled: bool) {
+		if !std::mem::take(&mut self.swipe_active) {
+			return;
+		}
+		let serial = SERIAL_COUNTER.inc();
+		for swipe in &self.swipes {
+			swipe.end(serial, event_time(), cancelled as i32);
+		}
+	}
+	fn end_pinch(&mut self, cancelled: bool) {
+		if !std::mem::take(&mut self.pinch_active) {
+			return;
+		}
+		let serial = SERIAL_COUNTER.inc();
+		for pinch in &self.pinches {
+			pinch.end(serial, event_time(), cancelled as i32);
+		}
+	}
+}
+
+impl GlobalDispatch<ZwpPointerGesturesV1, (), WaylandState> for WaylandState {
+	fn bind(
+		_state: &mut WaylandState,
+		_handle: &DisplayHandle,
+		_client: &Client,
+		resource: New<ZwpPointerGesturesV1>,
+		_global_data: &(),
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		data_init.init(resource, ());
+	}
+}
+
+impl Dispatch<ZwpPointerGesturesV1, (), WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		_resource: &ZwpPointerGesturesV1,
+		request: zwp_pointer_gestures_v1::Request,
+		_data: &(),
+		_dhandle: &DisplayHandle,
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			zwp_pointer_gestures_v1::Request::GetSwipeGesture { id, pointer } => {
+				let Some(seat_data) = pointer.data::<Arc<SeatData>>() else {return};
+				let swipe = data_init.init(id, seat_data.clone());
+				seat_data.pointer_gestures.lock().swipes.push(swipe);
+			}
+			zwp_pointer_gestures_v1::Request::GetPinchGesture { id, pointer } => {
+				let Some(seat_data) = pointer.data::<Arc<SeatData>>() else {return};
+				let pinch = data_init.init(id, seat_data.clone());
+				seat_data.pointer_gestures.lock().pinches.push(pinch);
+			}
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Dispatch<ZwpPointerGestureSwipeV1, Arc<SeatData>, WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		resource: &ZwpPointerGestureSwipeV1,
+		request: zwp_pointer_gesture_swipe_v1::Request,
+		seat_data: &Arc<SeatData>,
+		_dhandle: &DisplayHandle,
+		_data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			zwp_pointer_gesture_swipe_v1::Request::Destroy => {
+				seat_data
+					.pointer_gestures
+					.lock()
+					.swipes
+					.retain(|swipe| swipe != resource);
+			}
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Dispatch<ZwpPointerGesturePinchV1, Arc<SeatData>, WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		resource: &ZwpPointerGesturePinchV1,
+		request: zwp_pointer_gesture_pinch_v1::Request,
+		seat_data: &Arc<SeatData>,
+		_dhandle: &DisplayHandle,
+		_data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			zwp_pointer_gesture_pinch_v1::Request::Destroy => {
+				seat_data
+					.pointer_gestures
+					.lock()
+					.pinches
+					.retain(|pinch| pinch != resource);
+			}
+			_ => unreachable!(),
+		}
+	}
+}
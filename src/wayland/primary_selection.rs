@@ -0,0 +1,165 @@
+use super::{data_device::DataSourceData, state::WaylandState};
+use smithay::reexports::{
+	wayland_protocols::wp::primary_selection::zv1::server::{
+		zwp_primary_selection_device_manager_v1::{self, ZwpPrimarySelectionDeviceManagerV1},
+		zwp_primary_selection_device_v1::{self, ZwpPrimarySelectionDeviceV1},
+		zwp_primary_selection_offer_v1::{self, ZwpPrimarySelectionOfferV1},
+		zwp_primary_selection_source_v1::{self, ZwpPrimarySelectionSourceV1},
+	},
+	wayland_server::{Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource},
+};
+use std::os::fd::AsFd;
+use tracing::debug;
+
+impl WaylandState {
+	/// Same as the clipboard but completely independent of it.
+	fn broadcast_primary_selection(&mut self, dh: &DisplayHandle) {
+		self.primary_selection_devices
+			.retain(|device| device.is_alive());
+		for device in &self.primary_selection_devices {
+			offer_primary_selection(dh, device, self.primary_selection.as_ref());
+		}
+	}
+}
+
+fn offer_primary_selection(
+	dh: &DisplayHandle,
+	device: &ZwpPrimarySelectionDeviceV1,
+	source: Option<&ZwpPrimarySelectionSourceV1>,
+) {
+	let Some(source) = source else {
+		device.selection(None);
+		return;
+	};
+	let Ok(client) = dh.get_client(device.id()) else {return};
+	let Ok(offer) = client.create_resource::<ZwpPrimarySelectionOfferV1, _, WaylandState>(
+		dh,
+		device.version(),
+		source.clone(),
+	) else {return};
+	device.data_offer(&offer);
+	if let Some(source_data) = source.data::<DataSourceData>() {
+		for mime_type in source_data.mime_types() {
+			offer.offer(mime_type);
+		}
+	}
+	device.selection(Some(&offer));
+}
+
+impl GlobalDispatch<ZwpPrimarySelectionDeviceManagerV1, (), WaylandState> for WaylandState {
+	fn bind(
+		_state: &mut WaylandState,
+		_handle: &DisplayHandle,
+		_client: &Client,
+		resource: New<ZwpPrimarySelectionDeviceManagerV1>,
+		_global_data: &(),
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		data_init.init(resource, ());
+	}
+}
+
+impl Dispatch<ZwpPrimarySelectionDeviceManagerV1, (), WaylandState> for WaylandState {
+	fn request(
+		state: &mut WaylandState,
+		_client: &Client,
+		_resource: &ZwpPrimarySelectionDeviceManagerV1,
+		request: zwp_primary_selection_device_manager_v1::Request,
+		_data: &(),
+		dhandle: &DisplayHandle,
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			zwp_primary_selection_device_manager_v1::Request::CreateSource { id } => {
+				data_init.init(id, DataSourceData::default());
+			}
+			zwp_primary_selection_device_manager_v1::Request::GetDevice { id, seat: _ } => {
+				let device = data_init.init(id, ());
+				offer_primary_selection(dhandle, &device, state.primary_selection.as_ref());
+				state.primary_selection_devices.push(device);
+			}
+			zwp_primary_selection_device_manager_v1::Request::Destroy => (),
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Dispatch<ZwpPrimarySelectionSourceV1, DataSourceData, WaylandState> for WaylandState {
+	fn request(
+		state: &mut WaylandState,
+		_client: &Client,
+		resource: &ZwpPrimarySelectionSourceV1,
+		request: zwp_primary_selection_source_v1::Request,
+		data: &DataSourceData,
+		dhandle: &DisplayHandle,
+		_data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			zwp_primary_selection_source_v1::Request::Offer { mime_type } => {
+				data.add_mime_type(mime_type);
+			}
+			zwp_primary_selection_source_v1::Request::Destroy => {
+				if state.primary_selection.as_ref() == Some(resource) {
+					state.primary_selection = None;
+					state.broadcast_primary_selection(dhandle);
+				}
+			}
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Dispatch<ZwpPrimarySelectionOfferV1, ZwpPrimarySelectionSourceV1, WaylandState>
+	for WaylandState
+{
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		_resource: &ZwpPrimarySelectionOfferV1,
+		request: zwp_primary_selection_offer_v1::Request,
+		source: &ZwpPrimarySelectionSourceV1,
+		_dhandle: &DisplayHandle,
+		_data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			zwp_primary_selection_offer_v1::Request::Receive { mime_type, fd } => {
+				debug!(mime_type, "Primary selection transfer");
+				if source.is_alive() {
+					source.send(mime_type, fd.as_fd());
+				}
+			}
+			zwp_primary_selection_offer_v1::Request::Destroy => (),
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Dispatch<ZwpPrimarySelectionDeviceV1, (), WaylandState> for WaylandState {
+	fn request(
+		state: &mut WaylandState,
+		_client: &Client,
+		resource: &ZwpPrimarySelectionDeviceV1,
+		request: zwp_primary_selection_device_v1::Request,
+		_data: &(),
+		dhandle: &DisplayHandle,
+		_data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			zwp_primary_selection_device_v1::Request::SetSelection { source, serial: _ } => {
+				if let Some(old_source) = state.primary_selection.take() {
+					if Some(&old_source) != source.as_ref() {
+						old_source.cancelled();
+					}
+				}
+				state.primary_selection = source;
+				state.broadcast_primary_selection(dhandle);
+			}
+			zwp_primary_selection_device_v1::Request::Destroy => {
+				state
+					.primary_selection_devices
+					.retain(|device| device != resource);
+			}
+			_ => unreachable!(),
+		}
+	}
+}
@@ -1,52 +1,162 @@
 use super::{
-	panel_item::PanelItem, state::WaylandState, surface::CoreSurface, GLOBAL_DESTROY_QUEUE,
-	SERIAL_COUNTER,
+	data_device,
+	panel_item::PanelItem,
+	pointer_constraints,
+	pointer_gestures::{GestureEvent, PointerGestures},
+	state::WaylandState,
+	surface::CoreSurface,
+	text_input, GLOBAL_DESTROY_QUEUE, SERIAL_COUNTER,
 };
 use crate::core::task;
 use color_eyre::eyre::Result;
 use mint::Vector2;
 use nanoid::nanoid;
-use once_cell::sync::OnceCell;
+use once_cell::sync::{Lazy, OnceCell};
 use parking_lot::Mutex;
 use rand::{seq::IteratorRandom, thread_rng};
 use rustc_hash::{FxHashMap, FxHashSet};
 use smithay::{
-	input::keyboard::{KeymapFile, ModifiersState},
-	reexports::wayland_server::{
-		backend::{ClientId, GlobalId, ObjectId},
-		protocol::{
-			wl_keyboard::{self, KeyState, WlKeyboard},
-			wl_pointer::{self, Axis, ButtonState, WlPointer},
-			wl_seat::{self, Capability, WlSeat, EVT_NAME_SINCE},
-			wl_surface::WlSurface,
-			wl_touch::{self, WlTouch},
+	input::keyboard::KeymapFile,
+	reexports::{
+		wayland_protocols::wp::{
+			relative_pointer::zv1::server::{
+				zwp_relative_pointer_manager_v1::{self, ZwpRelativePointerManagerV1},
+				zwp_relative_pointer_v1::{self, ZwpRelativePointerV1},
+			},
+			text_input::zv3::server::zwp_text_input_v3::ZwpTextInputV3,
+		},
+		wayland_server::{
+			backend::{ClientId, GlobalId, ObjectId},
+			protocol::{
+				wl_keyboard::{self, KeyState, WlKeyboard},
+				wl_pointer::{self, Axis, AxisSource, ButtonState, WlPointer},
+				wl_seat::{self, Capability, WlSeat, EVT_NAME_SINCE},
+				wl_surface::WlSurface,
+				wl_touch::{self, WlTouch},
+			},
+			Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+			Weak as WlWeak,
 		},
-		Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, Weak as WlWeak,
 	},
 	wayland::compositor,
 };
 use std::{
 	collections::VecDeque,
-	sync::{Arc, Weak},
-	time::{Duration, Instant},
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc, Weak,
+	},
+	time::{Duration, Instant, SystemTime},
 };
 use tracing::{debug, warn};
-use xkbcommon::xkb::{self, Keymap};
+use xkbcommon::xkb::{self, Keymap, KEYMAP_COMPILE_NO_FLAGS};
+
+/// What keyboards start out as before a panel item sets a keymap on their surface.
+#[derive(Debug, Clone)]
+pub struct KeyboardConfig {
+	/// Empty names fall back to the `XKB_DEFAULT_*` environment variables, then to xkbcommon's
+	/// own defaults
+	pub layout: String,
+	pub variant: String,
+	pub options: Option<String>,
+	/// Keys per second, 0 turns repeating off
+	pub repeat_rate: i32,
+	/// Milliseconds a key has to be held before it starts repeating
+	pub repeat_delay: i32,
+}
+impl Default for KeyboardConfig {
+	fn default() -> Self {
+		KeyboardConfig {
+			layout: String::new(),
+			variant: String::new(),
+			options: None,
+			repeat_rate: 25,
+			repeat_delay: 600,
+		}
+	}
+}
+pub static KEYBOARD_CONFIG: OnceCell<KeyboardConfig> = OnceCell::new();
+
+/// Compiled from `KEYBOARD_CONFIG` the first time a surface needs it, then shared by every
+/// surface without a keymap of its own.
+struct DefaultKeymap {
+	keymap: Keymap,
+	file: Arc<KeymapFile>,
+}
+// Only used on the Wayland thread, which creates every surface's keyboard state
+unsafe impl Send for DefaultKeymap {}
+unsafe impl Sync for DefaultKeymap {}
+static DEFAULT_KEYMAP: OnceCell<Option<DefaultKeymap>> = OnceCell::new();
+
+fn default_keymap() -> Option<&'static DefaultKeymap> {
+	DEFAULT_KEYMAP
+		.get_or_init(|| {
+			let config = KEYBOARD_CONFIG.get_or_init(KeyboardConfig::default);
+			let keymap = Keymap::new_from_names(
+				&xkb::Context::new(0),
+				"",
+				"",
+				&config.layout,
+				&config.variant,
+				config.options.clone(),
+				KEYMAP_COMPILE_NO_FLAGS,
+			);
+			if keymap.is_none() {
+				warn!(?config, "Unable to compile the default keymap");
+			}
+			keymap.map(|keymap| DefaultKeymap {
+				file: Arc::new(KeymapFile::new(&keymap)),
+				keymap,
+			})
+		})
+		.as_ref()
+}
+
+fn send_repeat_info(keyboard: &WlKeyboard) {
+	if keyboard.version() < wl_keyboard::EVT_REPEAT_INFO_SINCE {
+		return;
+	}
+	let config = KEYBOARD_CONFIG.get_or_init(KeyboardConfig::default);
+	keyboard.repeat_info(config.repeat_rate, config.repeat_delay);
+}
 
 pub struct KeyboardInfo {
-	keymap: KeymapFile,
+	keymap: Arc<KeymapFile>,
 	state: xkb::State,
-	mods: ModifiersState,
 	keys: FxHashSet<u32>,
 }
 impl KeyboardInfo {
 	pub fn new(keymap: &Keymap) -> Self {
 		KeyboardInfo {
 			state: xkb::State::new(keymap),
-			keymap: KeymapFile::new(keymap),
-			mods: ModifiersState::default(),
+			keymap: Arc::new(KeymapFile::new(keymap)),
+			keys: FxHashSet::default(),
+		}
+	}
+	fn with_default_keymap() -> Option<Self> {
+		let default_keymap = default_keymap()?;
+		Some(KeyboardInfo {
+			state: xkb::State::new(&default_keymap.keymap),
+			keymap: default_keymap.file.clone(),
 			keys: FxHashSet::default(),
+		})
+	}
+	/// Sends the keymap along with everything else a keyboard needs before it gets keys.
+	fn send_keymap(&self, keyboard: &WlKeyboard) -> bool {
+		if self.keymap.send(keyboard).is_err() {
+			return false;
 		}
+		send_repeat_info(keyboard);
+		true
+	}
+	pub fn send_modifiers(&self, keyboard: &WlKeyboard) {
+		keyboard.modifiers(
+			SERIAL_COUNTER.inc(),
+			self.state.serialize_mods(xkb::STATE_MODS_DEPRESSED),
+			self.state.serialize_mods(xkb::STATE_MODS_LATCHED),
+			self.state.serialize_mods(xkb::STATE_MODS_LOCKED),
+			self.state.serialize_layout(xkb::STATE_LAYOUT_EFFECTIVE),
+		);
 	}
 	pub fn process(&mut self, key: u32, state: u32, keyboard: &WlKeyboard) -> Result<usize> {
 		let wl_key_state = match state {
@@ -59,16 +169,11 @@ impl KeyboardInfo {
 			1 => xkb::KeyDirection::Down,
 			_ => color_eyre::eyre::bail!("Invalid key state!"),
 		};
-		let state_components = self.state.update_key(key + 8, xkb_key_state);
+		let state_components = self
+			.state
+			.update_key(xkb::Keycode::new(key + 8), xkb_key_state);
 		if state_components != 0 {
-			self.mods.update_with(&self.state);
-			keyboard.modifiers(
-				0,
-				self.mods.serialized.depressed,
-				self.mods.serialized.latched,
-				self.mods.serialized.locked,
-				0,
-			);
+			self.send_modifiers(keyboard);
 		}
 		keyboard.key(SERIAL_COUNTER.inc(), 0, key, wl_key_state);
 		match wl_key_state {
@@ -88,6 +193,8 @@ unsafe impl Send for KeyboardInfo {}
 #[derive(Debug, Clone, Copy)]
 pub enum PointerEvent {
 	Motion(Vector2<f64>),
+	/// Straight from the input device, before it's mapped onto the surface, for relative pointers
+	RelativeMotion(Vector2<f64>),
 	Button {
 		button: u32,
 		state: u32,
@@ -96,6 +203,7 @@ pub enum PointerEvent {
 		axis_continuous: Option<Vector2<f32>>,
 		axis_discrete: Option<Vector2<f32>>,
 	},
+	Gesture(GestureEvent),
 }
 #[derive(Debug, Clone)]
 pub enum KeyboardEvent {
@@ -103,6 +211,19 @@ pub enum KeyboardEvent {
 	Key { key: u32, state: u32 },
 }
 
+/// The seat that has the one focused surface, whether it was focused explicitly or implicitly by
+/// getting keys. Always locked before any seat's surfaces.
+static KEYBOARD_FOCUSED_SEAT: Lazy<Mutex<Weak<SeatData>>> = Lazy::new(|| Mutex::new(Weak::new()));
+/// Takes the focus away from whichever other seat has it, so its surface gets a leave first.
+fn take_keyboard_focus(focused_seat: &mut Weak<SeatData>, seat: &Arc<SeatData>) {
+	if let Some(old_seat) = focused_seat.upgrade() {
+		if !Arc::ptr_eq(&old_seat, seat) {
+			old_seat.set_local_keyboard_focus(None);
+		}
+	}
+	*focused_seat = Arc::downgrade(seat);
+}
+
 const POINTER_EVENT_TIMEOUT: Duration = Duration::from_secs(1);
 struct SurfaceInfo {
 	wl_surface: WlWeak<WlSurface>,
@@ -120,10 +241,16 @@ impl SurfaceInfo {
 			pointer_queue: VecDeque::new(),
 			pointer_latest_event: Instant::now(),
 			keyboard_queue: VecDeque::new(),
-			keyboard_info: None,
+			keyboard_info: KeyboardInfo::with_default_keymap(),
 		}
 	}
-	fn handle_pointer_events(&mut self, pointer: &WlPointer, mut locked: bool) -> bool {
+	fn handle_pointer_events(
+		&mut self,
+		pointer: &WlPointer,
+		relative_pointers: &[ZwpRelativePointerV1],
+		gestures: &mut PointerGestures,
+		mut locked: bool,
+	) -> bool {
 		let Ok(focus) = self.wl_surface.upgrade() else { return false; };
 		let Some(core_surface) = CoreSurface::from_wl_surface(&focus) else { return false; };
 		let Some(focus_size) = core_surface.size() else { return false; };
@@ -140,9 +267,12 @@ impl SurfaceInfo {
 						pos.x.clamp(0.0, focus_size.x as f64),
 						pos.y.clamp(0.0, focus_size.y as f64),
 					);
+					pointer_constraints::activate(&focus);
 					locked = true;
 				}
 				(true, PointerEvent::Motion(pos)) => {
+					// A locked pointer doesn't send any absolute motion
+					let Some(pos) = pointer_constraints::constrain(&focus, pos) else {continue};
 					pointer.motion(
 						0,
 						pos.x.clamp(0.0, focus_size.x as f64),
@@ -150,6 +280,12 @@ impl SurfaceInfo {
 					);
 					pointer.frame();
 				}
+				(true, PointerEvent::RelativeMotion(delta)) => {
+					Self::send_relative_motion(relative_pointers, delta);
+					pointer.frame();
+				}
+				// Nothing to send it to until the pointer has entered the surface
+				(false, PointerEvent::RelativeMotion(_)) => {}
 				(true, PointerEvent::Button { button, state }) => {
 					pointer.button(
 						0,
@@ -170,50 +306,65 @@ impl SurfaceInfo {
 						axis_discrete,
 					},
 				) => {
-					if let Some(axis_continuous) = axis_continuous {
-						pointer.axis(0, Axis::HorizontalScroll, axis_continuous.x as f64);
-						pointer.axis(0, Axis::VerticalScroll, axis_continuous.y as f64);
-					}
-					if let Some(axis_discrete) = axis_discrete {
-						pointer.axis_discrete(Axis::HorizontalScroll, axis_discrete.x as i32);
-						pointer.axis_discrete(Axis::VerticalScroll, axis_discrete.y as i32);
-					}
-					if axis_discrete.is_none() && axis_continuous.is_none() {
-						pointer.axis_stop(0, Axis::HorizontalScroll);
-						pointer.axis_stop(0, Axis::VerticalScroll);
-					}
-					pointer.frame();
+					send_scroll(pointer, axis_continuous, axis_discrete);
 				}
+				(true, PointerEvent::Gesture(event)) => gestures.handle(&focus, event),
 				(locked, event) => {
 					warn!(locked, ?event, "Invalid pointer event!");
 				}
 			}
 		}
 		if self.pointer_latest_event.elapsed() > POINTER_EVENT_TIMEOUT {
+			gestures.cancel();
 			pointer.leave(SERIAL_COUNTER.inc(), &focus);
+			pointer_constraints::deactivate(&focus);
 			locked = false;
 		}
 
 		locked
 	}
-	fn handle_keyboard_events(&mut self, keyboard: &WlKeyboard, mut locked: bool) -> bool {
+	fn send_relative_motion(relative_pointers: &[ZwpRelativePointerV1], delta: Vector2<f64>) {
+		let (dx, dy) = (delta.x, delta.y);
+		if relative_pointers.is_empty() || (dx == 0.0 && dy == 0.0) {
+			return;
+		}
+		let utime = SystemTime::now()
+			.duration_since(SystemTime::UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_micros() as u64;
+		for relative_pointer in relative_pointers {
+			// There's no pointer acceleration anywhere so both deltas are the same
+			relative_pointer.relative_motion((utime >> 32) as u32, utime as u32, dx, dy, dx, dy);
+		}
+	}
+	fn handle_keyboard_events(
+		&mut self,
+		keyboard: &WlKeyboard,
+		mut locked: bool,
+		sticky: bool,
+	) -> bool {
 		let Ok(focus) = self.wl_surface.upgrade() else { return false; };
 		let Some(info) = self.keyboard_info.as_mut() else { return true; };
 
 		if !locked {
-			keyboard.enter(0, &focus, vec![]);
-			keyboard.repeat_info(0, 0);
-			locked = info.keymap.send(keyboard).is_ok();
+			locked = info.send_keymap(keyboard);
+			if locked {
+				keyboard.enter(SERIAL_COUNTER.inc(), &focus, vec![]);
+				info.send_modifiers(keyboard);
+			}
 		}
 		while let Some(event) = self.keyboard_queue.pop_front() {
 			debug!(locked, ?event, "Process keyboard event");
 			match (locked, event) {
 				(true, KeyboardEvent::Keymap) => {
-					let _ = info.keymap.send(keyboard);
+					// The new keymap comes with a fresh state, so nothing is held anymore
+					info.send_keymap(keyboard);
+					info.send_modifiers(keyboard);
 				}
 				(true, KeyboardEvent::Key { key, state }) => {
 					if let Ok(key_count) = info.process(key, state, keyboard) {
-						if key_count == 0 {
+						// Explicitly focused surfaces keep focus until something else takes it
+						if key_count == 0 && !sticky {
 							keyboard.leave(SERIAL_COUNTER.inc(), &focus);
 							return false;
 						}
@@ -233,8 +384,15 @@ pub struct SeatData {
 	global_id: OnceCell<GlobalId>,
 	surfaces: Mutex<FxHashMap<ObjectId, SurfaceInfo>>,
 	pointer: OnceCell<(WlPointer, Mutex<ObjectId>)>,
+	relative_pointers: Mutex<Vec<ZwpRelativePointerV1>>,
+	pub pointer_gestures: Mutex<PointerGestures>,
 	keyboard: OnceCell<(WlKeyboard, Mutex<ObjectId>)>,
+	keyboard_focus_sticky: AtomicBool,
 	touch: OnceCell<WlTouch>,
+	/// The surface each touch point went down on, it gets every event of that point until it's up
+	touch_points: Mutex<FxHashMap<i32, WlWeak<WlSurface>>>,
+	pub text_inputs: Mutex<Vec<ZwpTextInputV3>>,
+	pub text_input_focus: Mutex<Option<WlWeak<WlSurface>>>,
 }
 impl SeatData {
 	pub fn new(dh: &DisplayHandle, client: ClientId) -> Arc<Self> {
@@ -243,13 +401,19 @@ impl SeatData {
 			global_id: OnceCell::new(),
 			surfaces: Mutex::new(FxHashMap::default()),
 			pointer: OnceCell::new(),
+			relative_pointers: Mutex::new(Vec::new()),
+			pointer_gestures: Mutex::new(PointerGestures::default()),
 			keyboard: OnceCell::new(),
+			keyboard_focus_sticky: AtomicBool::new(false),
 			touch: OnceCell::new(),
+			touch_points: Mutex::new(FxHashMap::default()),
+			text_inputs: Mutex::new(Vec::new()),
+			text_input_focus: Mutex::new(None),
 		});
 
 		seat_data
 			.global_id
-			.set(dh.create_global::<WaylandState, _, _>(7, seat_data.clone()))
+			.set(dh.create_global::<WaylandState, _, _>(8, seat_data.clone()))
 			.unwrap();
 
 		seat_data
@@ -277,7 +441,7 @@ impl SeatData {
 		drop(surfaces);
 		self.handle_pointer_events();
 	}
-	pub fn keyboard_event(&self, surface: &WlSurface, event: KeyboardEvent) {
+	pub fn keyboard_event(self: &Arc<Self>, surface: &WlSurface, event: KeyboardEvent) {
 		let mut surfaces = self.surfaces.lock();
 		let Some(surface_info) = surfaces.get_mut(&surface.id()) else {return};
 		surface_info.keyboard_queue.push_back(event);
@@ -285,10 +449,51 @@ impl SeatData {
 		self.handle_keyboard_events();
 	}
 
+	pub fn touch_down(&self, surface: &WlSurface, id: i32, position: Vector2<f64>) {
+		let Some(touch) = self.touch.get() else {return};
+		let mut touch_points = self.touch_points.lock();
+		// A point going down again without coming up first gets moved over instead
+		if let Some(old_surface) = touch_points
+			.insert(id, surface.downgrade())
+			.and_then(|s| s.upgrade().ok())
+		{
+			if old_surface != *surface {
+				touch.up(SERIAL_COUNTER.inc(), event_time(), id);
+			}
+		}
+		touch.down(
+			SERIAL_COUNTER.inc(),
+			event_time(),
+			surface,
+			id,
+			position.x,
+			position.y,
+		);
+		touch.frame();
+	}
+	pub fn touch_motion(&self, id: i32, position: Vector2<f64>) {
+		let Some(touch) = self.touch.get() else {return};
+		if !self.touch_points.lock().contains_key(&id) {
+			return;
+		}
+		touch.motion(event_time(), id, position.x, position.y);
+		touch.frame();
+	}
+	pub fn touch_up(&self, id: i32) {
+		let Some(touch) = self.touch.get() else {return};
+		if self.touch_points.lock().remove(&id).is_none() {
+			return;
+		}
+		touch.up(SERIAL_COUNTER.inc(), event_time(), id);
+		touch.frame();
+	}
+
 	fn handle_pointer_events(&self) {
 		let mut surfaces = self.surfaces.lock();
 		let Some((pointer, pointer_focus)) = self.pointer.get() else {return};
 		let mut pointer_focus = pointer_focus.lock();
+		let relative_pointers = self.relative_pointers.lock();
+		let mut gestures = self.pointer_gestures.lock();
 
 		loop {
 			let locked = !pointer_focus.is_null();
@@ -307,7 +512,12 @@ impl SeatData {
 				break;
 			}
 			let Some(surface_info) = surfaces.get_mut(&pointer_focus) else {break};
-			if surface_info.handle_pointer_events(pointer, locked) {
+			if surface_info.handle_pointer_events(
+				pointer,
+				&relative_pointers,
+				&mut gestures,
+				locked,
+			) {
 				// We haven't gotten to a point where we can switch the focus
 				break;
 			} else {
@@ -315,7 +525,8 @@ impl SeatData {
 			}
 		}
 	}
-	fn handle_keyboard_events(&self) {
+	fn handle_keyboard_events(self: &Arc<Self>) {
+		let mut focused_seat = KEYBOARD_FOCUSED_SEAT.lock();
 		let mut surfaces = self.surfaces.lock();
 		let Some((keyboard, keyboard_focus)) = self.keyboard.get() else {return};
 		let mut keyboard_focus = keyboard_focus.lock();
@@ -334,15 +545,90 @@ impl SeatData {
 			}
 			// If there's still none, guess we're done with keyboard events for the time being
 			let Some(surface_info) = surfaces.get_mut(&keyboard_focus) else {break};
-			if surface_info.handle_keyboard_events(keyboard, locked) {
+			if !locked {
+				take_keyboard_focus(&mut focused_seat, self);
+				if let Ok(wl_surface) = surface_info.wl_surface.upgrade() {
+					text_input::set_focus(self, &wl_surface);
+					data_device::set_focus(&self.client);
+				}
+			}
+			let sticky = self.keyboard_focus_sticky.load(Ordering::Relaxed);
+			if surface_info.handle_keyboard_events(keyboard, locked, sticky) {
 				// We haven't gotten to a point where we can switch the focus
 				break;
 			} else {
 				*keyboard_focus = ObjectId::null();
+				if focused_seat.as_ptr() == Arc::as_ptr(self) {
+					*focused_seat = Weak::new();
+				}
 			}
 		}
 	}
 
+	/// Gives this surface keyboard focus until it's dropped or focus is set to something else.
+	/// Only one surface across every client has focus, `None` clears it.
+	pub fn set_keyboard_focus(self: &Arc<Self>, surface: Option<&WlSurface>) {
+		let mut focused_seat = KEYBOARD_FOCUSED_SEAT.lock();
+		match surface {
+			Some(_) => take_keyboard_focus(&mut focused_seat, self),
+			None => *focused_seat = Weak::new(),
+		}
+		drop(focused_seat);
+
+		self.set_local_keyboard_focus(surface);
+		self.handle_keyboard_events();
+	}
+	fn set_local_keyboard_focus(&self, surface: Option<&WlSurface>) {
+		let Some((keyboard, keyboard_focus)) = self.keyboard.get() else {return};
+		let mut surfaces = self.surfaces.lock();
+		let mut keyboard_focus = keyboard_focus.lock();
+		let new_focus = surface
+			.map(|surface| surface.id())
+			.unwrap_or(ObjectId::null());
+		if *keyboard_focus == new_focus {
+			self.keyboard_focus_sticky
+				.store(surface.is_some(), Ordering::Relaxed);
+			return;
+		}
+
+		if let Some(old_surface) = surfaces
+			.get(&keyboard_focus)
+			.and_then(|surface_info| surface_info.wl_surface.upgrade().ok())
+		{
+			keyboard.leave(SERIAL_COUNTER.inc(), &old_surface);
+		}
+		*keyboard_focus = ObjectId::null();
+		self.keyboard_focus_sticky.store(false, Ordering::Relaxed);
+
+		let Some(surface) = surface else { return };
+		let Some(surface_info) = surfaces.get_mut(&surface.id()) else {return};
+		let Some(info) = surface_info.keyboard_info.as_ref() else {return};
+		if !info.send_keymap(keyboard) {
+			return;
+		}
+		keyboard.enter(SERIAL_COUNTER.inc(), surface, vec![]);
+		info.send_modifiers(keyboard);
+		text_input::set_focus(self, surface);
+		data_device::set_focus(&self.client);
+		*keyboard_focus = new_focus;
+		self.keyboard_focus_sticky.store(true, Ordering::Relaxed);
+	}
+
+	pub fn pointer_focus_panel_item(&self) -> Option<Arc<PanelItem>> {
+		let (_, focus) = self.pointer.get()?;
+		let focus = focus.lock();
+		let surfaces = self.surfaces.lock();
+		surfaces.get(&focus)?.panel_item.upgrade()
+	}
+
+	pub fn surface_panel_item(&self, surface: &WlSurface) -> Option<Arc<PanelItem>> {
+		self.surfaces
+			.lock()
+			.get(&surface.id())?
+			.panel_item
+			.upgrade()
+	}
+
 	pub fn new_surface(&self, surface: &WlSurface, panel_item: Weak<PanelItem>) {
 		self.surfaces
 			.lock()
@@ -354,16 +640,106 @@ impl SeatData {
 			let mut pointer_focus = pointer_focus.lock();
 			if *pointer_focus == surface.id() {
 				*pointer_focus = ObjectId::null();
+				self.pointer_gestures.lock().cancel();
 			}
 		}
 		if let Some((_, keyboard_focus)) = self.keyboard.get() {
 			let mut keyboard_focus = keyboard_focus.lock();
 			if *keyboard_focus == surface.id() {
 				*keyboard_focus = ObjectId::null();
+				self.keyboard_focus_sticky.store(false, Ordering::Relaxed);
+			}
+		}
+		let mut text_input_focus = self.text_input_focus.lock();
+		if text_input_focus.as_ref().map(|focus| focus.id()) == Some(surface.id()) {
+			*text_input_focus = None;
+		}
+		// Cancelling takes every touch point of the client with it
+		let mut touch_points = self.touch_points.lock();
+		if touch_points
+			.values()
+			.any(|touch_surface| touch_surface.id() == surface.id())
+		{
+			touch_points.clear();
+			if let Some(touch) = self.touch.get() {
+				touch.cancel();
 			}
 		}
 	}
 }
+
+/// How far a single wheel step scrolls when only discrete steps are given, matching libinput.
+const SCROLL_STEP_DISTANCE: f64 = 15.0;
+
+/// Continuous scroll goes out as `axis`, discrete steps as `axis_value120` so fractional steps
+/// from high resolution input survive, or as whole `axis_discrete` steps for bindings older than
+/// v8. When neither is given the scroll has stopped.
+fn send_scroll(
+	pointer: &WlPointer,
+	axis_continuous: Option<Vector2<f32>>,
+	axis_discrete: Option<Vector2<f32>>,
+) {
+	let version = pointer.version();
+	let time = event_time();
+	if axis_continuous.is_none() && axis_discrete.is_none() {
+		if version >= wl_pointer::EVT_AXIS_STOP_SINCE {
+			pointer.axis_stop(time, Axis::HorizontalScroll);
+			pointer.axis_stop(time, Axis::VerticalScroll);
+			pointer.frame();
+		}
+		return;
+	}
+	if version >= wl_pointer::EVT_AXIS_SOURCE_SINCE {
+		pointer.axis_source(match axis_discrete {
+			Some(_) => AxisSource::Wheel,
+			None => AxisSource::Continuous,
+		});
+	}
+	for (axis, continuous, discrete) in [
+		(
+			Axis::HorizontalScroll,
+			axis_continuous.map(|a| a.x),
+			axis_discrete.map(|a| a.x),
+		),
+		(
+			Axis::VerticalScroll,
+			axis_continuous.map(|a| a.y),
+			axis_discrete.map(|a| a.y),
+		),
+	] {
+		let discrete = discrete.unwrap_or(0.0) as f64;
+		// Every discrete event needs an axis event in the same frame
+		let value = continuous
+			.map(|continuous| continuous as f64)
+			.unwrap_or(discrete * SCROLL_STEP_DISTANCE);
+		if value == 0.0 && discrete == 0.0 {
+			continue;
+		}
+		if version >= wl_pointer::EVT_AXIS_VALUE120_SINCE {
+			let value120 = (discrete * 120.0).round() as i32;
+			if value120 != 0 {
+				pointer.axis_value120(axis, value120);
+			}
+		} else if version >= wl_pointer::EVT_AXIS_DISCRETE_SINCE {
+			let steps = discrete.round() as i32;
+			if steps != 0 {
+				pointer.axis_discrete(axis, steps);
+			}
+		}
+		pointer.axis(time, axis, value);
+	}
+	if version >= wl_pointer::EVT_FRAME_SINCE {
+		pointer.frame();
+	}
+}
+
+/// Milliseconds on an arbitrary clock, clients only use it to tell how far apart events are.
+pub fn event_time() -> u32 {
+	SystemTime::now()
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.unwrap_or_default()
+		.as_millis() as u32
+}
 impl Drop for SeatData {
 	fn drop(&mut self) {
 		let id = self.global_id.take().unwrap();
@@ -388,7 +764,7 @@ impl GlobalDispatch<WlSeat, Arc<SeatData>, WaylandState> for WaylandState {
 			resource.name(nanoid!());
 		}
 
-		resource.capabilities(Capability::Pointer | Capability::Keyboard);
+		resource.capabilities(Capability::Pointer | Capability::Keyboard | Capability::Touch);
 	}
 
 	fn can_view(client: Client, data: &Arc<SeatData>) -> bool {
@@ -413,7 +789,7 @@ impl Dispatch<WlSeat, Arc<SeatData>, WaylandState> for WaylandState {
 			}
 			wl_seat::Request::GetKeyboard { id } => {
 				let keyboard = data_init.init(id, data.clone());
-				keyboard.repeat_info(0, 0);
+				send_repeat_info(&keyboard);
 				let _ = data.keyboard.set((keyboard, Mutex::new(ObjectId::null())));
 			}
 			wl_seat::Request::GetTouch { id } => {
@@ -462,11 +838,7 @@ impl Dispatch<WlPointer, Arc<SeatData>, WaylandState> for WaylandState {
 					})
 				}
 
-				let Some((_, focus)) = seat_data.pointer.get() else {return};
-				let focus = focus.lock();
-				let surfaces = seat_data.surfaces.lock();
-				let Some(surface_info) = surfaces.get(&focus) else {return};
-				let Some(panel_item) = surface_info.panel_item.upgrade() else {return};
+				let Some(panel_item) = seat_data.pointer_focus_panel_item() else {return};
 				panel_item.set_cursor(surface.as_ref(), hotspot_x, hotspot_y);
 			}
 			wl_pointer::Request::Release => (),
@@ -475,6 +847,63 @@ impl Dispatch<WlPointer, Arc<SeatData>, WaylandState> for WaylandState {
 	}
 }
 
+impl GlobalDispatch<ZwpRelativePointerManagerV1, (), WaylandState> for WaylandState {
+	fn bind(
+		_state: &mut WaylandState,
+		_handle: &DisplayHandle,
+		_client: &Client,
+		resource: New<ZwpRelativePointerManagerV1>,
+		_global_data: &(),
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		data_init.init(resource, ());
+	}
+}
+
+impl Dispatch<ZwpRelativePointerManagerV1, (), WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		_resource: &ZwpRelativePointerManagerV1,
+		request: zwp_relative_pointer_manager_v1::Request,
+		_data: &(),
+		_dh: &DisplayHandle,
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			zwp_relative_pointer_manager_v1::Request::GetRelativePointer { id, pointer } => {
+				let Some(seat_data) = pointer.data::<Arc<SeatData>>() else {return};
+				let relative_pointer = data_init.init(id, seat_data.clone());
+				seat_data.relative_pointers.lock().push(relative_pointer);
+			}
+			zwp_relative_pointer_manager_v1::Request::Destroy => (),
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Dispatch<ZwpRelativePointerV1, Arc<SeatData>, WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		resource: &ZwpRelativePointerV1,
+		request: zwp_relative_pointer_v1::Request,
+		seat_data: &Arc<SeatData>,
+		_dh: &DisplayHandle,
+		_data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			zwp_relative_pointer_v1::Request::Destroy => {
+				seat_data
+					.relative_pointers
+					.lock()
+					.retain(|relative_pointer| relative_pointer != resource);
+			}
+			_ => unreachable!(),
+		}
+	}
+}
+
 impl Dispatch<WlKeyboard, Arc<SeatData>, WaylandState> for WaylandState {
 	fn request(
 		_state: &mut WaylandState,
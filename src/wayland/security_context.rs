@@ -0,0 +1,208 @@
+use super::state::{ClientState, WaylandState};
+use crate::core::task;
+use color_eyre::eyre::Result;
+use parking_lot::Mutex;
+use smithay::reexports::{
+	wayland_protocols::wp::security_context::v1::server::{
+		wp_security_context_manager_v1::{self, WpSecurityContextManagerV1},
+		wp_security_context_v1::{self, WpSecurityContextV1},
+	},
+	wayland_server::{Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource},
+};
+use std::{
+	os::{fd::OwnedFd, unix::net::UnixListener},
+	sync::{Arc, Weak},
+};
+use tokio::{io::unix::AsyncFd, net::UnixListener as AsyncUnixListener};
+use tracing::{debug, info, warn};
+
+/// What a sandbox said about the clients connecting through its listener, all of it optional.
+#[derive(Debug, Clone, Default)]
+pub struct SecurityContext {
+	/// Like `flatpak`
+	pub sandbox_engine: Option<String>,
+	pub app_id: Option<String>,
+	/// Tells apart multiple running copies of the same app
+	pub instance_id: Option<String>,
+}
+
+/// A security context that hasn't been committed yet, after that only `committed` matters.
+pub struct PendingSecurityContext {
+	manager: WpSecurityContextManagerV1,
+	listen_fd: Option<OwnedFd>,
+	close_fd: Option<OwnedFd>,
+	context: SecurityContext,
+	committed: bool,
+}
+
+/// Clients from a sandbox can't make more listeners, that would let them out of it.
+fn is_sandboxed(client: &Client) -> bool {
+	client
+		.get_data::<ClientState>()
+		.is_some_and(|state| state.security_context.is_some())
+}
+
+/// Accepts clients on the sandbox's socket until it closes the other end of `close_fd`.
+fn start_listener(
+	state: Weak<Mutex<WaylandState>>,
+	mut dh: DisplayHandle,
+	listen_fd: OwnedFd,
+	close_fd: OwnedFd,
+	context: SecurityContext,
+) -> Result<()> {
+	let listener = UnixListener::from(listen_fd);
+	listener.set_nonblocking(true)?;
+	let listener = AsyncUnixListener::from_std(listener)?;
+	let close_fd = AsyncFd::new(close_fd)?;
+	task::new(|| "wayland security context listener", async move {
+		loop {
+			tokio::select! {
+				// The sandbox hung up, so no one else gets to connect through it
+				_ = close_fd.readable() => break,
+				accepted = listener.accept() => {
+					let stream = match accepted.and_then(|(stream, _)| stream.into_std()) {
+						Ok(stream) => stream,
+						Err(e) => {
+							warn!(?e, "Unable to accept sandboxed Wayland client");
+							continue;
+						}
+					};
+					let client_state = Arc::new(ClientState {
+						compositor_state: Default::default(),
+						security_context: Some(context.clone()),
+					});
+					let client = match dh.insert_client(stream, client_state) {
+						Ok(client) => client,
+						Err(e) => {
+							warn!(?e, "Unable to add sandboxed Wayland client");
+							continue;
+						}
+					};
+					let Some(state) = state.upgrade() else {break};
+					state.lock().new_client(client.id(), &dh);
+				}
+			}
+		}
+		debug!(?context, "Security context listener closed");
+	})?;
+	Ok(())
+}
+
+impl GlobalDispatch<WpSecurityContextManagerV1, (), WaylandState> for WaylandState {
+	fn bind(
+		_state: &mut WaylandState,
+		_handle: &DisplayHandle,
+		_client: &Client,
+		resource: New<WpSecurityContextManagerV1>,
+		_global_data: &(),
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		data_init.init(resource, ());
+	}
+
+	fn can_view(client: Client, _global_data: &()) -> bool {
+		!is_sandboxed(&client)
+	}
+}
+
+impl Dispatch<WpSecurityContextManagerV1, (), WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		client: &Client,
+		resource: &WpSecurityContextManagerV1,
+		request: wp_security_context_manager_v1::Request,
+		_data: &(),
+		_dhandle: &DisplayHandle,
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			wp_security_context_manager_v1::Request::CreateListener {
+				id,
+				listen_fd,
+				close_fd,
+			} => {
+				data_init.init(
+					id,
+					Mutex::new(PendingSecurityContext {
+						manager: resource.clone(),
+						listen_fd: Some(listen_fd),
+						close_fd: Some(close_fd),
+						context: SecurityContext::default(),
+						committed: false,
+					}),
+				);
+				if is_sandboxed(client) {
+					resource.post_error(
+						wp_security_context_manager_v1::Error::Nested,
+						"Sandboxed clients can't create security contexts",
+					);
+				}
+			}
+			wp_security_context_manager_v1::Request::Destroy => (),
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Dispatch<WpSecurityContextV1, Mutex<PendingSecurityContext>, WaylandState> for WaylandState {
+	fn request(
+		state: &mut WaylandState,
+		_client: &Client,
+		resource: &WpSecurityContextV1,
+		request: wp_security_context_v1::Request,
+		pending: &Mutex<PendingSecurityContext>,
+		dhandle: &DisplayHandle,
+		_data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		let mut pending = pending.lock();
+		if pending.committed && !matches!(request, wp_security_context_v1::Request::Destroy) {
+			resource.post_error(
+				wp_security_context_v1::Error::AlreadyUsed,
+				"Security context was already committed",
+			);
+			return;
+		}
+		let (field, value, name) = match request {
+			wp_security_context_v1::Request::SetSandboxEngine { name } => {
+				(&mut pending.context.sandbox_engine, name, "sandbox engine")
+			}
+			wp_security_context_v1::Request::SetAppId { app_id } => {
+				(&mut pending.context.app_id, app_id, "app ID")
+			}
+			wp_security_context_v1::Request::SetInstanceId { instance_id } => {
+				(&mut pending.context.instance_id, instance_id, "instance ID")
+			}
+			wp_security_context_v1::Request::Commit => {
+				pending.committed = true;
+				let (Some(listen_fd), Some(close_fd)) =
+					(pending.listen_fd.take(), pending.close_fd.take()) else {return};
+				let context = pending.context.clone();
+				info!(?context, "New security context");
+				let listener = start_listener(
+					state.weak_ref.clone(),
+					dhandle.clone(),
+					listen_fd,
+					close_fd,
+					context,
+				);
+				if let Err(e) = listener {
+					pending.manager.post_error(
+						wp_security_context_manager_v1::Error::InvalidListenFd,
+						format!("Unable to listen on the security context's socket: {e}"),
+					);
+				}
+				return;
+			}
+			wp_security_context_v1::Request::Destroy => return,
+			_ => unreachable!(),
+		};
+		if field.is_some() {
+			resource.post_error(
+				wp_security_context_v1::Error::AlreadySet,
+				format!("Security context {name} was already set"),
+			);
+			return;
+		}
+		*field = Some(value);
+	}
+}
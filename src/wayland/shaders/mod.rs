@@ -1,7 +1,107 @@
 #![allow(dead_code)]
 
+use glam::{Vec3, Vec4};
+
 // Basic gamma correction shader
 // pub const PANEL_SHADER_BYTES: &[u8] = include_bytes!("shader_unlit_gamma.sks");
 
 // Simula shader with fancy lanzcos sampling
 pub const PANEL_SHADER_BYTES: &[u8] = include_bytes!("shader_unlit_simula.sks");
+
+/// What the panel shader's `ps` does to a filtered, premultiplied texel before blending, kept in
+/// step with shader_unlit_simula.hlsl so the math can be checked without a GPU.
+pub fn panel_color(
+	premultiplied: Vec4,
+	transfer_function: f32,
+	bt2020: f32,
+	alpha_min: f32,
+	alpha_max: f32,
+) -> Vec4 {
+	let gamma = 2.2;
+	let alpha = premultiplied.w;
+	let mut rgb = if alpha > 0.0 {
+		premultiplied.truncate() / alpha
+	} else {
+		Vec3::ZERO
+	};
+	if transfer_function < 0.5 {
+		rgb = rgb.powf(gamma);
+	} else if transfer_function > 1.5 {
+		rgb = pq_to_linear(rgb);
+		rgb = rgb / (Vec3::ONE + rgb);
+	}
+	if bt2020 > 0.5 {
+		rgb = bt2020_to_bt709(rgb).clamp(Vec3::ZERO, Vec3::ONE);
+	}
+	let alpha = alpha_min + alpha * (alpha_max - alpha_min);
+	rgb.extend(alpha)
+}
+
+/// SMPTE ST 2084 EOTF, returns luminance relative to the 203 nit reference white.
+fn pq_to_linear(col: Vec3) -> Vec3 {
+	let m1 = 0.159_301_76;
+	let m2 = 78.84375;
+	let c1 = 0.8359375;
+	let c2 = 18.851_563;
+	let c3 = 18.6875;
+	let p = col.powf(1.0 / m2);
+	let nits = 10000.0 * ((p - c1).max(Vec3::ZERO) / (c2 - c3 * p)).powf(1.0 / m1);
+	nits / 203.0
+}
+
+fn bt2020_to_bt709(col: Vec3) -> Vec3 {
+	Vec3::new(
+		col.dot(Vec3::new(1.6605, -0.5876, -0.0728)),
+		col.dot(Vec3::new(-0.1246, 1.1329, -0.0083)),
+		col.dot(Vec3::new(-0.0182, -0.1006, 1.1187)),
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_close(a: Vec4, b: Vec4) {
+		assert!(a.abs_diff_eq(b, 1e-3), "{a} != {b}");
+	}
+
+	#[test]
+	fn premultiplied_color_is_unpremultiplied_before_the_transfer_function() {
+		// Half transparent linear grey stays grey instead of getting darkened by its alpha
+		let col = panel_color(Vec4::new(0.25, 0.25, 0.25, 0.5), 1.0, 0.0, 0.0, 1.0);
+		assert_close(col, Vec4::new(0.5, 0.5, 0.5, 0.5));
+		// sRGB gets decoded after unpremultiplying, not before
+		let col = panel_color(Vec4::new(0.25, 0.25, 0.25, 0.5), 0.0, 0.0, 0.0, 1.0);
+		assert_close(
+			col,
+			Vec4::new(0.5f32.powf(2.2), 0.5f32.powf(2.2), 0.5f32.powf(2.2), 0.5),
+		);
+		// Fully transparent texels don't divide by zero
+		let col = panel_color(Vec4::ZERO, 0.0, 0.0, 0.0, 1.0);
+		assert_close(col, Vec4::ZERO);
+	}
+
+	#[test]
+	fn pq_reference_white_tonemaps_to_half() {
+		// 203 nits is encoded as about 0.58 in PQ, which is 1.0 before the tonemap
+		let encoded = (203.0f32 / 10000.0).powf(0.159_301_76);
+		let encoded =
+			((0.8359375 + 18.851_563 * encoded) / (1.0 + 18.6875 * encoded)).powf(78.84375);
+		let col = panel_color(
+			Vec4::new(encoded, encoded, encoded, 1.0),
+			2.0,
+			0.0,
+			0.0,
+			1.0,
+		);
+		assert_close(col, Vec4::new(0.5, 0.5, 0.5, 1.0));
+	}
+
+	#[test]
+	fn bt2020_white_stays_white_and_alpha_is_mapped() {
+		let col = panel_color(Vec4::ONE, 1.0, 1.0, 0.0, 1.0);
+		assert_close(col, Vec4::ONE);
+		let col = panel_color(Vec4::new(0.0, 0.0, 0.0, 1.0), 1.0, 0.0, 0.2, 0.6);
+		assert_close(col, Vec4::new(0.0, 0.0, 0.0, 0.6));
+	}
+}
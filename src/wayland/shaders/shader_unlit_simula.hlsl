@@ -10,6 +10,11 @@
 //--ripple      = 4.0
 //--alpha_min   = 0.0
 //--alpha_max   = 1.0
+//--transfer_function = 0.0
+//--bt2020      = 0.0
+//--corner_radius = 0.0
+//--solid       = 0.0
+//--solid_color = 0.0, 0.0, 0.0, 0.0
 Texture2D    diffuse   : register(t0);
 SamplerState diffuse_s : register(s0);
 float4       diffuse_i;
@@ -19,6 +24,11 @@ float        fcFactor;
 float        ripple;
 float        alpha_min;
 float        alpha_max;
+float        transfer_function;
+float        bt2020;
+float        corner_radius;
+float        solid;
+float4       solid_color;
 
 struct vsIn {
 	float4 pos  : SV_Position;
@@ -105,15 +115,61 @@ float4 lowpassFilter(Texture2D tex, sampler2D texSampler, float2 uv, float alpha
 	return q/qSum;
 }
 
+// SMPTE ST 2084 EOTF, returns luminance relative to the 203 nit reference white
+float3 pqToLinear(float3 col) {
+	float m1 = 0.1593017578125;
+	float m2 = 78.84375;
+	float c1 = 0.8359375;
+	float c2 = 18.8515625;
+	float c3 = 18.6875;
+	float3 p = pow(col, float3(1.0 / m2));
+	float3 nits = 10000.0 * pow(max(p - c1, float3(0.0)) / (c2 - c3 * p), float3(1.0 / m1));
+	return nits / 203.0;
+}
+
+float3 bt2020ToBt709(float3 col) {
+	return float3(
+		dot(col, float3( 1.6605, -0.5876, -0.0728)),
+		dot(col, float3(-0.1246,  1.1329, -0.0083)),
+		dot(col, float3(-0.0182, -0.1006,  1.1187)));
+}
+
+// Signed distance in texels from the edge of the rounded rectangle covering the texture
+float roundedRectDistance(float2 uv) {
+	float2 halfSize = diffuse_i.xy * 0.5;
+	float2 q = abs(uv * diffuse_i.xy - halfSize) - halfSize + corner_radius;
+	return length(max(q, float2(0.0))) + min(max(q.x, q.y), 0.0) - corner_radius;
+}
+
 float4 ps(psIn input) : SV_TARGET {
 	float gamma = 2.2;
 	// float4 col = diffuse.Sample(diffuse_s, input.uv);
 
 	// float4 col = lowpassFilter(diffuse, diffuse_s, diffuse_i.xy, float2(1.0 - input.uv.x, input.uv.y), ripple);
-	float4 col = lowpassFilter(diffuse, diffuse_s, input.uv, ripple);
+	// Single-pixel buffers are drawn as their color, premultiplied like any other buffer
+	float4 col = solid > 0.5 ? solid_color : lowpassFilter(diffuse, diffuse_s, input.uv, ripple);
 	// float4 col = diffuse.Sample(diffuse_s, input.uv);
-	col.rgb = pow(col.rgb, float3(gamma));
+	// Wayland buffers are premultiplied, which is also what filtering needs, but the transfer
+	// functions and StereoKit's straight alpha blending need the actual color
+	col.rgb = col.a > 0.0 ? col.rgb / col.a : float3(0.0);
+	// 0 is sRGB, 1 is already linear, 2 is PQ which gets tonemapped down to SDR
+	if (transfer_function < 0.5) {
+		col.rgb = pow(col.rgb, float3(gamma));
+	} else if (transfer_function > 1.5) {
+		col.rgb = pqToLinear(col.rgb);
+		col.rgb = col.rgb / (1.0 + col.rgb);
+	}
+	if (bt2020 > 0.5) {
+		col.rgb = saturate(bt2020ToBt709(col.rgb));
+	}
 	col.a = map(col.a, 0, 1, alpha_min, alpha_max);
+	if (corner_radius > 0.0) {
+		float dist = roundedRectDistance(input.uv);
+		float edge = max(fwidth(dist), 0.0001);
+		float coverage = 1.0 - smoothstep(-edge, edge, dist);
+		clip(coverage - 0.001);
+		col.a *= coverage;
+	}
 
 	return col; 
 }
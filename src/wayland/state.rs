@@ -1,41 +1,239 @@
-use crate::wayland::seat::SeatData;
+use crate::{
+	core::destroy_queue,
+	wayland::{
+		seat::SeatData,
+		security_context::SecurityContext,
+		surface::{reap_client_surfaces, CoreSurface},
+	},
+};
+use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
 use rustc_hash::FxHashMap;
+use serde::Deserialize;
 use smithay::{
 	backend::{
 		allocator::dmabuf::Dmabuf,
-		renderer::{gles::GlesRenderer, ImportDma},
+		drm::DrmDeviceFd,
+		egl::EGLDevice,
+		renderer::{gles::GlesRenderer, ImportDma, ImportMemWl},
 	},
-	delegate_dmabuf, delegate_output, delegate_shm,
+	delegate_dmabuf, delegate_drm_syncobj, delegate_fractional_scale, delegate_output,
+	delegate_presentation, delegate_shm, delegate_single_pixel_buffer, delegate_viewporter,
 	output::{Mode, Output, Scale, Subpixel},
 	reexports::{
-		wayland_protocols::xdg::{
-			decoration::zv1::server::zxdg_decoration_manager_v1::ZxdgDecorationManagerV1,
-			shell::server::xdg_wm_base::XdgWmBase,
+		wayland_protocols::{
+			wp::{
+				color_management::v1::server::wp_color_manager_v1::WpColorManagerV1,
+				content_type::v1::server::wp_content_type_manager_v1::WpContentTypeManagerV1,
+				cursor_shape::v1::server::wp_cursor_shape_manager_v1::WpCursorShapeManagerV1,
+				idle_inhibit::zv1::server::zwp_idle_inhibit_manager_v1::ZwpIdleInhibitManagerV1,
+				pointer_constraints::zv1::server::zwp_pointer_constraints_v1::ZwpPointerConstraintsV1,
+				pointer_gestures::zv1::server::zwp_pointer_gestures_v1::ZwpPointerGesturesV1,
+				primary_selection::zv1::server::{
+					zwp_primary_selection_device_manager_v1::ZwpPrimarySelectionDeviceManagerV1,
+					zwp_primary_selection_device_v1::ZwpPrimarySelectionDeviceV1,
+					zwp_primary_selection_source_v1::ZwpPrimarySelectionSourceV1,
+				},
+				relative_pointer::zv1::server::zwp_relative_pointer_manager_v1::ZwpRelativePointerManagerV1,
+				security_context::v1::server::wp_security_context_manager_v1::WpSecurityContextManagerV1,
+				tearing_control::v1::server::wp_tearing_control_manager_v1::WpTearingControlManagerV1,
+				text_input::zv3::server::zwp_text_input_manager_v3::ZwpTextInputManagerV3,
+			},
+			xdg::{
+				decoration::zv1::server::zxdg_decoration_manager_v1::ZxdgDecorationManagerV1,
+				shell::server::xdg_wm_base::XdgWmBase,
+			},
 		},
 		wayland_protocols_misc::server_decoration::server::org_kde_kwin_server_decoration_manager::Mode as DecorationMode,
 		wayland_server::{
 			backend::{ClientData, ClientId, DisconnectReason},
-			protocol::{wl_buffer::WlBuffer, wl_data_device_manager::WlDataDeviceManager},
+			protocol::{
+				wl_buffer::WlBuffer, wl_data_device_manager::WlDataDeviceManager, wl_shm,
+				wl_surface::WlSurface,
+			},
 			Display, DisplayHandle,
 		},
 	},
-	utils::{Size, Transform},
+	utils::{Clock, DeviceFd, Monotonic, Size, Transform},
 	wayland::{
 		buffer::BufferHandler,
-		compositor::CompositorState,
-		dmabuf::{self, DmabufGlobal, DmabufHandler, DmabufState},
+		compositor::{self, CompositorClientState, CompositorState},
+		dmabuf::{DmabufGlobal, DmabufHandler, DmabufState, ImportNotifier},
+		drm_syncobj::{supports_syncobj_eventfd, DrmSyncobjHandler, DrmSyncobjState},
+		fractional_scale::{
+			with_fractional_scale, FractionalScaleHandler, FractionalScaleManagerState,
+		},
+		output::{OutputHandler, OutputManagerState},
+		presentation::PresentationState,
 		shell::kde::decoration::KdeDecorationState,
 		shm::{ShmHandler, ShmState},
+		single_pixel_buffer::SinglePixelBufferState,
+		viewporter::ViewporterState,
+	},
+};
+use std::{
+	fs::OpenOptions,
+	os::fd::OwnedFd,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc, Weak,
 	},
 };
-use std::sync::{Arc, Weak};
-use tracing::info;
+use tracing::{debug, info};
+
+/// Integer scale advertised on the output, also the fallback preferred fractional scale.
+pub const OUTPUT_SCALE: i32 = 2;
+
+/// Every virtual display, surfaces start out on the first one.
+pub static OUTPUTS: OnceCell<Vec<Output>> = OnceCell::new();
+/// Until the headset's display has been measured
+fn default_mode() -> Mode {
+	Mode {
+		size: (4096, 4096).into(),
+		refresh: 60000,
+	}
+}
+/// Refresh rates headsets run at, measured frame times get snapped to these so a few slow frames
+/// don't change what clients pace to.
+const REFRESH_RATES: &[i32] = &[60, 72, 80, 90, 120, 144];
+
+/// Outputs are laid out side by side in the logical space, each with its own mode.
+fn create_output(display_handle: &DisplayHandle, index: u32) -> Output {
+	let output = Output::new(
+		format!("{}x", index + 1),
+		smithay::output::PhysicalProperties {
+			size: Size::default(),
+			subpixel: Subpixel::None,
+			make: "Virtual XR Display".to_owned(),
+			model: "Your Headset Name Here".to_owned(),
+		},
+	);
+	let _output_global = output.create_global::<WaylandState>(display_handle);
+	output.change_current_state(
+		None,
+		Some(Transform::Normal),
+		Some(Scale::Integer(OUTPUT_SCALE)),
+		None,
+	);
+	set_output_mode(&output, index, default_mode());
+	output
+}
+fn set_output_mode(output: &Output, index: u32, mode: Mode) {
+	// Rotated outputs take up their height in the layout instead
+	let logical_width = output.current_transform().transform_size(mode.size).w / OUTPUT_SCALE;
+	output.change_current_state(
+		Some(mode),
+		None,
+		None,
+		Some((logical_width * index as i32, 0).into()),
+	);
+	output.set_preferred(mode);
+}
+
+/// How an output is presented, toolkits render rotated to match.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum OutputTransform {
+	Normal,
+	Rotate90,
+	Rotate180,
+	Rotate270,
+	Flipped,
+	Flipped90,
+	Flipped180,
+	Flipped270,
+}
+impl From<OutputTransform> for Transform {
+	fn from(transform: OutputTransform) -> Self {
+		match transform {
+			OutputTransform::Normal => Transform::Normal,
+			OutputTransform::Rotate90 => Transform::_90,
+			OutputTransform::Rotate180 => Transform::_180,
+			OutputTransform::Rotate270 => Transform::_270,
+			OutputTransform::Flipped => Transform::Flipped,
+			OutputTransform::Flipped90 => Transform::Flipped90,
+			OutputTransform::Flipped180 => Transform::Flipped180,
+			OutputTransform::Flipped270 => Transform::Flipped270,
+		}
+	}
+}
+pub fn set_output_transform(output: &Output, transform: OutputTransform) {
+	output.change_current_state(None, Some(transform.into()), None, None);
+	// The layout depends on the rotated size, so every output after this one moves too
+	let Some(outputs) = OUTPUTS.get() else {return};
+	for (index, output) in outputs.iter().enumerate() {
+		let mode = output.current_mode().unwrap_or_else(default_mode);
+		set_output_mode(output, index as u32, mode);
+	}
+}
 
-pub struct ClientState;
+/// Set on outputs that have been powered off, missing means on.
+struct OutputPowered(AtomicBool);
+/// Surfaces only on powered off outputs don't get processed or sent frame callbacks, so clients
+/// stop rendering for them. The outputs stay advertised, so once they're back on the callbacks
+/// that piled up go out and clients carry on with the same `wl_output`s.
+pub fn set_output_powered(output: &Output, powered: bool) {
+	let user_data = output.user_data();
+	user_data.insert_if_missing_threadsafe(|| OutputPowered(AtomicBool::new(true)));
+	let was_powered = user_data
+		.get::<OutputPowered>()
+		.unwrap()
+		.0
+		.swap(powered, Ordering::Relaxed);
+	if was_powered != powered {
+		info!(output = %output.name(), powered, "Set output power");
+	}
+}
+pub fn output_powered(output: &Output) -> bool {
+	output
+		.user_data()
+		.get::<OutputPowered>()
+		.is_none_or(|powered| powered.0.load(Ordering::Relaxed))
+}
+
+/// Matches every output's mode to the headset's display, from its size in pixels and the average
+/// frame time. Clients only get told when it actually changes.
+pub fn update_output_modes(display_size: (i32, i32), frame_time: f64) {
+	let Some(outputs) = OUTPUTS.get() else {return};
+	let measured_rate = 1.0 / frame_time.max(f64::EPSILON);
+	let distance = |rate: &i32| (*rate as f64 - measured_rate).abs();
+	let refresh_rate = REFRESH_RATES
+		.iter()
+		.copied()
+		.min_by(|a, b| distance(a).total_cmp(&distance(b)))
+		.unwrap_or(60);
+	let size = if display_size.0 > 0 && display_size.1 > 0 {
+		display_size.into()
+	} else {
+		default_mode().size
+	};
+	let mode = Mode {
+		size,
+		refresh: refresh_rate * 1000,
+	};
+	for (index, output) in outputs.iter().enumerate() {
+		if output.current_mode() != Some(mode) {
+			set_output_mode(output, index as u32, mode);
+		}
+	}
+}
+
+pub struct ClientState {
+	pub compositor_state: CompositorClientState,
+	/// Set for clients that connected through a sandbox's security context listener
+	pub security_context: Option<SecurityContext>,
+}
 impl ClientData for ClientState {
 	fn initialized(&self, client_id: ClientId) {
-		info!("Wayland client {:?} connected", client_id);
+		match &self.security_context {
+			Some(context) => info!(
+				sandbox_engine = ?context.sandbox_engine,
+				app_id = ?context.app_id,
+				instance_id = ?context.instance_id,
+				"Sandboxed Wayland client {:?} connected",
+				client_id
+			),
+			None => info!("Wayland client {:?} connected", client_id),
+		}
 	}
 
 	fn disconnected(&self, client_id: ClientId, reason: DisconnectReason) {
@@ -43,9 +241,17 @@ impl ClientData for ClientState {
 			"Wayland client {:?} disconnected because {:#?}",
 			client_id, reason
 		);
+		let reaped_surfaces = reap_client_surfaces(&client_id);
+		debug!(
+			reaped_surfaces,
+			pending_destroys = destroy_queue::len(),
+			"Reaped surfaces of disconnected client"
+		);
 	}
 }
 
+/// Some of the protocol states are never read again, they're kept so their globals stay advertised.
+#[allow(dead_code)]
 pub struct WaylandState {
 	pub weak_ref: Weak<Mutex<WaylandState>>,
 	pub display: Arc<Mutex<Display<WaylandState>>>,
@@ -57,9 +263,52 @@ pub struct WaylandState {
 	pub shm_state: ShmState,
 	pub dmabuf_state: DmabufState,
 	pub dmabuf_global: DmabufGlobal,
-	pub pending_dmabufs: Vec<Dmabuf>,
-	pub output: Output,
+	/// Only there when the renderer's DRM device can wait on the clients' timelines
+	pub syncobj_state: Option<DrmSyncobjState>,
+	pub single_pixel_buffer_state: SinglePixelBufferState,
+	pub viewporter_state: ViewporterState,
+	pub fractional_scale_manager_state: FractionalScaleManagerState,
+	pub presentation_state: PresentationState,
+	pub clock: Clock<Monotonic>,
+	/// Told whether the import worked once the render thread has tried it
+	pub pending_dmabufs: Vec<(Dmabuf, ImportNotifier)>,
+	pub output_manager_state: OutputManagerState,
 	pub seats: FxHashMap<ClientId, Arc<SeatData>>,
+	pub primary_selection_devices: Vec<ZwpPrimarySelectionDeviceV1>,
+	pub primary_selection: Option<ZwpPrimarySelectionSourceV1>,
+}
+
+/// `wl_shm` advertises exactly what the GLES renderer can upload, so smithay rejects buffers in
+/// any other format with `wl_shm.error.invalid_format` instead of them turning into a blank
+/// texture. ARGB8888 and XRGB8888 are required by the protocol and always advertised by smithay,
+/// the renderer adds ABGR8888 and XBGR8888 everywhere, plus 10 bit and half float formats where
+/// the GL extensions for them exist.
+fn extra_shm_formats(renderer: &GlesRenderer) -> Vec<wl_shm::Format> {
+	let formats = renderer
+		.shm_formats()
+		.filter(|format| !matches!(format, wl_shm::Format::Argb8888 | wl_shm::Format::Xrgb8888))
+		.collect::<Vec<_>>();
+	debug!(?formats, "Extra SHM formats");
+	formats
+}
+
+/// Explicit sync timelines are imported through the DRM device the renderer is on, which software
+/// renderers don't have and older kernels can't wait on without blocking.
+fn syncobj_import_device(renderer: &GlesRenderer) -> Option<DrmDeviceFd> {
+	let egl_device = EGLDevice::device_for_display(renderer.egl_context().display()).ok()?;
+	let render_node = egl_device.try_get_render_node().ok().flatten()?;
+	let render_node_path = render_node.dev_path()?;
+	let file = OpenOptions::new()
+		.read(true)
+		.write(true)
+		.open(&render_node_path)
+		.ok()?;
+	let device = DrmDeviceFd::new(DeviceFd::from(OwnedFd::from(file)));
+	if !supports_syncobj_eventfd(&device) {
+		info!(?render_node_path, "DRM device can't do explicit sync");
+		return None;
+	}
+	Some(device)
 }
 
 impl WaylandState {
@@ -67,41 +316,46 @@ impl WaylandState {
 		display: Arc<Mutex<Display<WaylandState>>>,
 		display_handle: DisplayHandle,
 		renderer: &GlesRenderer,
+		output_count: u32,
 	) -> Arc<Mutex<Self>> {
 		let compositor_state = CompositorState::new::<Self>(&display_handle);
 		// let xdg_activation_state = XdgActivationState::new::<Self, _>(&display_handle);
 		let kde_decoration_state =
 			KdeDecorationState::new::<Self>(&display_handle, DecorationMode::Server);
-		let shm_state = ShmState::new::<Self>(&display_handle, vec![]);
+		let shm_state = ShmState::new::<Self>(&display_handle, extra_shm_formats(renderer));
 		let mut dmabuf_state = DmabufState::new();
-		let dmabuf_global = dmabuf_state.create_global::<Self>(
-			&display_handle,
-			renderer.dmabuf_formats().collect::<Vec<_>>(),
-		);
-		let output = Output::new(
-			"1x".to_owned(),
-			smithay::output::PhysicalProperties {
-				size: Size::default(),
-				subpixel: Subpixel::None,
-				make: "Virtual XR Display".to_owned(),
-				model: "Your Headset Name Here".to_owned(),
-			},
-		);
-		let _output_global = output.create_global::<Self>(&display_handle);
-		let mode = Mode {
-			size: (4096, 4096).into(),
-			refresh: 60000,
-		};
-		output.change_current_state(
-			Some(mode),
-			Some(Transform::Normal),
-			Some(Scale::Integer(2)),
-			None,
-		);
-		output.set_preferred(mode);
+		let dmabuf_global =
+			dmabuf_state.create_global::<Self>(&display_handle, renderer.dmabuf_formats());
+		let syncobj_state = syncobj_import_device(renderer)
+			.map(|device| DrmSyncobjState::new::<Self>(&display_handle, device));
+		let single_pixel_buffer_state = SinglePixelBufferState::new::<Self>(&display_handle);
+		let viewporter_state = ViewporterState::new::<Self>(&display_handle);
+		let fractional_scale_manager_state =
+			FractionalScaleManagerState::new::<Self>(&display_handle);
+		let clock = Clock::new();
+		let presentation_state = PresentationState::new::<Self>(&display_handle, clock.id() as u32);
+		// xdg-output derives the logical size from the mode and scale, so it follows any mode change
+		let output_manager_state = OutputManagerState::new_with_xdg_output::<Self>(&display_handle);
+		let outputs = (0..output_count.max(1))
+			.map(|i| create_output(&display_handle, i))
+			.collect::<Vec<_>>();
+		OUTPUTS
+			.set(outputs)
+			.expect("Only one Wayland compositor can exist at once");
 		display_handle.create_global::<Self, WlDataDeviceManager, _>(3, ());
 		display_handle.create_global::<Self, XdgWmBase, _>(5, ());
 		display_handle.create_global::<Self, ZxdgDecorationManagerV1, _>(1, ());
+		display_handle.create_global::<Self, ZwpPointerConstraintsV1, _>(1, ());
+		display_handle.create_global::<Self, ZwpRelativePointerManagerV1, _>(1, ());
+		display_handle.create_global::<Self, ZwpPointerGesturesV1, _>(1, ());
+		display_handle.create_global::<Self, ZwpPrimarySelectionDeviceManagerV1, _>(1, ());
+		display_handle.create_global::<Self, WpCursorShapeManagerV1, _>(1, ());
+		display_handle.create_global::<Self, ZwpTextInputManagerV3, _>(1, ());
+		display_handle.create_global::<Self, WpTearingControlManagerV1, _>(1, ());
+		display_handle.create_global::<Self, ZwpIdleInhibitManagerV1, _>(1, ());
+		display_handle.create_global::<Self, WpColorManagerV1, _>(1, ());
+		display_handle.create_global::<Self, WpContentTypeManagerV1, _>(1, ());
+		display_handle.create_global::<Self, WpSecurityContextManagerV1, _>(1, ());
 
 		info!("Init Wayland compositor");
 
@@ -117,9 +371,17 @@ impl WaylandState {
 				shm_state,
 				dmabuf_state,
 				dmabuf_global,
+				syncobj_state,
+				single_pixel_buffer_state,
+				viewporter_state,
+				fractional_scale_manager_state,
+				presentation_state,
+				clock,
 				pending_dmabufs: Vec::new(),
-				output,
+				output_manager_state,
 				seats: FxHashMap::default(),
+				primary_selection_devices: Vec::new(),
+				primary_selection: None,
 			})
 		})
 	}
@@ -150,11 +412,38 @@ impl DmabufHandler for WaylandState {
 	fn dmabuf_imported(
 		&mut self,
 		_global: &DmabufGlobal,
-		_dmabuf: Dmabuf,
-	) -> Result<(), dmabuf::ImportError> {
-		Ok(())
+		dmabuf: Dmabuf,
+		notifier: ImportNotifier,
+	) {
+		// The renderer lives on the render thread, so the actual import happens there
+		self.pending_dmabufs.push((dmabuf, notifier));
+	}
+}
+impl OutputHandler for WaylandState {}
+impl DrmSyncobjHandler for WaylandState {
+	fn drm_syncobj_state(&mut self) -> Option<&mut DrmSyncobjState> {
+		self.syncobj_state.as_mut()
+	}
+}
+impl FractionalScaleHandler for WaylandState {
+	fn new_fractional_scale(&mut self, surface: WlSurface) {
+		// Start with the last scale sent to the surface so the client renders sharp right away,
+		// the panel's apparent size updates it once it has been drawn
+		let scale = CoreSurface::from_wl_surface(&surface)
+			.map(|core_surface| core_surface.preferred_scale())
+			.unwrap_or(OUTPUT_SCALE as f64);
+		compositor::with_states(&surface, |states| {
+			with_fractional_scale(states, |fractional_scale| {
+				fractional_scale.set_preferred_scale(scale);
+			});
+		});
 	}
 }
 delegate_dmabuf!(WaylandState);
+delegate_drm_syncobj!(WaylandState);
 delegate_shm!(WaylandState);
+delegate_single_pixel_buffer!(WaylandState);
 delegate_output!(WaylandState);
+delegate_viewporter!(WaylandState);
+delegate_fractional_scale!(WaylandState);
+delegate_presentation!(WaylandState);
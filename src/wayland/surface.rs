@@ -1,55 +1,348 @@
-use super::{shaders::PANEL_SHADER_BYTES, state::WaylandState};
+use super::{
+	color_management::ColorDescription,
+	shaders::PANEL_SHADER_BYTES,
+	state::{output_powered, WaylandState, OUTPUT_SCALE},
+	tearing_control,
+};
 use crate::{
 	core::{delta::Delta, destroy_queue, registry::Registry},
-	nodes::drawable::model::Model,
+	nodes::drawable::model::{anisotropy_level, tex_set_colors, Model},
 };
-use mint::Vector2;
+use color_eyre::eyre::{bail, eyre, Result};
+use glam::Vec3;
+use image::{codecs::png::PngEncoder, imageops, ColorType, ImageEncoder, RgbaImage};
+use mint::{Vector2, Vector4};
 use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
+use rustc_hash::FxHashMap;
 use send_wrapper::SendWrapper;
 use smithay::{
-	backend::renderer::{
-		gles::{GlesRenderer, GlesTexture},
-		utils::{import_surface_tree, on_commit_buffer_handler, RendererSurfaceStateUserData},
-		Renderer, Texture,
+	backend::{
+		allocator::Fourcc,
+		renderer::{
+			element::{
+				surface::{render_elements_from_surface_tree, WaylandSurfaceRenderElement},
+				Element, Id, Kind,
+			},
+			gles::{ffi, GlesRenderer, GlesTexture},
+			utils::{
+				draw_render_elements, import_surface_tree, on_commit_buffer_handler, CommitCounter,
+				RendererSurfaceStateUserData,
+			},
+			Bind, Color32F, ExportMem, Frame, Offscreen, Renderer, Texture, TextureMapping,
+		},
+	},
+	desktop::utils::{
+		send_frames_surface_tree, take_presentation_feedback_surface_tree,
+		OutputPresentationFeedback,
 	},
-	desktop::utils::send_frames_surface_tree,
 	output::Output,
-	reexports::wayland_server::{
-		self, protocol::wl_surface::WlSurface, Display, DisplayHandle, Resource,
+	reexports::{
+		wayland_protocols::wp::{
+			presentation_time::server::wp_presentation_feedback,
+			tearing_control::v1::server::wp_tearing_control_v1::PresentationHint,
+		},
+		wayland_server::{
+			self,
+			backend::ClientId,
+			protocol::{wl_buffer::WlBuffer, wl_output, wl_surface::WlSurface},
+			Display, DisplayHandle, Resource,
+		},
+	},
+	utils::{Logical, Monotonic, Rectangle, Scale, Size, Time, Transform as SmithayTransform},
+	wayland::{
+		compositor::{self, SurfaceAttributes, SurfaceData, TraversalAction},
+		drm_syncobj::DrmSyncobjCachedState,
+		fractional_scale::with_fractional_scale,
+		presentation::Refresh,
+		single_pixel_buffer::get_single_pixel_buffer,
+		viewporter::ViewportCachedState,
 	},
-	wayland::compositor::{self, SurfaceData},
 };
 use std::{
 	ffi::c_void,
-	sync::{Arc, Weak},
+	future::Future,
+	path::{Path, PathBuf},
+	sync::{
+		atomic::{AtomicBool, AtomicU32, Ordering},
+		Arc, Weak,
+	},
 	time::Duration,
 };
 use stereokit::{
-	Material, StereoKitDraw, Tex, TextureAddress, TextureFormat, TextureSample, TextureType,
-	Transparency,
+	Material, Shader, StereoKitDraw, Tex, TextureAddress, TextureFormat, TextureSample,
+	TextureType, Transparency,
 };
+use tokio::sync::oneshot;
+use tracing::warn;
 
 pub static CORE_SURFACES: Registry<CoreSurface> = Registry::new();
 
+/// Tints what each commit damaged, so clients redrawing everything for a blinking cursor stand
+/// out. Every surface goes through compositing while it's on, off it costs nothing.
+static DEBUG_DAMAGE: AtomicBool = AtomicBool::new(false);
+/// Premultiplied, the panel shader unpremultiplies it like the buffers under it
+const DAMAGE_TINT: Color32F = Color32F::new(0.4, 0.0, 0.0, 0.4);
+
+pub fn set_debug_damage(enabled: bool) {
+	DEBUG_DAMAGE.store(enabled, Ordering::Relaxed);
+}
+
+/// Used instead of the built-in panel shader for surfaces that didn't get their own.
+static PANEL_SHADER: OnceCell<PathBuf> = OnceCell::new();
+
+pub fn set_panel_shader(path: Option<PathBuf>) {
+	if let Some(path) = path {
+		let _ = PANEL_SHADER.set(path);
+	}
+}
+
+/// Falls back to the built-in panel shader if the one at `path` doesn't load. Custom shaders get
+/// the same parameters as the built-in one, any they don't declare are left out.
+fn load_panel_shader(sk: &impl StereoKitDraw, path: Option<&Path>) -> Shader {
+	if let Some(path) = path {
+		match sk.shader_create_file(path) {
+			Ok(shader) => return shader,
+			Err(e) => warn!(
+				?path,
+				?e,
+				"Unable to load panel shader, using the built-in one"
+			),
+		}
+	}
+	sk.shader_create_mem(PANEL_SHADER_BYTES).unwrap()
+}
+
+/// If the GPU work the surface tree's current buffers wait on with explicit sync is done, true
+/// for buffers without any. Release points are signaled by smithay once the buffers are released.
+fn acquire_points_signaled(surface: &WlSurface) -> bool {
+	let mut signaled = true;
+	compositor::with_surface_tree_downward(
+		surface,
+		(),
+		|_, _, _| TraversalAction::DoChildren(()),
+		|_, data, _| {
+			let mut syncobj_state = data.cached_state.get::<DrmSyncobjCachedState>();
+			let syncobj_state = syncobj_state.current();
+			if let Some(acquire_point) = syncobj_state.acquire_point.as_ref() {
+				signaled &= acquire_point.wait(0).is_ok();
+			}
+		},
+		|_, _, _| true,
+	);
+	signaled
+}
+/// The premultiplied color of a `wp_single_pixel_buffer_v1` buffer, `None` for any other buffer.
+fn single_pixel_color(buffer: &WlBuffer) -> Option<[f32; 4]> {
+	let pixel = get_single_pixel_buffer(buffer).ok()?;
+	Some([pixel.r, pixel.g, pixel.b, pixel.a].map(|channel| channel as f32 / u32::MAX as f32))
+}
+/// 10 bit and half float buffers have more precision than 8 bits per channel, which gets kept
+/// all the way to the panel shader.
+fn is_high_precision(format: Option<Fourcc>) -> bool {
+	gl_format(format) != ffi::RGBA8
+}
+/// The GL internal format StereoKit is told the buffer's texture has, everything that isn't 10
+/// bit or half float gets imported as 8 bits per channel.
+fn gl_format(format: Option<Fourcc>) -> ffi::types::GLenum {
+	match format {
+		Some(
+			Fourcc::Argb2101010 | Fourcc::Xrgb2101010 | Fourcc::Abgr2101010 | Fourcc::Xbgr2101010,
+		) => ffi::RGB10_A2,
+		Some(
+			Fourcc::Argb16161616f
+			| Fourcc::Xrgb16161616f
+			| Fourcc::Abgr16161616f
+			| Fourcc::Xbgr16161616f,
+		) => ffi::RGBA16F,
+		_ => ffi::RGBA8,
+	}
+}
+/// The panel shader's `corner_radius` for a texture `texture_width` texels wide, which differ
+/// from surface pixels with fractional scaling. Corners can't get past half the shorter side.
+fn shader_corner_radius(radius: f32, surface_size: Size<i32, Logical>, texture_width: u32) -> f32 {
+	let radius = radius
+		.min(surface_size.w.min(surface_size.h) as f32 / 2.0)
+		.max(0.0);
+	let texels_per_pixel = texture_width as f32 / surface_size.w.max(1) as f32;
+	radius * texels_per_pixel
+}
+/// Gives every live surface its queue offset for this frame, see `queue_offsets`.
+pub fn assign_queue_offsets(core_surfaces: &[Arc<CoreSurface>]) {
+	let requested = core_surfaces
+		.iter()
+		.map(|core_surface| (*core_surface.material_offset.lock(), core_surface.order))
+		.collect::<Vec<_>>();
+	for (core_surface, queue_offset) in core_surfaces.iter().zip(queue_offsets(&requested)) {
+		core_surface.set_queue_offset(queue_offset);
+	}
+}
+/// Each requested material offset gets its own band of queue offsets, and within a band the
+/// surfaces requesting it are ranked by creation order. Ranks are dense over the given
+/// `(material_offset, order)` pairs, so they only collide once more than `MATERIAL_OFFSET_SLOTS`
+/// surfaces share an offset, and then only the newest ones do.
+fn queue_offsets(requested: &[(u32, u32)]) -> Vec<i32> {
+	let mut sorted = (0..requested.len()).collect::<Vec<_>>();
+	sorted.sort_by_key(|&i| requested[i]);
+	let mut queue_offsets = vec![0; requested.len()];
+	let mut previous_offset = None;
+	let mut rank = 0;
+	for i in sorted {
+		let (material_offset, _) = requested[i];
+		rank = if previous_offset == Some(material_offset) {
+			rank + 1
+		} else {
+			0
+		};
+		previous_offset = Some(material_offset);
+		let slot = rank.min(MATERIAL_OFFSET_SLOTS - 1);
+		queue_offsets[i] = (material_offset * MATERIAL_OFFSET_SLOTS + slot) as i32;
+	}
+	queue_offsets
+}
+/// How a buffer maps onto the panel through the surface's viewport.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ViewportMapping {
+	/// What `size()` reports, the viewport's destination if it has one, otherwise the size of its
+	/// source rectangle or the buffer's logical size.
+	size: Size<i32, Logical>,
+	/// A cropped buffer can't be bound directly and has to be composited.
+	cropped: bool,
+}
+impl ViewportMapping {
+	fn new(buffer_size: Size<i32, Logical>, viewport: &ViewportCachedState) -> Self {
+		ViewportMapping {
+			size: viewport.size().unwrap_or(buffer_size),
+			cropped: viewport.src.is_some(),
+		}
+	}
+}
+/// What a frame does with a surface after its buffers are imported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SurfaceContent {
+	/// No buffer (detached with a null attach) or no size yet, the last mapped data stays up as
+	/// StereoKit's texture still points at it
+	Keep,
+	/// Nothing to draw at zero size (e.g. a 1x1 buffer at scale 2), so only the size changes
+	Empty,
+	Draw(Size<i32, Logical>),
+}
+impl SurfaceContent {
+	fn new(buffer_attached: bool, surface_size: Option<Size<i32, Logical>>) -> Self {
+		match (buffer_attached, surface_size) {
+			(true, Some(size)) if size.w <= 0 || size.h <= 0 => SurfaceContent::Empty,
+			(true, Some(size)) => SurfaceContent::Draw(size),
+			_ => SurfaceContent::Keep,
+		}
+	}
+}
+/// Cleared the first time the GPU can't render into a half float texture, from then on high
+/// precision surface trees get composited at 8 bits per channel.
+static HALF_FLOAT_COMPOSITE: AtomicBool = AtomicBool::new(true);
+
+/// Hands out the creation order used to break ties between equal material offsets.
+static SURFACE_ORDER: AtomicU32 = AtomicU32::new(0);
+/// How many surfaces can share a requested material offset before the newest ones share a queue
+/// offset, kept small so panels stay under the overlay offset used by models without depth testing.
+const MATERIAL_OFFSET_SLOTS: u32 = 16;
+/// Textures of dropped surfaces kept around for new ones, so popups opening and closing don't
+/// keep creating and freeing textures.
+static TEX_POOL: Mutex<Vec<SendWrapper<Tex>>> = Mutex::new(Vec::new());
+const TEX_POOL_SIZE: usize = 8;
+/// Captures are answered on the next frame the surface is processed, this only catches ones
+/// that never will be
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn take_pooled_tex(sk: &impl StereoKitDraw) -> SendWrapper<Tex> {
+	let Some(tex) = TEX_POOL.lock().pop() else {
+		return SendWrapper::new(sk.tex_create(TextureType::IMAGE_NO_MIPS, TextureFormat::RGBA32));
+	};
+	// Detach it from the previous surface's GL texture, which may be freed by now
+	tex_set_colors(tex.as_ref(), 1, 1, &[0_u8; 4]);
+	tex
+}
+fn return_pooled_tex(tex: SendWrapper<Tex>) {
+	let mut pool = TEX_POOL.lock();
+	if pool.len() < TEX_POOL_SIZE {
+		pool.push(tex);
+	} else {
+		drop(pool);
+		destroy_queue::add(tex);
+	}
+}
+
 pub struct CoreSurfaceData {
 	wl_tex: Option<SendWrapper<GlesTexture>>,
+	/// Premultiplied color of a single-pixel buffer, which doesn't have a texture
+	solid_color: Option<[f32; 4]>,
 	pub size: Vector2<u32>,
 }
+impl CoreSurfaceData {
+	/// Keeps the texture and color around for when the surface gets a size again.
+	fn zero_sized(old_data: Option<CoreSurfaceData>) -> Self {
+		CoreSurfaceData {
+			size: Vector2::from([0, 0]),
+			solid_color: old_data.as_ref().and_then(|old_data| old_data.solid_color),
+			wl_tex: old_data.and_then(|mut old_data| old_data.wl_tex.take()),
+		}
+	}
+}
 impl Drop for CoreSurfaceData {
 	fn drop(&mut self) {
 		destroy_queue::add(self.wl_tex.take());
 	}
 }
 
+#[derive(Clone)]
+struct CapturedPixels {
+	width: u32,
+	height: u32,
+	flipped: bool,
+	pixels: Vec<u8>,
+}
+
 pub struct CoreSurface {
 	display: Weak<Mutex<Display<WaylandState>>>,
+	#[allow(dead_code)]
 	pub dh: DisplayHandle,
 	pub weak_surface: wayland_server::Weak<WlSurface>,
+	/// Kept apart from the surface, which can't be upgraded once the client is gone.
+	client_id: Option<ClientId>,
 	mapped_data: Mutex<Option<CoreSurfaceData>>,
-	sk_tex: OnceCell<SendWrapper<Tex>>,
+	/// Fractional scale the client was last told to render at.
+	preferred_scale: Mutex<f64>,
+	sk_tex: OnceCell<Arc<SendWrapper<Tex>>>,
+	/// Set once the texture is bound to other materials, so it doesn't get reused by the pool
+	tex_shared: AtomicBool,
 	sk_mat: OnceCell<Arc<SendWrapper<Material>>>,
-	material_offset: Mutex<Delta<u32>>,
+	/// `None` uses the startup panel shader if there is one
+	shader: Mutex<Delta<Option<PathBuf>>>,
+	composite_tex: Mutex<Option<SendWrapper<GlesTexture>>>,
+	/// The commit each surface in the tree was at when it was last composited, for debug damage
+	damage_commits: Mutex<FxHashMap<Id, CommitCounter>>,
+	/// Breaks ties between surfaces requesting the same material offset so they're sorted the
+	/// same way every frame instead of z-fighting.
+	order: u32,
+	material_offset: Mutex<u32>,
+	/// Worked out from every live surface's material offset by `assign_queue_offsets`
+	queue_offset: Mutex<Delta<i32>>,
+	opacity: Mutex<Delta<f32>>,
+	/// In surface-space pixels, clamped once the surface's size is known.
+	corner_radius: Mutex<Delta<f32>>,
+	color_description: Mutex<Delta<ColorDescription>>,
+	mipmaps: Mutex<Delta<bool>>,
+	mipmaps_dirty: AtomicBool,
+	/// Only has an effect with mipmaps on, since that's when the texture is sampled anisotropically
+	anisotropy: Mutex<Delta<i32>>,
+	/// Bumped on every commit to the surface or its subsurfaces, so frames without one can skip
+	/// importing the buffers again.
+	commits: AtomicU32,
+	imported_commits: AtomicU32,
+	display_model: Mutex<Weak<Model>>,
+	presentation_feedback: Mutex<Option<OutputPresentationFeedback>>,
+	/// The outputs the surface is shown on, the first one is its primary output
+	outputs: Mutex<Vec<Output>>,
+	pending_captures: Mutex<Vec<oneshot::Sender<Result<CapturedPixels, String>>>>,
 	on_commit: Box<dyn Fn(u32) + Send + Sync>,
 	pub pending_material_applications: Mutex<Vec<(Arc<Model>, u32)>>,
 }
@@ -63,24 +356,63 @@ impl CoreSurface {
 	) {
 		compositor::with_states(surface, |data| {
 			data.data_map.insert_if_missing_threadsafe(|| {
-				CORE_SURFACES.add(CoreSurface {
-					display: Arc::downgrade(display),
+				CORE_SURFACES.add(CoreSurface::new(
+					Arc::downgrade(display),
 					dh,
-					weak_surface: surface.downgrade(),
-					mapped_data: Mutex::new(None),
-					sk_tex: OnceCell::new(),
-					sk_mat: OnceCell::new(),
-					material_offset: Mutex::new(Delta::new(0)),
-					on_commit: Box::new(on_commit) as Box<dyn Fn(u32) + Send + Sync>,
-					pending_material_applications: Mutex::new(Vec::new()),
-				})
+					surface,
+					Box::new(on_commit),
+				))
 			});
 		});
 	}
+	fn new(
+		display: Weak<Mutex<Display<WaylandState>>>,
+		dh: DisplayHandle,
+		surface: &WlSurface,
+		on_commit: Box<dyn Fn(u32) + Send + Sync>,
+	) -> Self {
+		CoreSurface {
+			display,
+			dh,
+			weak_surface: surface.downgrade(),
+			client_id: surface.client().map(|client| client.id()),
+			mapped_data: Mutex::new(None),
+			preferred_scale: Mutex::new(OUTPUT_SCALE as f64),
+			sk_tex: OnceCell::new(),
+			tex_shared: AtomicBool::new(false),
+			sk_mat: OnceCell::new(),
+			shader: Mutex::new(Delta::new(None)),
+			composite_tex: Mutex::new(None),
+			damage_commits: Mutex::new(FxHashMap::default()),
+			order: SURFACE_ORDER.fetch_add(1, Ordering::Relaxed),
+			material_offset: Mutex::new(0),
+			queue_offset: Mutex::new(Delta::new(0)),
+			opacity: Mutex::new(Delta::new(1.0)),
+			corner_radius: Mutex::new(Delta::new(0.0)),
+			color_description: Mutex::new(Delta::new(ColorDescription::SRGB)),
+			mipmaps: Mutex::new(Delta::new(false)),
+			mipmaps_dirty: AtomicBool::new(false),
+			anisotropy: Mutex::new(Delta::new(1)),
+			commits: AtomicU32::new(0),
+			imported_commits: AtomicU32::new(0),
+			display_model: Mutex::new(Weak::new()),
+			presentation_feedback: Mutex::new(None),
+			outputs: Mutex::new(Vec::new()),
+			pending_captures: Mutex::new(Vec::new()),
+			on_commit,
+			pending_material_applications: Mutex::new(Vec::new()),
+		}
+	}
 
 	pub fn commit(&self, count: u32) {
+		self.mark_committed();
 		(self.on_commit)(count);
 	}
+	/// For commits to subsurfaces, which end up in this surface's texture.
+	pub fn mark_committed(&self) {
+		self.mipmaps_dirty.store(true, Ordering::Relaxed);
+		self.commits.fetch_add(1, Ordering::Release);
+	}
 
 	pub fn from_wl_surface(surf: &WlSurface) -> Option<Arc<CoreSurface>> {
 		compositor::with_states(surf, |data| {
@@ -88,106 +420,609 @@ impl CoreSurface {
 		})
 	}
 
-	pub fn process(&self, sk: &impl StereoKitDraw, renderer: &mut GlesRenderer) {
+	pub fn process(
+		&self,
+		sk: &impl StereoKitDraw,
+		renderer: &mut GlesRenderer,
+		default_output: &Output,
+	) {
 		let Some(wl_surface) = self.wl_surface() else { return };
 
-		let sk_tex = self.sk_tex.get_or_init(|| {
-			SendWrapper::new(sk.tex_create(TextureType::IMAGE_NO_MIPS, TextureFormat::RGBA32))
-		});
-		self.sk_mat.get_or_init(|| {
-			let shader = sk.shader_create_mem(&PANEL_SHADER_BYTES).unwrap();
+		let sk_tex = self
+			.sk_tex
+			.get_or_init(|| Arc::new(take_pooled_tex(sk)))
+			.as_ref();
+		let shader_changed = self.shader.lock().delta().is_some();
+		let mut material_created = false;
+		let sk_mat = self.sk_mat.get_or_init(|| {
+			material_created = true;
+			let shader = load_panel_shader(sk, self.shader_path().as_deref());
 			let mat = sk.material_create(&shader);
 			sk.material_set_texture(&mat, "diffuse", sk_tex.as_ref());
+			// The panel shader unpremultiplies the buffer, so straight alpha blending is right
 			sk.material_set_transparency(&mat, Transparency::Blend);
+			let mut queue_offset = self.queue_offset.lock();
+			queue_offset.reset();
+			sk.material_set_queue_offset(&mat, **queue_offset);
 			Arc::new(SendWrapper::new(mat))
 		});
+		if shader_changed && !material_created {
+			self.change_shader(sk, sk_mat.as_ref().as_ref(), sk_tex.as_ref());
+		}
 
-		// Let smithay handle buffer management (has to be done here as RendererSurfaceStates is not thread safe)
-		on_commit_buffer_handler(&wl_surface);
-		// Import all surface buffers into textures
-		if import_surface_tree(renderer, &wl_surface).is_err() {
+		// Nothing to import if the client hasn't committed since the last frame, but the first
+		// frame always has to or there's no texture to show
+		let commits = self.commits.load(Ordering::Acquire);
+		let new_commit = self.imported_commits.load(Ordering::Relaxed) != commits
+			|| self.mapped_data.lock().is_none();
+		// Importing buffers the client's GPU is still rendering into would show torn or stale
+		// frames, so the last frame stays up until they're done
+		if new_commit && !acquire_points_signaled(&wl_surface) {
 			return;
 		}
+		if new_commit {
+			// Let smithay handle buffer management (has to be done here as RendererSurfaceStates is not thread safe)
+			on_commit_buffer_handler::<WaylandState>(&wl_surface);
+			// Import all surface buffers into textures, a failed import gets retried next frame
+			if import_surface_tree(renderer, &wl_surface).is_err() {
+				return;
+			}
+			self.imported_commits.store(commits, Ordering::Relaxed);
+		}
 
-		let mapped = compositor::with_states(&wl_surface, |data| {
-			data.data_map
-				.get::<RendererSurfaceStateUserData>()
-				.map(|surface_states| surface_states.borrow().buffer().is_some())
-				.unwrap_or(false)
+		let (content, cropped) = compositor::with_states(&wl_surface, |data| {
+			let Some(surface_states) = data.data_map.get::<RendererSurfaceStateUserData>() else {
+				return (SurfaceContent::Keep, false);
+			};
+			let surface_states = surface_states.lock().unwrap();
+			let mut viewport = data.cached_state.get::<ViewportCachedState>();
+			let mapping = surface_states
+				.buffer_size()
+				.map(|buffer_size| ViewportMapping::new(buffer_size, viewport.current()));
+			let content = SurfaceContent::new(
+				surface_states.buffer().is_some(),
+				mapping.map(|mapping| mapping.size),
+			);
+			(content, mapping.is_some_and(|mapping| mapping.cropped))
 		});
-
-		if !mapped {
-			return;
+		let surface_size = match content {
+			SurfaceContent::Keep => return,
+			SurfaceContent::Draw(surface_size) => surface_size,
+			SurfaceContent::Empty => {
+				let mut mapped_data = self.mapped_data.lock();
+				*mapped_data = Some(CoreSurfaceData::zero_sized(mapped_data.take()));
+				return;
+			}
+		};
+		self.update_preferred_scale(sk, &wl_surface);
+		if self.outputs.lock().is_empty() {
+			self.set_outputs(vec![default_output.clone()]);
 		}
 
+		// Subsurfaces get flattened into one texture so the panel only needs a single material,
+		// and cropping with a viewport or rotated buffers (for rotated outputs) need the same path
+		// as the buffer can't be bound directly
+		let cropped_or_transformed = compositor::with_states(&wl_surface, |data| {
+			let transformed = data
+				.cached_state
+				.get::<SurfaceAttributes>()
+				.current()
+				.buffer_transform
+				!= wl_output::Transform::Normal;
+			cropped || transformed
+		});
+		let composited_tex = if !new_commit
+			|| (!cropped_or_transformed
+				&& compositor::get_children(&wl_surface).is_empty()
+				&& !DEBUG_DAMAGE.load(Ordering::Relaxed))
+		{
+			None
+		} else {
+			match self.composite_surface_tree(renderer, &wl_surface) {
+				Ok(tex) => tex,
+				Err(e) => {
+					warn!(?e, "Unable to composite subsurfaces");
+					None
+				}
+			}
+		};
+
 		let mut mapped_data = self.mapped_data.lock();
 		self.with_states(|data| {
 			// let just_mapped = mapped_data.is_none();
 			// if just_mapped {
-			let renderer_surface_state = data
-				.data_map
-				.get::<RendererSurfaceStateUserData>()
-				.unwrap()
-				.borrow();
-			let smithay_tex = renderer_surface_state
-				.texture::<GlesRenderer>(renderer.id())
-				.unwrap()
-				.clone();
-
-			let sk_tex = self.sk_tex.get().unwrap();
+			let Some(renderer_surface_state) = data.data_map.get::<RendererSurfaceStateUserData>() else {return};
+			let renderer_surface_state = renderer_surface_state.lock().unwrap();
+			// Without a new commit the last texture (composited or not) is still current
+			let last_tex = (!new_commit)
+				.then(|| mapped_data.as_ref().and_then(|d| d.wl_tex.as_ref()))
+				.flatten()
+				.map(|tex| GlesTexture::clone(tex));
+			// Single-pixel buffers never get a texture, the panel shader just draws their color.
+			// Composited trees already had smithay draw them as a color into the texture
+			let solid_color = if composited_tex.is_some() {
+				None
+			} else if new_commit {
+				renderer_surface_state
+					.buffer()
+					.and_then(|buffer| single_pixel_color(buffer))
+			} else {
+				mapped_data
+					.as_ref()
+					.and_then(|mapped_data| mapped_data.solid_color)
+			};
+			// The import can fail without a texture even though there's a buffer
+			let smithay_tex = match solid_color {
+				Some(_) => None,
+				None => {
+					let Some(smithay_tex) = composited_tex.or(last_tex).or_else(|| {
+						renderer_surface_state
+							.texture::<GlesTexture>(renderer.context_id())
+							.cloned()
+					}) else {return};
+					Some(smithay_tex)
+				}
+			};
+
+			let sk_tex = self.sk_tex.get().unwrap().as_ref();
 			let sk_mat = self.sk_mat.get().unwrap();
-			unsafe {
-				sk.tex_set_surface(
-					sk_tex.as_ref(),
-					smithay_tex.tex_id() as usize as *mut c_void,
-					TextureType::IMAGE_NO_MIPS,
-					smithay::backend::renderer::gles::ffi::RGBA8.into(),
-					smithay_tex.width() as i32,
-					smithay_tex.height() as i32,
-					1,
-					false,
-				);
-				sk.tex_set_sample(sk_tex.as_ref(), TextureSample::Point);
-				sk.tex_set_address(sk_tex.as_ref(), TextureAddress::Clamp);
-			}
-			if let Some(material_offset) = self.material_offset.lock().delta() {
-				sk.material_set_queue_offset(sk_mat.as_ref().as_ref(), *material_offset as i32);
-			}
-
-			let surface_size = renderer_surface_state.surface_size().unwrap();
+			// import_surface_tree only uploads the damaged regions of SHM buffers into the same
+			// texture, so StereoKit only needs to be pointed at it again if the texture changed
+			let mut mipmaps = self.mipmaps.lock();
+			let mipmaps_changed = mipmaps.delta().is_some();
+			let old_tex = mapped_data
+				.as_ref()
+				.and_then(|mapped_data| mapped_data.wl_tex.as_ref());
+			let was_solid = mapped_data
+				.as_ref()
+				.is_some_and(|mapped_data| mapped_data.solid_color.is_some());
+			let texture_changed = mipmaps_changed
+				|| match (&smithay_tex, old_tex) {
+					(Some(smithay_tex), Some(old_tex)) => {
+						old_tex.tex_id() != smithay_tex.tex_id()
+							|| old_tex.width() != smithay_tex.width()
+							|| old_tex.height() != smithay_tex.height()
+					}
+					(None, None) => !was_solid,
+					_ => true,
+				};
+			// Mips have to be rebuilt from the new contents after every commit, so only do it
+			// for the surfaces that asked for it
+			if let Some(smithay_tex) = &smithay_tex {
+				if **mipmaps
+					&& (texture_changed || self.mipmaps_dirty.swap(false, Ordering::Relaxed))
+				{
+					let tex_id = smithay_tex.tex_id();
+					let _ = renderer.with_context(|gl| unsafe {
+						gl.BindTexture(ffi::TEXTURE_2D, tex_id);
+						gl.GenerateMipmap(ffi::TEXTURE_2D);
+						gl.BindTexture(ffi::TEXTURE_2D, 0);
+					});
+				}
+			}
+			let solid_changed = solid_color
+				!= mapped_data
+					.as_ref()
+					.and_then(|mapped_data| mapped_data.solid_color);
+			if let Some(color) = solid_color.filter(|_| solid_changed || texture_changed) {
+				let sk_mat = sk_mat.as_ref().as_ref();
+				sk.material_set_vector4(sk_mat, "solid_color", Vector4::from(color));
+			}
+			match &smithay_tex {
+				Some(smithay_tex) if texture_changed => {
+					sk.material_set_float(sk_mat.as_ref().as_ref(), "solid", 0.0);
+					let (tex_type, sample) = if **mipmaps {
+						(TextureType::IMAGE, TextureSample::Anisotropic)
+					} else {
+						(TextureType::IMAGE_NO_MIPS, TextureSample::Point)
+					};
+					unsafe {
+						sk.tex_set_surface(
+							sk_tex.as_ref(),
+							smithay_tex.tex_id() as usize as *mut c_void,
+							tex_type,
+							gl_format(smithay_tex.format()).into(),
+							smithay_tex.width() as i32,
+							smithay_tex.height() as i32,
+							1,
+							false,
+						);
+						sk.tex_set_sample(sk_tex.as_ref(), sample);
+						sk.tex_set_address(sk_tex.as_ref(), TextureAddress::Clamp);
+					}
+				}
+				None if texture_changed => {
+					// Detaches StereoKit's texture from the last buffer's GL texture, which gets
+					// freed along with the old mapped data
+					tex_set_colors(sk_tex.as_ref(), 1, 1, &[0_u8; 4]);
+					sk.material_set_float(sk_mat.as_ref().as_ref(), "solid", 1.0);
+				}
+				_ => (),
+			}
+			let mut anisotropy = self.anisotropy.lock();
+			if anisotropy.delta().is_some() || texture_changed {
+				sk.tex_set_anisotropy(sk_tex.as_ref(), **anisotropy);
+			}
+			if let Some(queue_offset) = self.queue_offset.lock().delta() {
+				sk.material_set_queue_offset(sk_mat.as_ref().as_ref(), *queue_offset);
+			}
+			// The panel shader scales the texture's alpha up to alpha_max
+			if let Some(opacity) = self.opacity.lock().delta() {
+				sk.material_set_float(sk_mat.as_ref().as_ref(), "alpha_max", *opacity);
+			}
+			if let Some(color_description) = self.color_description.lock().delta() {
+				let sk_mat = sk_mat.as_ref().as_ref();
+				let transfer_function = color_description.shader_transfer_function();
+				sk.material_set_float(sk_mat, "transfer_function", transfer_function);
+				sk.material_set_float(sk_mat, "bt2020", color_description.shader_bt2020());
+			}
+
+			if self.corner_radius.lock().delta().is_some() || texture_changed {
+				// A single-pixel buffer's panel samples the detached 1x1 texture's size
+				let texture_width = smithay_tex.as_ref().map_or(1, |tex| tex.width());
+				let radius =
+					shader_corner_radius(**self.corner_radius.lock(), surface_size, texture_width);
+				sk.material_set_float(sk_mat.as_ref().as_ref(), "corner_radius", radius);
+			}
 			let new_mapped_data = CoreSurfaceData {
 				size: Vector2::from([surface_size.w as u32, surface_size.h as u32]),
-				wl_tex: Some(SendWrapper::new(smithay_tex)),
+				wl_tex: smithay_tex.map(SendWrapper::new),
+				solid_color,
 			};
 			*mapped_data = Some(new_mapped_data);
 		});
+		self.process_captures(renderer);
+		// The content is drawn this step, so the feedback gets sent at the start of the next one
+		if let Some(output) = self.primary_output() {
+			let mut feedback = OutputPresentationFeedback::new(&output);
+			take_presentation_feedback_surface_tree(
+				&wl_surface,
+				&mut feedback,
+				|_, _| Some(output.clone()),
+				|_, _| wp_presentation_feedback::Kind::empty(),
+			);
+			*self.presentation_feedback.lock() = Some(feedback);
+		}
 		self.apply_surface_materials();
 	}
 
-	pub fn frame(&self, sk: &impl StereoKitDraw, output: Output) {
+	/// Estimates how big the panel looks in the headset from the distance to and scale of the
+	/// model it's applied to, and tells the client to render at a matching scale.
+	fn update_preferred_scale(&self, sk: &impl StereoKitDraw, wl_surface: &WlSurface) {
+		let Some(model) = self.display_model.lock().upgrade() else { return };
+		let (model_scale, _, position) = model.global_transform().to_scale_rotation_translation();
+		let head_position: Vec3 = sk.input_head().position;
+		let distance = position.distance(head_position).max(0.05);
+		let apparent_size = model_scale.max_element() / distance;
+		// Snap to quarter steps so small head movements don't make the client reallocate buffers
+		let scale = ((OUTPUT_SCALE as f32 * apparent_size).clamp(1.0, 4.0) * 4.0).round() / 4.0;
+		let scale = scale as f64;
+
+		let mut preferred_scale = self.preferred_scale.lock();
+		if *preferred_scale == scale {
+			return;
+		}
+		*preferred_scale = scale;
+		compositor::with_states(wl_surface, |states| {
+			with_fractional_scale(states, |fractional_scale| {
+				fractional_scale.set_preferred_scale(scale);
+			});
+		});
+	}
+
+	fn process_captures(&self, renderer: &mut GlesRenderer) {
+		let mut pending_captures = self.pending_captures.lock();
+		if pending_captures.is_empty() {
+			return;
+		}
+		let mapped_data = self.mapped_data.lock();
+		let Some(mapped_data) = mapped_data.as_ref() else {return};
+		let result = match (&mapped_data.solid_color, &mapped_data.wl_tex) {
+			(Some(color), _) => Ok(Self::fill_solid_color(mapped_data.size, *color)),
+			(None, Some(tex)) => Self::download_texture(renderer, tex).map_err(|e| e.to_string()),
+			(None, None) => return,
+		};
+		for capture in pending_captures.drain(..) {
+			let _ = capture.send(result.clone());
+		}
+	}
+	fn download_texture(renderer: &mut GlesRenderer, tex: &GlesTexture) -> Result<CapturedPixels> {
+		let size = tex.size();
+		let region = Rectangle::from_size(size);
+		let mapping = renderer.copy_texture(tex, region, Fourcc::Abgr8888)?;
+		let pixels = renderer.map_texture(&mapping)?.to_vec();
+		Ok(CapturedPixels {
+			width: size.w as u32,
+			height: size.h as u32,
+			flipped: mapping.flipped(),
+			pixels,
+		})
+	}
+
+	fn fill_solid_color(size: Vector2<u32>, color: [f32; 4]) -> CapturedPixels {
+		let pixel = color.map(|channel| (channel * 255.0).round() as u8);
+		CapturedPixels {
+			width: size.x,
+			height: size.y,
+			flipped: false,
+			pixels: pixel.repeat((size.x * size.y) as usize),
+		}
+	}
+
+	/// What the surface currently shows as a PNG. The pixels get read back on the render thread
+	/// and encoded on a blocking thread, so nothing waits on either.
+	pub fn capture_png(&self) -> Result<impl Future<Output = Result<Vec<u8>>>> {
+		if self.size().is_none() {
+			bail!("Surface isn't mapped yet");
+		}
+		let (result_tx, result_rx) = oneshot::channel();
+		self.pending_captures.lock().push(result_tx);
+		Ok(async move {
+			let captured = tokio::time::timeout(CAPTURE_TIMEOUT, result_rx)
+				.await
+				.map_err(|_| eyre!("Capturing the surface timed out"))?
+				.map_err(|_| eyre!("Surface was destroyed before it could be captured"))?
+				.map_err(|e| eyre!("Unable to read back the surface texture: {e}"))?;
+			tokio::task::spawn_blocking(move || Self::encode_png(captured)).await?
+		})
+	}
+	fn encode_png(captured: CapturedPixels) -> Result<Vec<u8>> {
+		let mut image = RgbaImage::from_raw(captured.width, captured.height, captured.pixels)
+			.ok_or_else(|| eyre!("Surface texture has the wrong number of pixels"))?;
+		if captured.flipped {
+			imageops::flip_vertical_in_place(&mut image);
+		}
+		let mut png = Vec::new();
+		PngEncoder::new(&mut png).write_image(
+			image.as_raw(),
+			image.width(),
+			image.height(),
+			ColorType::Rgba8,
+		)?;
+		Ok(png)
+	}
+
+	/// Draws the surface and all its subsurfaces in z-order into an offscreen texture the size of
+	/// the root surface, anything outside of that gets clipped.
+	fn composite_surface_tree(
+		&self,
+		renderer: &mut GlesRenderer,
+		wl_surface: &WlSurface,
+	) -> Result<Option<GlesTexture>> {
+		let context_id = renderer.context_id();
+		let Some((size, root_format)) = compositor::with_states(wl_surface, |data| {
+			let surface_states = data
+				.data_map
+				.get::<RendererSurfaceStateUserData>()?
+				.lock()
+				.unwrap();
+			let root_format = surface_states
+				.texture::<GlesTexture>(context_id)
+				.and_then(|tex| tex.format());
+			Some((surface_states.surface_size()?, root_format))
+		}) else {return Ok(None)};
+		let buffer_size = size.to_buffer(1, SmithayTransform::Normal);
+		let physical_size = size.to_physical(1);
+		// Compositing a high precision root surface at 8 bits would throw away what it has
+		let high_precision =
+			is_high_precision(root_format) && HALF_FLOAT_COMPOSITE.load(Ordering::Relaxed);
+		let format = if high_precision {
+			Fourcc::Abgr16161616f
+		} else {
+			Fourcc::Abgr8888
+		};
+
+		let mut composite_tex = self.composite_tex.lock();
+		let reusable = composite_tex
+			.as_ref()
+			.is_some_and(|tex| tex.size() == buffer_size && tex.format() == Some(format));
+		if !reusable {
+			let tex: GlesTexture = match renderer.create_buffer(format, buffer_size) {
+				Err(e) if format != Fourcc::Abgr8888 => {
+					warn!(
+						?e,
+						"Unable to composite at half float, falling back to 8 bit"
+					);
+					HALF_FLOAT_COMPOSITE.store(false, Ordering::Relaxed);
+					renderer.create_buffer(Fourcc::Abgr8888, buffer_size)?
+				}
+				tex => tex?,
+			};
+			if let Some(old_tex) = composite_tex.replace(SendWrapper::new(tex)) {
+				destroy_queue::add(old_tex);
+			}
+		}
+		let mut tex = GlesTexture::clone(composite_tex.as_ref().unwrap());
+
+		let elements: Vec<WaylandSurfaceRenderElement<GlesRenderer>> =
+			render_elements_from_surface_tree(
+				renderer,
+				wl_surface,
+				(0, 0),
+				1.0,
+				1.0,
+				Kind::Unspecified,
+			);
+		let full_damage = [Rectangle::from_size(physical_size)];
+		let mut framebuffer = renderer.bind(&mut tex)?;
+		let mut frame =
+			renderer.render(&mut framebuffer, physical_size, SmithayTransform::Normal)?;
+		frame.clear(Color32F::TRANSPARENT, &full_damage)?;
+		draw_render_elements(&mut frame, 1.0, &elements, &full_damage)?;
+		if DEBUG_DAMAGE.load(Ordering::Relaxed) {
+			// The tint stays in the texture until the next commit composites over it
+			let mut damage_commits = self.damage_commits.lock();
+			let scale = Scale::from(1.0);
+			for element in &elements {
+				let geometry = element.geometry(scale);
+				let last_commit = damage_commits.get(element.id()).copied();
+				for damage in element.damage_since(scale, last_commit).iter() {
+					let damage = Rectangle::new(damage.loc + geometry.loc, damage.size);
+					frame.draw_solid(damage, &full_damage, DAMAGE_TINT)?;
+				}
+			}
+			*damage_commits = elements
+				.iter()
+				.map(|element| (element.id().clone(), element.current_commit()))
+				.collect();
+		}
+		// Same GL context as StereoKit, so it samples the texture after this without waiting
+		let _sync_point = frame.finish()?;
+		drop(framebuffer);
+		Ok(Some(tex))
+	}
+
+	pub fn frame(&self, sk: &impl StereoKitDraw) {
 		let Some(wl_surface) = self.wl_surface() else { return };
+		let Some(primary_output) = self.primary_output() else { return };
 
-		send_frames_surface_tree(
-			&wl_surface,
-			&output,
-			Duration::from_secs_f64(sk.time_get()),
-			None,
-			|_, _| Some(output.clone()),
-		);
+		for output in self.outputs.lock().iter() {
+			send_frames_surface_tree(
+				&wl_surface,
+				output,
+				Duration::from_secs_f64(sk.time_get()),
+				None,
+				|_, _| Some(primary_output.clone()),
+			);
+		}
 	}
 
+	/// False once every output the surface is on has been powered off, surfaces that aren't on
+	/// one yet count as on.
+	pub fn powered(&self) -> bool {
+		let outputs = self.outputs.lock();
+		outputs.is_empty() || outputs.iter().any(output_powered)
+	}
+
+	fn primary_output(&self) -> Option<Output> {
+		self.outputs.lock().first().cloned()
+	}
+	/// Moves the surface onto these outputs, sending enter and leave events for the changes.
+	pub fn set_outputs(&self, new_outputs: Vec<Output>) {
+		let Some(wl_surface) = self.wl_surface() else { return };
+		let mut outputs = self.outputs.lock();
+		for output in outputs.iter() {
+			if !new_outputs.contains(output) {
+				output.leave(&wl_surface);
+			}
+		}
+		for output in new_outputs.iter() {
+			if !outputs.contains(output) {
+				output.enter(&wl_surface);
+			}
+		}
+		*outputs = new_outputs;
+	}
+
+	pub fn presented(&self, time: Duration, seq: u64) {
+		let Some(mut feedback) = self.presentation_feedback.lock().take() else { return };
+		let refresh = self
+			.primary_output()
+			.and_then(|output| output.current_mode())
+			.map(|mode| Refresh::fixed(Duration::from_secs_f64(1000.0 / mode.refresh as f64)))
+			.unwrap_or(Refresh::Unknown);
+		// Async surfaces still get vsynced, but they asked not to be told that they are
+		let hint = self
+			.wl_surface()
+			.map(|surface| tearing_control::presentation_hint(&surface));
+		let flags = match hint {
+			Some(PresentationHint::Async) => wp_presentation_feedback::Kind::empty(),
+			_ => wp_presentation_feedback::Kind::Vsync,
+		};
+		feedback.presented(Time::<Monotonic>::from(time), refresh, seq, flags);
+	}
+
+	/// Takes effect once `assign_queue_offsets` runs for the next frame.
 	pub fn set_material_offset(&self, material_offset: u32) {
-		*self.material_offset.lock().value_mut() = material_offset;
+		*self.material_offset.lock() = material_offset;
+	}
+	fn set_queue_offset(&self, queue_offset: i32) {
+		let mut current = self.queue_offset.lock();
+		if **current != queue_offset {
+			*current.value_mut() = queue_offset;
+		}
+	}
+
+	/// Applied on commit, the same as the buffer it describes.
+	pub fn set_color_description(&self, color_description: ColorDescription) {
+		let mut current = self.color_description.lock();
+		if **current != color_description {
+			*current.value_mut() = color_description;
+		}
+	}
+
+	/// Swaps the shader under the existing material, so models it's applied to keep it.
+	fn change_shader(&self, sk: &impl StereoKitDraw, sk_mat: &Material, sk_tex: &Tex) {
+		let shader = load_panel_shader(sk, self.shader_path().as_deref());
+		sk.material_set_shader(sk_mat, &shader);
+		// StereoKit only carries over parameters the new shader has too, so everything gets set
+		// again in case they were dropped by the last one
+		sk.material_set_texture(sk_mat, "diffuse", sk_tex);
+		let solid_color = self
+			.mapped_data
+			.lock()
+			.as_ref()
+			.and_then(|mapped_data| mapped_data.solid_color);
+		sk.material_set_float(sk_mat, "solid", solid_color.map_or(0.0, |_| 1.0));
+		if let Some(color) = solid_color {
+			sk.material_set_vector4(sk_mat, "solid_color", Vector4::from(color));
+		}
+		self.opacity.lock().mark_changed();
+		self.corner_radius.lock().mark_changed();
+		self.color_description.lock().mark_changed();
+	}
+
+	fn shader_path(&self) -> Option<PathBuf> {
+		let shader_path = self.shader.lock().peek().clone();
+		shader_path.or_else(|| PANEL_SHADER.get().cloned())
+	}
+	/// `None` goes back to the startup panel shader, or the built-in one without one.
+	pub fn set_shader(&self, shader_path: Option<PathBuf>) {
+		*self.shader.lock().value_mut() = shader_path;
+	}
+
+	pub fn set_corner_radius(&self, radius: f32) {
+		*self.corner_radius.lock().value_mut() = radius.max(0.0);
+	}
+
+	pub fn set_opacity(&self, opacity: f32) {
+		*self.opacity.lock().value_mut() = opacity.clamp(0.0, 1.0);
+	}
+
+	/// Off by default since the mips get regenerated on every commit.
+	pub fn set_mipmaps(&self, mipmaps: bool) {
+		*self.mipmaps.lock().value_mut() = mipmaps;
+	}
+
+	/// For panels seen at a steep angle, rounded to a level the GPU supports.
+	pub fn set_anisotropy(&self, level: u32) {
+		let level = anisotropy_level(level);
+		let mut anisotropy = self.anisotropy.lock();
+		if **anisotropy != level {
+			*anisotropy.value_mut() = level;
+		}
 	}
 
 	pub fn apply_material(&self, model: Arc<Model>, material_idx: u32) {
+		// The model keeps the panel material and the texture bound to it after the surface is gone
+		self.tex_shared.store(true, Ordering::Relaxed);
 		self.pending_material_applications
 			.lock()
 			.push((model, material_idx));
 	}
 
+	/// The texture the surface is drawn into, for binding to a parameter of another material.
+	/// `None` until the surface has been processed once. Single-pixel buffers are only a color in
+	/// the panel material, so they leave it transparent.
+	pub fn shared_texture(&self) -> Option<Weak<SendWrapper<Tex>>> {
+		let sk_tex = self.sk_tex.get()?;
+		self.tex_shared.store(true, Ordering::Relaxed);
+		Some(Arc::downgrade(sk_tex))
+	}
+
 	fn apply_surface_materials(&self) {
 		for (model, material_idx) in self.pending_material_applications.lock().drain(0..) {
+			*self.display_model.lock() = Arc::downgrade(&model);
 			model
 				.pending_material_replacements
 				.lock()
@@ -195,6 +1030,10 @@ impl CoreSurface {
 		}
 	}
 
+	pub fn preferred_scale(&self) -> f64 {
+		*self.preferred_scale.lock()
+	}
+
 	pub fn wl_surface(&self) -> Option<WlSurface> {
 		self.weak_surface.upgrade().ok()
 	}
@@ -207,24 +1046,219 @@ impl CoreSurface {
 			.map(|wl_surface| compositor::with_states(&wl_surface, f))
 	}
 
+	/// The viewport's destination size if it has one, otherwise the buffer size, in logical pixels.
+	/// Clients using fractional scaling render `size * scale` pixels into it.
 	pub fn size(&self) -> Option<Vector2<u32>> {
 		self.mapped_data.lock().as_ref().map(|d| d.size)
 	}
 
+	/// Maps a UV coordinate on the panel (0 to 1 across the texture) to a point on the surface,
+	/// `None` if it's outside of the texture or the surface isn't mapped.
+	pub fn uv_to_surface(&self, uv: Vector2<f32>) -> Option<Vector2<f64>> {
+		if !(0.0..=1.0).contains(&uv.x) || !(0.0..=1.0).contains(&uv.y) {
+			return None;
+		}
+		let size = self.size()?;
+		Some(Vector2::from([
+			uv.x as f64 * size.x as f64,
+			uv.y as f64 * size.y as f64,
+		]))
+	}
+
+	/// Clients disconnecting at any time is normal, so failures are only logged.
 	pub fn flush_clients(&self) {
-		self.display
-			.upgrade()
-			.unwrap()
-			.lock()
-			.flush_clients()
-			.unwrap();
+		let Some(display) = self.display.upgrade() else {return};
+		let result = display.lock().flush_clients();
+		if let Err(e) = result {
+			warn!(?e, "Unable to flush Wayland clients");
+		}
 	}
 }
+
+/// Frees the GPU resources of a disconnected client's surfaces, instead of waiting for whatever
+/// still holds the core surface to drop it. Returns how many were reaped.
+pub fn reap_client_surfaces(client_id: &ClientId) -> usize {
+	let mut reaped = 0;
+	CORE_SURFACES.retain(|core_surface| {
+		if core_surface.client_id.as_ref() != Some(client_id) {
+			return true;
+		}
+		core_surface.mapped_data.lock().take();
+		destroy_queue::add(core_surface.composite_tex.lock().take());
+		reaped += 1;
+		false
+	});
+	reaped
+}
+
 impl Drop for CoreSurface {
 	fn drop(&mut self) {
 		CORE_SURFACES.remove(self);
 
-		destroy_queue::add(self.sk_tex.take());
+		if let Some(sk_tex) = self.sk_tex.take() {
+			// Materials it was bound to still hold on to it, another surface can't take it over
+			match Arc::try_unwrap(sk_tex) {
+				Ok(sk_tex) if !*self.tex_shared.get_mut() => return_pooled_tex(sk_tex),
+				Ok(sk_tex) => destroy_queue::add(sk_tex),
+				Err(sk_tex) => destroy_queue::add(sk_tex),
+			}
+		}
 		destroy_queue::add(self.sk_mat.take());
+		destroy_queue::add(self.composite_tex.get_mut().take());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use smithay::reexports::wayland_server::backend::ObjectId;
+	use std::os::unix::net::UnixStream;
+
+	#[test]
+	fn corner_radius_is_clamped_and_converted_to_texels() {
+		let size = Size::from((200, 100));
+		assert_eq!(shader_corner_radius(10.0, size, 200), 10.0);
+		// Corners can't overlap, so the radius stops at half the shorter side
+		assert_eq!(shader_corner_radius(80.0, size, 200), 50.0);
+		assert_eq!(shader_corner_radius(-5.0, size, 200), 0.0);
+		// At a scale of 1.5 the buffer has 300 texels across the surface's 200 pixels
+		assert_eq!(shader_corner_radius(10.0, size, 300), 15.0);
+		// A zero size surface doesn't divide by zero
+		assert_eq!(shader_corner_radius(10.0, Size::from((0, 0)), 1), 0.0);
+	}
+
+	#[test]
+	fn surfaces_sharing_a_material_offset_get_distinct_stable_queue_offsets() {
+		// Creation order, not position in the registry, decides the rank
+		let requested = [(0, 7), (1, 3), (0, 2), (0, 40)];
+		assert_eq!(queue_offsets(&requested), [1, 16, 0, 2]);
+		// Ranks are dense, so surfaces created long apart don't wrap onto each other
+		let requested = [(0, 16), (0, 0)];
+		assert_eq!(queue_offsets(&requested), [1, 0]);
+		// Each surface keeps its offset frame to frame, whatever order they're listed in
+		let requested = [(0, 40), (0, 2), (1, 3), (0, 7)];
+		assert_eq!(queue_offsets(&requested), [2, 0, 16, 1]);
+		// Past the band's slots only the newest ones share the last queue offset
+		let requested = (0..18).map(|order| (0, order)).collect::<Vec<_>>();
+		let offsets = queue_offsets(&requested);
+		assert_eq!(offsets[..15], (0..15).collect::<Vec<_>>()[..]);
+		assert_eq!(offsets[15..], [15, 15, 15]);
+	}
+
+	#[test]
+	fn viewports_report_their_destination_size() {
+		let buffer_size = Size::from((1920, 1080));
+		let mapping = ViewportMapping::new(buffer_size, &ViewportCachedState::default());
+		assert_eq!(
+			mapping,
+			ViewportMapping {
+				size: buffer_size,
+				cropped: false,
+			}
+		);
+
+		// Cropping to the top left quarter without a destination shows it at the crop's size
+		let mut viewport = ViewportCachedState {
+			src: Some(Rectangle::new((0.0, 0.0).into(), (960.0, 540.0).into())),
+			dst: None,
+		};
+		let mapping = ViewportMapping::new(buffer_size, &viewport);
+		assert_eq!(mapping.size, Size::from((960, 540)));
+		assert!(mapping.cropped);
+
+		// With a destination the crop gets scaled to it, and that's the size the panel reports
+		viewport.dst = Some(Size::from((400, 300)));
+		let mapping = ViewportMapping::new(buffer_size, &viewport);
+		assert_eq!(mapping.size, Size::from((400, 300)));
+		assert!(mapping.cropped);
+		assert_eq!(
+			SurfaceContent::new(true, Some(mapping.size)),
+			SurfaceContent::Draw(Size::from((400, 300)))
+		);
+
+		// Scaling without a crop can still bind the buffer directly
+		viewport.src = None;
+		let mapping = ViewportMapping::new(buffer_size, &viewport);
+		assert_eq!(mapping.size, Size::from((400, 300)));
+		assert!(!mapping.cropped);
+	}
+
+	#[test]
+	fn zero_size_commits_report_a_size_of_zero() {
+		let size = Some(Size::from((0, 0)));
+		assert_eq!(SurfaceContent::new(true, size), SurfaceContent::Empty);
+		// A 1x1 buffer at scale 2 rounds down to nothing in one direction
+		let size = Some(Size::from((4, 0)));
+		assert_eq!(SurfaceContent::new(true, size), SurfaceContent::Empty);
+
+		let old_data = CoreSurfaceData {
+			wl_tex: None,
+			solid_color: Some([1.0, 0.0, 0.0, 1.0]),
+			size: Vector2::from([10, 10]),
+		};
+		let data = CoreSurfaceData::zero_sized(Some(old_data));
+		assert_eq!(data.size, Vector2::from([0, 0]));
+		assert_eq!(data.solid_color, Some([1.0, 0.0, 0.0, 1.0]));
+		assert_eq!(
+			CoreSurfaceData::zero_sized(None).size,
+			Vector2::from([0, 0])
+		);
+	}
+
+	#[test]
+	fn detaching_the_buffer_keeps_the_last_frame() {
+		let size = Size::from((200, 100));
+		assert_eq!(
+			SurfaceContent::new(true, Some(size)),
+			SurfaceContent::Draw(size)
+		);
+		// A null attach keeps the last frame up whatever size is left over
+		assert_eq!(SurfaceContent::new(false, Some(size)), SurfaceContent::Keep);
+		assert_eq!(SurfaceContent::new(false, None), SurfaceContent::Keep);
+		// Reattaching draws again
+		assert_eq!(
+			SurfaceContent::new(true, Some(size)),
+			SurfaceContent::Draw(size)
+		);
+	}
+
+	#[test]
+	fn disconnecting_reaps_only_that_clients_surfaces() {
+		let display = Display::<WaylandState>::new().unwrap();
+		let mut dh = display.handle();
+		let mut client_id = || {
+			let (stream, _) = UnixStream::pair().unwrap();
+			dh.insert_client(stream, Arc::new(())).unwrap().id()
+		};
+		let (disconnected, connected) = (client_id(), client_id());
+		// The surfaces only need to be told apart by their client
+		let surface = WlSurface::from_id(&display.handle(), ObjectId::null()).unwrap();
+		let core_surface = |client_id: &ClientId| {
+			let mut core_surface =
+				CoreSurface::new(Weak::new(), display.handle(), &surface, Box::new(|_| ()));
+			core_surface.client_id = Some(client_id.clone());
+			let core_surface = CORE_SURFACES.add(core_surface);
+			*core_surface.mapped_data.lock() = Some(CoreSurfaceData::zero_sized(None));
+			core_surface
+		};
+		let reaped = core_surface(&disconnected);
+		let kept = core_surface(&connected);
+
+		let queued = destroy_queue::len();
+		assert_eq!(reap_client_surfaces(&disconnected), 1);
+		// The mapped texture and the composite texture are both queued
+		assert!(destroy_queue::len() >= queued + 2);
+		assert!(reaped.mapped_data.lock().is_none());
+		assert!(!CORE_SURFACES.contains(&reaped));
+		assert!(kept.mapped_data.lock().is_some());
+		assert!(CORE_SURFACES.contains(&kept));
+	}
+
+	#[test]
+	fn single_pixel_captures_are_filled_with_the_color() {
+		let captured = CoreSurface::fill_solid_color(Vector2::from([2, 3]), [1.0, 0.5, 0.0, 0.5]);
+		assert_eq!((captured.width, captured.height), (2, 3));
+		assert!(!captured.flipped);
+		assert_eq!(captured.pixels, [255, 128, 0, 128].repeat(6));
 	}
 }
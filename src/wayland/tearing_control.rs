@@ -0,0 +1,96 @@
+use super::state::WaylandState;
+use parking_lot::Mutex;
+use smithay::{
+	reexports::{
+		wayland_protocols::wp::tearing_control::v1::server::{
+			wp_tearing_control_manager_v1::{self, WpTearingControlManagerV1},
+			wp_tearing_control_v1::{self, PresentationHint, WpTearingControlV1},
+		},
+		wayland_server::{
+			protocol::wl_surface::WlSurface, Client, DataInit, Dispatch, DisplayHandle,
+			GlobalDispatch, New, Resource, WEnum, Weak as WlWeak,
+		},
+	},
+	wayland::compositor,
+};
+use tracing::debug;
+
+struct TearingControlHint(Mutex<PresentationHint>);
+
+/// The presentation hint the client asked for, everything is vsynced by StereoKit anyway so this
+/// only changes what gets reported back in presentation feedback.
+pub fn presentation_hint(surface: &WlSurface) -> PresentationHint {
+	compositor::with_states(surface, |data| {
+		data.data_map
+			.get::<TearingControlHint>()
+			.map(|hint| *hint.0.lock())
+			.unwrap_or(PresentationHint::Vsync)
+	})
+}
+fn set_presentation_hint(surface: &WlSurface, new_hint: PresentationHint) {
+	compositor::with_states(surface, |data| {
+		data.data_map.insert_if_missing_threadsafe(|| {
+			TearingControlHint(Mutex::new(PresentationHint::Vsync))
+		});
+		*data.data_map.get::<TearingControlHint>().unwrap().0.lock() = new_hint;
+	});
+}
+
+impl GlobalDispatch<WpTearingControlManagerV1, (), WaylandState> for WaylandState {
+	fn bind(
+		_state: &mut WaylandState,
+		_handle: &DisplayHandle,
+		_client: &Client,
+		resource: New<WpTearingControlManagerV1>,
+		_global_data: &(),
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		data_init.init(resource, ());
+	}
+}
+
+impl Dispatch<WpTearingControlManagerV1, (), WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		_resource: &WpTearingControlManagerV1,
+		request: wp_tearing_control_manager_v1::Request,
+		_data: &(),
+		_dhandle: &DisplayHandle,
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			wp_tearing_control_manager_v1::Request::GetTearingControl { id, surface } => {
+				data_init.init(id, surface.downgrade());
+			}
+			wp_tearing_control_manager_v1::Request::Destroy => (),
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Dispatch<WpTearingControlV1, WlWeak<WlSurface>, WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		_resource: &WpTearingControlV1,
+		request: wp_tearing_control_v1::Request,
+		surface: &WlWeak<WlSurface>,
+		_dhandle: &DisplayHandle,
+		_data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		let Ok(surface) = surface.upgrade() else {return};
+		match request {
+			wp_tearing_control_v1::Request::SetPresentationHint { hint } => {
+				let WEnum::Value(hint) = hint else {return};
+				debug!(?surface, ?hint, "Set presentation hint");
+				set_presentation_hint(&surface, hint);
+			}
+			// Destroying the object resets the surface back to the default
+			wp_tearing_control_v1::Request::Destroy => {
+				set_presentation_hint(&surface, PresentationHint::Vsync);
+			}
+			_ => unreachable!(),
+		}
+	}
+}
@@ -0,0 +1,175 @@
+use super::{seat::SeatData, state::WaylandState};
+use parking_lot::Mutex;
+use smithay::reexports::{
+	wayland_protocols::wp::text_input::zv3::server::{
+		zwp_text_input_manager_v3::{self, ZwpTextInputManagerV3},
+		zwp_text_input_v3::{self, ZwpTextInputV3},
+	},
+	wayland_server::{
+		protocol::wl_surface::WlSurface, Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch,
+		New, Resource,
+	},
+};
+use std::sync::Arc;
+use tracing::debug;
+
+pub struct TextInputData {
+	seat_data: Arc<SeatData>,
+	state: Mutex<TextInputState>,
+}
+#[derive(Default)]
+struct TextInputState {
+	pending_enabled: bool,
+	enabled: bool,
+	commits: u32,
+}
+
+/// Text input focus follows keyboard focus, but doesn't get lost every time all keys are
+/// released so input methods don't flicker on each keystroke.
+pub fn set_focus(seat_data: &SeatData, surface: &WlSurface) {
+	let mut focus = seat_data.text_input_focus.lock();
+	let old_focus = focus.as_ref().and_then(|focus| focus.upgrade().ok());
+	if old_focus.as_ref() == Some(surface) {
+		return;
+	}
+	for text_input in seat_data.text_inputs.lock().iter() {
+		if let Some(old_focus) = &old_focus {
+			text_input.leave(old_focus);
+		}
+		text_input.enter(surface);
+	}
+	*focus = Some(surface.downgrade());
+}
+
+fn with_enabled_text_inputs(
+	seat_data: &SeatData,
+	surface: &WlSurface,
+	f: impl Fn(&ZwpTextInputV3),
+) {
+	let focus = seat_data.text_input_focus.lock();
+	if focus
+		.as_ref()
+		.and_then(|focus| focus.upgrade().ok())
+		.as_ref()
+		!= Some(surface)
+	{
+		return;
+	}
+	for text_input in seat_data.text_inputs.lock().iter() {
+		let Some(data) = text_input.data::<TextInputData>() else {continue};
+		let state = data.state.lock();
+		if !state.enabled {
+			continue;
+		}
+		f(text_input);
+		text_input.done(state.commits);
+	}
+}
+
+pub fn preedit(
+	seat_data: &SeatData,
+	surface: &WlSurface,
+	text: Option<String>,
+	cursor_begin: i32,
+	cursor_end: i32,
+) {
+	with_enabled_text_inputs(seat_data, surface, |text_input| {
+		text_input.preedit_string(text.clone(), cursor_begin, cursor_end);
+	});
+}
+pub fn commit(seat_data: &SeatData, surface: &WlSurface, text: String) {
+	with_enabled_text_inputs(seat_data, surface, |text_input| {
+		text_input.commit_string(Some(text.clone()));
+	});
+}
+
+impl GlobalDispatch<ZwpTextInputManagerV3, (), WaylandState> for WaylandState {
+	fn bind(
+		_state: &mut WaylandState,
+		_handle: &DisplayHandle,
+		_client: &Client,
+		resource: New<ZwpTextInputManagerV3>,
+		_global_data: &(),
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		data_init.init(resource, ());
+	}
+}
+
+impl Dispatch<ZwpTextInputManagerV3, (), WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		_resource: &ZwpTextInputManagerV3,
+		request: zwp_text_input_manager_v3::Request,
+		_data: &(),
+		_dhandle: &DisplayHandle,
+		data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			zwp_text_input_manager_v3::Request::GetTextInput { id, seat } => {
+				let Some(seat_data) = seat.data::<Arc<SeatData>>().cloned() else {return};
+				let text_input = data_init.init(
+					id,
+					TextInputData {
+						seat_data: seat_data.clone(),
+						state: Mutex::new(TextInputState::default()),
+					},
+				);
+				let focus = seat_data.text_input_focus.lock();
+				if let Some(focus) = focus.as_ref().and_then(|focus| focus.upgrade().ok()) {
+					text_input.enter(&focus);
+				}
+				seat_data.text_inputs.lock().push(text_input);
+			}
+			zwp_text_input_manager_v3::Request::Destroy => (),
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Dispatch<ZwpTextInputV3, TextInputData, WaylandState> for WaylandState {
+	fn request(
+		_state: &mut WaylandState,
+		_client: &Client,
+		resource: &ZwpTextInputV3,
+		request: zwp_text_input_v3::Request,
+		data: &TextInputData,
+		_dhandle: &DisplayHandle,
+		_data_init: &mut DataInit<'_, WaylandState>,
+	) {
+		match request {
+			zwp_text_input_v3::Request::Enable => data.state.lock().pending_enabled = true,
+			zwp_text_input_v3::Request::Disable => data.state.lock().pending_enabled = false,
+			zwp_text_input_v3::Request::Commit => {
+				let mut state = data.state.lock();
+				state.commits = state.commits.wrapping_add(1);
+				if state.enabled == state.pending_enabled {
+					return;
+				}
+				state.enabled = state.pending_enabled;
+				let enabled = state.enabled;
+				drop(state);
+				debug!(enabled, "Text input state changed");
+
+				// Let the panel know so it can show or hide a virtual keyboard
+				let focus = data.seat_data.text_input_focus.lock().clone();
+				let Some(focus) = focus.and_then(|focus| focus.upgrade().ok()) else {return};
+				let Some(panel_item) = data.seat_data.surface_panel_item(&focus) else {return};
+				panel_item.set_text_input_enabled(enabled);
+			}
+			// Virtual keyboards don't need any of the context yet
+			zwp_text_input_v3::Request::SetSurroundingText { .. } => (),
+			zwp_text_input_v3::Request::SetTextChangeCause { .. } => (),
+			zwp_text_input_v3::Request::SetContentType { .. } => (),
+			zwp_text_input_v3::Request::SetCursorRectangle { .. } => (),
+			zwp_text_input_v3::Request::Destroy => {
+				data.seat_data
+					.text_inputs
+					.lock()
+					.retain(|text_input| text_input != resource);
+			}
+			_ => unreachable!(),
+		}
+	}
+}
@@ -11,18 +11,19 @@ use nanoid::nanoid;
 use parking_lot::Mutex;
 use serde::{ser::SerializeSeq, Serialize, Serializer};
 use smithay::reexports::{
-	wayland_protocols::xdg::shell::server::{
-		xdg_popup::{self, XdgPopup},
-		xdg_positioner::{self, Anchor, ConstraintAdjustment, Gravity, XdgPositioner},
-		xdg_surface::{self, XdgSurface},
-		xdg_toplevel::{self, XdgToplevel, EVT_WM_CAPABILITIES_SINCE},
-		xdg_wm_base::{self, XdgWmBase},
+	wayland_protocols::xdg::{
+		decoration::zv1::server::zxdg_toplevel_decoration_v1::Mode as DecorationMode,
+		shell::server::{
+			xdg_popup::{self, XdgPopup},
+			xdg_positioner::{self, Anchor, ConstraintAdjustment, Gravity, XdgPositioner},
+			xdg_surface::{self, XdgSurface},
+			xdg_toplevel::{self, XdgToplevel, EVT_WM_CAPABILITIES_SINCE},
+			xdg_wm_base::{self, XdgWmBase},
+		},
 	},
 	wayland_server::{
-		backend::{ClientId, ObjectId},
-		protocol::wl_surface::WlSurface,
-		Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
-		Weak as WlWeak,
+		backend::ClientId, protocol::wl_surface::WlSurface, Client, DataInit, Dispatch,
+		DisplayHandle, GlobalDispatch, New, Resource, WEnum, Weak as WlWeak,
 	},
 };
 use std::{
@@ -156,6 +157,7 @@ impl Dispatch<XdgPositioner, Mutex<PositionerData>, WaylandState> for WaylandSta
 			xdg_positioner::Request::SetConstraintAdjustment {
 				constraint_adjustment,
 			} => {
+				let constraint_adjustment = u32::from(constraint_adjustment);
 				debug!(
 					?positioner,
 					constraint_adjustment, "Set positioner constraint adjustment"
@@ -346,7 +348,7 @@ impl Dispatch<XdgSurface, Mutex<XdgSurfaceData>, WaylandState> for WaylandState
 							let popup_data = popup_data.lock();
 							// panel_item.commit_popup(popup_data);
 							if c == 1 {
-								panel_item.new_popup(&xdg_popup, &*popup_data);
+								panel_item.new_popup(&xdg_popup, &popup_data);
 							}
 						}
 					},
@@ -393,6 +395,7 @@ pub struct ToplevelData {
 	max_size: Option<Vector2<u32>>,
 	min_size: Option<Vector2<u32>>,
 	states: Vec<u32>,
+	decoration_mode: Option<DecorationMode>,
 }
 impl ToplevelData {
 	fn new(xdg_surface: &XdgSurface) -> Self {
@@ -405,6 +408,7 @@ impl ToplevelData {
 			max_size: None,
 			min_size: None,
 			states: Vec::new(),
+			decoration_mode: None,
 		}
 	}
 
@@ -415,6 +419,20 @@ impl ToplevelData {
 	pub fn xdg_surface(&self) -> XdgSurface {
 		self.xdg_surface.upgrade().unwrap()
 	}
+	/// The mode last sent to the toplevel's decoration object, `None` if it doesn't have one.
+	pub fn decoration_mode(&self) -> Option<DecorationMode> {
+		self.decoration_mode
+	}
+	pub fn set_decoration_mode(&mut self, mode: Option<DecorationMode>) {
+		self.decoration_mode = mode;
+	}
+	/// `None` until the client sets one, which most do right after creating the toplevel.
+	pub fn title(&self) -> Option<String> {
+		self.title.clone()
+	}
+	pub fn app_id(&self) -> Option<String> {
+		self.app_id.clone()
+	}
 	fn panel_item(&self) -> Option<Arc<PanelItem>> {
 		let xdg_surface = self.xdg_surface();
 		let xdg_surface_data = XdgSurfaceData::get(&xdg_surface).lock();
@@ -425,7 +443,7 @@ impl Serialize for ToplevelData {
 	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
 		let xdg_surface = self.xdg_surface();
 		let xdg_surface_data = XdgSurfaceData::get(&xdg_surface).lock();
-		let geometry = xdg_surface_data.geometry.clone();
+		let geometry = xdg_surface_data.geometry;
 		let wl_surface = xdg_surface_data.wl_surface();
 		let Some(core_surface) = CoreSurface::from_wl_surface(&wl_surface) else {return serde_error::<S>("Core surface not found")};
 		let Some(size) = core_surface.size() else {return serializer.serialize_none()};
@@ -472,11 +490,27 @@ impl Dispatch<XdgToplevel, Mutex<ToplevelData>, WaylandState> for WaylandState {
 			}
 			xdg_toplevel::Request::SetTitle { title } => {
 				debug!(?xdg_toplevel, ?title, "Set XDG Toplevel title");
-				data.lock().title = (!title.is_empty()).then_some(title);
+				let title = (!title.is_empty()).then_some(title);
+				let mut data = data.lock();
+				if data.title == title {
+					return;
+				}
+				data.title = title.clone();
+				let Some(panel_item) = data.panel_item() else { return };
+				drop(data);
+				panel_item.set_toplevel_title(title);
 			}
 			xdg_toplevel::Request::SetAppId { app_id } => {
 				debug!(?xdg_toplevel, ?app_id, "Set XDG Toplevel app ID");
-				data.lock().app_id = (!app_id.is_empty()).then_some(app_id);
+				let app_id = (!app_id.is_empty()).then_some(app_id);
+				let mut data = data.lock();
+				if data.app_id == app_id {
+					return;
+				}
+				data.app_id = app_id.clone();
+				let Some(panel_item) = data.panel_item() else { return };
+				drop(data);
+				panel_item.set_toplevel_app_id(app_id);
 			}
 			xdg_toplevel::Request::ShowWindowMenu { seat, serial, x, y } => {
 				debug!(
@@ -590,12 +624,7 @@ impl PopupData {
 	}
 
 	pub fn positioner_data(&self) -> Option<PositionerData> {
-		Some(
-			self.positioner
-				.data::<Mutex<PositionerData>>()?
-				.lock()
-				.clone(),
-		)
+		Some(*self.positioner.data::<Mutex<PositionerData>>()?.lock())
 	}
 }
 
@@ -641,7 +670,7 @@ impl Dispatch<XdgPopup, Mutex<PopupData>, WaylandState> for WaylandState {
 				debug!(?xdg_popup, ?positioner, token, "XDG popup reposition");
 				data.positioner = positioner;
 				let Some(panel_item) = data.panel_item() else {return};
-				panel_item.reposition_popup(&*data);
+				panel_item.reposition_popup(&data);
 				// xdg_popup.popup_done(); // temporary hack to avoid apps locking up before popups are implemented
 			}
 			xdg_popup::Request::Destroy => {
@@ -659,7 +688,7 @@ impl Dispatch<XdgPopup, Mutex<PopupData>, WaylandState> for WaylandState {
 	fn destroyed(
 		_state: &mut WaylandState,
 		_client: ClientId,
-		_resource: ObjectId,
+		_resource: &XdgPopup,
 		data: &Mutex<PopupData>,
 	) {
 		let data = data.lock();